@@ -0,0 +1,311 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
+
+use gtk4::{Label, TextView, cairo, glib, prelude::*};
+
+use crate::{commands::CommandRegistry, document::Document, git, gutter::Gutter, history::History};
+
+const BLAME_COLUMN_WIDTH: i32 = 380;
+const BLAME_FONT_SIZE: f64 = 12.0;
+const BLAME_DELAY: Duration = Duration::from_millis(500);
+
+struct Commit {
+    hash: String,
+    author: String,
+    date: String,
+    summary: String,
+    path: String,
+}
+
+impl Commit {
+    fn is_uncommitted(&self) -> bool {
+        self.hash.bytes().all(|byte| byte == b'0')
+    }
+
+    fn annotation(&self) -> String {
+        if self.is_uncommitted() {
+            "Uncommitted change".to_owned()
+        } else {
+            format!(
+                "{} {} {}  {}",
+                &self.hash[..7],
+                self.date,
+                self.author,
+                self.summary
+            )
+        }
+    }
+}
+
+fn parse_blame(output: &str) -> (Vec<Commit>, Vec<usize>) {
+    let mut commits: Vec<Commit> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current = None;
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            if let Some(current) = current {
+                lines.push(current);
+            }
+            continue;
+        }
+        let hash = line.split(' ').next().unwrap_or("");
+        if hash.len() == 40 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            let index = *indices.entry(hash.to_owned()).or_insert_with(|| {
+                commits.push(Commit {
+                    hash: hash.to_owned(),
+                    author: String::new(),
+                    date: String::new(),
+                    summary: String::new(),
+                    path: String::new(),
+                });
+                commits.len() - 1
+            });
+            current = Some(index);
+            continue;
+        }
+        let (Some(index), Some((key, value))) = (current, line.split_once(' ')) else {
+            continue;
+        };
+        let commit = &mut commits[index];
+        match key {
+            "author" => commit.author = value.to_owned(),
+            "author-time" => {
+                commit.date = value
+                    .parse()
+                    .ok()
+                    .and_then(|time| glib::DateTime::from_unix_local(time).ok())
+                    .and_then(|time| time.format("%Y-%m-%d").ok())
+                    .map(Into::into)
+                    .unwrap_or_default()
+            }
+            "summary" => commit.summary = value.to_owned(),
+            "filename" => commit.path = value.to_owned(),
+            _ => {}
+        }
+    }
+    (commits, lines)
+}
+
+pub struct Blame {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    gutter: Rc<Gutter>,
+    history: Rc<History>,
+    column: Cell<usize>,
+    enabled: Cell<bool>,
+    root: RefCell<Option<PathBuf>>,
+    commits: RefCell<Vec<Commit>>,
+    lines: RefCell<Vec<usize>>,
+    generation: Cell<u64>,
+    pending: Cell<bool>,
+}
+
+impl Blame {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        commands: &CommandRegistry,
+        gutter: &Rc<Gutter>,
+        history: &Rc<History>,
+    ) -> Rc<Blame> {
+        let blame = Rc::new(Blame {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            gutter: gutter.clone(),
+            history: history.clone(),
+            column: Cell::new(0),
+            enabled: Cell::new(false),
+            root: RefCell::new(None),
+            commits: RefCell::new(Vec::new()),
+            lines: RefCell::new(Vec::new()),
+            generation: Cell::new(0),
+            pending: Cell::new(false),
+        });
+        let painting = Rc::downgrade(&blame);
+        let clicking = Rc::downgrade(&blame);
+        let column = gutter.add_column(
+            0,
+            move |context, iter, width, y, height| {
+                if let Some(blame) = painting.upgrade() {
+                    blame.paint(context, iter.line() as usize, width, y, height);
+                }
+            },
+            move |iter, button| {
+                if let Some(blame) = clicking.upgrade()
+                    && button == 1
+                {
+                    blame.open_line(iter.line() as usize);
+                }
+            },
+        );
+        blame.column.set(column);
+        let weak = Rc::downgrade(&blame);
+        document.buffer.connect_changed(move |_| {
+            if let Some(blame) = weak.upgrade() {
+                blame.schedule();
+            }
+        });
+        let weak = Rc::downgrade(&blame);
+        document.connect_opened(move |_| {
+            if let Some(blame) = weak.upgrade() {
+                blame.commits.borrow_mut().clear();
+                blame.lines.borrow_mut().clear();
+                blame.refresh();
+            }
+        });
+        let weak = Rc::downgrade(&blame);
+        document.connect_saved(move |_| {
+            if let Some(blame) = weak.upgrade() {
+                blame.refresh();
+            }
+        });
+        let toggling = blame.clone();
+        commands.register("git.blame", "Toggle Blame", move || toggling.toggle());
+        blame
+    }
+
+    fn toggle(self: &Rc<Self>) {
+        let enabled = !self.enabled.get();
+        self.enabled.set(enabled);
+        self.gutter.set_column_width(
+            self.column.get(),
+            if enabled { BLAME_COLUMN_WIDTH } else { 0 },
+        );
+        if enabled {
+            self.refresh();
+        } else {
+            self.generation.set(self.generation.get() + 1);
+            self.commits.borrow_mut().clear();
+            self.lines.borrow_mut().clear();
+        }
+    }
+
+    fn schedule(self: &Rc<Self>) {
+        if !self.enabled.get() || self.pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::timeout_add_local_once(BLAME_DELAY, move || {
+            if let Some(blame) = weak.upgrade() {
+                blame.pending.set(false);
+                blame.refresh();
+            }
+        });
+    }
+
+    fn refresh(self: &Rc<Self>) {
+        if !self.enabled.get() {
+            return;
+        }
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        let path = self.document.path();
+        let Some(root) = git::repository_root(&path) else {
+            self.status.set_text("The file is not in a git repository");
+            self.root.replace(None);
+            self.commits.borrow_mut().clear();
+            self.lines.borrow_mut().clear();
+            self.gutter.queue_draw();
+            return;
+        };
+        let relative = git::relative_path(&root, &path);
+        self.root.replace(Some(root.clone()));
+        let weak = Rc::downgrade(self);
+        git::run(
+            &root,
+            &["blame", "--porcelain", "--contents", "-", "--", &relative],
+            Some(&self.document.text()),
+            move |result| {
+                let Some(blame) = weak.upgrade() else {
+                    return;
+                };
+                if blame.generation.get() != generation {
+                    return;
+                }
+                match result {
+                    Ok(output) => {
+                        let (commits, lines) = parse_blame(&output);
+                        blame.commits.replace(commits);
+                        blame.lines.replace(lines);
+                    }
+                    Err(error) => {
+                        blame.status.set_text(&format!("Blame failed: {}", error));
+                        blame.commits.borrow_mut().clear();
+                        blame.lines.borrow_mut().clear();
+                    }
+                }
+                blame.gutter.queue_draw();
+            },
+        );
+    }
+
+    fn paint(&self, context: &cairo::Context, line: usize, width: f64, y: f64, height: f64) {
+        if !self.enabled.get() {
+            return;
+        }
+        let lines = self.lines.borrow();
+        let Some(&index) = lines.get(line) else {
+            return;
+        };
+        if line > 0 && lines.get(line - 1) == Some(&index) {
+            return;
+        }
+        let commits = self.commits.borrow();
+        let Some(commit) = commits.get(index) else {
+            return;
+        };
+        let line_height = self
+            .text_view
+            .buffer()
+            .iter_at_line(line as i32)
+            .map(|iter| self.text_view.iter_location(&iter).height() as f64)
+            .unwrap_or(height);
+        context.rectangle(0.0, y, width - 8.0, height);
+        context.clip();
+        context.select_font_face(
+            "monospace",
+            cairo::FontSlant::Normal,
+            cairo::FontWeight::Normal,
+        );
+        context.set_font_size(BLAME_FONT_SIZE);
+        if commit.is_uncommitted() {
+            context.set_source_rgb(0.55, 0.75, 0.55);
+        } else {
+            context.set_source_rgb(0.54, 0.56, 0.58);
+        }
+        let Ok(extents) = context.font_extents() else {
+            return;
+        };
+        let baseline = y + (line_height + extents.ascent() - extents.descent()) / 2.0;
+        context.move_to(4.0, baseline);
+        context.show_text(&commit.annotation()).ok();
+    }
+
+    fn open_line(&self, line: usize) {
+        let Some(root) = self.root.borrow().clone() else {
+            return;
+        };
+        let Some(&index) = self.lines.borrow().get(line) else {
+            return;
+        };
+        let commits = self.commits.borrow();
+        let Some(commit) = commits.get(index) else {
+            return;
+        };
+        if commit.is_uncommitted() {
+            self.status.set_text("The line has not been committed yet");
+        } else {
+            self.history
+                .open_revision(&root, &commit.hash, &commit.path);
+        }
+    }
+}
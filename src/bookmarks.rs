@@ -0,0 +1,81 @@
+use gtk4::{TextBuffer, TextTag, TextView, prelude::*};
+
+use crate::commands::CommandRegistry;
+
+pub fn bookmark_tag(buffer: &TextBuffer) -> TextTag {
+    if let Some(tag) = buffer.tag_table().lookup("bookmark") {
+        return tag;
+    }
+    buffer
+        .create_tag(Some("bookmark"), &[("paragraph-background", &"#69a5ff18")])
+        .expect("Could not create tag for bookmarks")
+}
+
+pub fn toggle_bookmark(buffer: &TextBuffer) {
+    let tag = bookmark_tag(buffer);
+    let mut line_start = buffer.iter_at_mark(&buffer.get_insert());
+    line_start.set_line_offset(0);
+    let mut line_end = line_start;
+    line_end.forward_line();
+    if line_start.has_tag(&tag) {
+        buffer.remove_tag(&tag, &line_start, &line_end);
+    } else {
+        buffer.apply_tag(&tag, &line_start, &line_end);
+    }
+}
+
+pub fn bookmark_lines(buffer: &TextBuffer) -> Vec<i32> {
+    let tag = bookmark_tag(buffer);
+    let mut lines = Vec::new();
+    let mut iter = buffer.start_iter();
+    if !iter.starts_tag(Some(&tag)) && !iter.forward_to_tag_toggle(Some(&tag)) {
+        return lines;
+    }
+    loop {
+        let first_line = iter.line();
+        iter.forward_to_tag_toggle(Some(&tag));
+        let mut last_char = iter;
+        last_char.backward_char();
+        lines.extend(first_line..=last_char.line());
+        if !iter.forward_to_tag_toggle(Some(&tag)) {
+            break;
+        }
+    }
+    lines
+}
+
+pub fn goto_bookmark(text_view: &TextView, forward: bool) {
+    let buffer = text_view.buffer();
+    let lines = bookmark_lines(&buffer);
+    let current = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let target = if forward {
+        lines.iter().find(|line| **line > current).or(lines.first())
+    } else {
+        lines
+            .iter()
+            .rev()
+            .find(|line| **line < current)
+            .or(lines.last())
+    };
+    if let Some(line) = target
+        && let Some(iter) = buffer.iter_at_line(*line)
+    {
+        buffer.place_cursor(&iter);
+        text_view.scroll_mark_onscreen(&buffer.get_insert());
+    }
+}
+
+pub fn register_bookmark_commands(commands: &CommandRegistry, text_view: &TextView) {
+    let toggling = text_view.clone();
+    commands.register("bookmarks.toggle", "Toggle Bookmark", move || {
+        toggle_bookmark(&toggling.buffer())
+    });
+    let next = text_view.clone();
+    commands.register("bookmarks.next", "Go to Next Bookmark", move || {
+        goto_bookmark(&next, true)
+    });
+    let previous = text_view.clone();
+    commands.register("bookmarks.previous", "Go to Previous Bookmark", move || {
+        goto_bookmark(&previous, false)
+    });
+}
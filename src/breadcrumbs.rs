@@ -0,0 +1,380 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use gtk4::{
+    Adjustment, Align, Box, Button, Label, ListBox, Orientation, PolicyType, Popover, PositionType,
+    ScrolledWindow, SelectionMode, TextView, gdk, glib, prelude::*,
+};
+use tree_sitter::Point;
+
+use crate::{
+    commands::CommandRegistry,
+    document::Document,
+    outline::{self, Symbol},
+    syntax,
+};
+
+const MAX_STICKY_LINES: usize = 5;
+
+type Chain = Vec<(Symbol, Vec<Symbol>)>;
+
+pub struct Breadcrumbs {
+    text_view: TextView,
+    document: Rc<Document>,
+    adjustment: Adjustment,
+    bar: Box,
+    sticky: Box,
+    sticky_enabled: Cell<bool>,
+    symbols: RefCell<Vec<Symbol>>,
+    chain: RefCell<Option<Chain>>,
+    sticky_symbols: RefCell<Option<Vec<Symbol>>>,
+    popover: Popover,
+    popover_list: ListBox,
+    popover_symbols: RefCell<Vec<Symbol>>,
+    refresh_pending: Rc<Cell<bool>>,
+}
+
+fn crumb_button(icon: &str, name: &str) -> Button {
+    let content = Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(4)
+        .build();
+    if !icon.is_empty() {
+        let glyph = Label::new(Some(icon));
+        glyph.add_css_class("completion_icon");
+        glyph.add_css_class(if icon == "λ" {
+            "completion_function"
+        } else {
+            "completion_type"
+        });
+        content.append(&glyph);
+    }
+    content.append(&Label::new(Some(name)));
+    Button::builder().child(&content).has_frame(false).build()
+}
+
+impl Breadcrumbs {
+    pub fn new(
+        text_view: &TextView,
+        scrolled_window: &ScrolledWindow,
+        document: &Rc<Document>,
+        commands: &CommandRegistry,
+        visible: bool,
+        sticky_enabled: bool,
+    ) -> Rc<Breadcrumbs> {
+        let bar = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .css_name("breadcrumbs")
+            .visible(visible)
+            .build();
+        let sticky = Box::builder()
+            .orientation(Orientation::Vertical)
+            .valign(Align::Start)
+            .name("sticky_header")
+            .visible(false)
+            .build();
+        let popover_list = ListBox::builder()
+            .selection_mode(SelectionMode::Browse)
+            .build();
+        let popover = Popover::builder()
+            .child(
+                &ScrolledWindow::builder()
+                    .child(&popover_list)
+                    .hscrollbar_policy(PolicyType::Never)
+                    .max_content_height(300)
+                    .propagate_natural_height(true)
+                    .propagate_natural_width(true)
+                    .build(),
+            )
+            .has_arrow(false)
+            .position(PositionType::Bottom)
+            .build();
+        popover.set_parent(&bar);
+        let breadcrumbs = Rc::new(Breadcrumbs {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            adjustment: scrolled_window.vadjustment(),
+            bar,
+            sticky,
+            sticky_enabled: Cell::new(sticky_enabled),
+            symbols: RefCell::new(Vec::new()),
+            chain: RefCell::new(None),
+            sticky_symbols: RefCell::new(None),
+            popover,
+            popover_list: popover_list.clone(),
+            popover_symbols: RefCell::new(Vec::new()),
+            refresh_pending: Rc::new(Cell::new(false)),
+        });
+        let weak = Rc::downgrade(&breadcrumbs);
+        popover_list.connect_row_activated(move |_, row| {
+            if let Some(breadcrumbs) = weak.upgrade() {
+                breadcrumbs.popover.popdown();
+                let symbol = breadcrumbs
+                    .popover_symbols
+                    .borrow()
+                    .get(row.index() as usize)
+                    .cloned();
+                if let Some(symbol) = symbol {
+                    outline::jump_to(&breadcrumbs.text_view, &symbol);
+                }
+            }
+        });
+        let buffer = text_view.buffer();
+        let weak = Rc::downgrade(&breadcrumbs);
+        buffer.connect_changed(move |_| {
+            if let Some(breadcrumbs) = weak.upgrade() {
+                breadcrumbs.schedule_refresh();
+            }
+        });
+        let weak = Rc::downgrade(&breadcrumbs);
+        buffer.connect_mark_set(move |buffer, _, mark| {
+            if mark != &buffer.get_insert() {
+                return;
+            }
+            if let Some(breadcrumbs) = weak.upgrade() {
+                breadcrumbs.update_bar();
+            }
+        });
+        let weak = Rc::downgrade(&breadcrumbs);
+        breadcrumbs.adjustment.connect_value_changed(move |_| {
+            if let Some(breadcrumbs) = weak.upgrade() {
+                breadcrumbs.update_sticky();
+            }
+        });
+        let weak = Rc::downgrade(&breadcrumbs);
+        document.connect_language_changed(move |_| {
+            if let Some(breadcrumbs) = weak.upgrade() {
+                breadcrumbs.schedule_refresh();
+            }
+        });
+        let toggling = breadcrumbs.clone();
+        commands.register("view.toggle_breadcrumbs", "Toggle Breadcrumbs", move || {
+            toggling.bar.set_visible(!toggling.bar.is_visible());
+        });
+        let toggling = breadcrumbs.clone();
+        commands.register(
+            "view.toggle_sticky_scroll",
+            "Toggle Sticky Scroll",
+            move || {
+                toggling.sticky_enabled.set(!toggling.sticky_enabled.get());
+                toggling.update_sticky();
+            },
+        );
+        breadcrumbs.refresh();
+        breadcrumbs
+    }
+
+    pub fn bar(&self) -> &Box {
+        &self.bar
+    }
+
+    pub fn sticky_header(&self) -> &Box {
+        &self.sticky
+    }
+
+    fn schedule_refresh(self: &Rc<Self>) {
+        if self.refresh_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(breadcrumbs) = weak.upgrade() {
+                breadcrumbs.refresh_pending.set(false);
+                breadcrumbs.refresh();
+            }
+        });
+    }
+
+    fn refresh(self: &Rc<Self>) {
+        let mut symbols = Vec::new();
+        if self.document.language() == "qat" {
+            let content = self.document.text();
+            if let Some(tree) = syntax::parse_qat(&content) {
+                symbols = outline::nested_symbols(&tree, &content);
+            }
+        }
+        self.symbols.replace(symbols);
+        self.chain.replace(None);
+        self.update_bar();
+        self.sticky_symbols.replace(None);
+        self.update_sticky();
+    }
+
+    fn update_bar(self: &Rc<Self>) {
+        let buffer = self.text_view.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let point = Point {
+            row: cursor.line() as usize,
+            column: cursor.line_index() as usize,
+        };
+        let chain = outline::enclosing(&self.symbols.borrow(), point);
+        let same = self.chain.borrow().as_ref().is_some_and(|current| {
+            current.len() == chain.len()
+                && current.iter().zip(&chain).all(|((shown, _), (symbol, _))| {
+                    shown.name == symbol.name && shown.start == symbol.start
+                })
+        });
+        if same {
+            return;
+        }
+        let mut child = self.bar.first_child();
+        while let Some(current) = child {
+            child = current.next_sibling();
+            if !current.is::<Popover>() {
+                self.bar.remove(&current);
+            }
+        }
+        let file_name = self
+            .document
+            .path()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file_crumb = crumb_button("", &file_name);
+        let weak = Rc::downgrade(self);
+        file_crumb.connect_clicked(move |button| {
+            if let Some(breadcrumbs) = weak.upgrade() {
+                let symbols = breadcrumbs.symbols.borrow().clone();
+                breadcrumbs.show_siblings(button, symbols, None);
+            }
+        });
+        self.bar.append(&file_crumb);
+        for (index, (symbol, _)) in chain.iter().enumerate() {
+            self.bar.append(&Label::new(Some("›")));
+            let crumb = crumb_button(outline::kind_icon(symbol.kind), &symbol.name);
+            let weak = Rc::downgrade(self);
+            crumb.connect_clicked(move |button| {
+                if let Some(breadcrumbs) = weak.upgrade() {
+                    let entry = breadcrumbs
+                        .chain
+                        .borrow()
+                        .as_ref()
+                        .and_then(|chain| chain.get(index).cloned());
+                    if let Some((symbol, siblings)) = entry {
+                        breadcrumbs.show_siblings(button, siblings, Some(&symbol));
+                    }
+                }
+            });
+            self.bar.append(&crumb);
+        }
+        self.chain.replace(Some(chain));
+    }
+
+    fn show_siblings(&self, button: &Button, symbols: Vec<Symbol>, current: Option<&Symbol>) {
+        if symbols.is_empty() {
+            if let Some(current) = current {
+                outline::jump_to(&self.text_view, current);
+            }
+            return;
+        }
+        self.popover_list.remove_all();
+        for symbol in &symbols {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(6)
+                .build();
+            let icon = Label::new(Some(outline::kind_icon(symbol.kind)));
+            icon.add_css_class("completion_icon");
+            row.append(&icon);
+            row.append(
+                &Label::builder()
+                    .label(&symbol.name)
+                    .hexpand(true)
+                    .xalign(0.0)
+                    .build(),
+            );
+            let kind = Label::new(Some(symbol.kind));
+            kind.add_css_class("dim-label");
+            row.append(&kind);
+            self.popover_list.append(&row);
+        }
+        let selected = current
+            .and_then(|current| {
+                symbols
+                    .iter()
+                    .position(|symbol| symbol.start == current.start)
+            })
+            .unwrap_or(0);
+        self.popover_list
+            .select_row(self.popover_list.row_at_index(selected as i32).as_ref());
+        self.popover_symbols.replace(symbols);
+        if let Some(bounds) = button.compute_bounds(&self.bar) {
+            self.popover.set_pointing_to(Some(&gdk::Rectangle::new(
+                bounds.x() as i32,
+                bounds.y() as i32,
+                bounds.width() as i32,
+                bounds.height() as i32,
+            )));
+        }
+        self.popover.popup();
+    }
+
+    fn update_sticky(self: &Rc<Self>) {
+        let mut headers: Vec<Symbol> = Vec::new();
+        if self.sticky_enabled.get() {
+            let (top, _) = self.text_view.line_at_y(self.adjustment.value() as i32);
+            let top = top.line().max(0) as usize;
+            let symbols = self.symbols.borrow();
+            let mut count = 0;
+            loop {
+                let row = top + count;
+                headers = outline::enclosing(
+                    &symbols,
+                    Point {
+                        row,
+                        column: usize::MAX,
+                    },
+                )
+                .into_iter()
+                .map(|(symbol, _)| symbol)
+                .filter(|symbol| symbol.start.row < row && symbol.end.row > row)
+                .take(MAX_STICKY_LINES)
+                .collect();
+                if headers.len() <= count {
+                    break;
+                }
+                count = headers.len();
+            }
+        }
+        let unchanged = self.sticky_symbols.borrow().as_ref().is_some_and(|shown| {
+            shown.len() == headers.len()
+                && shown
+                    .iter()
+                    .zip(&headers)
+                    .all(|(shown, symbol)| shown.start == symbol.start)
+        });
+        if unchanged {
+            return;
+        }
+        while let Some(child) = self.sticky.first_child() {
+            self.sticky.remove(&child);
+        }
+        let buffer = self.text_view.buffer();
+        for symbol in &headers {
+            let Some(start) = buffer.iter_at_line(symbol.start.row as i32) else {
+                continue;
+            };
+            let mut end = start;
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            let text = buffer.text(&start, &end, true);
+            let line = Button::builder()
+                .child(&Label::builder().label(text.trim_end()).xalign(0.0).build())
+                .has_frame(false)
+                .build();
+            let weak = Rc::downgrade(self);
+            let target = symbol.clone();
+            line.connect_clicked(move |_| {
+                if let Some(breadcrumbs) = weak.upgrade() {
+                    outline::jump_to(&breadcrumbs.text_view, &target);
+                }
+            });
+            self.sticky.append(&line);
+        }
+        self.sticky.set_visible(!headers.is_empty());
+        self.sticky_symbols.replace(Some(headers));
+    }
+}
@@ -0,0 +1,132 @@
+use std::{cell::RefCell, rc::Rc};
+
+#[derive(Clone)]
+pub struct Argument {
+    pub prompt: String,
+    pub choices: Vec<(String, String)>,
+}
+
+type Prompter = Rc<dyn Fn(&str)>;
+type RunHandler = Rc<dyn Fn(&str)>;
+pub type Action = Rc<dyn Fn(&str)>;
+
+struct Command {
+    name: String,
+    title: String,
+    argument: Option<Argument>,
+    action: Action,
+}
+
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    commands: Rc<RefCell<Vec<Command>>>,
+    prompter: Rc<RefCell<Option<Prompter>>>,
+    run_handlers: Rc<RefCell<Vec<RunHandler>>>,
+}
+
+impl CommandRegistry {
+    fn insert(&self, command: Command) {
+        let mut commands = self.commands.borrow_mut();
+        commands.retain(|existing| existing.name != command.name);
+        commands.push(command);
+    }
+
+    pub fn register(&self, name: &str, title: &str, action: impl Fn() + 'static) {
+        self.insert(Command {
+            name: name.to_owned(),
+            title: title.to_owned(),
+            argument: None,
+            action: Rc::new(move |_| action()),
+        });
+    }
+
+    pub fn register_with_argument(
+        &self,
+        name: &str,
+        title: &str,
+        argument: Argument,
+        action: impl Fn(&str) + 'static,
+    ) {
+        self.insert(Command {
+            name: name.to_owned(),
+            title: title.to_owned(),
+            argument: Some(argument),
+            action: Rc::new(action),
+        });
+    }
+
+    pub fn set_argument_prompter(&self, prompter: impl Fn(&str) + 'static) {
+        self.prompter.replace(Some(Rc::new(prompter)));
+    }
+
+    pub fn connect_run(&self, handler: impl Fn(&str) + 'static) {
+        self.run_handlers.borrow_mut().push(Rc::new(handler));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands
+            .borrow()
+            .iter()
+            .any(|command| command.name == name)
+    }
+
+    pub fn title(&self, name: &str) -> Option<String> {
+        self.commands
+            .borrow()
+            .iter()
+            .find(|command| command.name == name)
+            .map(|command| command.title.clone())
+    }
+
+    pub fn argument(&self, name: &str) -> Option<Argument> {
+        self.commands
+            .borrow()
+            .iter()
+            .find(|command| command.name == name)
+            .and_then(|command| command.argument.clone())
+    }
+
+    pub fn action(&self, name: &str) -> Option<Action> {
+        self.commands
+            .borrow()
+            .iter()
+            .find(|command| command.name == name)
+            .map(|command| command.action.clone())
+    }
+
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.commands
+            .borrow()
+            .iter()
+            .map(|command| (command.name.clone(), command.title.clone()))
+            .collect()
+    }
+
+    pub fn run(&self, name: &str) -> bool {
+        if self.argument(name).is_some() {
+            let prompter = self.prompter.borrow().clone();
+            return match prompter {
+                Some(prompter) => {
+                    prompter(name);
+                    true
+                }
+                None => false,
+            };
+        }
+        self.run_with_argument(name, "")
+    }
+
+    pub fn run_with_argument(&self, name: &str, argument: &str) -> bool {
+        match self.action(name) {
+            Some(action) => {
+                action(argument);
+                let handlers = self.run_handlers.borrow().clone();
+                for handler in handlers {
+                    handler(name);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
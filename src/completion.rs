@@ -0,0 +1,677 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, EventControllerKey, Label, ListBox, Orientation, PolicyType, Popover, PositionType,
+    PropagationPhase, ScrolledWindow, SelectionMode, TextIter, TextView, TextWindowType, Widget,
+    gdk,
+    glib::{self, Propagation},
+    prelude::*,
+};
+use tree_sitter::{Node, Point};
+
+use crate::{
+    commands::CommandRegistry, document::Document, fuzzy, snippets::Snippets, syntax,
+    word_index::WordIndex,
+};
+
+const MAX_RECENT: usize = 20;
+const MAX_ROWS: usize = 50;
+const AUTO_TRIGGER_LENGTH: usize = 2;
+const CURRENT_BUFFER_BONUS: i32 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Keyword,
+    BuiltinType,
+    Constant,
+    Variable,
+    Parameter,
+    Field,
+    Function,
+    Type,
+    Snippet,
+    Word,
+}
+
+impl Kind {
+    fn icon(self) -> &'static str {
+        match self {
+            Kind::Keyword => "k",
+            Kind::BuiltinType | Kind::Type => "T",
+            Kind::Constant => "c",
+            Kind::Variable => "v",
+            Kind::Parameter => "p",
+            Kind::Field => "f",
+            Kind::Function => "λ",
+            Kind::Snippet => "✂",
+            Kind::Word => "w",
+        }
+    }
+
+    fn css_class(self) -> &'static str {
+        match self {
+            Kind::Keyword => "completion_keyword",
+            Kind::BuiltinType => "completion_builtin",
+            Kind::Type => "completion_type",
+            Kind::Constant => "completion_constant",
+            Kind::Variable | Kind::Parameter => "completion_variable",
+            Kind::Field => "completion_field",
+            Kind::Function => "completion_function",
+            Kind::Snippet => "completion_snippet",
+            Kind::Word => "completion_word",
+        }
+    }
+
+    fn from_label(label: &str) -> Kind {
+        match label {
+            "keyword" => Kind::Keyword,
+            "constant" => Kind::Constant,
+            "variable" => Kind::Variable,
+            "parameter" => Kind::Parameter,
+            "field" => Kind::Field,
+            "function" => Kind::Function,
+            "type" => Kind::Type,
+            "snippet" => Kind::Snippet,
+            _ => Kind::Word,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Kind::Keyword => "keyword",
+            Kind::BuiltinType => "builtin type",
+            Kind::Constant => "constant",
+            Kind::Variable => "variable",
+            Kind::Parameter => "parameter",
+            Kind::Field => "field",
+            Kind::Function => "function",
+            Kind::Type => "type",
+            Kind::Snippet => "snippet",
+            Kind::Word => "word",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Candidate {
+    label: String,
+    kind: Kind,
+    detail: String,
+    proximity: i32,
+    body: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ProvidedItem {
+    pub label: String,
+    pub kind: &'static str,
+    pub detail: String,
+    pub text: String,
+    pub snippet: bool,
+}
+
+type Provider = Rc<dyn Fn(&TextIter) -> Vec<ProvidedItem>>;
+
+#[derive(Clone)]
+struct Item {
+    candidate: Candidate,
+    positions: Vec<usize>,
+}
+
+pub struct Completion {
+    text_view: TextView,
+    document: Rc<Document>,
+    snippets: Rc<Snippets>,
+    word_index: Rc<WordIndex>,
+    popover: Popover,
+    list: ListBox,
+    scrolled_window: ScrolledWindow,
+    items: RefCell<Vec<Item>>,
+    recent: RefCell<Vec<String>>,
+    anchor: Cell<Option<i32>>,
+    manual: Cell<bool>,
+    typed: Cell<bool>,
+    inserting: Cell<bool>,
+    refresh_pending: Rc<Cell<bool>>,
+    provider: RefCell<Option<Provider>>,
+    awaiting: Cell<Option<(i32, bool)>>,
+}
+
+fn is_identifier_char(character: char) -> bool {
+    character.is_alphanumeric() || character == '_'
+}
+
+fn word_start(cursor: &TextIter) -> TextIter {
+    let mut start = *cursor;
+    loop {
+        let mut previous = start;
+        if !previous.backward_char() || !is_identifier_char(previous.char()) {
+            break;
+        }
+        start = previous;
+    }
+    start
+}
+
+fn scope_of<'tree>(node: Node<'tree>, kind: Kind) -> Option<Node<'tree>> {
+    match kind {
+        Kind::Variable => node.parent(),
+        Kind::Parameter | Kind::Field => {
+            let wanted: &[&str] = if kind == Kind::Parameter {
+                syntax::FUNCTION_KINDS
+            } else {
+                syntax::TYPE_KINDS
+            };
+            let mut ancestor = node.parent();
+            while let Some(current) = ancestor {
+                if wanted.contains(&current.kind()) {
+                    return Some(current);
+                }
+                ancestor = current.parent();
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+fn depth(node: Node) -> i32 {
+    let mut depth = 0;
+    let mut ancestor = node.parent();
+    while let Some(current) = ancestor {
+        depth += 1;
+        ancestor = current.parent();
+    }
+    depth
+}
+
+fn contains(node: Node, point: Point) -> bool {
+    node.start_position() <= point && point <= node.end_position()
+}
+
+fn declared_identifiers(content: &str, cursor: Point) -> Vec<Candidate> {
+    let Some(tree) = syntax::parse_qat(content) else {
+        return Vec::new();
+    };
+    let mut candidates = Vec::new();
+    let mut pending = vec![tree.root_node()];
+    while let Some(node) = pending.pop() {
+        let mut walker = node.walk();
+        pending.extend(node.children(&mut walker));
+        let node_kind = node.kind();
+        let kind = match node_kind {
+            "statement_declaration" => Kind::Variable,
+            "function_parameter_single" => Kind::Parameter,
+            "struct_field" | "mix_field" | "toggle_field" => Kind::Field,
+            kind if syntax::FUNCTION_KINDS.contains(&kind) => Kind::Function,
+            kind if syntax::TYPE_KINDS.contains(&kind) => Kind::Type,
+            _ => continue,
+        };
+        let Some(name) = node.child_by_field_name("name") else {
+            continue;
+        };
+        if contains(name, cursor) {
+            continue;
+        }
+        let Some(label) = content.get(name.byte_range()) else {
+            continue;
+        };
+        if label.is_empty() {
+            continue;
+        }
+        let in_scope = match scope_of(node, kind) {
+            Some(scope) => {
+                contains(scope, cursor)
+                    && (kind != Kind::Variable || node.start_position() < cursor)
+            }
+            None => matches!(kind, Kind::Function | Kind::Type),
+        };
+        let proximity = if !in_scope {
+            0
+        } else if matches!(kind, Kind::Function | Kind::Type) {
+            5
+        } else {
+            let distance = cursor.row.abs_diff(node.start_position().row) as i32;
+            10 + (depth(node) * 2).min(30) - (distance / 20).min(5)
+        };
+        candidates.push(Candidate {
+            label: label.to_owned(),
+            kind,
+            detail: kind.label().to_owned(),
+            proximity,
+            body: None,
+            text: None,
+        });
+    }
+    candidates
+}
+
+impl Completion {
+    pub fn new(
+        text_view: &TextView,
+        key_target: &impl IsA<Widget>,
+        document: &Rc<Document>,
+        snippets: &Rc<Snippets>,
+        word_index: &Rc<WordIndex>,
+        commands: &CommandRegistry,
+    ) -> Rc<Completion> {
+        let list = ListBox::builder()
+            .selection_mode(SelectionMode::Browse)
+            .build();
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&list)
+            .hscrollbar_policy(PolicyType::Never)
+            .max_content_height(240)
+            .propagate_natural_height(true)
+            .propagate_natural_width(true)
+            .build();
+        let popover = Popover::builder()
+            .child(&scrolled_window)
+            .has_arrow(false)
+            .position(PositionType::Bottom)
+            .autohide(false)
+            .build();
+        popover.add_css_class("completion");
+        popover.set_parent(text_view);
+        let completion = Rc::new(Completion {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            snippets: snippets.clone(),
+            word_index: word_index.clone(),
+            popover,
+            list: list.clone(),
+            scrolled_window,
+            items: RefCell::new(Vec::new()),
+            recent: RefCell::new(Vec::new()),
+            anchor: Cell::new(None),
+            manual: Cell::new(false),
+            typed: Cell::new(false),
+            inserting: Cell::new(false),
+            refresh_pending: Rc::new(Cell::new(false)),
+            provider: RefCell::new(None),
+            awaiting: Cell::new(None),
+        });
+        let controller = EventControllerKey::new();
+        controller.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&completion);
+        controller.connect_key_pressed(move |_, keyval, _, modifiers| {
+            let Some(completion) = weak.upgrade() else {
+                return Propagation::Proceed;
+            };
+            completion.key_pressed(keyval, modifiers)
+        });
+        key_target.add_controller(controller);
+        let weak = Rc::downgrade(&completion);
+        list.connect_row_activated(move |_, row| {
+            if let Some(completion) = weak.upgrade() {
+                completion.accept(row.index() as usize);
+            }
+        });
+        let buffer = text_view.buffer();
+        let weak = Rc::downgrade(&completion);
+        buffer.connect_insert_text(move |_, _, text| {
+            let Some(completion) = weak.upgrade() else {
+                return;
+            };
+            if completion.inserting.get() || !completion.text_view.has_focus() {
+                return;
+            }
+            let mut characters = text.chars();
+            if let (Some(character), None) = (characters.next(), characters.next())
+                && is_identifier_char(character)
+            {
+                completion.typed.set(true);
+            }
+        });
+        let weak = Rc::downgrade(&completion);
+        buffer.connect_changed(move |_| {
+            if let Some(completion) = weak.upgrade() {
+                completion.schedule_refresh();
+            }
+        });
+        let weak = Rc::downgrade(&completion);
+        buffer.connect_mark_set(move |buffer, _, mark| {
+            if mark != &buffer.get_insert() {
+                return;
+            }
+            if let Some(completion) = weak.upgrade()
+                && completion.popover.is_visible()
+            {
+                completion.schedule_refresh();
+            }
+        });
+        let weak = Rc::downgrade(&completion);
+        text_view.connect_has_focus_notify(move |text_view| {
+            if !text_view.has_focus()
+                && let Some(completion) = weak.upgrade()
+            {
+                completion.hide();
+            }
+        });
+        let triggering = completion.clone();
+        commands.register("completion.trigger", "Trigger Suggestions", move || {
+            triggering.trigger()
+        });
+        completion
+    }
+
+    pub fn trigger(&self) {
+        self.manual.set(true);
+        self.anchor.set(None);
+        self.refresh();
+    }
+
+    pub fn set_provider(&self, provider: impl Fn(&TextIter) -> Vec<ProvidedItem> + 'static) {
+        self.provider.replace(Some(Rc::new(provider)));
+    }
+
+    pub fn provider_updated(&self) {
+        let Some((anchor, manual)) = self.awaiting.get() else {
+            return;
+        };
+        let buffer = self.text_view.buffer();
+        if word_start(&buffer.iter_at_mark(&buffer.get_insert())).offset() != anchor {
+            return;
+        }
+        if manual {
+            self.manual.set(true);
+        } else {
+            self.typed.set(true);
+        }
+        self.refresh();
+    }
+
+    fn hide(&self) {
+        self.awaiting.set(None);
+        self.manual.set(false);
+        self.anchor.set(None);
+        self.popover.popdown();
+    }
+
+    fn schedule_refresh(self: &Rc<Self>) {
+        if self.refresh_pending.get() {
+            return;
+        }
+        self.refresh_pending.set(true);
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(completion) = weak.upgrade() {
+                completion.refresh_pending.set(false);
+                completion.refresh();
+            }
+        });
+    }
+
+    fn candidates(&self, cursor: &TextIter, prefix: &str) -> Vec<Candidate> {
+        let language = self.document.language();
+        let mut candidates = Vec::new();
+        if language == "qat" {
+            let words = [
+                (syntax::KEYWORDS, Kind::Keyword),
+                (syntax::BUILTIN_TYPES, Kind::BuiltinType),
+                (syntax::CONSTANTS, Kind::Constant),
+            ];
+            for (list, kind) in words {
+                candidates.extend(list.iter().map(|word| Candidate {
+                    label: (*word).to_owned(),
+                    kind,
+                    detail: kind.label().to_owned(),
+                    proximity: 0,
+                    body: None,
+                    text: None,
+                }));
+            }
+            let point = Point {
+                row: cursor.line() as usize,
+                column: cursor.line_index() as usize,
+            };
+            candidates.extend(declared_identifiers(&self.document.text(), point));
+        }
+        let provider = self.provider.borrow().clone();
+        if let Some(provider) = provider {
+            candidates.extend(provider(cursor).into_iter().map(|item| {
+                let kind = Kind::from_label(item.kind);
+                Candidate {
+                    label: item.label,
+                    kind,
+                    detail: if item.detail.is_empty() {
+                        kind.label().to_owned()
+                    } else {
+                        item.detail
+                    },
+                    proximity: 0,
+                    body: item.snippet.then(|| item.text.clone()),
+                    text: Some(item.text),
+                }
+            }));
+        }
+        candidates.extend(
+            self.snippets
+                .snippets_for(&language)
+                .into_iter()
+                .filter(|snippet| !snippet.prefix.is_empty())
+                .map(|snippet| Candidate {
+                    label: snippet.prefix,
+                    kind: Kind::Snippet,
+                    detail: snippet.description,
+                    proximity: 0,
+                    body: Some(snippet.body),
+                    text: None,
+                }),
+        );
+        let known: HashSet<String> = candidates
+            .iter()
+            .map(|candidate| candidate.label.clone())
+            .collect();
+        candidates.extend(
+            self.word_index
+                .words(&self.text_view.buffer(), prefix)
+                .into_iter()
+                .filter(|(word, _)| !known.contains(word))
+                .map(|(word, in_current)| Candidate {
+                    label: word,
+                    kind: Kind::Word,
+                    detail: if in_current {
+                        Kind::Word.label().to_owned()
+                    } else {
+                        "word, other buffer".to_owned()
+                    },
+                    proximity: if in_current { CURRENT_BUFFER_BONUS } else { 0 },
+                    body: None,
+                    text: None,
+                }),
+        );
+        candidates
+    }
+
+    fn refresh(&self) {
+        let typed = self.typed.replace(false);
+        let buffer = self.text_view.buffer();
+        if buffer.has_selection() {
+            self.hide();
+            return;
+        }
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let start = word_start(&cursor);
+        let prefix = buffer.text(&start, &cursor, true).to_string();
+        let manual = self.manual.get();
+        let visible = self.popover.is_visible();
+        let anchor_moved = self
+            .anchor
+            .get()
+            .is_some_and(|anchor| anchor != start.offset());
+        if anchor_moved || (visible && !manual && prefix.is_empty()) {
+            self.hide();
+            return;
+        }
+        let auto = typed
+            && prefix.chars().count() >= AUTO_TRIGGER_LENGTH
+            && !prefix.starts_with(|first: char| first.is_ascii_digit());
+        if !visible && !manual && !auto {
+            return;
+        }
+        let awaiting = Some((start.offset(), manual));
+        let recent = self.recent.borrow().clone();
+        let mut best: HashMap<(String, bool), (i32, Item)> = HashMap::new();
+        for candidate in self.candidates(&cursor, &prefix) {
+            if !manual && candidate.kind != Kind::Snippet && candidate.label == prefix {
+                continue;
+            }
+            let Some(found) = fuzzy::fuzzy_match(&prefix, &candidate.label) else {
+                continue;
+            };
+            let recency = recent
+                .iter()
+                .position(|label| *label == candidate.label)
+                .map(|index| (MAX_RECENT - index) as i32 * 3)
+                .unwrap_or(0);
+            let score = found.score + candidate.proximity + recency;
+            let key = (candidate.label.clone(), candidate.kind == Kind::Snippet);
+            if best.get(&key).is_none_or(|(existing, _)| *existing < score) {
+                best.insert(
+                    key,
+                    (
+                        score,
+                        Item {
+                            candidate,
+                            positions: found.positions,
+                        },
+                    ),
+                );
+            }
+        }
+        let mut items: Vec<(i32, Item)> = best.into_values().collect();
+        items.sort_by(|(score, item), (other_score, other)| {
+            other_score
+                .cmp(score)
+                .then_with(|| item.candidate.label.cmp(&other.candidate.label))
+        });
+        items.truncate(MAX_ROWS);
+        if items.is_empty() {
+            self.hide();
+            self.awaiting.set(awaiting);
+            return;
+        }
+        self.list.remove_all();
+        for (_, item) in &items {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(8)
+                .build();
+            let icon = Label::builder().label(item.candidate.kind.icon()).build();
+            icon.add_css_class("completion_icon");
+            icon.add_css_class(item.candidate.kind.css_class());
+            row.append(&icon);
+            let label = Label::builder().hexpand(true).xalign(0.0).build();
+            label.set_markup(&fuzzy::highlighted_markup(
+                &item.candidate.label,
+                &item.positions,
+            ));
+            row.append(&label);
+            let detail = Label::new(Some(&item.candidate.detail));
+            detail.add_css_class("dim-label");
+            row.append(&detail);
+            self.list.append(&row);
+        }
+        self.list.select_row(self.list.row_at_index(0).as_ref());
+        self.scrolled_window.vadjustment().set_value(0.0);
+        self.items
+            .replace(items.into_iter().map(|(_, item)| item).collect());
+        self.anchor.set(Some(start.offset()));
+        self.awaiting.set(awaiting);
+        let location = self.text_view.iter_location(&start);
+        let (x, y) = self.text_view.buffer_to_window_coords(
+            TextWindowType::Widget,
+            location.x(),
+            location.y() + location.height(),
+        );
+        self.popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, 1)));
+        self.popover.popup();
+    }
+
+    fn key_pressed(&self, keyval: gdk::Key, modifiers: gdk::ModifierType) -> Propagation {
+        if !self.popover.is_visible() || !self.text_view.has_focus() {
+            return Propagation::Proceed;
+        }
+        if !(modifiers - gdk::ModifierType::SHIFT_MASK).is_empty() {
+            return Propagation::Proceed;
+        }
+        match keyval {
+            gdk::Key::Up => self.move_selection(-1),
+            gdk::Key::Down => self.move_selection(1),
+            gdk::Key::Page_Up => self.move_selection(-10),
+            gdk::Key::Page_Down => self.move_selection(10),
+            gdk::Key::Return | gdk::Key::KP_Enter | gdk::Key::Tab => {
+                let index = self.list.selected_row().map(|row| row.index()).unwrap_or(0);
+                self.accept(index as usize);
+            }
+            gdk::Key::Escape => self.hide(),
+            _ => return Propagation::Proceed,
+        }
+        Propagation::Stop
+    }
+
+    fn move_selection(&self, delta: i32) {
+        let count = self.items.borrow().len() as i32;
+        if count == 0 {
+            return;
+        }
+        let current = self.list.selected_row().map(|row| row.index()).unwrap_or(0);
+        let Some(row) = self
+            .list
+            .row_at_index((current + delta).clamp(0, count - 1))
+        else {
+            return;
+        };
+        self.list.select_row(Some(&row));
+        if let Some(bounds) = row.compute_bounds(&self.list) {
+            let adjustment = self.scrolled_window.vadjustment();
+            let top = bounds.y() as f64;
+            let bottom = top + bounds.height() as f64;
+            if top < adjustment.value() {
+                adjustment.set_value(top);
+            } else if bottom > adjustment.value() + adjustment.page_size() {
+                adjustment.set_value(bottom - adjustment.page_size());
+            }
+        }
+    }
+
+    fn accept(&self, index: usize) {
+        let Some(item) = self.items.borrow().get(index).cloned() else {
+            return;
+        };
+        self.hide();
+        {
+            let mut recent = self.recent.borrow_mut();
+            recent.retain(|label| *label != item.candidate.label);
+            recent.insert(0, item.candidate.label.clone());
+            recent.truncate(MAX_RECENT);
+        }
+        let buffer = self.text_view.buffer();
+        let mut cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let mut start = word_start(&cursor);
+        self.inserting.set(true);
+        match &item.candidate.body {
+            Some(body) => self.snippets.expand(&start, &cursor, body, ""),
+            None => {
+                buffer.begin_user_action();
+                buffer.delete(&mut start, &mut cursor);
+                let text = item
+                    .candidate
+                    .text
+                    .as_ref()
+                    .unwrap_or(&item.candidate.label);
+                buffer.insert(&mut start, text);
+                buffer.end_user_action();
+            }
+        }
+        self.inserting.set(false);
+        self.text_view.grab_focus();
+    }
+}
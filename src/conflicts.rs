@@ -0,0 +1,433 @@
+use std::{
+    cell::{Cell, RefCell},
+    ops::Range,
+    rc::Rc,
+};
+
+use gtk4::{Box, Button, Label, Orientation, TextView, glib, prelude::*};
+
+use crate::{commands::CommandRegistry, document::Document, notification::Notification};
+
+const OURS_MARKER: &str = "<<<<<<<";
+const BASE_MARKER: &str = "|||||||";
+const SEPARATOR_MARKER: &str = "=======";
+const THEIRS_MARKER: &str = ">>>>>>>";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Conflict {
+    pub start: usize,
+    pub base: Option<usize>,
+    pub separator: usize,
+    pub end: usize,
+}
+
+impl Conflict {
+    fn ours(&self) -> Range<usize> {
+        self.start + 1..self.base.unwrap_or(self.separator)
+    }
+
+    fn theirs(&self) -> Range<usize> {
+        self.separator + 1..self.end
+    }
+
+    fn contains(&self, line: usize) -> bool {
+        self.start <= line && line <= self.end
+    }
+}
+
+fn is_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker).is_some_and(|rest| {
+        rest.trim_end_matches('\r').is_empty()
+            || (marker != SEPARATOR_MARKER && rest.starts_with([' ', '\t']))
+    })
+}
+
+pub fn find(content: &str) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    if !content.contains(OURS_MARKER) {
+        return conflicts;
+    }
+    let mut start = None;
+    let mut base = None;
+    let mut separator = None;
+    for (index, line) in content.lines().enumerate() {
+        if is_marker(line, OURS_MARKER) {
+            start = Some(index);
+            base = None;
+            separator = None;
+        } else if start.is_none() {
+            continue;
+        } else if is_marker(line, BASE_MARKER) && base.is_none() && separator.is_none() {
+            base = Some(index);
+        } else if is_marker(line, SEPARATOR_MARKER) && separator.is_none() {
+            separator = Some(index);
+        } else if is_marker(line, THEIRS_MARKER)
+            && let (Some(start), Some(separator)) = (start.take(), separator.take())
+        {
+            conflicts.push(Conflict {
+                start,
+                base: base.take(),
+                separator,
+                end: index,
+            });
+        }
+    }
+    conflicts
+}
+
+pub fn mask(content: &str, side: Side) -> Option<String> {
+    let conflicts = find(content);
+    if conflicts.is_empty() {
+        return None;
+    }
+    let mut masked = String::with_capacity(content.len());
+    let mut conflicts = conflicts.iter().peekable();
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        while conflicts
+            .peek()
+            .is_some_and(|conflict| conflict.end < index)
+        {
+            conflicts.next();
+        }
+        let hidden = conflicts.peek().is_some_and(|conflict| {
+            conflict.contains(index)
+                && !match side {
+                    Side::Ours => conflict.ours().contains(&index),
+                    Side::Theirs => conflict.theirs().contains(&index),
+                }
+        });
+        if hidden {
+            masked.extend(
+                line.bytes()
+                    .map(|byte| if byte == b'\n' { '\n' } else { ' ' }),
+            );
+        } else {
+            masked.push_str(line);
+        }
+    }
+    Some(masked)
+}
+
+pub struct Conflicts {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    conflicts: RefCell<Vec<Conflict>>,
+    actions: RefCell<Vec<Box>>,
+    pending: Cell<bool>,
+    warned: Cell<bool>,
+}
+
+impl Conflicts {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        commands: &CommandRegistry,
+    ) -> Rc<Conflicts> {
+        let buffer = text_view.buffer();
+        buffer.create_tag(
+            Some("conflict_marker"),
+            &[
+                ("foreground", &"#8a8f94"),
+                ("weight", &700),
+                ("paragraph-background", &"#33373b"),
+            ],
+        );
+        buffer.create_tag(
+            Some("conflict_ours"),
+            &[("paragraph-background", &"#69a5ff26")],
+        );
+        buffer.create_tag(
+            Some("conflict_base"),
+            &[("paragraph-background", &"#8a8f9426")],
+        );
+        buffer.create_tag(
+            Some("conflict_theirs"),
+            &[("paragraph-background", &"#a5ff8e26")],
+        );
+        let conflicts = Rc::new(Conflicts {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            conflicts: RefCell::new(Vec::new()),
+            actions: RefCell::new(Vec::new()),
+            pending: Cell::new(false),
+            warned: Cell::new(false),
+        });
+        let weak = Rc::downgrade(&conflicts);
+        buffer.connect_changed(move |_| {
+            if let Some(conflicts) = weak.upgrade() {
+                conflicts.warned.set(false);
+                conflicts.schedule();
+            }
+        });
+        if let Some(adjustment) = text_view.vadjustment() {
+            let weak = Rc::downgrade(&conflicts);
+            adjustment.connect_changed(move |_| {
+                if let Some(conflicts) = weak.upgrade() {
+                    conflicts.place_actions();
+                }
+            });
+        }
+        let weak = Rc::downgrade(&conflicts);
+        document.connect_save_guard(move |content| {
+            let Some(conflicts) = weak.upgrade() else {
+                return Ok(());
+            };
+            let count = find(content).len();
+            if count == 0 || conflicts.warned.replace(true) {
+                return Ok(());
+            }
+            let message = format!(
+                "{} still contains {} unresolved merge conflict{}. Save again to write it with the conflict markers.",
+                conflicts
+                    .document
+                    .path()
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                count,
+                if count == 1 { "" } else { "s" }
+            );
+            conflicts
+                .notification
+                .show("Unresolved merge conflicts", &message);
+            Err("the file still contains merge conflict markers; save again to keep them".to_owned())
+        });
+        for (name, title, resolution) in [
+            ("conflict.accept_ours", "Accept Ours", Resolution::Ours),
+            (
+                "conflict.accept_theirs",
+                "Accept Theirs",
+                Resolution::Theirs,
+            ),
+            ("conflict.accept_both", "Accept Both", Resolution::Both),
+        ] {
+            let resolving = conflicts.clone();
+            commands.register(name, title, move || match resolving.current() {
+                Some(index) => resolving.resolve(index, resolution),
+                None => resolving
+                    .status
+                    .set_text("The cursor is not inside a merge conflict"),
+            });
+        }
+        let forward = conflicts.clone();
+        commands.register("conflict.next", "Next Conflict", move || {
+            forward.go_to_conflict(true)
+        });
+        let backward = conflicts.clone();
+        commands.register("conflict.previous", "Previous Conflict", move || {
+            backward.go_to_conflict(false)
+        });
+        conflicts
+    }
+
+    fn schedule(self: &Rc<Self>) {
+        if self.pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(conflicts) = weak.upgrade() {
+                conflicts.pending.set(false);
+                conflicts.update();
+            }
+        });
+    }
+
+    fn update(self: &Rc<Self>) {
+        let buffer = self.text_view.buffer();
+        let (start, end) = buffer.bounds();
+        for tag in [
+            "conflict_marker",
+            "conflict_ours",
+            "conflict_base",
+            "conflict_theirs",
+        ] {
+            buffer.remove_tag_by_name(tag, &start, &end);
+        }
+        let conflicts = find(&self.document.text());
+        let apply = |tag: &str, lines: Range<usize>| {
+            if lines.is_empty() {
+                return;
+            }
+            let start = buffer
+                .iter_at_line(lines.start as i32)
+                .unwrap_or_else(|| buffer.end_iter());
+            let end = buffer
+                .iter_at_line(lines.end as i32)
+                .unwrap_or_else(|| buffer.end_iter());
+            buffer.apply_tag_by_name(tag, &start, &end);
+        };
+        for conflict in &conflicts {
+            apply("conflict_marker", conflict.start..conflict.start + 1);
+            apply("conflict_ours", conflict.ours());
+            if let Some(base) = conflict.base {
+                apply("conflict_marker", base..base + 1);
+                apply("conflict_base", base + 1..conflict.separator);
+            }
+            apply(
+                "conflict_marker",
+                conflict.separator..conflict.separator + 1,
+            );
+            apply("conflict_theirs", conflict.theirs());
+            apply("conflict_marker", conflict.end..conflict.end + 1);
+        }
+        let count = conflicts.len();
+        self.conflicts.replace(conflicts);
+        let mut actions = self.actions.borrow_mut();
+        while actions.len() > count {
+            if let Some(widget) = actions.pop() {
+                self.text_view.remove(&widget);
+            }
+        }
+        while actions.len() < count {
+            let widget = self.create_actions(actions.len());
+            self.text_view.add_overlay(&widget, 0, 0);
+            actions.push(widget);
+        }
+        drop(actions);
+        self.place_actions();
+    }
+
+    fn create_actions(self: &Rc<Self>, index: usize) -> Box {
+        let widget = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(4)
+            .name("conflict_actions")
+            .build();
+        for (label, resolution) in [
+            ("Accept Ours", Resolution::Ours),
+            ("Accept Theirs", Resolution::Theirs),
+            ("Accept Both", Resolution::Both),
+        ] {
+            let button = Button::builder().label(label).has_frame(false).build();
+            let weak = Rc::downgrade(self);
+            button.connect_clicked(move |_| {
+                if let Some(conflicts) = weak.upgrade() {
+                    conflicts.resolve(index, resolution);
+                }
+            });
+            widget.append(&button);
+        }
+        widget
+    }
+
+    fn place_actions(&self) {
+        let buffer = self.text_view.buffer();
+        let conflicts = self.conflicts.borrow();
+        for (conflict, widget) in conflicts.iter().zip(self.actions.borrow().iter()) {
+            let Some(mut iter) = buffer.iter_at_line(conflict.start as i32) else {
+                continue;
+            };
+            let (y, _) = self.text_view.line_yrange(&iter);
+            if !iter.ends_line() {
+                iter.forward_to_line_end();
+            }
+            let x = self.text_view.iter_location(&iter).x();
+            self.text_view.move_overlay(widget, x + 24, y);
+        }
+    }
+
+    fn cursor_line(&self) -> usize {
+        let buffer = self.text_view.buffer();
+        buffer.iter_at_mark(&buffer.get_insert()).line() as usize
+    }
+
+    fn current(&self) -> Option<usize> {
+        let line = self.cursor_line();
+        self.conflicts
+            .borrow()
+            .iter()
+            .position(|conflict| conflict.contains(line))
+    }
+
+    fn lines(&self, lines: Range<usize>) -> String {
+        let buffer = self.text_view.buffer();
+        let start = buffer
+            .iter_at_line(lines.start as i32)
+            .unwrap_or_else(|| buffer.end_iter());
+        let end = buffer
+            .iter_at_line(lines.end as i32)
+            .unwrap_or_else(|| buffer.end_iter());
+        buffer.text(&start, &end, true).to_string()
+    }
+
+    fn resolve(&self, index: usize, resolution: Resolution) {
+        let Some(conflict) = self.conflicts.borrow().get(index).copied() else {
+            return;
+        };
+        let replacement = match resolution {
+            Resolution::Ours => self.lines(conflict.ours()),
+            Resolution::Theirs => self.lines(conflict.theirs()),
+            Resolution::Both => self.lines(conflict.ours()) + &self.lines(conflict.theirs()),
+        };
+        let buffer = self.text_view.buffer();
+        let mut start = buffer
+            .iter_at_line(conflict.start as i32)
+            .unwrap_or_else(|| buffer.end_iter());
+        let mut end = buffer
+            .iter_at_line(conflict.end as i32 + 1)
+            .unwrap_or_else(|| buffer.end_iter());
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &replacement);
+        buffer.end_user_action();
+        let iter = buffer
+            .iter_at_line(conflict.start as i32)
+            .unwrap_or_else(|| buffer.end_iter());
+        buffer.place_cursor(&iter);
+        let remaining = self.conflicts.borrow().len() - 1;
+        self.status.set_text(&match remaining {
+            0 => "Resolved the last merge conflict".to_owned(),
+            1 => "Resolved conflict, 1 remaining".to_owned(),
+            _ => format!("Resolved conflict, {} remaining", remaining),
+        });
+    }
+
+    fn go_to_conflict(&self, forward: bool) {
+        let line = self.cursor_line();
+        let conflicts = self.conflicts.borrow();
+        if conflicts.is_empty() {
+            self.status.set_text("No merge conflicts");
+            return;
+        }
+        let index = if forward {
+            conflicts
+                .iter()
+                .position(|conflict| conflict.start > line)
+                .unwrap_or(0)
+        } else {
+            conflicts
+                .iter()
+                .rposition(|conflict| conflict.end < line)
+                .unwrap_or(conflicts.len() - 1)
+        };
+        let buffer = self.text_view.buffer();
+        let iter = buffer
+            .iter_at_line(conflicts[index].start as i32)
+            .unwrap_or_else(|| buffer.end_iter());
+        buffer.place_cursor(&iter);
+        self.text_view
+            .scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.3);
+        self.status
+            .set_text(&format!("Conflict {} of {}", index + 1, conflicts.len()));
+    }
+}
@@ -0,0 +1,572 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsStr,
+    path::Path,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use gtk4::{gio, glib};
+use serde_json::{Value, json};
+
+use crate::framing;
+
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+type ResponseHandler = Box<dyn FnOnce(Result<Value, String>)>;
+type EventHandler = Rc<dyn Fn(&str, &Value)>;
+type RequestHandler = Rc<dyn Fn(&str, &Value) -> Result<Value, String>>;
+type Handler = Rc<dyn Fn()>;
+
+pub struct Adapter {
+    pub name: String,
+    process: gio::Subprocess,
+    writer: Rc<framing::Writer>,
+    next_seq: Cell<i64>,
+    pending: RefCell<HashMap<i64, ResponseHandler>>,
+    running: Cell<bool>,
+    capabilities: RefCell<Value>,
+    event_handlers: RefCell<Vec<EventHandler>>,
+    request_handler: RefCell<Option<RequestHandler>>,
+    exited_handlers: RefCell<Vec<Handler>>,
+}
+
+async fn read_messages(weak: Weak<Adapter>, input: gio::DataInputStream) {
+    while let Some(message) = framing::read_message(&input).await {
+        let Some(adapter) = weak.upgrade() else {
+            return;
+        };
+        match message {
+            Ok(message) => adapter.dispatch(message),
+            Err(error) => eprintln!("Invalid message from {}: {}", adapter.name, error),
+        }
+    }
+    if let Some(adapter) = weak.upgrade() {
+        adapter.exited();
+    }
+}
+
+impl Adapter {
+    pub fn start(cwd: &Path, command: &[String]) -> Result<Rc<Adapter>, String> {
+        let launcher = gio::SubprocessLauncher::new(
+            gio::SubprocessFlags::STDIN_PIPE | gio::SubprocessFlags::STDOUT_PIPE,
+        );
+        if cwd.is_dir() {
+            launcher.set_cwd(cwd);
+        }
+        let arguments: Vec<&OsStr> = command.iter().map(OsStr::new).collect();
+        let process = launcher
+            .spawn(&arguments)
+            .map_err(|error| error.message().to_owned())?;
+        let (Some(stdin), Some(stdout)) = (process.stdin_pipe(), process.stdout_pipe()) else {
+            return Err("The debug adapter has no standard streams".to_owned());
+        };
+        let adapter = Rc::new(Adapter {
+            name: command[0].clone(),
+            process,
+            writer: framing::Writer::new(stdin),
+            next_seq: Cell::new(1),
+            pending: RefCell::new(HashMap::new()),
+            running: Cell::new(true),
+            capabilities: RefCell::new(Value::Null),
+            event_handlers: RefCell::new(Vec::new()),
+            request_handler: RefCell::new(None),
+            exited_handlers: RefCell::new(Vec::new()),
+        });
+        glib::spawn_future_local(read_messages(
+            Rc::downgrade(&adapter),
+            gio::DataInputStream::new(&stdout),
+        ));
+        Ok(adapter)
+    }
+
+    pub fn initialize(
+        self: &Rc<Self>,
+        adapter_id: &str,
+        handler: impl FnOnce(Result<Value, String>) + 'static,
+    ) {
+        let arguments = json!({
+            "clientID": "moon",
+            "clientName": "moon",
+            "adapterID": adapter_id,
+            "locale": "en",
+            "linesStartAt1": true,
+            "columnsStartAt1": true,
+            "pathFormat": "path",
+            "supportsVariableType": true,
+            "supportsRunInTerminalRequest": false,
+        });
+        let weak = Rc::downgrade(self);
+        self.request("initialize", arguments, move |result| {
+            if let (Some(adapter), Ok(capabilities)) = (weak.upgrade(), &result) {
+                adapter.capabilities.replace(capabilities.clone());
+            }
+            handler(result);
+        });
+    }
+
+    pub fn supports(&self, name: &str) -> bool {
+        !matches!(
+            self.capabilities.borrow()[name],
+            Value::Null | Value::Bool(false)
+        )
+    }
+
+    pub fn connect_event(&self, handler: impl Fn(&str, &Value) + 'static) {
+        self.event_handlers.borrow_mut().push(Rc::new(handler));
+    }
+
+    pub fn set_request_handler(
+        &self,
+        handler: impl Fn(&str, &Value) -> Result<Value, String> + 'static,
+    ) {
+        self.request_handler.replace(Some(Rc::new(handler)));
+    }
+
+    pub fn connect_exited(&self, handler: impl Fn() + 'static) {
+        self.exited_handlers.borrow_mut().push(Rc::new(handler));
+    }
+
+    fn next_seq(&self) -> i64 {
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+        seq
+    }
+
+    pub fn request(
+        self: &Rc<Self>,
+        command: &str,
+        arguments: Value,
+        handler: impl FnOnce(Result<Value, String>) + 'static,
+    ) {
+        if !self.running.get() {
+            handler(Err(format!("{} is not running", self.name)));
+            return;
+        }
+        let seq = self.next_seq();
+        self.pending.borrow_mut().insert(seq, Box::new(handler));
+        self.send(json!({
+            "seq": seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        }));
+    }
+
+    fn send(&self, message: Value) {
+        if !self.running.get() {
+            return;
+        }
+        self.writer.send(&message);
+    }
+
+    fn dispatch(self: &Rc<Self>, message: Value) {
+        let body = message.get("body").cloned().unwrap_or(Value::Null);
+        match message["type"].as_str() {
+            Some("response") => {
+                let handler = message["request_seq"]
+                    .as_i64()
+                    .and_then(|seq| self.pending.borrow_mut().remove(&seq));
+                if let Some(handler) = handler {
+                    handler(if message["success"].as_bool().unwrap_or(false) {
+                        Ok(body)
+                    } else {
+                        Err(error_message(&message, &body))
+                    });
+                }
+            }
+            Some("event") => {
+                let event = message["event"].as_str().unwrap_or("");
+                let handlers = self.event_handlers.borrow().clone();
+                for handler in handlers {
+                    handler(event, &body);
+                }
+            }
+            Some("request") => {
+                let command = message["command"].as_str().unwrap_or("");
+                let arguments = message.get("arguments").cloned().unwrap_or(Value::Null);
+                let handler = self.request_handler.borrow().clone();
+                let result = match handler {
+                    Some(handler) => handler(command, &arguments),
+                    None => Err(format!("Unsupported request {}", command)),
+                };
+                let mut response = json!({
+                    "seq": self.next_seq(),
+                    "type": "response",
+                    "request_seq": message["seq"],
+                    "command": command,
+                    "success": result.is_ok(),
+                });
+                match result {
+                    Ok(body) => response["body"] = body,
+                    Err(error) => response["message"] = Value::String(error),
+                }
+                self.send(response);
+            }
+            _ => {}
+        }
+    }
+
+    fn exited(&self) {
+        if !self.running.replace(false) {
+            return;
+        }
+        let pending: Vec<ResponseHandler> = self.pending.take().into_values().collect();
+        for handler in pending {
+            handler(Err(format!("{} exited", self.name)));
+        }
+        let handlers = self.exited_handlers.borrow().clone();
+        for handler in handlers {
+            handler();
+        }
+    }
+
+    pub fn disconnect(self: &Rc<Self>, terminate: bool) {
+        if !self.running.get() {
+            return;
+        }
+        let process = self.process.clone();
+        self.request(
+            "disconnect",
+            json!({ "terminateDebuggee": terminate }),
+            move |_| process.force_exit(),
+        );
+        let adapter = self.clone();
+        glib::timeout_add_local_once(DISCONNECT_TIMEOUT, move || adapter.process.force_exit());
+    }
+}
+
+fn error_message(message: &Value, body: &Value) -> String {
+    if let Some(error) = body["error"].as_object() {
+        let mut text = error
+            .get("format")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_owned();
+        if let Some(variables) = error.get("variables").and_then(Value::as_object) {
+            for (name, value) in variables {
+                text = text.replace(&format!("{{{}}}", name), value.as_str().unwrap_or(""));
+            }
+        }
+        if !text.is_empty() {
+            return text;
+        }
+    }
+    message["message"]
+        .as_str()
+        .unwrap_or("The debug adapter reported an error")
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::fake_server::{self, Peer};
+
+    const ADAPTER: &str = "dap::tests::debug_adapter";
+
+    #[test]
+    fn debug_adapter() {
+        if !fake_server::is_server() {
+            return;
+        }
+        let mut peer = Peer::connect();
+        let mut seq = 0;
+        let mut program = "/tmp/main.qat".to_owned();
+        loop {
+            let request = peer.receive();
+            assert_eq!(request["type"], "request");
+            let command = request["command"].as_str().unwrap_or("").to_owned();
+            let arguments = &request["arguments"];
+            seq += 1;
+            peer.send(json!({
+                "seq": seq,
+                "type": "event",
+                "event": "output",
+                "body": {
+                    "category": "stdout",
+                    "output": format!("> {} {}\n", command, arguments),
+                },
+            }));
+            if matches!(command.as_str(), "launch" | "attach")
+                && let Some(debugged) = arguments["program"].as_str()
+            {
+                program = debugged.to_owned();
+            }
+            let body = match command.as_str() {
+                "initialize" => json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsConditionalBreakpoints": true,
+                }),
+                "setBreakpoints" => json!({
+                    "breakpoints": arguments["breakpoints"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|breakpoint| json!({
+                            "verified": true,
+                            "line": breakpoint["line"],
+                            "message": breakpoint["condition"],
+                        }))
+                        .collect::<Vec<_>>(),
+                }),
+                "stackTrace" => json!({
+                    "stackFrames": [{
+                        "id": 1000,
+                        "name": "main",
+                        "line": 3,
+                        "column": 1,
+                        "source": { "path": program },
+                    }],
+                    "totalFrames": 1,
+                }),
+                "scopes" => json!({
+                    "scopes": [{
+                        "name": "Locals",
+                        "variablesReference": arguments["frameId"].as_i64().unwrap_or(0) + 1,
+                        "expensive": false,
+                    }],
+                }),
+                "variables" if arguments["variablesReference"] == 1001 => json!({
+                    "variables": [{
+                        "name": "x",
+                        "value": "2",
+                        "type": "i32",
+                        "variablesReference": 0,
+                    }],
+                }),
+                "evaluate" => json!({
+                    "result": format!(
+                        "{} in frame {}",
+                        arguments["expression"].as_str().unwrap_or(""),
+                        arguments["frameId"]
+                    ),
+                    "variablesReference": 0,
+                }),
+                "continue" => json!({ "allThreadsContinued": true }),
+                "launch" | "attach" | "configurationDone" | "next" | "stepIn" | "stepOut"
+                | "disconnect" => json!({}),
+                _ => {
+                    seq += 1;
+                    peer.send(json!({
+                        "seq": seq,
+                        "type": "response",
+                        "request_seq": request["seq"],
+                        "command": command,
+                        "success": false,
+                        "body": {
+                            "error": {
+                                "id": 1,
+                                "format": "Unknown command {command}",
+                                "variables": { "command": command },
+                            },
+                        },
+                    }));
+                    continue;
+                }
+            };
+            seq += 1;
+            peer.send(json!({
+                "seq": seq,
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": true,
+                "body": body,
+            }));
+            let event = match command.as_str() {
+                "initialize" => Some(("initialized", json!({}))),
+                "launch" => Some((
+                    "process",
+                    json!({ "name": arguments["program"], "startMethod": "launch" }),
+                )),
+                "attach" => Some((
+                    "process",
+                    json!({
+                        "name": "attached",
+                        "systemProcessId": arguments["processId"],
+                        "startMethod": "attach",
+                    }),
+                )),
+                "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" => Some((
+                    "stopped",
+                    json!({
+                        "reason": if matches!(command.as_str(), "configurationDone" | "continue") {
+                            "breakpoint"
+                        } else {
+                            "step"
+                        },
+                        "description": command,
+                        "threadId": 1,
+                        "allThreadsStopped": true,
+                    }),
+                )),
+                "disconnect" => std::process::exit(0),
+                _ => None,
+            };
+            if let Some((event, body)) = event {
+                seq += 1;
+                peer.send(json!({ "seq": seq, "type": "event", "event": event, "body": body }));
+            }
+        }
+    }
+
+    struct Session {
+        adapter: Rc<Adapter>,
+        events: Rc<RefCell<Vec<(String, Value)>>>,
+        exited: Rc<Cell<bool>>,
+    }
+
+    impl Session {
+        fn start() -> Session {
+            let adapter = Adapter::start(&env::temp_dir(), &fake_server::command(ADAPTER))
+                .expect("Could not start the fake debug adapter");
+            let events = Rc::new(RefCell::new(Vec::new()));
+            let recording = events.clone();
+            adapter.connect_event(move |event, body| {
+                recording
+                    .borrow_mut()
+                    .push((event.to_owned(), body.clone()));
+            });
+            let exited = Rc::new(Cell::new(false));
+            let exiting = exited.clone();
+            adapter.connect_exited(move || exiting.set(true));
+            let initialized = Rc::new(RefCell::new(None));
+            let initializing = initialized.clone();
+            adapter.initialize("qat", move |result| {
+                initializing.replace(Some(result));
+            });
+            fake_server::run_until(|| initialized.borrow().is_some());
+            assert!(initialized.take().unwrap().is_ok());
+            let session = Session {
+                adapter,
+                events,
+                exited,
+            };
+            fake_server::run_until(|| !session.events("initialized").is_empty());
+            session
+        }
+
+        fn call(&self, command: &str, arguments: Value) -> Result<Value, String> {
+            let response = Rc::new(RefCell::new(None));
+            let responding = response.clone();
+            self.adapter.request(command, arguments, move |result| {
+                responding.replace(Some(result));
+            });
+            fake_server::run_until(|| response.borrow().is_some());
+            response.take().unwrap()
+        }
+
+        fn events(&self, name: &str) -> Vec<Value> {
+            self.events
+                .borrow()
+                .iter()
+                .filter(|(event, _)| event == name)
+                .map(|(_, body)| body.clone())
+                .collect()
+        }
+
+        fn disconnect(&self, terminate: bool) {
+            self.adapter.disconnect(terminate);
+            fake_server::run_until(|| self.exited.get());
+        }
+    }
+
+    #[gtk4::test]
+    fn launch_session_stops_and_steps() {
+        let session = Session::start();
+        assert!(session.adapter.supports("supportsConditionalBreakpoints"));
+        assert!(!session.adapter.supports("supportsStepBack"));
+
+        session
+            .call("launch", json!({ "program": "/tmp/main" }))
+            .expect("Launch failed");
+        fake_server::run_until(|| !session.events("process").is_empty());
+        assert_eq!(session.events("process")[0]["name"], "/tmp/main");
+
+        let breakpoints = session
+            .call(
+                "setBreakpoints",
+                json!({
+                    "source": { "path": "/tmp/main.qat" },
+                    "breakpoints": [{ "line": 3, "condition": "x > 1" }, { "line": 7 }],
+                }),
+            )
+            .expect("Setting breakpoints failed");
+        assert_eq!(breakpoints["breakpoints"][0]["line"], 3);
+        assert_eq!(breakpoints["breakpoints"][0]["message"], "x > 1");
+        assert_eq!(breakpoints["breakpoints"][1]["line"], 7);
+        assert!(breakpoints["breakpoints"][1]["message"].is_null());
+
+        session
+            .call("configurationDone", json!({}))
+            .expect("Configuration failed");
+        fake_server::run_until(|| session.events("stopped").len() == 1);
+        let stopped = &session.events("stopped")[0];
+        assert_eq!(stopped["reason"], "breakpoint");
+        let thread = stopped["threadId"].clone();
+
+        let stack = session
+            .call("stackTrace", json!({ "threadId": thread }))
+            .expect("Reading the stack failed");
+        let frame = &stack["stackFrames"][0];
+        assert_eq!(frame["name"], "main");
+        let scopes = session
+            .call("scopes", json!({ "frameId": frame["id"] }))
+            .expect("Reading the scopes failed");
+        let variables = session
+            .call(
+                "variables",
+                json!({ "variablesReference": scopes["scopes"][0]["variablesReference"] }),
+            )
+            .expect("Reading the variables failed");
+        assert_eq!(variables["variables"][0]["name"], "x");
+        assert_eq!(variables["variables"][0]["value"], "2");
+
+        for (count, (command, reason)) in [
+            ("continue", "breakpoint"),
+            ("next", "step"),
+            ("stepIn", "step"),
+            ("stepOut", "step"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            session
+                .call(command, json!({ "threadId": thread }))
+                .unwrap_or_else(|error| panic!("{} failed: {}", command, error));
+            fake_server::run_until(|| session.events("stopped").len() == count + 2);
+            let stopped = &session.events("stopped")[count + 1];
+            assert_eq!(stopped["description"], command);
+            assert_eq!(stopped["reason"], reason);
+        }
+
+        assert_eq!(
+            session.call("evaluate", json!({ "expression": "x", "frameId": 1000 })),
+            Ok(json!({ "result": "x in frame 1000", "variablesReference": 0 }))
+        );
+        assert_eq!(
+            session.call("goto", json!({ "threadId": thread, "targetId": 1 })),
+            Err("Unknown command goto".to_owned())
+        );
+
+        session.disconnect(true);
+        assert!(session.call("threads", Value::Null).is_err());
+    }
+
+    #[gtk4::test]
+    fn attach_session_disconnects() {
+        let session = Session::start();
+        session
+            .call("attach", json!({ "processId": 4242 }))
+            .expect("Attach failed");
+        fake_server::run_until(|| !session.events("process").is_empty());
+        let process = &session.events("process")[0];
+        assert_eq!(process["startMethod"], "attach");
+        assert_eq!(process["systemProcessId"], 4242);
+        session.disconnect(false);
+    }
+}
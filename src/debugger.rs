@@ -0,0 +1,1826 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Button, Entry, Label, ListBox, Notebook, Orientation, Paned, PolicyType, ScrolledWindow,
+    SelectionMode, TextIter, TextMark, TextView, pango, prelude::*,
+};
+use serde_json::{Value, json};
+
+use crate::{
+    commands::{Argument, CommandRegistry},
+    dap::Adapter,
+    document::Document,
+    gutter::Gutter,
+    notification::Notification,
+    results::{self, Location},
+    settings::EditorSettings,
+    workspace,
+};
+
+const DEBUG_FILE: &str = "debug.toml";
+const BREAKPOINT_COLUMN_WIDTH: i32 = 16;
+const STACK_LEVELS: i64 = 64;
+
+struct Configuration {
+    name: String,
+    adapter: String,
+    command: Vec<String>,
+    request: String,
+    arguments: Value,
+    root: PathBuf,
+}
+
+struct Breakpoint {
+    line: i32,
+    condition: Option<String>,
+    id: Option<i64>,
+    verified: bool,
+    mark: Option<TextMark>,
+}
+
+struct Session {
+    adapter: Rc<Adapter>,
+    name: String,
+    request: String,
+    generation: u64,
+    thread: Option<i64>,
+    stopped: bool,
+}
+
+struct Frame {
+    id: i64,
+    name: String,
+    location: Option<Location>,
+}
+
+struct Variable {
+    depth: usize,
+    name: String,
+    value: String,
+    kind: String,
+    reference: i64,
+    expanded: bool,
+}
+
+struct Watch {
+    expression: String,
+    value: String,
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn substitute(value: Value, variables: &[(&str, String)]) -> Value {
+    match value {
+        Value::String(mut text) => {
+            for (name, replacement) in variables {
+                text = text.replace(&format!("${{{}}}", name), replacement);
+            }
+            Value::String(text)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| substitute(item, variables))
+                .collect(),
+        ),
+        Value::Object(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, item)| (key, substitute(item, variables)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn load_configurations(
+    root: &Path,
+    adapters: &HashMap<String, Vec<String>>,
+    file: &Path,
+) -> Result<Vec<Configuration>, String> {
+    let path = root.join(DEBUG_FILE);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+    let text = |path: Option<&OsStr>| {
+        path.map_or(String::new(), |path| path.to_string_lossy().into_owned())
+    };
+    let variables = [
+        ("workspaceFolder", root.to_string_lossy().into_owned()),
+        ("file", file.to_string_lossy().into_owned()),
+        ("fileDirname", text(file.parent().map(Path::as_os_str))),
+        ("fileBasename", text(file.file_name())),
+        ("fileBasenameNoExtension", text(file.file_stem())),
+    ];
+    let mut configurations = Vec::new();
+    for entry in table
+        .get("configuration")
+        .and_then(|value| value.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.as_table())
+    {
+        let Some(name) = entry.get("name").and_then(|value| value.as_str()) else {
+            return Err(format!("A configuration in {} has no name", path.display()));
+        };
+        let (adapter, command) = match entry.get("adapter") {
+            Some(toml::Value::String(adapter)) => (
+                adapter.clone(),
+                adapters
+                    .get(adapter)
+                    .cloned()
+                    .unwrap_or_else(|| adapter.split_whitespace().map(str::to_owned).collect()),
+            ),
+            Some(toml::Value::Array(arguments)) => {
+                let command: Vec<String> = arguments
+                    .iter()
+                    .filter_map(|argument| argument.as_str())
+                    .map(str::to_owned)
+                    .collect();
+                (command.first().cloned().unwrap_or_default(), command)
+            }
+            _ => (String::new(), Vec::new()),
+        };
+        if command.is_empty() {
+            return Err(format!("Configuration {} has no adapter", name));
+        }
+        let request = entry
+            .get("request")
+            .and_then(|value| value.as_str())
+            .unwrap_or("launch");
+        if request != "launch" && request != "attach" {
+            return Err(format!(
+                "Configuration {} has an invalid request {}",
+                name, request
+            ));
+        }
+        let mut arguments = serde_json::Map::new();
+        for (key, value) in entry {
+            if !["name", "adapter", "request"].contains(&key.as_str())
+                && let Ok(value) = serde_json::to_value(value)
+            {
+                arguments.insert(key.clone(), value);
+            }
+        }
+        configurations.push(Configuration {
+            name: name.to_owned(),
+            adapter,
+            command,
+            request: request.to_owned(),
+            arguments: substitute(Value::Object(arguments), &variables),
+            root: root.to_path_buf(),
+        });
+    }
+    Ok(configurations)
+}
+
+fn location(frame: &Value) -> Option<Location> {
+    let path = frame["source"]["path"].as_str()?;
+    Some(Location {
+        path: canonical(Path::new(path)),
+        line: (frame["line"].as_i64().unwrap_or(1) as i32 - 1).max(0),
+        column: (frame["column"].as_i64().unwrap_or(1) as i32 - 1).max(0),
+    })
+}
+
+pub struct Debugger {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    commands: CommandRegistry,
+    gutter: Rc<Gutter>,
+    adapters: HashMap<String, Vec<String>>,
+    widget: Box,
+    title: Label,
+    continue_button: Button,
+    pause_button: Button,
+    step_buttons: [Button; 3],
+    session_buttons: [Button; 2],
+    stack: ListBox,
+    variables_list: ListBox,
+    watch_list: ListBox,
+    output: TextView,
+    output_end: TextMark,
+    breakpoints: RefCell<HashMap<PathBuf, Vec<Breakpoint>>>,
+    session: RefCell<Option<Session>>,
+    generation: Cell<u64>,
+    frames: RefCell<Vec<Frame>>,
+    frame: Cell<Option<i64>>,
+    variables: RefCell<Vec<Variable>>,
+    watches: RefCell<Vec<Watch>>,
+    current: RefCell<Option<Location>>,
+    last: RefCell<Option<String>>,
+}
+
+impl Debugger {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        commands: &CommandRegistry,
+        gutter: &Rc<Gutter>,
+        settings: &EditorSettings,
+    ) -> Rc<Debugger> {
+        text_view.buffer().create_tag(
+            Some("debug_current_line"),
+            &[("paragraph-background", &"#fbd37d30")],
+        );
+        let button = |label: &str| {
+            Button::builder()
+                .label(label)
+                .has_frame(false)
+                .sensitive(false)
+                .build()
+        };
+        let title = Label::builder().hexpand(true).xalign(0.0).build();
+        let continue_button = button("Continue");
+        let pause_button = button("Pause");
+        let step_buttons = [button("Step Over"), button("Step Into"), button("Step Out")];
+        let session_buttons = [button("Restart"), button("Stop")];
+        let close = Button::builder().label("✕").has_frame(false).build();
+        let header = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&title);
+        header.append(&continue_button);
+        header.append(&pause_button);
+        for button in step_buttons.iter().chain(&session_buttons) {
+            header.append(button);
+        }
+        header.append(&close);
+        let list = || {
+            ListBox::builder()
+                .selection_mode(SelectionMode::Browse)
+                .build()
+        };
+        let scrolled = |child: &gtk4::Widget| {
+            ScrolledWindow::builder()
+                .child(child)
+                .hscrollbar_policy(PolicyType::Automatic)
+                .vexpand(true)
+                .build()
+        };
+        let stack = list();
+        let stack_box = Box::builder().orientation(Orientation::Vertical).build();
+        stack_box.append(&Label::builder().label("Call Stack").xalign(0.0).build());
+        stack_box.append(&scrolled(stack.upcast_ref()));
+        let variables_list = list();
+        let watch_list = list();
+        let watch_entry = Entry::builder()
+            .placeholder_text("Add watch expression")
+            .build();
+        let watch_box = Box::builder().orientation(Orientation::Vertical).build();
+        watch_box.append(&scrolled(watch_list.upcast_ref()));
+        watch_box.append(&watch_entry);
+        let output = TextView::builder()
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .wrap_mode(gtk4::WrapMode::WordChar)
+            .left_margin(6)
+            .build();
+        let output_buffer = output.buffer();
+        output_buffer.create_tag(Some("debug_console"), &[("foreground", &"#8a8f94")]);
+        output_buffer.create_tag(Some("debug_stderr"), &[("foreground", &"#ff7272")]);
+        let output_end = output_buffer.create_mark(None, &output_buffer.end_iter(), false);
+        let notebook = Notebook::new();
+        notebook.append_page(
+            &scrolled(variables_list.upcast_ref()),
+            Some(&Label::new(Some("Variables"))),
+        );
+        notebook.append_page(&watch_box, Some(&Label::new(Some("Watch"))));
+        notebook.append_page(
+            &scrolled(output.upcast_ref()),
+            Some(&Label::new(Some("Output"))),
+        );
+        let body = Paned::builder()
+            .orientation(Orientation::Horizontal)
+            .start_child(&stack_box)
+            .end_child(&notebook)
+            .position(320)
+            .vexpand(true)
+            .build();
+        let widget = Box::builder()
+            .orientation(Orientation::Vertical)
+            .height_request(220)
+            .name("debug_panel")
+            .visible(false)
+            .build();
+        widget.append(&header);
+        widget.append(&body);
+        let debugger = Rc::new(Debugger {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            commands: commands.clone(),
+            gutter: gutter.clone(),
+            adapters: settings.debug_adapters.clone(),
+            widget,
+            title,
+            continue_button: continue_button.clone(),
+            pause_button: pause_button.clone(),
+            step_buttons: step_buttons.clone(),
+            session_buttons: session_buttons.clone(),
+            stack: stack.clone(),
+            variables_list: variables_list.clone(),
+            watch_list,
+            output,
+            output_end,
+            breakpoints: RefCell::new(HashMap::new()),
+            session: RefCell::new(None),
+            generation: Cell::new(0),
+            frames: RefCell::new(Vec::new()),
+            frame: Cell::new(None),
+            variables: RefCell::new(Vec::new()),
+            watches: RefCell::new(Vec::new()),
+            current: RefCell::new(None),
+            last: RefCell::new(None),
+        });
+        for (button, command) in [
+            (&continue_button, "debug.continue"),
+            (&pause_button, "debug.pause"),
+            (&step_buttons[0], "debug.step_over"),
+            (&step_buttons[1], "debug.step_into"),
+            (&step_buttons[2], "debug.step_out"),
+            (&session_buttons[0], "debug.restart"),
+            (&session_buttons[1], "debug.stop"),
+        ] {
+            let commands = commands.clone();
+            button.connect_clicked(move |_| {
+                commands.run(command);
+            });
+        }
+        let weak = Rc::downgrade(&debugger);
+        close.connect_clicked(move |_| {
+            if let Some(debugger) = weak.upgrade() {
+                debugger.widget.set_visible(false);
+            }
+        });
+        let weak = Rc::downgrade(&debugger);
+        stack.connect_row_activated(move |_, row| {
+            if let Some(debugger) = weak.upgrade() {
+                debugger.select_frame(row.index() as usize);
+            }
+        });
+        let weak = Rc::downgrade(&debugger);
+        variables_list.connect_row_activated(move |_, row| {
+            if let Some(debugger) = weak.upgrade() {
+                debugger.toggle_variable(row.index() as usize);
+            }
+        });
+        let weak = Rc::downgrade(&debugger);
+        watch_entry.connect_activate(move |entry| {
+            if let Some(debugger) = weak.upgrade() {
+                debugger.add_watch(&entry.text());
+                entry.set_text("");
+            }
+        });
+        let painting = Rc::downgrade(&debugger);
+        let weak = Rc::downgrade(&debugger);
+        gutter.add_column(
+            BREAKPOINT_COLUMN_WIDTH,
+            move |context, iter, width, y, _| {
+                if let Some(debugger) = painting.upgrade() {
+                    debugger.paint(context, iter, width, y);
+                }
+            },
+            move |iter, button| {
+                let Some(debugger) = weak.upgrade() else {
+                    return;
+                };
+                if button == 3 {
+                    debugger.text_view.buffer().place_cursor(iter);
+                    debugger.commands.run("debug.conditional_breakpoint");
+                } else if button == 1 {
+                    debugger.toggle_breakpoint(iter.line());
+                }
+            },
+        );
+        let weak = Rc::downgrade(&debugger);
+        document.connect_closing(move |_| {
+            if let Some(debugger) = weak.upgrade() {
+                debugger.detach_marks();
+            }
+        });
+        let weak = Rc::downgrade(&debugger);
+        document.connect_opened(move |_| {
+            if let Some(debugger) = weak.upgrade() {
+                debugger.attach_marks();
+                debugger.apply_current_line();
+                debugger.update_choices();
+            }
+        });
+        let weak = Rc::downgrade(&debugger);
+        document.connect_saved(move |_| {
+            let Some(debugger) = weak.upgrade() else {
+                return;
+            };
+            if debugger.document.path().file_name() == Some(OsStr::new(DEBUG_FILE)) {
+                debugger.update_choices();
+            }
+            if debugger.session.borrow().is_some() {
+                debugger.send_breakpoints(&canonical(&debugger.document.path()));
+            }
+        });
+        debugger.register_commands();
+        debugger.update_choices();
+        debugger.update_controls();
+        debugger
+    }
+
+    pub fn widget(&self) -> &Box {
+        &self.widget
+    }
+
+    fn register_commands(self: &Rc<Self>) {
+        let debugger = self.clone();
+        self.commands
+            .register("debug.continue", "Debug: Start or Continue", move || {
+                debugger.continue_or_start()
+            });
+        let debugger = self.clone();
+        self.commands
+            .register("debug.pause", "Debug: Pause", move || debugger.pause());
+        for (name, title, request) in [
+            ("debug.step_over", "Debug: Step Over", "next"),
+            ("debug.step_into", "Debug: Step Into", "stepIn"),
+            ("debug.step_out", "Debug: Step Out", "stepOut"),
+        ] {
+            let debugger = self.clone();
+            self.commands
+                .register(name, title, move || debugger.step(request));
+        }
+        let debugger = self.clone();
+        self.commands
+            .register("debug.stop", "Debug: Stop", move || debugger.stop());
+        let debugger = self.clone();
+        self.commands
+            .register("debug.restart", "Debug: Restart", move || {
+                debugger.restart()
+            });
+        let debugger = self.clone();
+        self.commands.register(
+            "debug.toggle_breakpoint",
+            "Debug: Toggle Breakpoint",
+            move || {
+                let buffer = debugger.text_view.buffer();
+                let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+                debugger.toggle_breakpoint(line);
+            },
+        );
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "debug.conditional_breakpoint",
+            "Debug: Set Conditional Breakpoint",
+            Argument {
+                prompt: "Condition".to_owned(),
+                choices: Vec::new(),
+            },
+            move |condition| {
+                if let Some(debugger) = weak.upgrade() {
+                    let buffer = debugger.text_view.buffer();
+                    let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+                    debugger.set_condition(line, condition.trim());
+                }
+            },
+        );
+        let debugger = self.clone();
+        self.commands.register(
+            "debug.clear_breakpoints",
+            "Debug: Remove All Breakpoints",
+            move || debugger.clear_breakpoints(),
+        );
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "debug.add_watch",
+            "Debug: Add Watch",
+            Argument {
+                prompt: "Expression".to_owned(),
+                choices: Vec::new(),
+            },
+            move |expression| {
+                if let Some(debugger) = weak.upgrade() {
+                    debugger.add_watch(expression);
+                }
+            },
+        );
+        let debugger = self.clone();
+        self.commands
+            .register("debug.panel", "Debug: Toggle Panel", move || {
+                debugger.widget.set_visible(!debugger.widget.is_visible())
+            });
+        self.update_watch_choices();
+    }
+
+    fn configurations(&self) -> Result<Vec<Configuration>, String> {
+        let path = self.document.path();
+        load_configurations(&workspace::find_root(&path), &self.adapters, &path)
+    }
+
+    fn update_choices(self: &Rc<Self>) {
+        let choices = self
+            .configurations()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|configuration| {
+                (
+                    configuration.name,
+                    format!("{} {}", configuration.request, configuration.adapter),
+                )
+            })
+            .collect();
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "debug.start",
+            "Debug: Start",
+            Argument {
+                prompt: "Configuration".to_owned(),
+                choices,
+            },
+            move |name| {
+                if let Some(debugger) = weak.upgrade() {
+                    debugger.start(name.trim());
+                }
+            },
+        );
+    }
+
+    fn update_watch_choices(self: &Rc<Self>) {
+        let choices = self
+            .watches
+            .borrow()
+            .iter()
+            .map(|watch| (watch.expression.clone(), watch.value.clone()))
+            .collect();
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "debug.remove_watch",
+            "Debug: Remove Watch",
+            Argument {
+                prompt: "Watch".to_owned(),
+                choices,
+            },
+            move |expression| {
+                let Some(debugger) = weak.upgrade() else {
+                    return;
+                };
+                let index = debugger
+                    .watches
+                    .borrow()
+                    .iter()
+                    .position(|watch| watch.expression == expression.trim());
+                if let Some(index) = index {
+                    debugger.remove_watch(index);
+                }
+            },
+        );
+    }
+
+    fn session_generation(&self) -> Option<u64> {
+        self.session
+            .borrow()
+            .as_ref()
+            .map(|session| session.generation)
+    }
+
+    fn adapter(&self, generation: u64) -> Option<Rc<Adapter>> {
+        self.session
+            .borrow()
+            .as_ref()
+            .filter(|session| session.generation == generation)
+            .map(|session| session.adapter.clone())
+    }
+
+    fn continue_or_start(self: &Rc<Self>) {
+        if self.session.borrow().is_none() {
+            let last = self.last.borrow().clone();
+            match last {
+                Some(name) => self.start(&name),
+                None => {
+                    self.commands.run("debug.start");
+                }
+            }
+            return;
+        };
+        self.step("continue");
+    }
+
+    fn start(self: &Rc<Self>, name: &str) {
+        let configurations = match self.configurations() {
+            Ok(configurations) => configurations,
+            Err(error) => {
+                self.notification
+                    .show("Could not load debug configurations", &error);
+                return;
+            }
+        };
+        let Some(configuration) = configurations
+            .into_iter()
+            .find(|configuration| configuration.name == name)
+        else {
+            let root = workspace::find_root(&self.document.path());
+            self.status.set_text(&format!(
+                "No debug configuration named {} in {}",
+                name,
+                root.join(DEBUG_FILE).display()
+            ));
+            return;
+        };
+        self.stop();
+        if self.document.buffer.is_modified()
+            && let Err(error) = self.document.save()
+        {
+            eprintln!(
+                "Could not save {}: {}",
+                self.document.path().display(),
+                error
+            );
+        }
+        let adapter = match Adapter::start(&configuration.root, &configuration.command) {
+            Ok(adapter) => adapter,
+            Err(error) => {
+                self.notification.show(
+                    &format!("Could not start {}", configuration.command[0]),
+                    &error,
+                );
+                return;
+            }
+        };
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        let weak = Rc::downgrade(self);
+        adapter.connect_event(move |event, body| {
+            if let Some(debugger) = weak.upgrade()
+                && debugger.session_generation() == Some(generation)
+            {
+                debugger.handle_event(generation, event, body);
+            }
+        });
+        adapter.set_request_handler(|command, _| Err(format!("{} is not supported", command)));
+        let weak = Rc::downgrade(self);
+        adapter.connect_exited(move || {
+            if let Some(debugger) = weak.upgrade()
+                && debugger.session_generation() == Some(generation)
+            {
+                debugger.append_output("Debug adapter exited\n", "console");
+                debugger.session.replace(None);
+                debugger.end_session();
+            }
+        });
+        self.last.replace(Some(configuration.name.clone()));
+        self.session.replace(Some(Session {
+            adapter: adapter.clone(),
+            name: configuration.name.clone(),
+            request: configuration.request.clone(),
+            generation,
+            thread: None,
+            stopped: false,
+        }));
+        self.output.buffer().set_text("");
+        self.append_output(
+            &format!(
+                "{} {} with {}\n",
+                configuration.request,
+                configuration.name,
+                configuration.command.join(" ")
+            ),
+            "console",
+        );
+        self.widget.set_visible(true);
+        self.update_controls();
+        let weak = Rc::downgrade(self);
+        let adapter_id = configuration.adapter.clone();
+        adapter.initialize(&adapter_id, move |result| {
+            let Some(debugger) = weak.upgrade() else {
+                return;
+            };
+            let Some(adapter) = debugger.adapter(generation) else {
+                return;
+            };
+            if let Err(error) = result {
+                debugger
+                    .notification
+                    .show(&format!("Could not initialize {}", adapter.name), &error);
+                debugger.stop();
+                return;
+            }
+            let weak = Rc::downgrade(&debugger);
+            let request = configuration.request.clone();
+            let name = configuration.name.clone();
+            adapter.request(
+                &configuration.request,
+                configuration.arguments,
+                move |result| {
+                    if let (Some(debugger), Err(error)) = (weak.upgrade(), result)
+                        && debugger.session_generation() == Some(generation)
+                    {
+                        debugger
+                            .notification
+                            .show(&format!("Could not {} {}", request, name), &error);
+                        debugger.stop();
+                    }
+                },
+            );
+        });
+    }
+
+    pub fn stop(self: &Rc<Self>) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        session.adapter.disconnect(session.request == "launch");
+        self.end_session();
+    }
+
+    fn restart(self: &Rc<Self>) {
+        let name = self
+            .session
+            .borrow()
+            .as_ref()
+            .map(|session| session.name.clone())
+            .or_else(|| self.last.borrow().clone());
+        match name {
+            Some(name) => self.start(&name),
+            None => {
+                self.commands.run("debug.start");
+            }
+        }
+    }
+
+    fn end_session(self: &Rc<Self>) {
+        self.resumed();
+        for breakpoint in self.breakpoints.borrow_mut().values_mut().flatten() {
+            breakpoint.id = None;
+            breakpoint.verified = false;
+        }
+        self.gutter.queue_draw();
+        self.status.set_text("Debug session ended");
+    }
+
+    fn update_controls(&self) {
+        let session = self.session.borrow();
+        let stopped = session.as_ref().is_some_and(|session| session.stopped);
+        self.continue_button
+            .set_sensitive(session.is_none() || stopped);
+        self.pause_button
+            .set_sensitive(session.is_some() && !stopped);
+        for button in &self.step_buttons {
+            button.set_sensitive(stopped);
+        }
+        for button in &self.session_buttons {
+            button.set_sensitive(session.is_some());
+        }
+        self.title.set_text(&match session.as_ref() {
+            Some(session) if session.stopped => format!("{} — paused", session.name),
+            Some(session) => format!("{} — running", session.name),
+            None => "No debug session".to_owned(),
+        });
+    }
+
+    fn handle_event(self: &Rc<Self>, generation: u64, event: &str, body: &Value) {
+        match event {
+            "initialized" => self.configure(generation),
+            "stopped" => {
+                let reason = body["reason"].as_str().unwrap_or("pause").to_owned();
+                if let Some(session) = self.session.borrow_mut().as_mut() {
+                    session.stopped = true;
+                    if let Some(thread) = body["threadId"].as_i64() {
+                        session.thread = Some(thread);
+                    }
+                }
+                self.status.set_text(&format!("Paused on {}", reason));
+                self.update_controls();
+                self.with_thread(generation, move |debugger, _| {
+                    debugger.load_stack(generation)
+                });
+            }
+            "continued" => {
+                let thread = self
+                    .session
+                    .borrow()
+                    .as_ref()
+                    .and_then(|session| session.thread);
+                if body["allThreadsContinued"].as_bool().unwrap_or(true)
+                    || body["threadId"].as_i64() == thread
+                {
+                    self.resumed();
+                }
+            }
+            "output" => {
+                let category = body["category"].as_str().unwrap_or("console");
+                if category != "telemetry" {
+                    self.append_output(body["output"].as_str().unwrap_or(""), category);
+                }
+            }
+            "breakpoint" => {
+                let breakpoint = &body["breakpoint"];
+                let Some(id) = breakpoint["id"].as_i64() else {
+                    return;
+                };
+                for entry in self.breakpoints.borrow_mut().values_mut().flatten() {
+                    if entry.id == Some(id) {
+                        entry.verified = breakpoint["verified"].as_bool().unwrap_or(false);
+                    }
+                }
+                self.gutter.queue_draw();
+            }
+            "exited" => self.append_output(
+                &format!(
+                    "Process exited with code {}\n",
+                    body["exitCode"].as_i64().unwrap_or(0)
+                ),
+                "console",
+            ),
+            "terminated" => self.stop(),
+            _ => {}
+        }
+    }
+
+    fn configure(self: &Rc<Self>, generation: u64) {
+        let Some(adapter) = self.adapter(generation) else {
+            return;
+        };
+        let paths: Vec<PathBuf> = self.breakpoints.borrow().keys().cloned().collect();
+        for path in paths {
+            self.send_breakpoints(&path);
+        }
+        if adapter.supports("exceptionBreakpointFilters") {
+            adapter.request("setExceptionBreakpoints", json!({ "filters": [] }), |_| {});
+        }
+        if adapter.supports("supportsConfigurationDoneRequest") {
+            let weak = Rc::downgrade(self);
+            adapter.request("configurationDone", Value::Null, move |result| {
+                if let (Some(debugger), Err(error)) = (weak.upgrade(), result) {
+                    eprintln!("Could not finish debug configuration: {}", error);
+                    debugger.stop();
+                }
+            });
+        }
+    }
+
+    fn with_thread(self: &Rc<Self>, generation: u64, then: impl FnOnce(&Rc<Self>, i64) + 'static) {
+        let Some(adapter) = self.adapter(generation) else {
+            return;
+        };
+        let thread = self
+            .session
+            .borrow()
+            .as_ref()
+            .and_then(|session| session.thread);
+        if let Some(thread) = thread {
+            then(self, thread);
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        adapter.request("threads", Value::Null, move |result| {
+            let Some(debugger) = weak.upgrade() else {
+                return;
+            };
+            let Some(thread) = result
+                .ok()
+                .and_then(|body| body["threads"][0]["id"].as_i64())
+            else {
+                return;
+            };
+            if let Some(session) = debugger.session.borrow_mut().as_mut()
+                && session.generation == generation
+            {
+                session.thread = Some(thread);
+            } else {
+                return;
+            }
+            then(&debugger, thread);
+        });
+    }
+
+    fn step(self: &Rc<Self>, request: &str) {
+        let Some(generation) = self.session_generation() else {
+            return;
+        };
+        if !self
+            .session
+            .borrow()
+            .as_ref()
+            .is_some_and(|session| session.stopped)
+        {
+            return;
+        }
+        let request = request.to_owned();
+        self.with_thread(generation, move |debugger, thread| {
+            let Some(adapter) = debugger.adapter(generation) else {
+                return;
+            };
+            let weak = Rc::downgrade(debugger);
+            adapter.request(&request, json!({ "threadId": thread }), move |result| {
+                if let (Some(debugger), Err(error)) = (weak.upgrade(), result) {
+                    debugger.status.set_text(&error);
+                }
+            });
+            debugger.resumed();
+        });
+    }
+
+    fn pause(self: &Rc<Self>) {
+        let Some(generation) = self.session_generation() else {
+            return;
+        };
+        self.with_thread(generation, move |debugger, thread| {
+            if let Some(adapter) = debugger.adapter(generation) {
+                adapter.request("pause", json!({ "threadId": thread }), |_| {});
+            }
+        });
+    }
+
+    fn resumed(self: &Rc<Self>) {
+        if let Some(session) = self.session.borrow_mut().as_mut() {
+            session.stopped = false;
+        }
+        self.frames.borrow_mut().clear();
+        self.frame.set(None);
+        self.variables.borrow_mut().clear();
+        self.current.replace(None);
+        self.apply_current_line();
+        self.render_stack();
+        self.render_variables();
+        for watch in self.watches.borrow_mut().iter_mut() {
+            watch.value = String::new();
+        }
+        self.render_watches();
+        self.update_controls();
+    }
+
+    fn load_stack(self: &Rc<Self>, generation: u64) {
+        let Some(adapter) = self.adapter(generation) else {
+            return;
+        };
+        let Some(thread) = self
+            .session
+            .borrow()
+            .as_ref()
+            .and_then(|session| session.thread)
+        else {
+            return;
+        };
+        let weak = Rc::downgrade(self);
+        adapter.request(
+            "stackTrace",
+            json!({ "threadId": thread, "startFrame": 0, "levels": STACK_LEVELS }),
+            move |result| {
+                let Some(debugger) = weak.upgrade() else {
+                    return;
+                };
+                if debugger.session_generation() != Some(generation) {
+                    return;
+                }
+                let body = match result {
+                    Ok(body) => body,
+                    Err(error) => {
+                        debugger.status.set_text(&error);
+                        return;
+                    }
+                };
+                let frames = body["stackFrames"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|frame| {
+                        Some(Frame {
+                            id: frame["id"].as_i64()?,
+                            name: frame["name"].as_str().unwrap_or("").to_owned(),
+                            location: location(frame),
+                        })
+                    })
+                    .collect();
+                debugger.frames.replace(frames);
+                debugger.render_stack();
+                debugger.select_frame(0);
+            },
+        );
+    }
+
+    fn render_stack(&self) {
+        self.stack.remove_all();
+        let root = workspace::find_root(&self.document.path());
+        for frame in self.frames.borrow().iter() {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(12)
+                .build();
+            row.append(
+                &Label::builder()
+                    .label(&frame.name)
+                    .xalign(0.0)
+                    .ellipsize(pango::EllipsizeMode::End)
+                    .build(),
+            );
+            if let Some(location) = &frame.location {
+                let detail = Label::new(Some(&format!(
+                    "{}:{}",
+                    location
+                        .path
+                        .strip_prefix(&root)
+                        .unwrap_or(&location.path)
+                        .display(),
+                    location.line + 1
+                )));
+                detail.add_css_class("dim-label");
+                row.append(&detail);
+            }
+            self.stack.append(&row);
+        }
+    }
+
+    fn select_frame(self: &Rc<Self>, index: usize) {
+        let Some(generation) = self.session_generation() else {
+            return;
+        };
+        let Some((id, location)) = self
+            .frames
+            .borrow()
+            .get(index)
+            .map(|frame| (frame.id, frame.location.clone()))
+        else {
+            return;
+        };
+        self.frame.set(Some(id));
+        if let Some(row) = self.stack.row_at_index(index as i32) {
+            self.stack.select_row(Some(&row));
+        }
+        if let Some(location) = &location {
+            results::open_location(&self.document, &self.text_view, &self.status, location);
+        }
+        self.current.replace(location);
+        self.apply_current_line();
+        self.load_scopes(generation, id);
+        self.evaluate_watches(generation);
+    }
+
+    fn apply_current_line(&self) {
+        let buffer = self.text_view.buffer();
+        let (start, end) = buffer.bounds();
+        buffer.remove_tag_by_name("debug_current_line", &start, &end);
+        if let Some(line) = self.current_line()
+            && let Some(start) = buffer.iter_at_line(line)
+        {
+            let mut end = start;
+            end.forward_line();
+            buffer.apply_tag_by_name("debug_current_line", &start, &end);
+        }
+        self.gutter.queue_draw();
+    }
+
+    fn current_line(&self) -> Option<i32> {
+        self.current
+            .borrow()
+            .as_ref()
+            .filter(|location| location.path == canonical(&self.document.path()))
+            .map(|location| location.line)
+    }
+
+    fn load_scopes(self: &Rc<Self>, generation: u64, frame: i64) {
+        let Some(adapter) = self.adapter(generation) else {
+            return;
+        };
+        let weak = Rc::downgrade(self);
+        adapter.request("scopes", json!({ "frameId": frame }), move |result| {
+            let Some(debugger) = weak.upgrade() else {
+                return;
+            };
+            if debugger.frame.get() != Some(frame) {
+                return;
+            }
+            let body = result.unwrap_or_default();
+            let scopes: Vec<&Value> = body["scopes"].as_array().into_iter().flatten().collect();
+            debugger.variables.replace(
+                scopes
+                    .iter()
+                    .map(|scope| Variable {
+                        depth: 0,
+                        name: scope["name"].as_str().unwrap_or("").to_owned(),
+                        value: String::new(),
+                        kind: String::new(),
+                        reference: scope["variablesReference"].as_i64().unwrap_or(0),
+                        expanded: false,
+                    })
+                    .collect(),
+            );
+            debugger.render_variables();
+            if let Some(index) = scopes
+                .iter()
+                .position(|scope| !scope["expensive"].as_bool().unwrap_or(false))
+            {
+                debugger.toggle_variable(index);
+            }
+        });
+    }
+
+    fn toggle_variable(self: &Rc<Self>, index: usize) {
+        let Some(generation) = self.session_generation() else {
+            return;
+        };
+        let Some((depth, reference, expanded)) = self
+            .variables
+            .borrow()
+            .get(index)
+            .map(|variable| (variable.depth, variable.reference, variable.expanded))
+        else {
+            return;
+        };
+        if expanded {
+            let mut variables = self.variables.borrow_mut();
+            let end = variables[index + 1..]
+                .iter()
+                .position(|variable| variable.depth <= depth)
+                .map_or(variables.len(), |offset| index + 1 + offset);
+            variables.drain(index + 1..end);
+            variables[index].expanded = false;
+            drop(variables);
+            self.render_variables();
+            return;
+        }
+        if reference == 0 {
+            return;
+        }
+        let Some(adapter) = self.adapter(generation) else {
+            return;
+        };
+        let frame = self.frame.get();
+        let weak = Rc::downgrade(self);
+        adapter.request(
+            "variables",
+            json!({ "variablesReference": reference }),
+            move |result| {
+                let Some(debugger) = weak.upgrade() else {
+                    return;
+                };
+                if debugger.frame.get() != frame {
+                    return;
+                }
+                let body = result.unwrap_or_default();
+                let mut variables = debugger.variables.borrow_mut();
+                if variables
+                    .get(index)
+                    .is_none_or(|variable| variable.reference != reference || variable.expanded)
+                {
+                    return;
+                }
+                variables[index].expanded = true;
+                let children: Vec<Variable> = body["variables"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|variable| Variable {
+                        depth: depth + 1,
+                        name: variable["name"].as_str().unwrap_or("").to_owned(),
+                        value: variable["value"].as_str().unwrap_or("").to_owned(),
+                        kind: variable["type"].as_str().unwrap_or("").to_owned(),
+                        reference: variable["variablesReference"].as_i64().unwrap_or(0),
+                        expanded: false,
+                    })
+                    .collect();
+                variables.splice(index + 1..index + 1, children);
+                drop(variables);
+                debugger.render_variables();
+            },
+        );
+    }
+
+    fn render_variables(&self) {
+        self.variables_list.remove_all();
+        for variable in self.variables.borrow().iter() {
+            let marker = match (variable.reference, variable.expanded) {
+                (0, _) => " ",
+                (_, true) => "▾",
+                (_, false) => "▸",
+            };
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(6)
+                .margin_start(variable.depth as i32 * 16)
+                .build();
+            row.append(&Label::new(Some(&format!("{} {}", marker, variable.name))));
+            if !variable.value.is_empty() {
+                row.append(
+                    &Label::builder()
+                        .label(format!("= {}", variable.value))
+                        .xalign(0.0)
+                        .ellipsize(pango::EllipsizeMode::End)
+                        .build(),
+                );
+            }
+            if !variable.kind.is_empty() {
+                let kind = Label::new(Some(&variable.kind));
+                kind.add_css_class("dim-label");
+                row.append(&kind);
+            }
+            self.variables_list.append(&row);
+        }
+    }
+
+    fn add_watch(self: &Rc<Self>, expression: &str) {
+        let expression = expression.trim();
+        if expression.is_empty() {
+            return;
+        }
+        self.watches.borrow_mut().push(Watch {
+            expression: expression.to_owned(),
+            value: String::new(),
+        });
+        self.render_watches();
+        self.update_watch_choices();
+        if let Some(generation) = self.session_generation() {
+            self.evaluate_watches(generation);
+        }
+    }
+
+    fn remove_watch(self: &Rc<Self>, index: usize) {
+        if index < self.watches.borrow().len() {
+            self.watches.borrow_mut().remove(index);
+        }
+        self.render_watches();
+        self.update_watch_choices();
+    }
+
+    fn evaluate_watches(self: &Rc<Self>, generation: u64) {
+        let (Some(adapter), Some(frame)) = (self.adapter(generation), self.frame.get()) else {
+            return;
+        };
+        let expressions: Vec<String> = self
+            .watches
+            .borrow()
+            .iter()
+            .map(|watch| watch.expression.clone())
+            .collect();
+        for expression in expressions {
+            let weak = Rc::downgrade(self);
+            let arguments = json!({
+                "expression": expression,
+                "frameId": frame,
+                "context": "watch",
+            });
+            adapter.request("evaluate", arguments, move |result| {
+                let Some(debugger) = weak.upgrade() else {
+                    return;
+                };
+                if debugger.frame.get() != Some(frame) {
+                    return;
+                }
+                let value = match result {
+                    Ok(body) => body["result"].as_str().unwrap_or("").to_owned(),
+                    Err(error) => error,
+                };
+                for watch in debugger.watches.borrow_mut().iter_mut() {
+                    if watch.expression == expression {
+                        watch.value = value.clone();
+                    }
+                }
+                debugger.render_watches();
+                debugger.update_watch_choices();
+            });
+        }
+    }
+
+    fn render_watches(self: &Rc<Self>) {
+        self.watch_list.remove_all();
+        for (index, watch) in self.watches.borrow().iter().enumerate() {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(6)
+                .build();
+            row.append(&Label::new(Some(&watch.expression)));
+            row.append(
+                &Label::builder()
+                    .label(if watch.value.is_empty() {
+                        "= not available".to_owned()
+                    } else {
+                        format!("= {}", watch.value)
+                    })
+                    .hexpand(true)
+                    .xalign(0.0)
+                    .ellipsize(pango::EllipsizeMode::End)
+                    .build(),
+            );
+            let remove = Button::builder().label("✕").has_frame(false).build();
+            let weak = Rc::downgrade(self);
+            remove.connect_clicked(move |_| {
+                if let Some(debugger) = weak.upgrade() {
+                    debugger.remove_watch(index);
+                }
+            });
+            row.append(&remove);
+            self.watch_list.append(&row);
+        }
+    }
+
+    fn append_output(&self, text: &str, category: &str) {
+        let buffer = self.output.buffer();
+        let mut end = buffer.end_iter();
+        match category {
+            "stderr" => buffer.insert_with_tags_by_name(&mut end, text, &["debug_stderr"]),
+            "console" | "important" => {
+                buffer.insert_with_tags_by_name(&mut end, text, &["debug_console"])
+            }
+            _ => buffer.insert(&mut end, text),
+        }
+        self.output.scroll_mark_onscreen(&self.output_end);
+    }
+
+    fn attach_marks(&self) {
+        let buffer = self.text_view.buffer();
+        let path = canonical(&self.document.path());
+        let mut breakpoints = self.breakpoints.borrow_mut();
+        let Some(entries) = breakpoints.get_mut(&path) else {
+            return;
+        };
+        entries.retain(|breakpoint| breakpoint.line < buffer.line_count());
+        for breakpoint in entries {
+            if let Some(iter) = buffer.iter_at_line(breakpoint.line) {
+                breakpoint.mark = Some(buffer.create_mark(None, &iter, true));
+            }
+        }
+    }
+
+    fn detach_marks(&self) {
+        self.sync_lines();
+        let buffer = self.text_view.buffer();
+        for breakpoint in self.breakpoints.borrow_mut().values_mut().flatten() {
+            if let Some(mark) = breakpoint.mark.take() {
+                buffer.delete_mark(&mark);
+            }
+        }
+    }
+
+    fn sync_lines(&self) {
+        let buffer = self.text_view.buffer();
+        let path = canonical(&self.document.path());
+        let mut breakpoints = self.breakpoints.borrow_mut();
+        let Some(entries) = breakpoints.get_mut(&path) else {
+            return;
+        };
+        for breakpoint in entries.iter_mut() {
+            if let Some(mark) = &breakpoint.mark {
+                breakpoint.line = buffer.iter_at_mark(mark).line();
+            }
+        }
+        let mut seen = Vec::new();
+        entries.retain(|breakpoint| {
+            if seen.contains(&breakpoint.line) {
+                if let Some(mark) = &breakpoint.mark {
+                    buffer.delete_mark(mark);
+                }
+                return false;
+            }
+            seen.push(breakpoint.line);
+            true
+        });
+    }
+
+    fn breakpoint_index(&self, line: i32) -> Option<usize> {
+        let buffer = self.text_view.buffer();
+        self.breakpoints
+            .borrow()
+            .get(&canonical(&self.document.path()))?
+            .iter()
+            .position(|breakpoint| {
+                breakpoint
+                    .mark
+                    .as_ref()
+                    .map_or(breakpoint.line, |mark| buffer.iter_at_mark(mark).line())
+                    == line
+            })
+    }
+
+    fn toggle_breakpoint(self: &Rc<Self>, line: i32) {
+        self.sync_lines();
+        let path = canonical(&self.document.path());
+        match self.breakpoint_index(line) {
+            Some(index) => {
+                let mut breakpoints = self.breakpoints.borrow_mut();
+                if let Some(entries) = breakpoints.get_mut(&path) {
+                    let breakpoint = entries.remove(index);
+                    if let Some(mark) = breakpoint.mark {
+                        self.text_view.buffer().delete_mark(&mark);
+                    }
+                }
+            }
+            None => self.add_breakpoint(&path, line, None),
+        }
+        self.breakpoints_changed(&path);
+    }
+
+    fn set_condition(self: &Rc<Self>, line: i32, condition: &str) {
+        self.sync_lines();
+        let path = canonical(&self.document.path());
+        let condition = (!condition.is_empty()).then(|| condition.to_owned());
+        match self.breakpoint_index(line) {
+            Some(index) => {
+                if let Some(breakpoint) = self
+                    .breakpoints
+                    .borrow_mut()
+                    .get_mut(&path)
+                    .and_then(|entries| entries.get_mut(index))
+                {
+                    breakpoint.condition = condition;
+                }
+            }
+            None => self.add_breakpoint(&path, line, condition),
+        }
+        self.breakpoints_changed(&path);
+    }
+
+    fn add_breakpoint(&self, path: &Path, line: i32, condition: Option<String>) {
+        let buffer = self.text_view.buffer();
+        let Some(iter) = buffer.iter_at_line(line) else {
+            return;
+        };
+        self.breakpoints
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_default()
+            .push(Breakpoint {
+                line,
+                condition,
+                id: None,
+                verified: false,
+                mark: Some(buffer.create_mark(None, &iter, true)),
+            });
+    }
+
+    fn clear_breakpoints(self: &Rc<Self>) {
+        let buffer = self.text_view.buffer();
+        let paths: Vec<PathBuf> = self.breakpoints.borrow().keys().cloned().collect();
+        for breakpoint in self.breakpoints.borrow_mut().values_mut().flatten() {
+            if let Some(mark) = breakpoint.mark.take() {
+                buffer.delete_mark(&mark);
+            }
+        }
+        self.breakpoints.borrow_mut().clear();
+        for path in paths {
+            self.breakpoints_changed(&path);
+        }
+        self.gutter.queue_draw();
+    }
+
+    fn breakpoints_changed(self: &Rc<Self>, path: &Path) {
+        self.gutter.queue_draw();
+        if self.session.borrow().is_some() {
+            self.send_breakpoints(path);
+        }
+    }
+
+    fn send_breakpoints(self: &Rc<Self>, path: &Path) {
+        let Some(generation) = self.session_generation() else {
+            return;
+        };
+        let Some(adapter) = self.adapter(generation) else {
+            return;
+        };
+        if *path == canonical(&self.document.path()) {
+            self.sync_lines();
+        }
+        let sent: Vec<(i32, Option<String>)> = self
+            .breakpoints
+            .borrow()
+            .get(path)
+            .into_iter()
+            .flatten()
+            .map(|breakpoint| (breakpoint.line, breakpoint.condition.clone()))
+            .collect();
+        let breakpoints: Vec<Value> = sent
+            .iter()
+            .map(|(line, condition)| match condition {
+                Some(condition) => json!({ "line": line + 1, "condition": condition }),
+                None => json!({ "line": line + 1 }),
+            })
+            .collect();
+        let arguments = json!({
+            "source": {
+                "path": path.to_string_lossy(),
+                "name": path.file_name().map(|name| name.to_string_lossy()),
+            },
+            "breakpoints": breakpoints,
+            "lines": sent.iter().map(|(line, _)| line + 1).collect::<Vec<_>>(),
+            "sourceModified": false,
+        });
+        let weak = Rc::downgrade(self);
+        let path = path.to_path_buf();
+        adapter.request("setBreakpoints", arguments, move |result| {
+            let (Some(debugger), Ok(body)) = (weak.upgrade(), result) else {
+                return;
+            };
+            if debugger.session_generation() != Some(generation) {
+                return;
+            }
+            if let Some(entries) = debugger.breakpoints.borrow_mut().get_mut(&path) {
+                for ((line, _), reported) in sent
+                    .iter()
+                    .zip(body["breakpoints"].as_array().into_iter().flatten())
+                {
+                    if let Some(breakpoint) = entries
+                        .iter_mut()
+                        .find(|breakpoint| breakpoint.line == *line)
+                    {
+                        breakpoint.id = reported["id"].as_i64();
+                        breakpoint.verified = reported["verified"].as_bool().unwrap_or(false);
+                    }
+                }
+            }
+            debugger.gutter.queue_draw();
+        });
+    }
+
+    fn paint(&self, context: &gtk4::cairo::Context, iter: &TextIter, width: f64, y: f64) {
+        let line = iter.line();
+        let center = y + self.text_view.iter_location(iter).height() as f64 / 2.0;
+        if let Some(index) = self.breakpoint_index(line) {
+            let breakpoints = self.breakpoints.borrow();
+            if let Some(breakpoint) = breakpoints
+                .get(&canonical(&self.document.path()))
+                .and_then(|entries| entries.get(index))
+            {
+                match breakpoint.condition {
+                    Some(_) => context.set_source_rgb(1.0, 0.7, 0.3),
+                    None => context.set_source_rgb(0.9, 0.3, 0.3),
+                }
+                context.arc(width / 2.0, center, 5.0, 0.0, std::f64::consts::TAU);
+                if self.session.borrow().is_some() && !breakpoint.verified {
+                    context.set_line_width(1.5);
+                    context.stroke().ok();
+                } else {
+                    context.fill().ok();
+                }
+            }
+        }
+        if self.current_line() == Some(line) {
+            context.set_source_rgb(0.98, 0.83, 0.49);
+            context.move_to(3.0, center - 5.0);
+            context.line_to(width - 3.0, center);
+            context.line_to(3.0, center + 5.0);
+            context.close_path();
+            context.fill().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use gtk4::TextBuffer;
+
+    use super::*;
+    use crate::fake_server;
+
+    const ADAPTER: &str = "dap::tests::debug_adapter";
+    const SOURCE: &str = "let a = 1.\nlet b = a + 1.\nsay b.\n";
+
+    struct Fixture {
+        root: PathBuf,
+        path: PathBuf,
+        buffer: TextBuffer,
+        commands: CommandRegistry,
+        debugger: Rc<Debugger>,
+        requests: Rc<RefCell<Vec<(String, Value)>>>,
+        exited: Rc<Cell<bool>>,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let root = env::temp_dir().join(format!("moon-debugger-{}-{}", name, process::id()));
+            fs::create_dir_all(&root).expect("Could not create the test directory");
+            let root = canonical(&root);
+            let adapter = serde_json::to_string(&fake_server::command(ADAPTER))
+                .expect("Could not write the adapter command");
+            fs::write(
+                root.join(DEBUG_FILE),
+                format!(
+                    "[[configuration]]\nname = \"Launch\"\nadapter = {0}\nprogram = \"${{file}}\"\n\n\
+                     [[configuration]]\nname = \"Attach\"\nrequest = \"attach\"\nadapter = {0}\n\
+                     program = \"${{file}}\"\nprocessId = 4242\n",
+                    adapter
+                ),
+            )
+            .expect("Could not write the debug configuration");
+            let path = root.join("main.qat");
+            fs::write(&path, SOURCE).expect("Could not write the test file");
+            let buffer = TextBuffer::new(None);
+            let document = Document::open(&buffer, &path).expect("Could not open the test file");
+            let text_view = TextView::with_buffer(&buffer);
+            let scrolled_window = ScrolledWindow::builder().child(&text_view).build();
+            let commands = CommandRegistry::default();
+            let debugger = Debugger::new(
+                &text_view,
+                &document,
+                &Label::new(None),
+                &Notification::new(&commands),
+                &commands,
+                &Gutter::new(&text_view, &scrolled_window),
+                &EditorSettings::default(),
+            );
+            Fixture {
+                root,
+                path,
+                buffer,
+                commands,
+                debugger,
+                requests: Rc::new(RefCell::new(Vec::new())),
+                exited: Rc::new(Cell::new(false)),
+            }
+        }
+
+        fn start(&self, name: &str) {
+            assert!(self.commands.run_with_argument("debug.start", name));
+            let adapter = self
+                .debugger
+                .session
+                .borrow()
+                .as_ref()
+                .map(|session| session.adapter.clone())
+                .expect("The debug session did not start");
+            let recording = self.requests.clone();
+            adapter.connect_event(move |event, body| {
+                if event == "output"
+                    && let Some(request) = body["output"]
+                        .as_str()
+                        .and_then(|output| output.trim_end().strip_prefix("> ")?.split_once(' '))
+                {
+                    recording.borrow_mut().push((
+                        request.0.to_owned(),
+                        serde_json::from_str(request.1).unwrap_or_default(),
+                    ));
+                }
+            });
+            let exiting = self.exited.clone();
+            adapter.connect_exited(move || exiting.set(true));
+        }
+
+        fn sent(&self) -> Vec<String> {
+            self.requests
+                .borrow()
+                .iter()
+                .map(|(command, _)| command.clone())
+                .collect()
+        }
+
+        fn arguments(&self, command: &str) -> Vec<Value> {
+            self.requests
+                .borrow()
+                .iter()
+                .filter(|(name, _)| name == command)
+                .map(|(_, arguments)| arguments.clone())
+                .collect()
+        }
+
+        fn marked_lines(&self) -> Vec<i32> {
+            let tag = self
+                .buffer
+                .tag_table()
+                .lookup("debug_current_line")
+                .expect("The current line tag is missing");
+            (0..self.buffer.line_count())
+                .filter(|line| {
+                    self.buffer
+                        .iter_at_line(*line)
+                        .is_some_and(|iter| iter.has_tag(&tag))
+                })
+                .collect()
+        }
+
+        fn shown_watches(&self) -> Vec<String> {
+            let mut shown = Vec::new();
+            let mut index = 0;
+            while let Some(row) = self.debugger.watch_list.row_at_index(index) {
+                let value = row
+                    .child()
+                    .and_then(|child| child.first_child())
+                    .and_then(|expression| expression.next_sibling())
+                    .and_downcast::<Label>()
+                    .expect("A watch row has no value label");
+                shown.push(value.label().to_string());
+                index += 1;
+            }
+            shown
+        }
+
+        fn stop(&self) {
+            self.debugger.stop();
+            fake_server::run_until(|| self.exited.get());
+            fs::remove_dir_all(&self.root).ok();
+        }
+    }
+
+    #[gtk4::test]
+    fn launch_sends_breakpoints_and_marks_the_stopped_line() {
+        let fixture = Fixture::new("launch");
+        let debugger = &fixture.debugger;
+        debugger.toggle_breakpoint(0);
+        fixture
+            .buffer
+            .place_cursor(&fixture.buffer.iter_at_line(2).unwrap());
+        fixture
+            .commands
+            .run_with_argument("debug.conditional_breakpoint", "b > 1");
+        fixture
+            .commands
+            .run_with_argument("debug.add_watch", "b * 2");
+        assert_eq!(fixture.shown_watches(), ["= not available"]);
+
+        fixture.start("Launch");
+        fake_server::run_until(|| fixture.arguments("variables").len() == 1);
+        assert_eq!(
+            fixture.sent(),
+            [
+                "initialize",
+                "launch",
+                "setBreakpoints",
+                "configurationDone",
+                "stackTrace",
+                "scopes",
+                "evaluate",
+                "variables",
+            ]
+        );
+        assert_eq!(
+            fixture.arguments("launch"),
+            [json!({ "program": fixture.path.to_string_lossy() })]
+        );
+        let breakpoints = &fixture.arguments("setBreakpoints")[0];
+        assert_eq!(
+            breakpoints["source"]["path"],
+            *fixture.path.to_string_lossy()
+        );
+        assert_eq!(breakpoints["source"]["name"], "main.qat");
+        assert_eq!(
+            breakpoints["breakpoints"],
+            json!([{ "line": 1 }, { "line": 3, "condition": "b > 1" }])
+        );
+        assert_eq!(breakpoints["lines"], json!([1, 3]));
+        assert!(
+            debugger.breakpoints.borrow()[&fixture.path]
+                .iter()
+                .all(|breakpoint| breakpoint.verified)
+        );
+
+        assert_eq!(debugger.current_line(), Some(2));
+        assert_eq!(fixture.marked_lines(), [2]);
+        assert_eq!(
+            fixture.arguments("evaluate"),
+            [json!({ "expression": "b * 2", "frameId": 1000, "context": "watch" })]
+        );
+        fake_server::run_until(|| !debugger.watches.borrow()[0].value.is_empty());
+        assert_eq!(fixture.shown_watches(), ["= b * 2 in frame 1000"]);
+
+        fixture.commands.run("debug.step_over");
+        assert!(fixture.marked_lines().is_empty());
+        assert_eq!(fixture.shown_watches(), ["= not available"]);
+        fake_server::run_until(|| fixture.arguments("evaluate").len() == 2);
+        assert_eq!(fixture.arguments("next"), [json!({ "threadId": 1 })]);
+        fake_server::run_until(|| !debugger.watches.borrow()[0].value.is_empty());
+        assert_eq!(fixture.marked_lines(), [2]);
+
+        fixture.stop();
+        assert!(fixture.marked_lines().is_empty());
+        assert_eq!(
+            fixture.arguments("disconnect"),
+            [json!({ "terminateDebuggee": true })]
+        );
+    }
+
+    #[gtk4::test]
+    fn attach_uses_the_attach_configuration() {
+        let fixture = Fixture::new("attach");
+        fixture.start("Attach");
+        fake_server::run_until(|| fixture.debugger.current_line().is_some());
+        assert_eq!(
+            fixture.sent()[..4],
+            ["initialize", "attach", "configurationDone", "stackTrace"]
+        );
+        assert_eq!(
+            fixture.arguments("attach"),
+            [json!({ "processId": 4242, "program": fixture.path.to_string_lossy() })]
+        );
+        assert!(fixture.arguments("setBreakpoints").is_empty());
+
+        fixture.stop();
+        assert_eq!(
+            fixture.arguments("disconnect"),
+            [json!({ "terminateDebuggee": false })]
+        );
+    }
+}
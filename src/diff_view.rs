@@ -0,0 +1,1188 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Button, EventControllerKey, FileDialog, Label, Orientation, Paned, PolicyType,
+    PropagationPhase, ScrolledWindow, Stack, TextBuffer, TextIter, TextMark, TextView,
+    ToggleButton, Window, WrapMode, cairo, gdk, gio, glib, pango, prelude::*,
+};
+
+use crate::{
+    commands::CommandRegistry,
+    diff::{self, Hunk},
+    document::{self, Document},
+    gutter::Gutter,
+    highlight,
+};
+
+const NUMBER_COLUMN_WIDTH: i32 = 48;
+const MARKER_COLUMN_WIDTH: i32 = 8;
+const NUMBER_FONT_SIZE: f64 = 12.0;
+const MAX_CHARACTER_DIFF_LENGTH: usize = 1000;
+const MIN_CHARACTER_SIMILARITY: f64 = 0.4;
+
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+const UNIFIED: usize = 2;
+
+enum Target {
+    ReadOnly,
+    File(PathBuf),
+    Buffer(TextMark, TextMark),
+}
+
+pub struct Side {
+    title: String,
+    language: String,
+    text: String,
+    target: Target,
+    modified: bool,
+}
+
+impl Side {
+    pub fn snapshot(title: &str, text: &str, language: &str) -> Side {
+        Side {
+            title: title.to_owned(),
+            language: language.to_owned(),
+            text: text.to_owned(),
+            target: Target::ReadOnly,
+            modified: false,
+        }
+    }
+
+    fn file(path: &Path) -> io::Result<Side> {
+        Ok(Side {
+            title: path.display().to_string(),
+            language: document::language_for_path(path).to_owned(),
+            text: fs::read_to_string(path)?,
+            target: Target::File(path.to_path_buf()),
+            modified: false,
+        })
+    }
+
+    fn range(
+        title: &str,
+        buffer: &TextBuffer,
+        start: &TextIter,
+        end: &TextIter,
+        language: &str,
+    ) -> Side {
+        Side {
+            title: title.to_owned(),
+            language: language.to_owned(),
+            text: buffer.text(start, end, true).to_string(),
+            target: Target::Buffer(
+                buffer.create_mark(None, start, true),
+                buffer.create_mark(None, end, false),
+            ),
+            modified: false,
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        matches!(self.target, Target::Buffer(..))
+    }
+
+    fn reload(&mut self) {
+        if let Target::Buffer(start, end) = &self.target
+            && let Some(buffer) = start.buffer()
+        {
+            self.text = buffer
+                .text(&buffer.iter_at_mark(start), &buffer.iter_at_mark(end), true)
+                .to_string();
+        }
+    }
+
+    fn detach(&self) {
+        if let Target::Buffer(start, end) = &self.target
+            && let Some(buffer) = start.buffer()
+        {
+            buffer.delete_mark(start);
+            buffer.delete_mark(end);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RowKind {
+    Same,
+    Added,
+    Removed,
+    Filler,
+}
+
+#[derive(Clone, Copy)]
+struct Row {
+    numbers: [Option<usize>; 2],
+    kind: RowKind,
+    hunk: Option<usize>,
+}
+
+struct Pane {
+    view: TextView,
+    scrolled_window: ScrolledWindow,
+    gutter: Rc<Gutter>,
+    title: Label,
+    widget: Box,
+    rows: RefCell<Vec<Row>>,
+}
+
+impl Pane {
+    fn new(parent: &TextView) -> Pane {
+        let buffer = TextBuffer::new(Some(&parent.buffer().tag_table()));
+        let view = TextView::builder()
+            .buffer(&buffer)
+            .editable(false)
+            .monospace(true)
+            .wrap_mode(WrapMode::None)
+            .name("diff_view")
+            .left_margin(6)
+            .build();
+        if let Some(tabs) = parent.tabs() {
+            view.set_tabs(&tabs);
+        }
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&view)
+            .hscrollbar_policy(PolicyType::Automatic)
+            .hexpand(true)
+            .vexpand(true)
+            .build();
+        let gutter = Gutter::new(&view, &scrolled_window);
+        let title = Label::builder()
+            .xalign(0.0)
+            .ellipsize(pango::EllipsizeMode::Start)
+            .build();
+        title.add_css_class("dim-label");
+        let widget = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(4)
+            .build();
+        widget.append(&title);
+        widget.append(&scrolled_window);
+        Pane {
+            view,
+            scrolled_window,
+            gutter,
+            title,
+            widget,
+            rows: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn row_of_hunk(&self, hunk: usize) -> Option<usize> {
+        self.rows
+            .borrow()
+            .iter()
+            .position(|row| row.hunk == Some(hunk))
+    }
+}
+
+fn push_line(text: &mut String, line: &str) {
+    text.push_str(line.trim_end_matches(['\n', '\r']));
+    text.push('\n');
+}
+
+fn apply_line_tag(buffer: &TextBuffer, tag: &str, row: usize) {
+    if let Some(start) = buffer.iter_at_line(row as i32) {
+        let end = buffer
+            .iter_at_line(row as i32 + 1)
+            .unwrap_or_else(|| buffer.end_iter());
+        buffer.apply_tag_by_name(tag, &start, &end);
+    }
+}
+
+fn apply_character_tag(buffer: &TextBuffer, tag: &str, row: usize, start: usize, length: usize) {
+    if let (Some(start), Some(end)) = (
+        buffer.iter_at_line_offset(row as i32, start as i32),
+        buffer.iter_at_line_offset(row as i32, (start + length) as i32),
+    ) {
+        buffer.apply_tag_by_name(tag, &start, &end);
+    }
+}
+
+fn byte_span(text: &str, lines: std::ops::Range<usize>) -> (usize, usize) {
+    let mut start = 0;
+    let mut end = 0;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        if index < lines.start {
+            start += line.len();
+        }
+        if index < lines.end {
+            end += line.len();
+        }
+    }
+    (start, end)
+}
+
+pub struct DiffView {
+    window: Window,
+    sides: RefCell<[Side; 2]>,
+    hunks: RefCell<Vec<Hunk>>,
+    current: Cell<Option<usize>>,
+    panes: [Pane; 3],
+    stack: Stack,
+    inline: ToggleButton,
+    counter: Label,
+    message: Label,
+    save: Button,
+    syncing: Cell<bool>,
+    rendering: Cell<bool>,
+    pending: Cell<bool>,
+    warned: Cell<bool>,
+}
+
+impl DiffView {
+    fn new(parent: &TextView, left: Side, right: Side) -> Rc<DiffView> {
+        let panes = [Pane::new(parent), Pane::new(parent), Pane::new(parent)];
+        let previous = Button::builder().label("◀").has_frame(false).build();
+        previous.set_tooltip_text(Some("Previous change (Alt+Up)"));
+        let next = Button::builder().label("▶").has_frame(false).build();
+        next.set_tooltip_text(Some("Next change (Alt+Down)"));
+        let counter = Label::new(None);
+        let copy_left = Button::builder().label("← Copy Left").build();
+        copy_left.set_tooltip_text(Some("Copy the change to the left side (Alt+Left)"));
+        let copy_right = Button::builder().label("Copy Right →").build();
+        copy_right.set_tooltip_text(Some("Copy the change to the right side (Alt+Right)"));
+        let inline = ToggleButton::builder().label("Inline").build();
+        let save = Button::builder().label("Save").sensitive(false).build();
+        let message = Label::builder()
+            .hexpand(true)
+            .xalign(1.0)
+            .ellipsize(pango::EllipsizeMode::End)
+            .build();
+        let toolbar = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .name("diff_toolbar")
+            .build();
+        for widget in [
+            previous.upcast_ref::<gtk4::Widget>(),
+            next.upcast_ref(),
+            counter.upcast_ref(),
+            copy_left.upcast_ref(),
+            copy_right.upcast_ref(),
+            inline.upcast_ref(),
+            save.upcast_ref(),
+            message.upcast_ref(),
+        ] {
+            toolbar.append(widget);
+        }
+        let paned = Paned::builder()
+            .orientation(Orientation::Horizontal)
+            .start_child(&panes[LEFT].widget)
+            .end_child(&panes[RIGHT].widget)
+            .wide_handle(true)
+            .build();
+        let stack = Stack::new();
+        stack.add_named(&paned, Some("split"));
+        stack.add_named(&panes[UNIFIED].widget, Some("inline"));
+        let content = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(4)
+            .build();
+        content.append(&toolbar);
+        content.append(&stack);
+        let window = Window::builder()
+            .title(format!("{} ↔ {}", left.title, right.title))
+            .default_width(1400)
+            .default_height(900)
+            .child(&content)
+            .build();
+        if let Some(root) = parent.root().and_downcast::<Window>() {
+            window.set_transient_for(Some(&root));
+        }
+        let view = Rc::new(DiffView {
+            window,
+            sides: RefCell::new([left, right]),
+            hunks: RefCell::new(Vec::new()),
+            current: Cell::new(None),
+            panes,
+            stack,
+            inline,
+            counter,
+            message,
+            save,
+            syncing: Cell::new(false),
+            rendering: Cell::new(false),
+            pending: Cell::new(false),
+            warned: Cell::new(false),
+        });
+        for (index, numbers) in [(LEFT, &[0][..]), (RIGHT, &[1]), (UNIFIED, &[0, 1])] {
+            let pane = &view.panes[index];
+            for &number in numbers {
+                let painting = Rc::downgrade(&view);
+                let clicking = Rc::downgrade(&view);
+                pane.gutter.add_column(
+                    NUMBER_COLUMN_WIDTH,
+                    move |context, iter, width, y, height| {
+                        if let Some(view) = painting.upgrade() {
+                            view.paint_number(
+                                index,
+                                number,
+                                context,
+                                iter.line() as usize,
+                                width,
+                                y,
+                                height,
+                            );
+                        }
+                    },
+                    move |iter, _| {
+                        if let Some(view) = clicking.upgrade() {
+                            view.select_row(index, iter.line() as usize);
+                        }
+                    },
+                );
+            }
+            let painting = Rc::downgrade(&view);
+            let clicking = Rc::downgrade(&view);
+            pane.gutter.add_column(
+                MARKER_COLUMN_WIDTH,
+                move |context, iter, width, y, height| {
+                    if let Some(view) = painting.upgrade() {
+                        view.paint_marker(index, context, iter.line() as usize, width, y, height);
+                    }
+                },
+                move |iter, _| {
+                    if let Some(view) = clicking.upgrade() {
+                        view.select_row(index, iter.line() as usize);
+                    }
+                },
+            );
+            let weak = Rc::downgrade(&view);
+            pane.view
+                .buffer()
+                .connect_mark_set(move |buffer, iter, mark| {
+                    if let Some(view) = weak.upgrade()
+                        && !view.rendering.get()
+                        && *mark == buffer.get_insert()
+                    {
+                        view.select_row(index, iter.line() as usize);
+                    }
+                });
+        }
+        for (from, to) in [(LEFT, RIGHT), (RIGHT, LEFT)] {
+            let source = &view.panes[from].scrolled_window;
+            let target = &view.panes[to].scrolled_window;
+            for (adjustment, mirrored) in [
+                (source.vadjustment(), target.vadjustment()),
+                (source.hadjustment(), target.hadjustment()),
+            ] {
+                let weak = Rc::downgrade(&view);
+                adjustment.connect_value_changed(move |adjustment| {
+                    if let Some(view) = weak.upgrade()
+                        && !view.syncing.replace(true)
+                    {
+                        mirrored.set_value(adjustment.value());
+                        view.syncing.set(false);
+                    }
+                });
+            }
+        }
+        let weak = Rc::downgrade(&view);
+        previous.connect_clicked(move |_| {
+            if let Some(view) = weak.upgrade() {
+                view.go_to_change(false);
+            }
+        });
+        let weak = Rc::downgrade(&view);
+        next.connect_clicked(move |_| {
+            if let Some(view) = weak.upgrade() {
+                view.go_to_change(true);
+            }
+        });
+        let weak = Rc::downgrade(&view);
+        copy_left.connect_clicked(move |_| {
+            if let Some(view) = weak.upgrade() {
+                view.copy_change(LEFT);
+            }
+        });
+        let weak = Rc::downgrade(&view);
+        copy_right.connect_clicked(move |_| {
+            if let Some(view) = weak.upgrade() {
+                view.copy_change(RIGHT);
+            }
+        });
+        let weak = Rc::downgrade(&view);
+        view.inline.connect_toggled(move |button| {
+            if let Some(view) = weak.upgrade() {
+                view.stack.set_visible_child_name(if button.is_active() {
+                    "inline"
+                } else {
+                    "split"
+                });
+                if let Some(hunk) = view.current.get() {
+                    view.scroll_to_change(hunk);
+                }
+            }
+        });
+        let weak = Rc::downgrade(&view);
+        view.save.connect_clicked(move |_| {
+            if let Some(view) = weak.upgrade() {
+                view.save_files();
+            }
+        });
+        let keys = EventControllerKey::new();
+        keys.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&view);
+        keys.connect_key_pressed(move |_, key, _, modifiers| {
+            let Some(view) = weak.upgrade() else {
+                return glib::Propagation::Proceed;
+            };
+            if !modifiers.contains(gdk::ModifierType::ALT_MASK) {
+                return glib::Propagation::Proceed;
+            }
+            match key {
+                gdk::Key::Up => view.go_to_change(false),
+                gdk::Key::Down => view.go_to_change(true),
+                gdk::Key::Left => view.copy_change(LEFT),
+                gdk::Key::Right => view.copy_change(RIGHT),
+                _ => return glib::Propagation::Proceed,
+            }
+            glib::Propagation::Stop
+        });
+        view.window.add_controller(keys);
+        let weak = Rc::downgrade(&view);
+        view.window.connect_close_request(move |_| {
+            if let Some(view) = weak.upgrade()
+                && view.sides.borrow().iter().any(|side| side.modified)
+                && !view.warned.replace(true)
+            {
+                view.message
+                    .set_text("Unsaved changes; close again to discard them");
+                return glib::Propagation::Stop;
+            }
+            glib::Propagation::Proceed
+        });
+        view.render();
+        view.window.present();
+        if !view.hunks.borrow().is_empty() {
+            view.current.set(Some(0));
+            view.update_counter();
+            view.scroll_to_change(0);
+        }
+        view
+    }
+
+    fn is_live(&self) -> bool {
+        self.sides.borrow().iter().any(Side::is_live)
+    }
+
+    fn detach(&self) {
+        for side in self.sides.borrow().iter() {
+            side.detach();
+        }
+    }
+
+    fn schedule(self: &Rc<Self>) {
+        if self.pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(view) = weak.upgrade() {
+                view.pending.set(false);
+                view.refresh();
+            }
+        });
+    }
+
+    fn refresh(&self) {
+        for side in self.sides.borrow_mut().iter_mut() {
+            side.reload();
+        }
+        self.render();
+    }
+
+    fn render(&self) {
+        self.rendering.set(true);
+        let scroll =
+            [LEFT, UNIFIED].map(|index| self.panes[index].scrolled_window.vadjustment().value());
+        let sides = self.sides.borrow();
+        let old: Vec<&str> = sides[LEFT]
+            .text
+            .split_inclusive('\n')
+            .map(|line| line.trim_end_matches(['\n', '\r']))
+            .collect();
+        let new: Vec<&str> = sides[RIGHT]
+            .text
+            .split_inclusive('\n')
+            .map(|line| line.trim_end_matches(['\n', '\r']))
+            .collect();
+        let hunks = diff::diff(&old, &new);
+        let mut rows: [Vec<Row>; 3] = Default::default();
+        let mut texts = [String::new(), String::new()];
+        let mut sources: Vec<(usize, usize)> = Vec::new();
+        let mut pairs: Vec<(usize, usize, usize)> = Vec::new();
+        let (mut a, mut b) = (0, 0);
+        let end = Hunk {
+            old_start: old.len(),
+            old_len: 0,
+            new_start: new.len(),
+            new_len: 0,
+        };
+        for (index, hunk) in hunks
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(index, hunk)| (Some(index), hunk))
+            .chain([(None, end)])
+        {
+            while a < hunk.old_start && b < hunk.new_start {
+                sources.push((LEFT, rows[LEFT].len()));
+                push_line(&mut texts[LEFT], old[a]);
+                push_line(&mut texts[RIGHT], new[b]);
+                rows[LEFT].push(Row {
+                    numbers: [Some(a), None],
+                    kind: RowKind::Same,
+                    hunk: None,
+                });
+                rows[RIGHT].push(Row {
+                    numbers: [None, Some(b)],
+                    kind: RowKind::Same,
+                    hunk: None,
+                });
+                rows[UNIFIED].push(Row {
+                    numbers: [Some(a), Some(b)],
+                    kind: RowKind::Same,
+                    hunk: None,
+                });
+                a += 1;
+                b += 1;
+            }
+            if index.is_none() {
+                break;
+            }
+            let first = rows[LEFT].len();
+            for offset in 0..hunk.old_len.max(hunk.new_len) {
+                for (side, lines, start, length, kind) in [
+                    (LEFT, &old, a, hunk.old_len, RowKind::Removed),
+                    (RIGHT, &new, b, hunk.new_len, RowKind::Added),
+                ] {
+                    let mut numbers = [None, None];
+                    if offset < length {
+                        push_line(&mut texts[side], lines[start + offset]);
+                        numbers[side] = Some(start + offset);
+                    } else {
+                        texts[side].push('\n');
+                    }
+                    rows[side].push(Row {
+                        numbers,
+                        kind: if offset < length {
+                            kind
+                        } else {
+                            RowKind::Filler
+                        },
+                        hunk: index,
+                    });
+                }
+                if offset < hunk.old_len && offset < hunk.new_len {
+                    pairs.push((first + offset, a + offset, b + offset));
+                }
+            }
+            for (side, length, kind) in [
+                (LEFT, hunk.old_len, RowKind::Removed),
+                (RIGHT, hunk.new_len, RowKind::Added),
+            ] {
+                for offset in 0..length {
+                    sources.push((side, first + offset));
+                    let mut numbers = [None, None];
+                    numbers[side] = Some(if side == LEFT { a } else { b } + offset);
+                    rows[UNIFIED].push(Row {
+                        numbers,
+                        kind,
+                        hunk: index,
+                    });
+                }
+            }
+            a += hunk.old_len;
+            b += hunk.new_len;
+        }
+        for (side, text) in texts.iter().enumerate() {
+            let buffer = self.panes[side].view.buffer();
+            buffer.set_text(text);
+            highlight::highlight(&buffer, &sides[side].language);
+            for (index, row) in rows[side].iter().enumerate() {
+                let tag = match row.kind {
+                    RowKind::Same => continue,
+                    RowKind::Added => "diff_line_added",
+                    RowKind::Removed => "diff_line_removed",
+                    RowKind::Filler => "diff_filler",
+                };
+                apply_line_tag(&buffer, tag, index);
+            }
+        }
+        let left_buffer = self.panes[LEFT].view.buffer();
+        let right_buffer = self.panes[RIGHT].view.buffer();
+        for (row, a, b) in pairs {
+            let x: Vec<char> = old[a].chars().collect();
+            let y: Vec<char> = new[b].chars().collect();
+            if x.len().max(y.len()) > MAX_CHARACTER_DIFF_LENGTH {
+                continue;
+            }
+            let changes = diff::diff(&x, &y);
+            let changed: usize = changes
+                .iter()
+                .map(|change| change.old_len + change.new_len)
+                .sum();
+            let total = x.len() + y.len();
+            if total == 0 || 1.0 - (changed as f64 / total as f64) < MIN_CHARACTER_SIMILARITY {
+                continue;
+            }
+            for change in changes {
+                apply_character_tag(
+                    &left_buffer,
+                    "diff_character_removed",
+                    row,
+                    change.old_start,
+                    change.old_len,
+                );
+                apply_character_tag(
+                    &right_buffer,
+                    "diff_character_added",
+                    row,
+                    change.new_start,
+                    change.new_len,
+                );
+            }
+        }
+        let unified = self.panes[UNIFIED].view.buffer();
+        unified.set_text("");
+        let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+        for (side, row) in sources {
+            match runs.last_mut() {
+                Some((last, _, end)) if *last == side && *end == row => *end += 1,
+                _ => runs.push((side, row, row + 1)),
+            }
+        }
+        for (side, start, end) in runs {
+            let buffer = self.panes[side].view.buffer();
+            if let Some(start) = buffer.iter_at_line(start as i32) {
+                let end = buffer
+                    .iter_at_line(end as i32)
+                    .unwrap_or_else(|| buffer.end_iter());
+                unified.insert_range(&mut unified.end_iter(), &start, &end);
+            }
+        }
+        for (pane, title) in [
+            (LEFT, sides[LEFT].title.clone()),
+            (RIGHT, sides[RIGHT].title.clone()),
+            (
+                UNIFIED,
+                format!("{} ↔ {}", sides[LEFT].title, sides[RIGHT].title),
+            ),
+        ] {
+            let modified = match pane {
+                UNIFIED => sides.iter().any(|side| side.modified),
+                _ => sides[pane].modified,
+            };
+            self.panes[pane].title.set_text(&if modified {
+                format!("{} •", title)
+            } else {
+                title
+            });
+        }
+        self.save
+            .set_sensitive(sides.iter().any(|side| side.modified));
+        drop(sides);
+        for (pane, rows) in self.panes.iter().zip(rows) {
+            pane.rows.replace(rows);
+            pane.gutter.queue_draw();
+        }
+        let count = hunks.len();
+        self.hunks.replace(hunks);
+        self.current.set(match self.current.get() {
+            _ if count == 0 => None,
+            Some(current) => Some(current.min(count - 1)),
+            None => None,
+        });
+        self.update_counter();
+        self.rendering.set(false);
+        if scroll.iter().all(|value| *value == 0.0) {
+            return;
+        }
+        let adjustments =
+            [LEFT, UNIFIED].map(|index| self.panes[index].scrolled_window.vadjustment());
+        glib::idle_add_local_once(move || {
+            for (adjustment, value) in adjustments.iter().zip(scroll) {
+                adjustment.set_value(value);
+            }
+        });
+    }
+
+    fn update_counter(&self) {
+        let count = self.hunks.borrow().len();
+        self.counter.set_text(&match (count, self.current.get()) {
+            (0, _) => "No differences".to_owned(),
+            (_, Some(current)) => format!("Change {} of {}", current + 1, count),
+            (1, None) => "1 change".to_owned(),
+            (_, None) => format!("{} changes", count),
+        });
+        for pane in &self.panes {
+            pane.gutter.queue_draw();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn paint_number(
+        &self,
+        pane: usize,
+        number: usize,
+        context: &cairo::Context,
+        line: usize,
+        width: f64,
+        y: f64,
+        height: f64,
+    ) {
+        let Some(Some(value)) = self.panes[pane]
+            .rows
+            .borrow()
+            .get(line)
+            .map(|row| row.numbers[number])
+        else {
+            return;
+        };
+        let text = (value + 1).to_string();
+        context.select_font_face(
+            "monospace",
+            cairo::FontSlant::Normal,
+            cairo::FontWeight::Normal,
+        );
+        context.set_font_size(NUMBER_FONT_SIZE);
+        context.set_source_rgb(0.54, 0.56, 0.58);
+        let (Ok(font), Ok(extents)) = (context.font_extents(), context.text_extents(&text)) else {
+            return;
+        };
+        context.move_to(
+            width - 6.0 - extents.x_advance(),
+            y + (height + font.ascent() - font.descent()) / 2.0,
+        );
+        context.show_text(&text).ok();
+    }
+
+    fn paint_marker(
+        &self,
+        pane: usize,
+        context: &cairo::Context,
+        line: usize,
+        width: f64,
+        y: f64,
+        height: f64,
+    ) {
+        let Some(row) = self.panes[pane].rows.borrow().get(line).copied() else {
+            return;
+        };
+        let (red, green, blue) = match row.kind {
+            RowKind::Same => return,
+            RowKind::Added => (0.45, 0.8, 0.45),
+            RowKind::Removed => (1.0, 0.45, 0.45),
+            RowKind::Filler => (0.4, 0.42, 0.44),
+        };
+        if row.hunk.is_some() && row.hunk == self.current.get() {
+            context.set_source_rgb(red, green, blue);
+            context.rectangle(0.0, y, width, height);
+        } else {
+            context.set_source_rgba(red, green, blue, 0.6);
+            context.rectangle(0.0, y, 3.0, height);
+        }
+        context.fill().ok();
+    }
+
+    fn select_row(&self, pane: usize, row: usize) {
+        if let Some(hunk) = self.panes[pane]
+            .rows
+            .borrow()
+            .get(row)
+            .and_then(|row| row.hunk)
+        {
+            self.current.set(Some(hunk));
+            self.update_counter();
+        }
+    }
+
+    fn go_to_change(&self, forward: bool) {
+        let count = self.hunks.borrow().len();
+        if count == 0 {
+            self.message.set_text("No differences");
+            return;
+        }
+        let hunk = match (self.current.get(), forward) {
+            (Some(current), true) => (current + 1) % count,
+            (Some(current), false) => (current + count - 1) % count,
+            (None, true) => 0,
+            (None, false) => count - 1,
+        };
+        self.current.set(Some(hunk));
+        self.update_counter();
+        self.scroll_to_change(hunk);
+    }
+
+    fn scroll_to_change(&self, hunk: usize) {
+        let pane = if self.inline.is_active() {
+            &self.panes[UNIFIED]
+        } else {
+            &self.panes[LEFT]
+        };
+        let Some(row) = pane.row_of_hunk(hunk) else {
+            return;
+        };
+        let buffer = pane.view.buffer();
+        if let Some(iter) = buffer.iter_at_line(row as i32) {
+            self.rendering.set(true);
+            buffer.place_cursor(&iter);
+            self.rendering.set(false);
+            pane.view
+                .scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.3);
+        }
+    }
+
+    fn copy_change(&self, to: usize) {
+        let Some(hunk) = self
+            .current
+            .get()
+            .and_then(|current| self.hunks.borrow().get(current).copied())
+        else {
+            self.message.set_text("No change is selected");
+            return;
+        };
+        let left = hunk.old_start..hunk.old_start + hunk.old_len;
+        let right = hunk.new_start..hunk.new_start + hunk.new_len;
+        let (from, source, target) = if to == RIGHT {
+            (LEFT, left, right)
+        } else {
+            (RIGHT, right, left)
+        };
+        let mut sides = self.sides.borrow_mut();
+        let (start, end) = byte_span(&sides[from].text, source);
+        let mut replacement = sides[from].text[start..end].to_owned();
+        let side = &mut sides[to];
+        let (start, end) = byte_span(&side.text, target);
+        if !replacement.is_empty() && !replacement.ends_with('\n') && end < side.text.len() {
+            replacement.push('\n');
+        }
+        if !replacement.is_empty()
+            && start == side.text.len()
+            && !side.text.is_empty()
+            && !side.text.ends_with('\n')
+        {
+            replacement.insert(0, '\n');
+        }
+        match &side.target {
+            Target::ReadOnly => {
+                self.message
+                    .set_text(&format!("{} is read-only", side.title));
+                return;
+            }
+            Target::File(_) => {
+                side.text.replace_range(start..end, &replacement);
+                side.modified = true;
+                self.warned.set(false);
+            }
+            Target::Buffer(mark, _) => {
+                let Some(buffer) = mark.buffer() else {
+                    return;
+                };
+                let base = buffer.iter_at_mark(mark).offset();
+                let start_offset = base + side.text[..start].chars().count() as i32;
+                let end_offset = start_offset + side.text[start..end].chars().count() as i32;
+                drop(sides);
+                let mut start = buffer.iter_at_offset(start_offset);
+                let mut end = buffer.iter_at_offset(end_offset);
+                buffer.begin_user_action();
+                buffer.delete(&mut start, &mut end);
+                buffer.insert(&mut start, &replacement);
+                buffer.end_user_action();
+                self.refresh();
+                self.message.set_text(if to == RIGHT {
+                    "Copied the change to the right"
+                } else {
+                    "Copied the change to the left"
+                });
+                return;
+            }
+        }
+        drop(sides);
+        self.render();
+        self.message.set_text(if to == RIGHT {
+            "Copied the change to the right"
+        } else {
+            "Copied the change to the left"
+        });
+    }
+
+    fn save_files(&self) {
+        for side in self.sides.borrow_mut().iter_mut() {
+            if let (true, Target::File(path)) = (side.modified, &side.target) {
+                match fs::write(path, &side.text) {
+                    Ok(()) => side.modified = false,
+                    Err(error) => {
+                        self.message.set_text(&format!(
+                            "Could not save {}: {}",
+                            path.display(),
+                            error
+                        ));
+                        return;
+                    }
+                }
+            }
+        }
+        self.message.set_text("Saved");
+        self.render();
+    }
+}
+
+pub struct DiffViewer {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    marked: RefCell<Option<(String, String, String)>>,
+    views: RefCell<Vec<Rc<DiffView>>>,
+}
+
+impl DiffViewer {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        commands: &CommandRegistry,
+    ) -> Rc<DiffViewer> {
+        let buffer = text_view.buffer();
+        buffer.create_tag(
+            Some("diff_line_added"),
+            &[("paragraph-background", &"#a5ff8e18")],
+        );
+        buffer.create_tag(
+            Some("diff_line_removed"),
+            &[("paragraph-background", &"#ff727218")],
+        );
+        buffer.create_tag(
+            Some("diff_character_added"),
+            &[("background", &"#a5ff8e50")],
+        );
+        buffer.create_tag(
+            Some("diff_character_removed"),
+            &[("background", &"#ff727250")],
+        );
+        buffer.create_tag(Some("diff_filler"), &[("paragraph-background", &"#1b1d20")]);
+        let viewer = Rc::new(DiffViewer {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            marked: RefCell::new(None),
+            views: RefCell::new(Vec::new()),
+        });
+        let weak = Rc::downgrade(&viewer);
+        document.buffer.connect_changed(move |_| {
+            if let Some(viewer) = weak.upgrade() {
+                for view in viewer.views.borrow().iter() {
+                    if view.is_live() {
+                        view.schedule();
+                    }
+                }
+            }
+        });
+        let weak = Rc::downgrade(&viewer);
+        document.connect_closing(move |_| {
+            if let Some(viewer) = weak.upgrade() {
+                let views = viewer.views.borrow().clone();
+                for view in views {
+                    if view.is_live() {
+                        view.window.destroy();
+                    }
+                }
+            }
+        });
+        let comparing = viewer.clone();
+        commands.register("diff.with_saved", "Compare with Saved", move || {
+            comparing.compare_with_saved()
+        });
+        let comparing = viewer.clone();
+        commands.register("diff.with_file", "Compare with File…", move || {
+            let weak = Rc::downgrade(&comparing);
+            comparing.choose_file("Compare with File", None, move |path| {
+                if let Some(viewer) = weak.upgrade() {
+                    viewer.compare_files(None, &path);
+                }
+            });
+        });
+        let comparing = viewer.clone();
+        commands.register("diff.files", "Compare Two Files…", move || {
+            let weak = Rc::downgrade(&comparing);
+            comparing.choose_file("First File", None, move |first| {
+                let Some(viewer) = weak.upgrade() else {
+                    return;
+                };
+                let weak = Rc::downgrade(&viewer);
+                let directory = first.parent().map(Path::to_path_buf);
+                viewer.choose_file("Second File", directory, move |second| {
+                    if let Some(viewer) = weak.upgrade() {
+                        viewer.compare_files(Some(&first), &second);
+                    }
+                });
+            });
+        });
+        let marking = viewer.clone();
+        commands.register(
+            "diff.mark_selection",
+            "Mark Selection for Comparison",
+            move || marking.mark_selection(),
+        );
+        let comparing = viewer.clone();
+        commands.register(
+            "diff.with_marked",
+            "Compare Selection with Marked",
+            move || comparing.compare_with_marked(),
+        );
+        viewer
+    }
+
+    fn file_name(&self) -> String {
+        self.document
+            .path()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn buffer_side(&self, selection: bool) -> Side {
+        let buffer = &self.document.buffer;
+        let language = self.document.language();
+        match buffer.selection_bounds().filter(|_| selection) {
+            Some((start, end)) => Side::range(
+                &format!(
+                    "{}:{}–{}",
+                    self.file_name(),
+                    start.line() + 1,
+                    end.line() + 1
+                ),
+                buffer,
+                &start,
+                &end,
+                &language,
+            ),
+            None => Side::range(
+                &format!("{} (buffer)", self.file_name()),
+                buffer,
+                &buffer.start_iter(),
+                &buffer.end_iter(),
+                &language,
+            ),
+        }
+    }
+
+    fn open(self: &Rc<Self>, left: Side, right: Side) {
+        let view = DiffView::new(&self.text_view, left, right);
+        let weak = Rc::downgrade(self);
+        let closing = Rc::downgrade(&view);
+        view.window.connect_destroy(move |_| {
+            if let (Some(viewer), Some(view)) = (weak.upgrade(), closing.upgrade()) {
+                view.detach();
+                viewer
+                    .views
+                    .borrow_mut()
+                    .retain(|other| !Rc::ptr_eq(other, &view));
+            }
+        });
+        self.views.borrow_mut().push(view);
+    }
+
+    pub fn compare_with_buffer(self: &Rc<Self>, side: Side) {
+        let buffer = self.buffer_side(false);
+        self.open(side, buffer);
+    }
+
+    fn compare_with_saved(self: &Rc<Self>) {
+        let path = self.document.path();
+        match fs::read_to_string(&path) {
+            Ok(text) => self.compare_with_buffer(Side::snapshot(
+                &format!("{} (saved)", self.file_name()),
+                &text,
+                &self.document.language(),
+            )),
+            Err(error) => {
+                self.status
+                    .set_text(&format!("Could not read {}: {}", path.display(), error))
+            }
+        }
+    }
+
+    fn compare_files(self: &Rc<Self>, first: Option<&Path>, second: &Path) {
+        let left = match first.map(Side::file) {
+            Some(Ok(side)) => side,
+            Some(Err(error)) => {
+                self.status
+                    .set_text(&format!("Could not read file: {}", error));
+                return;
+            }
+            None => self.buffer_side(false),
+        };
+        match Side::file(second) {
+            Ok(right) => self.open(left, right),
+            Err(error) => {
+                left.detach();
+                self.status
+                    .set_text(&format!("Could not read {}: {}", second.display(), error));
+            }
+        }
+    }
+
+    fn choose_file(
+        &self,
+        title: &str,
+        directory: Option<PathBuf>,
+        chosen: impl FnOnce(PathBuf) + 'static,
+    ) {
+        let dialog = FileDialog::builder().title(title).modal(true).build();
+        if let Some(directory) =
+            directory.or_else(|| self.document.path().parent().map(Path::to_path_buf))
+        {
+            dialog.set_initial_folder(Some(&gio::File::for_path(directory)));
+        }
+        let parent = self.text_view.root().and_downcast::<Window>();
+        dialog.open(parent.as_ref(), gio::Cancellable::NONE, move |result| {
+            if let Some(path) = result.ok().and_then(|file| file.path()) {
+                chosen(path);
+            }
+        });
+    }
+
+    fn mark_selection(&self) {
+        let buffer = &self.document.buffer;
+        let Some((start, end)) = buffer.selection_bounds() else {
+            self.status.set_text("Select the text to compare first");
+            return;
+        };
+        let title = format!(
+            "{}:{}–{}",
+            self.file_name(),
+            start.line() + 1,
+            end.line() + 1
+        );
+        self.status
+            .set_text(&format!("Marked {} for comparison", title));
+        self.marked.replace(Some((
+            title,
+            buffer.text(&start, &end, true).to_string(),
+            self.document.language(),
+        )));
+    }
+
+    fn compare_with_marked(self: &Rc<Self>) {
+        let Some(marked) = self
+            .marked
+            .borrow()
+            .as_ref()
+            .map(|(title, text, language)| Side::snapshot(title, text, language))
+        else {
+            self.status
+                .set_text("Mark a selection for comparison first");
+            return;
+        };
+        let side = self.buffer_side(true);
+        self.open(marked, side);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gtk4::{TextBuffer, prelude::*};
+
+type Handler = Box<dyn Fn(&str)>;
+type Guard = Box<dyn Fn(&str) -> Result<(), String>>;
+
+pub const LANGUAGES: &[&str] = &["qat", "rust", "css", "plain"];
+
+pub fn language_for_path(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("qat") => "qat",
+        Some("rs") => "rust",
+        Some("css") => "css",
+        _ => "plain",
+    }
+}
+
+pub struct Document {
+    pub buffer: TextBuffer,
+    path: RefCell<PathBuf>,
+    language: RefCell<String>,
+    save_guards: RefCell<Vec<Guard>>,
+    saving_handlers: RefCell<Vec<Handler>>,
+    saved_handlers: RefCell<Vec<Handler>>,
+    language_handlers: RefCell<Vec<Handler>>,
+    opened_handlers: RefCell<Vec<Handler>>,
+    closing_handlers: RefCell<Vec<Handler>>,
+}
+
+impl Document {
+    pub fn open(buffer: &TextBuffer, path: &Path) -> io::Result<Rc<Document>> {
+        let content = fs::read_to_string(path)?;
+        buffer.begin_irreversible_action();
+        buffer.set_text(&content);
+        buffer.end_irreversible_action();
+        buffer.set_modified(false);
+        Ok(Rc::new(Document {
+            buffer: buffer.clone(),
+            path: RefCell::new(path.to_path_buf()),
+            language: RefCell::new(language_for_path(path).to_owned()),
+            save_guards: RefCell::new(Vec::new()),
+            saving_handlers: RefCell::new(Vec::new()),
+            saved_handlers: RefCell::new(Vec::new()),
+            language_handlers: RefCell::new(Vec::new()),
+            opened_handlers: RefCell::new(Vec::new()),
+            closing_handlers: RefCell::new(Vec::new()),
+        }))
+    }
+
+    pub fn load(&self, path: &Path) -> io::Result<()> {
+        let content = fs::read_to_string(path)?;
+        let previous = self.path.borrow().to_string_lossy().into_owned();
+        for handler in self.closing_handlers.borrow().iter() {
+            handler(&previous);
+        }
+        self.buffer.begin_irreversible_action();
+        self.buffer.set_text(&content);
+        self.buffer.end_irreversible_action();
+        self.buffer.set_modified(false);
+        self.buffer.place_cursor(&self.buffer.start_iter());
+        self.path.replace(path.to_path_buf());
+        self.set_language(language_for_path(path));
+        for handler in self.opened_handlers.borrow().iter() {
+            handler(&content);
+        }
+        Ok(())
+    }
+
+    pub fn connect_opened(&self, handler: impl Fn(&str) + 'static) {
+        self.opened_handlers.borrow_mut().push(Box::new(handler));
+    }
+
+    pub fn connect_closing(&self, handler: impl Fn(&str) + 'static) {
+        self.closing_handlers.borrow_mut().push(Box::new(handler));
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.borrow().clone()
+    }
+
+    pub fn language(&self) -> String {
+        self.language.borrow().clone()
+    }
+
+    pub fn set_language(&self, language: &str) {
+        self.language.replace(language.to_owned());
+        for handler in self.language_handlers.borrow().iter() {
+            handler(language);
+        }
+    }
+
+    pub fn connect_language_changed(&self, handler: impl Fn(&str) + 'static) {
+        self.language_handlers.borrow_mut().push(Box::new(handler));
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer
+            .text(&self.buffer.start_iter(), &self.buffer.end_iter(), true)
+            .to_string()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let current = self.text();
+        for guard in self.save_guards.borrow().iter() {
+            guard(&current).map_err(io::Error::other)?;
+        }
+        let language = self.language();
+        for handler in self.saving_handlers.borrow().iter() {
+            handler(&language);
+        }
+        let content = self.text();
+        fs::write(&*self.path.borrow(), &content)?;
+        self.buffer.set_modified(false);
+        for handler in self.saved_handlers.borrow().iter() {
+            handler(&content);
+        }
+        Ok(())
+    }
+
+    pub fn connect_save_guard(&self, guard: impl Fn(&str) -> Result<(), String> + 'static) {
+        self.save_guards.borrow_mut().push(Box::new(guard));
+    }
+
+    pub fn connect_saving(&self, handler: impl Fn(&str) + 'static) {
+        self.saving_handlers.borrow_mut().push(Box::new(handler));
+    }
+
+    pub fn connect_saved(&self, handler: impl Fn(&str) + 'static) {
+        self.saved_handlers.borrow_mut().push(Box::new(handler));
+    }
+}
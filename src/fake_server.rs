@@ -0,0 +1,96 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::fd::FromRawFd,
+    thread,
+    time::{Duration, Instant},
+};
+
+use gtk4::glib;
+use serde_json::Value;
+
+const SERVER_VARIABLE: &str = "MOON_FAKE_SERVER";
+const OUTPUT_DESCRIPTOR: i32 = 3;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn command(test: &str) -> Vec<String> {
+    let executable = env::current_exe().expect("Could not find the test executable");
+    vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        format!(
+            "{}=1 exec \"$0\" --exact {} --nocapture {}>&1 1>/dev/null",
+            SERVER_VARIABLE, test, OUTPUT_DESCRIPTOR
+        ),
+        executable.to_string_lossy().into_owned(),
+    ]
+}
+
+pub fn is_server() -> bool {
+    env::var_os(SERVER_VARIABLE).is_some()
+}
+
+pub fn run_until(condition: impl Fn() -> bool) {
+    let context = glib::MainContext::default();
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition() {
+        assert!(
+            Instant::now() < deadline,
+            "Timed out waiting for the server"
+        );
+        if !context.iteration(false) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+pub struct Peer {
+    input: BufReader<io::Stdin>,
+    output: File,
+}
+
+impl Peer {
+    pub fn connect() -> Peer {
+        Peer {
+            input: BufReader::new(io::stdin()),
+            output: unsafe { File::from_raw_fd(OUTPUT_DESCRIPTOR) },
+        }
+    }
+
+    pub fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            let read = self
+                .input
+                .read_line(&mut line)
+                .expect("Could not read from the client");
+            assert!(read > 0, "The client closed the connection");
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().expect("Invalid Content-Length header");
+            }
+        }
+        let mut body = vec![0; length];
+        self.input
+            .read_exact(&mut body)
+            .expect("Could not read a message body");
+        serde_json::from_slice(&body).expect("Invalid message from the client")
+    }
+
+    pub fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .and_then(|_| self.output.flush())
+        .expect("Could not write to the client");
+    }
+}
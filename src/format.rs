@@ -0,0 +1,524 @@
+use std::{cell::Cell, collections::HashMap, ffi::OsStr, ops::Range, rc::Rc, time::Duration};
+
+use gtk4::{Label, TextBuffer, TextView, gio, glib, prelude::*};
+use tree_sitter::Node;
+
+use crate::{
+    commands::CommandRegistry, conflicts, diff, document::Document, notification::Notification,
+    settings, syntax,
+};
+
+const ATOMIC_KINDS: &[&str] = &[
+    "comment_line",
+    "comment_multi",
+    "literal_string",
+    "multiline_string",
+];
+const SPACED_OPERATORS: &[&str] = &[":=", "->", "<-"];
+
+struct Token<'content> {
+    text: &'content str,
+    kind: &'static str,
+    newlines_before: usize,
+    space_before: bool,
+}
+
+fn collect_tokens<'content>(
+    node: Node,
+    content: &'content str,
+    end: &mut usize,
+    tokens: &mut Vec<Token<'content>>,
+) -> Result<(), String> {
+    let kind = ATOMIC_KINDS
+        .iter()
+        .find(|atomic| **atomic == node.kind())
+        .copied();
+    if kind.is_none() && node.child_count() > 0 {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            collect_tokens(child, content, end, tokens)?;
+        }
+        return Ok(());
+    }
+    let range = node.byte_range();
+    let text = content.get(range.clone()).unwrap_or("").trim_end();
+    if text.is_empty() {
+        return Ok(());
+    }
+    let gap = content.get(*end..range.start).unwrap_or("");
+    if !gap.chars().all(char::is_whitespace) {
+        return Err("The file contains text the formatter does not understand".to_owned());
+    }
+    tokens.push(Token {
+        text,
+        kind: kind.unwrap_or(""),
+        newlines_before: gap.matches('\n').count(),
+        space_before: !gap.is_empty(),
+    });
+    *end = range.start + text.len();
+    Ok(())
+}
+
+fn tokens(content: &str) -> Result<Vec<Token<'_>>, String> {
+    if !conflicts::find(content).is_empty() {
+        return Err("Cannot format a file with unresolved merge conflicts".to_owned());
+    }
+    let tree = syntax::parse_qat(content).ok_or("Could not parse the file")?;
+    if tree.root_node().has_error() {
+        return Err("Cannot format a file with syntax errors".to_owned());
+    }
+    let mut end = 0;
+    let mut collected = Vec::new();
+    collect_tokens(tree.root_node(), content, &mut end, &mut collected)?;
+    if !content[end..].chars().all(char::is_whitespace) {
+        return Err("The file contains text the formatter does not understand".to_owned());
+    }
+    Ok(collected)
+}
+
+fn needs_space(previous: &Token, token: &Token) -> bool {
+    if SPACED_OPERATORS.contains(&previous.text) || SPACED_OPERATORS.contains(&token.text) {
+        return true;
+    }
+    if matches!(token.text, "," | ";" | ")" | "]") || matches!(previous.text, "(" | "[") {
+        return false;
+    }
+    if token.text == "{" || token.kind == "comment_line" || previous.text == "," {
+        return true;
+    }
+    token.space_before
+}
+
+pub fn format_qat(content: &str) -> Result<String, String> {
+    let tokens = tokens(content)?;
+    let mut output = String::new();
+    let mut depth: usize = 0;
+    let mut nesting: usize = 0;
+    let mut closed_top_level = false;
+    for (index, token) in tokens.iter().enumerate() {
+        let mut breaks = token.newlines_before.min(2);
+        if let Some(previous) = index.checked_sub(1).map(|previous| &tokens[previous]) {
+            if previous.kind == "comment_line" {
+                breaks = breaks.max(1);
+            } else if token.text == "{" {
+                breaks = 0;
+            }
+            if previous.text == "{" {
+                if token.text != "}" || breaks > 0 {
+                    breaks = 1;
+                }
+            } else if token.text == "}" {
+                breaks = 1;
+            } else if closed_top_level
+                && breaks > 0
+                && nesting == 0
+                && !matches!(token.text, "," | "." | ";" | ")" | "]")
+            {
+                breaks = 2;
+            }
+        } else {
+            breaks = 0;
+        }
+        match token.text {
+            "}" => depth = depth.saturating_sub(1),
+            ")" | "]" => nesting = nesting.saturating_sub(1),
+            _ => {}
+        }
+        if breaks > 0 {
+            output.truncate(output.trim_end_matches([' ', '\t']).len());
+            output.push_str(&"\n".repeat(breaks));
+            output.push_str(&"\t".repeat(depth + nesting));
+        } else if let Some(previous) = index.checked_sub(1).map(|previous| &tokens[previous])
+            && needs_space(previous, token)
+        {
+            output.push(' ');
+        }
+        output.push_str(token.text);
+        closed_top_level = false;
+        match token.text {
+            "{" => depth += 1,
+            "}" => closed_top_level = depth == 0 && nesting == 0,
+            "(" | "[" => nesting += 1,
+            _ => {}
+        }
+    }
+    output.truncate(output.trim_end().len());
+    output.push('\n');
+    let original: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+    let formatted = self::tokens(&output)?;
+    if formatted.iter().map(|token| token.text).ne(original) {
+        return Err("Formatting would change the meaning of the file".to_owned());
+    }
+    Ok(output)
+}
+
+pub fn apply_text(buffer: &TextBuffer, text: &str, lines: Option<Range<usize>>) -> usize {
+    let current = buffer
+        .text(&buffer.start_iter(), &buffer.end_iter(), true)
+        .to_string();
+    let old: Vec<&str> = current.split('\n').collect();
+    let new: Vec<&str> = text.split('\n').collect();
+    let hunks: Vec<diff::Hunk> = diff::diff(&old, &new)
+        .into_iter()
+        .filter(|hunk| {
+            lines.as_ref().is_none_or(|lines| {
+                hunk.old_start < lines.end && hunk.old_start + hunk.old_len.max(1) > lines.start
+            })
+        })
+        .collect();
+    if hunks.is_empty() {
+        return 0;
+    }
+    buffer.begin_user_action();
+    for hunk in hunks.iter().rev() {
+        let replacement = &new[hunk.new_start..hunk.new_start + hunk.new_len];
+        let at_end = hunk.old_start + hunk.old_len >= old.len();
+        if at_end && hunk.old_start > 0 {
+            let mut start = buffer
+                .iter_at_line((hunk.old_start - 1) as i32)
+                .unwrap_or_else(|| buffer.end_iter());
+            if !start.ends_line() {
+                start.forward_to_line_end();
+            }
+            let mut end = buffer.end_iter();
+            buffer.delete(&mut start, &mut end);
+            let inserted: String = replacement
+                .iter()
+                .map(|line| format!("\n{}", line))
+                .collect();
+            buffer.insert(&mut start, &inserted);
+        } else if at_end {
+            let (mut start, mut end) = buffer.bounds();
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &replacement.join("\n"));
+        } else {
+            let mut start = buffer
+                .iter_at_line(hunk.old_start as i32)
+                .unwrap_or_else(|| buffer.end_iter());
+            let mut end = buffer
+                .iter_at_line((hunk.old_start + hunk.old_len) as i32)
+                .unwrap_or_else(|| buffer.end_iter());
+            buffer.delete(&mut start, &mut end);
+            let inserted: String = replacement
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            buffer.insert(&mut start, &inserted);
+        }
+    }
+    buffer.end_user_action();
+    hunks.len()
+}
+
+pub fn selected_lines(buffer: &TextBuffer) -> Option<Range<usize>> {
+    let (start, end) = buffer.selection_bounds()?;
+    let last = if end.starts_line() && end.line() > start.line() {
+        end.line() - 1
+    } else {
+        end.line()
+    };
+    Some(start.line() as usize..last as usize + 1)
+}
+
+pub struct Formatter {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    external: HashMap<String, Vec<String>>,
+    timeout: Duration,
+    running: Cell<Option<u64>>,
+    generation: Cell<u64>,
+}
+
+impl Formatter {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        commands: &CommandRegistry,
+        editor_settings: &settings::EditorSettings,
+    ) -> Rc<Formatter> {
+        let formatter = Rc::new(Formatter {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            external: editor_settings.formatters.clone(),
+            timeout: Duration::from_secs(editor_settings.formatter_timeout),
+            running: Cell::new(None),
+            generation: Cell::new(0),
+        });
+        let formatting = formatter.clone();
+        commands.register("format.document", "Format Document", move || {
+            formatting.format(None)
+        });
+        let formatting = formatter.clone();
+        commands.register("format.selection", "Format Selection", move || {
+            formatting.format(selected_lines(&formatting.text_view.buffer()))
+        });
+        if editor_settings.format_on_save {
+            let weak = Rc::downgrade(&formatter);
+            document.connect_saving(move |language| {
+                if let Some(formatter) = weak.upgrade()
+                    && language == "qat"
+                    && !formatter.external.contains_key(language)
+                {
+                    formatter.format(None);
+                }
+            });
+        }
+        formatter
+    }
+
+    pub fn format(self: &Rc<Self>, lines: Option<Range<usize>>) {
+        let language = self.document.language();
+        if let Some(command) = self.external.get(&language) {
+            self.run_external(command, lines);
+            return;
+        }
+        if language != "qat" {
+            self.status
+                .set_text("No formatter is available for this language");
+            return;
+        }
+        match format_qat(&self.document.text()) {
+            Ok(formatted) => {
+                let changed = apply_text(&self.document.buffer, &formatted, lines);
+                self.status.set_text(if changed == 0 {
+                    "Already formatted"
+                } else {
+                    "Formatted"
+                });
+            }
+            Err(reason) => self.status.set_text(&reason),
+        }
+    }
+
+    fn run_external(self: &Rc<Self>, command: &[String], lines: Option<Range<usize>>) {
+        if self.running.get().is_some() {
+            self.status.set_text("A formatter is already running");
+            return;
+        }
+        let launcher = gio::SubprocessLauncher::new(
+            gio::SubprocessFlags::STDIN_PIPE
+                | gio::SubprocessFlags::STDOUT_PIPE
+                | gio::SubprocessFlags::STDERR_PIPE,
+        );
+        if let Some(directory) = self.document.path().parent()
+            && directory.is_dir()
+        {
+            launcher.set_cwd(directory);
+        }
+        let arguments: Vec<&OsStr> = command.iter().map(OsStr::new).collect();
+        let process = match launcher.spawn(&arguments) {
+            Ok(process) => process,
+            Err(error) => {
+                self.notification
+                    .show(&format!("Could not run {}", command[0]), error.message());
+                return;
+            }
+        };
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        self.running.set(Some(generation));
+        self.status.set_text(&format!("Running {}…", command[0]));
+        let original = self.document.text();
+        let cancellable = gio::Cancellable::new();
+        let timed_out = Rc::new(Cell::new(false));
+        let timeout = {
+            let process = process.clone();
+            let cancellable = cancellable.clone();
+            let timed_out = timed_out.clone();
+            glib::timeout_add_local_once(self.timeout, move || {
+                timed_out.set(true);
+                process.force_exit();
+                cancellable.cancel();
+            })
+        };
+        let weak = Rc::downgrade(self);
+        let name = command[0].clone();
+        let checking = process.clone();
+        process.communicate_utf8_async(Some(original.clone()), Some(&cancellable), move |result| {
+            let Some(formatter) = weak.upgrade() else {
+                return;
+            };
+            if formatter.running.get() != Some(generation) {
+                return;
+            }
+            formatter.running.set(None);
+            if timed_out.get() {
+                formatter.status.set_text("");
+                formatter.notification.show(
+                    &format!("{} timed out", name),
+                    &format!(
+                        "The formatter did not finish within {} seconds",
+                        formatter.timeout.as_secs()
+                    ),
+                );
+                return;
+            }
+            timeout.remove();
+            let (stdout, stderr) = match result {
+                Ok((stdout, stderr)) => (stdout.unwrap_or_default(), stderr.unwrap_or_default()),
+                Err(error) => {
+                    formatter.status.set_text("");
+                    formatter
+                        .notification
+                        .show(&format!("{} failed", name), error.message());
+                    return;
+                }
+            };
+            if !checking.is_successful() {
+                formatter.status.set_text("");
+                formatter.notification.show(
+                    &format!("{} exited with status {}", name, checking.exit_status()),
+                    &stderr,
+                );
+                return;
+            }
+            if formatter.document.text() != original {
+                formatter
+                    .status
+                    .set_text("The buffer changed while formatting");
+                return;
+            }
+            if stdout.is_empty() && !original.is_empty() {
+                formatter
+                    .notification
+                    .show(&format!("{} produced no output", name), &stderr);
+                formatter.status.set_text("");
+                return;
+            }
+            let changed = apply_text(&formatter.document.buffer, &stdout, lines);
+            formatter.status.set_text(if changed == 0 {
+                "Already formatted"
+            } else {
+                "Formatted"
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn corpus() -> Vec<(PathBuf, String)> {
+        let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/format");
+        let mut files: Vec<(PathBuf, String)> = fs::read_dir(&directory)
+            .expect("Could not read the format fixtures")
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "qat"))
+            .map(|path| {
+                let content = fs::read_to_string(&path).expect("Could not read a fixture");
+                (path, content)
+            })
+            .collect();
+        files.sort();
+        assert!(!files.is_empty());
+        files
+    }
+
+    fn comments(content: &str) -> Vec<String> {
+        tokens(content)
+            .expect("Could not tokenize")
+            .iter()
+            .filter(|token| token.kind.starts_with("comment"))
+            .map(|token| token.text.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for (path, content) in corpus() {
+            let once = format_qat(&content)
+                .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            let twice =
+                format_qat(&once).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            assert_eq!(once, twice, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn formatting_keeps_comments() {
+        for (path, content) in corpus() {
+            let formatted = format_qat(&content)
+                .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            assert_eq!(
+                comments(&content),
+                comments(&formatted),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    fn applied(original: &str, text: &str, lines: Option<Range<usize>>) -> (String, usize) {
+        let buffer = TextBuffer::new(None);
+        buffer.set_text(original);
+        let changed = apply_text(&buffer, text, lines);
+        let (start, end) = buffer.bounds();
+        (buffer.text(&start, &end, true).to_string(), changed)
+    }
+
+    #[gtk4::test]
+    fn apply_text_edits_the_last_line() {
+        assert_eq!(
+            applied("a\nb\nc", "a\nb\nz", None),
+            ("a\nb\nz".to_owned(), 1)
+        );
+        assert_eq!(
+            applied("a\nb\nc\n", "a\nb\nz\n", None),
+            ("a\nb\nz\n".to_owned(), 1)
+        );
+    }
+
+    #[gtk4::test]
+    fn apply_text_inserts_lines() {
+        assert_eq!(
+            applied("a\nb\nc", "x\na\nb\nc", None),
+            ("x\na\nb\nc".to_owned(), 1)
+        );
+        assert_eq!(
+            applied("a\nb\nc", "a\nx\nb\nc", None),
+            ("a\nx\nb\nc".to_owned(), 1)
+        );
+        assert_eq!(
+            applied("a\nb\nc", "a\nb\nc\nx", None),
+            ("a\nb\nc\nx".to_owned(), 1)
+        );
+        assert_eq!(
+            applied("a\nb\nc", "a\nb\nc\n", None),
+            ("a\nb\nc\n".to_owned(), 1)
+        );
+    }
+
+    #[gtk4::test]
+    fn apply_text_deletes_lines() {
+        assert_eq!(applied("a\nb\nc", "b\nc", None), ("b\nc".to_owned(), 1));
+        assert_eq!(applied("a\nb\nc", "a\nc", None), ("a\nc".to_owned(), 1));
+        assert_eq!(applied("a\nb\nc", "a\nb", None), ("a\nb".to_owned(), 1));
+        assert_eq!(
+            applied("a\nb\nc\n", "a\nb\nc", None),
+            ("a\nb\nc".to_owned(), 1)
+        );
+        assert_eq!(applied("a\nb\nc", "", None), ("".to_owned(), 1));
+    }
+
+    #[gtk4::test]
+    fn apply_text_limits_changes_to_lines() {
+        assert_eq!(
+            applied("a\nb\nc\nd", "x\nb\nc\ny", Some(3..4)),
+            ("a\nb\nc\ny".to_owned(), 1)
+        );
+        assert_eq!(
+            applied("a\nb\nc", "x\nb\nc", Some(1..3)),
+            ("a\nb\nc".to_owned(), 0)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::Rc,
+};
+
+use gtk4::{gio, glib, prelude::*};
+use serde_json::Value;
+
+fn frame(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    let mut bytes = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    bytes.extend_from_slice(body.as_bytes());
+    bytes
+}
+
+pub async fn read_message(
+    input: &gio::DataInputStream,
+) -> Option<Result<Value, serde_json::Error>> {
+    loop {
+        let mut length = None;
+        loop {
+            let line = input
+                .read_line_utf8_future(glib::Priority::DEFAULT)
+                .await
+                .ok()??;
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        return match input
+            .read_all_future(vec![0; length], glib::Priority::DEFAULT)
+            .await
+        {
+            Ok((body, read, None)) if read == length => Some(serde_json::from_slice(&body)),
+            _ => None,
+        };
+    }
+}
+
+pub struct Writer {
+    stream: gio::OutputStream,
+    outgoing: RefCell<VecDeque<Vec<u8>>>,
+    writing: Cell<bool>,
+}
+
+impl Writer {
+    pub fn new(stream: gio::OutputStream) -> Rc<Writer> {
+        Rc::new(Writer {
+            stream,
+            outgoing: RefCell::new(VecDeque::new()),
+            writing: Cell::new(false),
+        })
+    }
+
+    pub fn is_writing(&self) -> bool {
+        self.writing.get()
+    }
+
+    pub fn send(self: &Rc<Self>, message: &Value) {
+        self.outgoing.borrow_mut().push_back(frame(message));
+        if self.writing.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::spawn_future_local(async move {
+            loop {
+                let Some(writer) = weak.upgrade() else {
+                    return;
+                };
+                let Some(bytes) = writer.outgoing.borrow_mut().pop_front() else {
+                    writer.writing.set(false);
+                    return;
+                };
+                let stream = writer.stream.clone();
+                drop(writer);
+                if stream
+                    .write_all_future(bytes, glib::Priority::DEFAULT)
+                    .await
+                    .is_err()
+                {
+                    if let Some(writer) = weak.upgrade() {
+                        writer.outgoing.borrow_mut().clear();
+                        writer.writing.set(false);
+                    }
+                    return;
+                }
+            }
+        });
+    }
+
+    pub fn write_now(&self, message: &Value) -> bool {
+        self.stream
+            .write_all(&frame(message), gio::Cancellable::NONE)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn read_all(data: &[u8]) -> Vec<Option<Value>> {
+        let input = gio::DataInputStream::new(&gio::MemoryInputStream::from_bytes(
+            &glib::Bytes::from(data),
+        ));
+        glib::MainContext::default().block_on(async {
+            let mut messages = Vec::new();
+            while let Some(message) = read_message(&input).await {
+                messages.push(message.ok());
+            }
+            messages
+        })
+    }
+
+    #[gtk4::test]
+    fn frames_round_trip() {
+        let message = json!({ "seq": 1, "text": "é😀" });
+        let framed = frame(&message);
+        assert!(framed.starts_with(b"Content-Length: 25\r\n\r\n"));
+        assert_eq!(read_all(&framed), vec![Some(message)]);
+    }
+
+    #[gtk4::test]
+    fn messages_are_read_in_order() {
+        assert_eq!(
+            read_all(
+                b"Content-Length: 8\r\nContent-Type: application/json\r\n\r\n{\"a\":1}\n\
+                  content-length:7\r\n\r\n{\"b\":2}"
+            ),
+            vec![Some(json!({ "a": 1 })), Some(json!({ "b": 2 }))]
+        );
+    }
+
+    #[gtk4::test]
+    fn headers_without_a_length_are_skipped() {
+        assert_eq!(
+            read_all(b"Content-Type: text/plain\r\n\r\nContent-Length: 2\r\n\r\n{}"),
+            vec![Some(json!({}))]
+        );
+    }
+
+    #[gtk4::test]
+    fn invalid_and_truncated_bodies() {
+        assert_eq!(
+            read_all(b"Content-Length: 3\r\n\r\n{]}Content-Length: 10\r\n\r\n{}"),
+            vec![None]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use gtk4::glib;
+
+pub struct FuzzyMatch {
+    pub score: i32,
+    pub positions: Vec<usize>,
+}
+
+fn is_word_start(characters: &[char], index: usize) -> bool {
+    if index == 0 {
+        return true;
+    }
+    let previous = characters[index - 1];
+    let current = characters[index];
+    !previous.is_alphanumeric() || (previous.is_lowercase() && current.is_uppercase())
+}
+
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<FuzzyMatch> {
+    let pattern: Vec<char> = pattern
+        .chars()
+        .filter(|character| !character.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let characters: Vec<char> = text.chars().collect();
+    let lowered: Vec<char> = characters
+        .iter()
+        .map(|character| character.to_lowercase().next().unwrap_or(*character))
+        .collect();
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut score = 0;
+    let mut index = 0;
+    for wanted in &pattern {
+        let mut found = None;
+        let mut candidate = index;
+        while candidate < lowered.len() {
+            if lowered[candidate] == *wanted {
+                if found.is_none() {
+                    found = Some(candidate);
+                }
+                if is_word_start(&characters, candidate)
+                    || positions.last() == Some(&(candidate.wrapping_sub(1)))
+                {
+                    found = Some(candidate);
+                    break;
+                }
+            }
+            candidate += 1;
+        }
+        let position = found?;
+        score += 1;
+        if is_word_start(&characters, position) {
+            score += 8;
+        }
+        match positions.last() {
+            Some(last) if position == last + 1 => score += 5,
+            Some(last) => score -= (position - last - 1).min(5) as i32,
+            None => score -= position.min(10) as i32,
+        }
+        positions.push(position);
+        index = position + 1;
+    }
+    if pattern.len() == characters.len() {
+        score += 10;
+    }
+    Some(FuzzyMatch { score, positions })
+}
+
+pub fn highlighted_markup(text: &str, positions: &[usize]) -> String {
+    let mut markup = String::new();
+    for (index, character) in text.chars().enumerate() {
+        let escaped = glib::markup_escape_text(&character.to_string());
+        if positions.contains(&index) {
+            markup.push_str(&format!("<b>{}</b>", escaped));
+        } else {
+            markup.push_str(&escaped);
+        }
+    }
+    markup
+}
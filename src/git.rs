@@ -0,0 +1,60 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use gtk4::gio;
+
+pub fn repository_root(path: &Path) -> Option<PathBuf> {
+    path.parent()?
+        .ancestors()
+        .find(|directory| directory.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+pub fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn run(
+    root: &Path,
+    arguments: &[&str],
+    input: Option<&str>,
+    handler: impl FnOnce(Result<String, String>) + 'static,
+) {
+    let mut flags = gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_PIPE;
+    if input.is_some() {
+        flags |= gio::SubprocessFlags::STDIN_PIPE;
+    }
+    let launcher = gio::SubprocessLauncher::new(flags);
+    launcher.set_cwd(root);
+    launcher.setenv("GIT_OPTIONAL_LOCKS", "0", true);
+    launcher.setenv("GIT_TERMINAL_PROMPT", "0", true);
+    let mut command: Vec<&OsStr> = vec![OsStr::new("git")];
+    command.extend(arguments.iter().map(OsStr::new));
+    let process = match launcher.spawn(&command) {
+        Ok(process) => process,
+        Err(error) => {
+            handler(Err(error.message().to_owned()));
+            return;
+        }
+    };
+    let checking = process.clone();
+    process.communicate_utf8_async(
+        input.map(str::to_owned),
+        gio::Cancellable::NONE,
+        move |result| match result {
+            Ok((stdout, _)) if checking.is_successful() => {
+                handler(Ok(stdout.map(Into::into).unwrap_or_default()))
+            }
+            Ok((_, stderr)) => handler(Err(stderr
+                .map(|stderr| stderr.trim().to_owned())
+                .filter(|stderr| !stderr.is_empty())
+                .unwrap_or_else(|| format!("git exited with status {}", checking.exit_status())))),
+            Err(error) => handler(Err(error.message().to_owned())),
+        },
+    );
+}
@@ -0,0 +1,646 @@
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Button, Label, Orientation, PolicyType, Popover, PositionType, ScrolledWindow, TextView,
+    TextWindowType, gdk, gio, glib, prelude::*,
+};
+
+use crate::{
+    commands::CommandRegistry,
+    diff::{self, Hunk},
+    document::Document,
+    git,
+    gutter::Gutter,
+    notification::Notification,
+    settings::{DiffBase, EditorSettings},
+};
+
+const CHANGE_COLUMN_WIDTH: i32 = 6;
+
+struct Base {
+    root: PathBuf,
+    relative: String,
+    text: String,
+}
+
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split('\n').collect()
+}
+
+fn region(
+    lines: &[&str],
+    start: usize,
+    end: usize,
+    length: impl Fn(&str) -> usize,
+) -> (usize, usize) {
+    let mut starts = Vec::with_capacity(lines.len() + 1);
+    let mut offset = 0;
+    starts.push(0);
+    for line in lines {
+        offset += length(line) + 1;
+        starts.push(offset);
+    }
+    let total = offset - 1;
+    if end < lines.len() {
+        (starts[start], starts[end])
+    } else if start > 0 {
+        (starts[start] - 1, total)
+    } else {
+        (0, total)
+    }
+}
+
+fn replace_hunks(old: &str, new: &str, hunks: &[Hunk]) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let mut result = old.to_owned();
+    for hunk in hunks.iter().rev() {
+        let (old_start, old_end) = region(
+            &old_lines,
+            hunk.old_start,
+            hunk.old_start + hunk.old_len,
+            str::len,
+        );
+        let (new_start, new_end) = region(
+            &new_lines,
+            hunk.new_start,
+            hunk.new_start + hunk.new_len,
+            str::len,
+        );
+        result.replace_range(old_start..old_end, &new[new_start..new_end]);
+    }
+    result
+}
+
+fn overlaps(hunk: &Hunk, start: usize, len: usize) -> bool {
+    hunk.new_start <= start + len && start <= hunk.new_start + hunk.new_len
+}
+
+pub struct GitGutter {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    gutter: Rc<Gutter>,
+    diff_base: DiffBase,
+    base: RefCell<Option<Base>>,
+    hunks: RefCell<Vec<Hunk>>,
+    generation: Cell<u64>,
+    update_pending: Cell<bool>,
+    monitor: RefCell<Option<gio::FileMonitor>>,
+    popover: Popover,
+    popover_title: Label,
+    original: Label,
+    shown: Cell<Option<usize>>,
+}
+
+impl GitGutter {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        commands: &CommandRegistry,
+        gutter: &Rc<Gutter>,
+        settings: &EditorSettings,
+    ) -> Rc<GitGutter> {
+        let popover_title = Label::builder().hexpand(true).xalign(0.0).build();
+        let header = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&popover_title);
+        let original = Label::builder()
+            .xalign(0.0)
+            .yalign(0.0)
+            .selectable(true)
+            .css_classes(["monospace", "git_original"])
+            .build();
+        let content = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(6)
+            .build();
+        content.append(&header);
+        content.append(
+            &ScrolledWindow::builder()
+                .child(&original)
+                .hscrollbar_policy(PolicyType::Automatic)
+                .propagate_natural_height(true)
+                .propagate_natural_width(true)
+                .max_content_height(300)
+                .max_content_width(700)
+                .build(),
+        );
+        let popover = Popover::builder()
+            .child(&content)
+            .position(PositionType::Bottom)
+            .build();
+        popover.set_parent(text_view);
+        let git_gutter = Rc::new(GitGutter {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            gutter: gutter.clone(),
+            diff_base: settings.diff_base,
+            base: RefCell::new(None),
+            hunks: RefCell::new(Vec::new()),
+            generation: Cell::new(0),
+            update_pending: Cell::new(false),
+            monitor: RefCell::new(None),
+            popover,
+            popover_title,
+            original,
+            shown: Cell::new(None),
+        });
+        for (label, command) in [
+            ("‹", "git.previous_change"),
+            ("›", "git.next_change"),
+            ("Revert", "git.revert_change"),
+            ("Stage", "git.stage_change"),
+        ] {
+            let button = Button::builder().label(label).has_frame(false).build();
+            let weak = Rc::downgrade(&git_gutter);
+            button.connect_clicked(move |_| {
+                let Some(git_gutter) = weak.upgrade() else {
+                    return;
+                };
+                let Some(index) = git_gutter.shown.get() else {
+                    return;
+                };
+                match command {
+                    "git.previous_change" => {
+                        git_gutter.show_hunk(git_gutter.adjacent(index, false))
+                    }
+                    "git.next_change" => git_gutter.show_hunk(git_gutter.adjacent(index, true)),
+                    "git.revert_change" => git_gutter.revert_hunk(index),
+                    _ => git_gutter.stage_hunk(index),
+                }
+            });
+            header.append(&button);
+        }
+        let painting = Rc::downgrade(&git_gutter);
+        let weak = Rc::downgrade(&git_gutter);
+        gutter.add_column(
+            CHANGE_COLUMN_WIDTH,
+            move |context, iter, width, y, height| {
+                let Some(git_gutter) = painting.upgrade() else {
+                    return;
+                };
+                let line = iter.line() as usize;
+                let last = iter.buffer().line_count() as usize - 1;
+                for hunk in git_gutter.hunks.borrow().iter() {
+                    if hunk.new_len == 0 {
+                        let top = if hunk.new_start == line {
+                            y
+                        } else if hunk.new_start > last && line == last {
+                            y + height
+                        } else {
+                            continue;
+                        };
+                        context.set_source_rgb(1.0, 0.45, 0.45);
+                        context.move_to(0.0, top - 4.0);
+                        context.line_to(width, top);
+                        context.line_to(0.0, top + 4.0);
+                        context.close_path();
+                        context.fill().ok();
+                    } else if hunk.new_start <= line && line < hunk.new_start + hunk.new_len {
+                        if hunk.old_len == 0 {
+                            context.set_source_rgb(0.45, 0.8, 0.45);
+                        } else {
+                            context.set_source_rgb(0.41, 0.65, 1.0);
+                        }
+                        context.rectangle(1.0, y, width - 2.0, height);
+                        context.fill().ok();
+                    }
+                }
+            },
+            move |iter, button| {
+                if let Some(git_gutter) = weak.upgrade()
+                    && button == 1
+                    && let Some(index) = git_gutter.hunk_at(iter.line() as usize)
+                {
+                    git_gutter.show_hunk(index);
+                }
+            },
+        );
+        let weak = Rc::downgrade(&git_gutter);
+        document.buffer.connect_changed(move |_| {
+            if let Some(git_gutter) = weak.upgrade() {
+                git_gutter.schedule_update();
+            }
+        });
+        let weak = Rc::downgrade(&git_gutter);
+        document.connect_opened(move |_| {
+            if let Some(git_gutter) = weak.upgrade() {
+                git_gutter.watch_index();
+                git_gutter.load_base();
+            }
+        });
+        let weak = Rc::downgrade(&git_gutter);
+        document.connect_saved(move |_| {
+            if let Some(git_gutter) = weak.upgrade() {
+                git_gutter.load_base();
+            }
+        });
+        git_gutter.register_commands(commands);
+        git_gutter.watch_index();
+        git_gutter.load_base();
+        git_gutter
+    }
+
+    fn register_commands(self: &Rc<Self>, commands: &CommandRegistry) {
+        let git_gutter = self.clone();
+        commands.register("git.next_change", "Go to Next Change", move || {
+            git_gutter.go_to_change(true)
+        });
+        let git_gutter = self.clone();
+        commands.register("git.previous_change", "Go to Previous Change", move || {
+            git_gutter.go_to_change(false)
+        });
+        let git_gutter = self.clone();
+        commands.register("git.show_change", "Show Change", move || {
+            match git_gutter.hunk_at(git_gutter.cursor_line()) {
+                Some(index) => git_gutter.show_hunk(index),
+                None => git_gutter.status.set_text("No change on this line"),
+            }
+        });
+        let git_gutter = self.clone();
+        commands.register(
+            "git.revert_change",
+            "Revert Change",
+            move || match git_gutter.hunk_at(git_gutter.cursor_line()) {
+                Some(index) => git_gutter.revert_hunk(index),
+                None => git_gutter.status.set_text("No change on this line"),
+            },
+        );
+        let git_gutter = self.clone();
+        commands.register("git.stage_change", "Stage Change", move || match git_gutter
+            .hunk_at(git_gutter.cursor_line())
+        {
+            Some(index) => git_gutter.stage_hunk(index),
+            None => git_gutter.status.set_text("No change on this line"),
+        });
+    }
+
+    fn cursor_line(&self) -> usize {
+        let buffer = self.text_view.buffer();
+        buffer.iter_at_mark(&buffer.get_insert()).line() as usize
+    }
+
+    fn watch_index(self: &Rc<Self>) {
+        if let Some(monitor) = self.monitor.take() {
+            monitor.cancel();
+        }
+        let Some(root) = git::repository_root(&self.document.path()) else {
+            return;
+        };
+        let weak = Rc::downgrade(self);
+        let watched = root.clone();
+        git::run(
+            &root,
+            &["rev-parse", "--git-path", "index"],
+            None,
+            move |result| {
+                let Some(git_gutter) = weak.upgrade() else {
+                    return;
+                };
+                let Ok(index) = result else {
+                    return;
+                };
+                if git::repository_root(&git_gutter.document.path()).as_ref() != Some(&watched) {
+                    return;
+                }
+                git_gutter.monitor_index(&watched.join(index.trim()));
+            },
+        );
+    }
+
+    fn monitor_index(self: &Rc<Self>, index: &Path) {
+        if let Some(monitor) = self.monitor.take() {
+            monitor.cancel();
+        }
+        let Ok(monitor) = gio::File::for_path(index)
+            .monitor_file(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE)
+        else {
+            return;
+        };
+        let weak = Rc::downgrade(self);
+        monitor.connect_changed(move |_, _, _, event| {
+            if let Some(git_gutter) = weak.upgrade()
+                && matches!(
+                    event,
+                    gio::FileMonitorEvent::ChangesDoneHint | gio::FileMonitorEvent::Created
+                )
+            {
+                git_gutter.load_base();
+            }
+        });
+        self.monitor.replace(Some(monitor));
+    }
+
+    fn load_base(self: &Rc<Self>) {
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        let path = self.document.path();
+        let Some(root) = git::repository_root(&path) else {
+            self.set_base(None);
+            return;
+        };
+        let relative = git::relative_path(&root, &path);
+        let object = match self.diff_base {
+            DiffBase::Index => format!(":{}", relative),
+            DiffBase::Head => format!("HEAD:{}", relative),
+        };
+        let weak = Rc::downgrade(self);
+        let directory = root.clone();
+        git::run(&directory, &["show", &object], None, move |result| {
+            let Some(git_gutter) = weak.upgrade() else {
+                return;
+            };
+            if git_gutter.generation.get() != generation {
+                return;
+            }
+            git_gutter.set_base(result.ok().map(|text| Base {
+                root,
+                relative,
+                text,
+            }));
+        });
+    }
+
+    fn set_base(&self, base: Option<Base>) {
+        self.base.replace(base);
+        self.update();
+    }
+
+    fn schedule_update(self: &Rc<Self>) {
+        if self.update_pending.replace(true) {
+            return;
+        }
+        let git_gutter = self.clone();
+        glib::idle_add_local_once(move || {
+            git_gutter.update_pending.set(false);
+            git_gutter.update();
+        });
+    }
+
+    fn update(&self) {
+        let hunks = match self.base.borrow().as_ref() {
+            Some(base) => {
+                let text = self.document.text();
+                diff::diff(&split_lines(&base.text), &split_lines(&text))
+            }
+            None => Vec::new(),
+        };
+        let changed = *self.hunks.borrow() != hunks;
+        self.hunks.replace(hunks);
+        if changed {
+            self.shown.set(None);
+            self.popover.popdown();
+        }
+        self.gutter.queue_draw();
+    }
+
+    fn hunk_at(&self, line: usize) -> Option<usize> {
+        let hunks = self.hunks.borrow();
+        let last = self.text_view.buffer().line_count() as usize - 1;
+        hunks
+            .iter()
+            .position(|hunk| hunk.new_start <= line && line < hunk.new_start + hunk.new_len)
+            .or_else(|| {
+                hunks.iter().position(|hunk| {
+                    hunk.new_len == 0
+                        && (hunk.new_start == line
+                            || hunk.new_start == line + 1
+                            || (hunk.new_start > last && line == last))
+                })
+            })
+    }
+
+    fn adjacent(&self, index: usize, forward: bool) -> usize {
+        let count = self.hunks.borrow().len().max(1);
+        if forward {
+            (index + 1) % count
+        } else {
+            (index + count - 1) % count
+        }
+    }
+
+    fn go_to_change(&self, forward: bool) {
+        let line = self.cursor_line();
+        let hunks = self.hunks.borrow();
+        if hunks.is_empty() {
+            self.status.set_text("No changes");
+            return;
+        }
+        let target = if forward {
+            hunks
+                .iter()
+                .find(|hunk| hunk.new_start > line)
+                .unwrap_or(&hunks[0])
+        } else {
+            hunks
+                .iter()
+                .rev()
+                .find(|hunk| hunk.new_start + hunk.new_len.max(1) <= line)
+                .unwrap_or(&hunks[hunks.len() - 1])
+        };
+        let buffer = self.text_view.buffer();
+        let iter = buffer
+            .iter_at_line(target.new_start as i32)
+            .unwrap_or_else(|| buffer.end_iter());
+        buffer.place_cursor(&iter);
+        self.text_view
+            .scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.3);
+        let index = hunks.iter().position(|hunk| hunk == target).unwrap_or(0);
+        self.status
+            .set_text(&format!("Change {} of {}", index + 1, hunks.len()));
+    }
+
+    fn show_hunk(&self, index: usize) {
+        let Some(hunk) = self.hunks.borrow().get(index).copied() else {
+            return;
+        };
+        let base = self.base.borrow();
+        let Some(base) = base.as_ref() else {
+            return;
+        };
+        let original: Vec<&str> = split_lines(&base.text)
+            .into_iter()
+            .skip(hunk.old_start)
+            .take(hunk.old_len)
+            .collect();
+        let summary = match (hunk.old_len, hunk.new_len) {
+            (0, added) => format!("{} added", lines(added)),
+            (removed, 0) => format!("{} removed", lines(removed)),
+            (removed, added) => format!("{} changed to {}", lines(removed), added),
+        };
+        self.popover_title.set_text(&format!(
+            "Change {} of {} — {}",
+            index + 1,
+            self.hunks.borrow().len(),
+            summary
+        ));
+        self.original.set_visible(!original.is_empty());
+        self.original.set_text(&original.join("\n"));
+        let buffer = self.text_view.buffer();
+        let last_line = (hunk.new_start + hunk.new_len).max(1) as i32 - 1;
+        let iter = buffer
+            .iter_at_line(last_line)
+            .unwrap_or_else(|| buffer.end_iter());
+        self.text_view
+            .scroll_to_iter(&mut iter.clone(), 0.0, false, 0.0, 0.0);
+        let location = self.text_view.iter_location(&iter);
+        let (x, y) = self.text_view.buffer_to_window_coords(
+            TextWindowType::Widget,
+            location.x(),
+            location.y() + location.height(),
+        );
+        self.popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(x.max(0), y, 1, 1)));
+        self.shown.set(Some(index));
+        self.popover.popup();
+    }
+
+    fn revert_hunk(&self, index: usize) {
+        let Some(hunk) = self.hunks.borrow().get(index).copied() else {
+            return;
+        };
+        let replacement = {
+            let base = self.base.borrow();
+            let Some(base) = base.as_ref() else {
+                return;
+            };
+            let lines = split_lines(&base.text);
+            let (start, end) = region(
+                &lines,
+                hunk.old_start,
+                hunk.old_start + hunk.old_len,
+                str::len,
+            );
+            base.text[start..end].to_owned()
+        };
+        let text = self.document.text();
+        let (start, end) = region(
+            &split_lines(&text),
+            hunk.new_start,
+            hunk.new_start + hunk.new_len,
+            |line| line.chars().count(),
+        );
+        let buffer = self.text_view.buffer();
+        let mut start = buffer.iter_at_offset(start as i32);
+        let mut end = buffer.iter_at_offset(end as i32);
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &replacement);
+        buffer.end_user_action();
+        buffer.place_cursor(&start);
+        self.popover.popdown();
+        self.status.set_text("Reverted change");
+    }
+
+    fn stage_hunk(self: &Rc<Self>, index: usize) {
+        let Some(hunk) = self.hunks.borrow().get(index).copied() else {
+            return;
+        };
+        let Some((root, relative)) = self
+            .base
+            .borrow()
+            .as_ref()
+            .map(|base| (base.root.clone(), base.relative.clone()))
+        else {
+            return;
+        };
+        let text = self.document.text();
+        let weak = Rc::downgrade(self);
+        let object = format!(":{}", relative);
+        git::run(&root.clone(), &["show", &object], None, move |result| {
+            let Some(git_gutter) = weak.upgrade() else {
+                return;
+            };
+            let index_text = result.unwrap_or_default();
+            let hunks: Vec<Hunk> = diff::diff(&split_lines(&index_text), &split_lines(&text))
+                .into_iter()
+                .filter(|staged| overlaps(staged, hunk.new_start, hunk.new_len))
+                .collect();
+            if hunks.is_empty() {
+                git_gutter.status.set_text("The change is already staged");
+                return;
+            }
+            let staged = replace_hunks(&index_text, &text, &hunks);
+            git_gutter.write_index(&root, relative, staged);
+        });
+    }
+
+    fn write_index(self: &Rc<Self>, root: &Path, relative: String, content: String) {
+        let weak = Rc::downgrade(self);
+        let directory = root.to_path_buf();
+        let listed = relative.clone();
+        git::run(
+            root,
+            &["ls-files", "-s", "--", &listed],
+            None,
+            move |result| {
+                let mode = result
+                    .ok()
+                    .and_then(|line| line.split_whitespace().next().map(str::to_owned))
+                    .unwrap_or_else(|| "100644".to_owned());
+                let root = directory.clone();
+                let hashed = relative.clone();
+                git::run(
+                    &directory,
+                    &["hash-object", "-w", "--stdin", "--path", &hashed],
+                    Some(&content),
+                    move |result| {
+                        let Some(git_gutter) = weak.upgrade() else {
+                            return;
+                        };
+                        let object = match result {
+                            Ok(object) => object.trim().to_owned(),
+                            Err(error) => {
+                                git_gutter
+                                    .notification
+                                    .show("Could not stage change", &error);
+                                return;
+                            }
+                        };
+                        let entry = format!("{},{},{}", mode, object, relative);
+                        git::run(
+                            &root,
+                            &["update-index", "--add", "--cacheinfo", &entry],
+                            None,
+                            move |result| {
+                                let Some(git_gutter) = weak.upgrade() else {
+                                    return;
+                                };
+                                match result {
+                                    Ok(_) => {
+                                        git_gutter.popover.popdown();
+                                        git_gutter.status.set_text("Staged change");
+                                        git_gutter.load_base();
+                                    }
+                                    Err(error) => git_gutter
+                                        .notification
+                                        .show("Could not stage change", &error),
+                                }
+                            },
+                        );
+                    },
+                );
+            },
+        );
+    }
+}
+
+fn lines(count: usize) -> String {
+    if count == 1 {
+        "1 line".to_owned()
+    } else {
+        format!("{} lines", count)
+    }
+}
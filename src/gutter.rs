@@ -0,0 +1,152 @@
+use std::{cell::RefCell, rc::Rc};
+
+use gtk4::{
+    DrawingArea, GestureClick, ScrolledWindow, TextIter, TextView, TextWindowType, cairo,
+    prelude::*,
+};
+
+type Painter = Box<dyn Fn(&cairo::Context, &TextIter, f64, f64, f64)>;
+type ClickHandler = Box<dyn Fn(&TextIter, u32)>;
+
+struct Column {
+    width: i32,
+    painter: Painter,
+    clicked: ClickHandler,
+}
+
+pub struct Gutter {
+    area: DrawingArea,
+    text_view: TextView,
+    columns: RefCell<Vec<Column>>,
+}
+
+impl Gutter {
+    pub fn new(text_view: &TextView, scrolled_window: &ScrolledWindow) -> Rc<Gutter> {
+        let area = DrawingArea::builder()
+            .width_request(0)
+            .name("gutter")
+            .build();
+        text_view.set_gutter(TextWindowType::Left, Some(&area));
+        let gutter = Rc::new(Gutter {
+            area: area.clone(),
+            text_view: text_view.clone(),
+            columns: RefCell::new(Vec::new()),
+        });
+        let weak = Rc::downgrade(&gutter);
+        area.set_draw_func(move |_, context, _, _| {
+            if let Some(gutter) = weak.upgrade() {
+                gutter.draw(context);
+            }
+        });
+        let adjustment = scrolled_window.vadjustment();
+        let weak_area = area.downgrade();
+        let redraw = move |_: &gtk4::Adjustment| {
+            if let Some(area) = weak_area.upgrade() {
+                area.queue_draw();
+            }
+        };
+        adjustment.connect_value_changed(redraw.clone());
+        adjustment.connect_changed(redraw);
+        let weak_area = area.downgrade();
+        text_view.buffer().connect_changed(move |_| {
+            if let Some(area) = weak_area.upgrade() {
+                area.queue_draw();
+            }
+        });
+        let click = GestureClick::builder().button(0).build();
+        let weak = Rc::downgrade(&gutter);
+        click.connect_pressed(move |gesture, _, x, y| {
+            if let Some(gutter) = weak.upgrade() {
+                gutter.pressed(gesture.current_button(), x, y);
+            }
+        });
+        area.add_controller(click);
+        gutter
+    }
+
+    pub fn add_column(
+        &self,
+        width: i32,
+        painter: impl Fn(&cairo::Context, &TextIter, f64, f64, f64) + 'static,
+        clicked: impl Fn(&TextIter, u32) + 'static,
+    ) -> usize {
+        self.columns.borrow_mut().push(Column {
+            width,
+            painter: Box::new(painter),
+            clicked: Box::new(clicked),
+        });
+        self.update_width();
+        self.columns.borrow().len() - 1
+    }
+
+    pub fn set_column_width(&self, index: usize, width: i32) {
+        if let Some(column) = self.columns.borrow_mut().get_mut(index) {
+            column.width = width;
+        }
+        self.update_width();
+        self.area.queue_draw();
+    }
+
+    fn update_width(&self) {
+        self.area.set_width_request(
+            self.columns
+                .borrow()
+                .iter()
+                .map(|column| column.width)
+                .sum(),
+        );
+    }
+
+    pub fn queue_draw(&self) {
+        self.area.queue_draw();
+    }
+
+    fn draw(&self, context: &cairo::Context) {
+        let visible = self.text_view.visible_rect();
+        let bottom = visible.y() + visible.height();
+        let (mut line_start, _) = self.text_view.line_at_y(visible.y());
+        let columns = self.columns.borrow();
+        loop {
+            let (y, height) = self.text_view.line_yrange(&line_start);
+            if y > bottom {
+                break;
+            }
+            let (_, window_y) = self
+                .text_view
+                .buffer_to_window_coords(TextWindowType::Left, 0, y);
+            let mut x = 0;
+            for column in columns.iter() {
+                context.save().ok();
+                context.translate(x as f64, 0.0);
+                (column.painter)(
+                    context,
+                    &line_start,
+                    column.width as f64,
+                    window_y as f64,
+                    height as f64,
+                );
+                context.restore().ok();
+                x += column.width;
+            }
+            if !line_start.forward_line() {
+                break;
+            }
+        }
+    }
+
+    fn pressed(&self, button: u32, x: f64, y: f64) {
+        let (_, buffer_y) =
+            self.text_view
+                .window_to_buffer_coords(TextWindowType::Left, 0, y as i32);
+        let (line_start, _) = self.text_view.line_at_y(buffer_y);
+        let mut left = 0.0;
+        for column in self.columns.borrow().iter() {
+            let right = left + column.width as f64;
+            if x >= left && x < right {
+                (column.clicked)(&line_start, button);
+                return;
+            }
+            left = right;
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Button, Label, ListBox, Orientation, PolicyType, ScrolledWindow, SelectionMode,
+    TextBuffer, TextView, Window, pango, prelude::*,
+};
+
+use crate::{
+    commands::CommandRegistry,
+    diff_view::{DiffViewer, Side},
+    document::{self, Document},
+    git, highlight,
+    notification::Notification,
+};
+
+struct Entry {
+    hash: String,
+    short: String,
+    author: String,
+    date: String,
+    summary: String,
+    path: String,
+}
+
+fn parse_log(output: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('\x1f').collect();
+        if let [hash, short, author, date, summary] = fields.as_slice() {
+            entries.push(Entry {
+                hash: hash.to_string(),
+                short: short.to_string(),
+                author: author.to_string(),
+                date: date.to_string(),
+                summary: summary.to_string(),
+                path: String::new(),
+            });
+        } else if !line.is_empty()
+            && let Some(entry) = entries.last_mut()
+        {
+            entry.path = line.to_owned();
+        }
+    }
+    entries
+}
+
+pub struct History {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    diff_viewer: Rc<DiffViewer>,
+    widget: Box,
+    title: Label,
+    list: ListBox,
+    entries: RefCell<Vec<Entry>>,
+    root: RefCell<Option<PathBuf>>,
+    generation: Cell<u64>,
+}
+
+impl History {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        commands: &CommandRegistry,
+        diff_viewer: &Rc<DiffViewer>,
+    ) -> Rc<History> {
+        let title = Label::builder().hexpand(true).xalign(0.0).build();
+        let close = Button::builder().label("✕").has_frame(false).build();
+        let header = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&title);
+        header.append(&close);
+        let list = ListBox::builder()
+            .selection_mode(SelectionMode::Browse)
+            .build();
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&list)
+            .hscrollbar_policy(PolicyType::Automatic)
+            .vexpand(true)
+            .build();
+        let widget = Box::builder()
+            .orientation(Orientation::Vertical)
+            .height_request(200)
+            .name("history_panel")
+            .visible(false)
+            .build();
+        widget.append(&header);
+        widget.append(&scrolled_window);
+        let history = Rc::new(History {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            diff_viewer: diff_viewer.clone(),
+            widget,
+            title,
+            list: list.clone(),
+            entries: RefCell::new(Vec::new()),
+            root: RefCell::new(None),
+            generation: Cell::new(0),
+        });
+        let weak = Rc::downgrade(&history);
+        close.connect_clicked(move |_| {
+            if let Some(history) = weak.upgrade() {
+                history.widget.set_visible(false);
+            }
+        });
+        let weak = Rc::downgrade(&history);
+        list.connect_row_activated(move |_, row| {
+            if let Some(history) = weak.upgrade() {
+                history.open_entry(row.index() as usize);
+            }
+        });
+        let weak = Rc::downgrade(&history);
+        document.connect_opened(move |_| {
+            if let Some(history) = weak.upgrade()
+                && history.widget.is_visible()
+            {
+                history.show();
+            }
+        });
+        let showing = history.clone();
+        commands.register("git.history", "Show File History", move || {
+            if showing.widget.is_visible() {
+                showing.widget.set_visible(false);
+            } else {
+                showing.show();
+            }
+        });
+        history
+    }
+
+    pub fn widget(&self) -> &Box {
+        &self.widget
+    }
+
+    fn show(self: &Rc<Self>) {
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        let path = self.document.path();
+        let Some(root) = git::repository_root(&path) else {
+            self.status.set_text("The file is not in a git repository");
+            return;
+        };
+        let relative = git::relative_path(&root, &path);
+        let weak = Rc::downgrade(self);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.root.replace(Some(root.clone()));
+        git::run(
+            &root,
+            &[
+                "log",
+                "--follow",
+                "--name-only",
+                "--date=short",
+                "--format=%H%x1f%h%x1f%an%x1f%ad%x1f%s",
+                "--",
+                &relative,
+            ],
+            None,
+            move |result| {
+                let Some(history) = weak.upgrade() else {
+                    return;
+                };
+                if history.generation.get() != generation {
+                    return;
+                }
+                match result {
+                    Ok(output) => history.set_entries(&name, parse_log(&output)),
+                    Err(error) => history
+                        .notification
+                        .show("Could not read the file history", &error),
+                }
+            },
+        );
+    }
+
+    fn set_entries(self: &Rc<Self>, name: &str, entries: Vec<Entry>) {
+        self.list.remove_all();
+        for (index, entry) in entries.iter().enumerate() {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(12)
+                .build();
+            for detail in [&entry.short, &entry.date] {
+                let label = Label::new(Some(detail));
+                label.add_css_class("dim-label");
+                row.append(&label);
+            }
+            row.append(&Label::new(Some(&entry.author)));
+            row.append(
+                &Label::builder()
+                    .label(&entry.summary)
+                    .hexpand(true)
+                    .xalign(0.0)
+                    .ellipsize(pango::EllipsizeMode::End)
+                    .build(),
+            );
+            let compare = Button::builder().label("Compare").has_frame(false).build();
+            let weak = Rc::downgrade(self);
+            compare.connect_clicked(move |_| {
+                if let Some(history) = weak.upgrade() {
+                    history.compare_entry(index);
+                }
+            });
+            row.append(&compare);
+            self.list.append(&row);
+        }
+        self.title
+            .set_text(&format!("History of {} ({} commits)", name, entries.len()));
+        self.entries.replace(entries);
+        self.widget.set_visible(true);
+    }
+
+    fn open_entry(&self, index: usize) {
+        let root = self.root.borrow().clone();
+        if let (Some(root), Some((hash, path))) = (
+            root,
+            self.entries
+                .borrow()
+                .get(index)
+                .map(|entry| (entry.hash.clone(), entry.path.clone())),
+        ) {
+            self.open_revision(&root, &hash, &path);
+        }
+    }
+
+    pub fn open_revision(&self, root: &Path, hash: &str, path: &str) {
+        let short: String = hash.chars().take(7).collect();
+        let title = format!("{} @ {}", path, short);
+        let language = document::language_for_path(Path::new(path));
+        let object = format!("{}:{}", hash, path);
+        let text_view = self.text_view.clone();
+        let notification = self.notification.clone();
+        git::run(root, &["show", &object], None, move |result| match result {
+            Ok(text) => {
+                let buffer = TextBuffer::new(Some(&text_view.buffer().tag_table()));
+                buffer.set_text(&text);
+                highlight::highlight(&buffer, language);
+                open_window(&text_view, &title, &buffer);
+            }
+            Err(error) => notification.show(&format!("Could not open {}", title), &error),
+        });
+    }
+
+    fn compare_entry(&self, index: usize) {
+        let Some(root) = self.root.borrow().clone() else {
+            return;
+        };
+        let Some((hash, short, path)) = self
+            .entries
+            .borrow()
+            .get(index)
+            .map(|entry| (entry.hash.clone(), entry.short.clone(), entry.path.clone()))
+        else {
+            return;
+        };
+        let title = format!("{} @ {}", path, short);
+        let language = document::language_for_path(Path::new(&path));
+        let object = format!("{}:{}", hash, path);
+        let diff_viewer = self.diff_viewer.clone();
+        let notification = self.notification.clone();
+        git::run(
+            &root,
+            &["show", &object],
+            None,
+            move |result| match result {
+                Ok(text) => {
+                    diff_viewer.compare_with_buffer(Side::snapshot(&title, &text, language))
+                }
+                Err(error) => notification.show(&format!("Could not compare {}", title), &error),
+            },
+        );
+    }
+}
+
+fn open_window(text_view: &TextView, title: &str, buffer: &TextBuffer) {
+    let view = TextView::builder()
+        .buffer(buffer)
+        .editable(false)
+        .monospace(true)
+        .name("revision_view")
+        .left_margin(10)
+        .top_margin(10)
+        .bottom_margin(10)
+        .right_margin(10)
+        .build();
+    view.set_tabs(
+        &text_view
+            .tabs()
+            .unwrap_or_else(|| pango::TabArray::new(0, true)),
+    );
+    let window = Window::builder()
+        .title(title)
+        .default_width(1000)
+        .default_height(800)
+        .child(
+            &ScrolledWindow::builder()
+                .child(&view)
+                .hscrollbar_policy(PolicyType::Automatic)
+                .build(),
+        )
+        .build();
+    if let Some(parent) = text_view.root().and_downcast::<Window>() {
+        window.set_transient_for(Some(&parent));
+    }
+    window.present();
+}
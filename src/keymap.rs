@@ -0,0 +1,610 @@
+use std::{cell::RefCell, fs, path::PathBuf, rc::Rc};
+
+use gtk4::{
+    EventControllerKey, Label, PropagationPhase, Widget, gdk, gio, glib::Propagation, prelude::*,
+};
+
+use crate::{commands::CommandRegistry, settings};
+
+const DEFAULT_PRESET: &[(&str, &str)] = &[
+    ("ctrl+s", "file.save"),
+    ("ctrl+shift+p", "palette.open"),
+    ("ctrl+g", "view.go_to_line"),
+    ("ctrl+space", "completion.trigger"),
+    ("ctrl+shift+o", "outline.go_to_symbol"),
+    ("ctrl+t", "workspace.symbols"),
+    ("f12", "navigate.definition"),
+    ("shift+f12", "navigate.references"),
+    ("f2", "edit.rename"),
+    ("ctrl+shift+i", "format.document"),
+    ("ctrl+k ctrl+f", "format.selection"),
+    ("ctrl+period", "lsp.code_actions"),
+    ("ctrl+k ctrl+i", "lsp.hover"),
+    ("ctrl+shift+space", "lsp.signature_help"),
+    ("ctrl+shift+m", "lsp.diagnostics"),
+    ("ctrl+shift+b", "tasks.run"),
+    ("ctrl+alt+b", "tasks.rerun"),
+    ("ctrl+k ctrl+x", "tasks.cancel"),
+    ("ctrl+shift+u", "tasks.output"),
+    ("ctrl+k ctrl+m", "tasks.problems"),
+    ("f5", "debug.continue"),
+    ("shift+f5", "debug.stop"),
+    ("ctrl+shift+f5", "debug.restart"),
+    ("f6", "debug.pause"),
+    ("f10", "debug.step_over"),
+    ("f11", "debug.step_into"),
+    ("shift+f11", "debug.step_out"),
+    ("ctrl+k ctrl+b", "debug.toggle_breakpoint"),
+    ("ctrl+k ctrl+shift+b", "debug.conditional_breakpoint"),
+    ("alt+f5", "git.next_change"),
+    ("alt+shift+f5", "git.previous_change"),
+    ("ctrl+k ctrl+g", "git.show_change"),
+    ("ctrl+k ctrl+u", "git.revert_change"),
+    ("ctrl+k ctrl+s", "git.stage_change"),
+    ("ctrl+k ctrl+a", "git.blame"),
+    ("ctrl+k ctrl+h", "git.history"),
+    ("alt+f7", "conflict.next"),
+    ("alt+shift+f7", "conflict.previous"),
+    ("ctrl+k ctrl+o", "conflict.accept_ours"),
+    ("ctrl+k ctrl+t", "conflict.accept_theirs"),
+    ("ctrl+k ctrl+shift+o", "conflict.accept_both"),
+    ("ctrl+k ctrl+d", "diff.with_saved"),
+    ("ctrl+k ctrl+shift+m", "diff.mark_selection"),
+    ("ctrl+k ctrl+shift+d", "diff.with_marked"),
+    ("alt+up", "edit.move_lines_up"),
+    ("alt+down", "edit.move_lines_down"),
+    ("ctrl+shift+d", "edit.duplicate_lines"),
+    ("ctrl+shift+k", "edit.delete_lines"),
+    ("ctrl+j", "edit.join_lines"),
+    ("f9", "edit.sort_lines"),
+    ("ctrl+f9", "edit.sort_lines_natural"),
+    ("shift+f9", "edit.sort_lines_unique"),
+    ("ctrl+shift+f9", "edit.sort_lines_natural_unique"),
+    ("alt+f9", "edit.reverse_lines"),
+    ("ctrl+f2", "bookmarks.toggle"),
+    ("alt+page_down", "bookmarks.next"),
+    ("alt+page_up", "bookmarks.previous"),
+    ("ctrl+k ctrl+r", "keymap.reload"),
+    ("ctrl+k ctrl+v", "vim.toggle"),
+    ("ctrl+grave", "terminal.toggle"),
+    ("ctrl+alt+grave", "terminal.new"),
+    ("ctrl+page_down", "terminal.next"),
+    ("ctrl+page_up", "terminal.previous"),
+];
+
+const EMACS_PRESET: &[(&str, &str)] = &[
+    ("ctrl+x ctrl+s", "file.save"),
+    ("ctrl+x ctrl+c", "app.quit"),
+    ("alt+x", "palette.open"),
+    ("alt+g g", "view.go_to_line"),
+    ("ctrl+slash", "edit.undo"),
+    ("ctrl+shift+question", "edit.redo"),
+    ("alt+slash", "completion.trigger"),
+    ("alt+g i", "outline.go_to_symbol"),
+    ("alt+period", "navigate.definition"),
+    ("alt+shift+question", "navigate.references"),
+    ("ctrl+x t", "workspace.symbols"),
+    ("ctrl+c r", "edit.rename"),
+    ("ctrl+c f", "format.document"),
+    ("ctrl+c shift+f", "format.selection"),
+    ("ctrl+c a", "lsp.code_actions"),
+    ("ctrl+c h", "lsp.hover"),
+    ("ctrl+c s", "lsp.signature_help"),
+    ("ctrl+c e", "lsp.diagnostics"),
+    ("ctrl+c c", "tasks.run"),
+    ("ctrl+c shift+c", "tasks.rerun"),
+    ("ctrl+c k", "tasks.cancel"),
+    ("ctrl+c o", "tasks.output"),
+    ("ctrl+c shift+e", "tasks.problems"),
+    ("f5", "debug.continue"),
+    ("shift+f5", "debug.stop"),
+    ("ctrl+shift+f5", "debug.restart"),
+    ("f6", "debug.pause"),
+    ("f10", "debug.step_over"),
+    ("f11", "debug.step_into"),
+    ("shift+f11", "debug.step_out"),
+    ("ctrl+x space", "debug.toggle_breakpoint"),
+    ("ctrl+x shift+space", "debug.conditional_breakpoint"),
+    ("ctrl+x v bracketright", "git.next_change"),
+    ("ctrl+x v bracketleft", "git.previous_change"),
+    ("ctrl+x v equal", "git.show_change"),
+    ("ctrl+x v n", "git.revert_change"),
+    ("ctrl+x v s", "git.stage_change"),
+    ("ctrl+x v g", "git.blame"),
+    ("ctrl+x v l", "git.history"),
+    ("ctrl+c shift+asciicircum n", "conflict.next"),
+    ("ctrl+c shift+asciicircum p", "conflict.previous"),
+    ("ctrl+c shift+asciicircum u", "conflict.accept_ours"),
+    ("ctrl+c shift+asciicircum l", "conflict.accept_theirs"),
+    ("ctrl+c shift+asciicircum a", "conflict.accept_both"),
+    ("ctrl+c d", "diff.with_saved"),
+    ("ctrl+c shift+d", "diff.with_marked"),
+    ("ctrl+a", "cursor.line_start"),
+    ("ctrl+e", "cursor.line_end"),
+    ("ctrl+n", "cursor.next_line"),
+    ("ctrl+p", "cursor.previous_line"),
+    ("ctrl+f", "cursor.forward_char"),
+    ("ctrl+b", "cursor.backward_char"),
+    ("alt+f", "cursor.forward_word"),
+    ("alt+b", "cursor.backward_word"),
+    ("alt+shift+less", "cursor.buffer_start"),
+    ("alt+shift+greater", "cursor.buffer_end"),
+    ("ctrl+k", "edit.delete_lines"),
+    ("alt+up", "edit.move_lines_up"),
+    ("alt+down", "edit.move_lines_down"),
+    ("alt+j", "edit.join_lines"),
+    ("ctrl+x ctrl+d", "edit.duplicate_lines"),
+    ("ctrl+x s", "edit.sort_lines"),
+    ("ctrl+x shift+s", "edit.sort_lines_natural"),
+    ("ctrl+x u", "edit.sort_lines_unique"),
+    ("ctrl+x r r", "edit.reverse_lines"),
+    ("ctrl+x r m", "bookmarks.toggle"),
+    ("ctrl+x r n", "bookmarks.next"),
+    ("ctrl+x r p", "bookmarks.previous"),
+    ("ctrl+x ctrl+r", "keymap.reload"),
+    ("ctrl+x ctrl+v", "vim.toggle"),
+    ("ctrl+grave", "terminal.toggle"),
+    ("ctrl+alt+grave", "terminal.new"),
+    ("ctrl+page_down", "terminal.next"),
+    ("ctrl+page_up", "terminal.previous"),
+];
+
+const VIM_LEADER_PRESET: &[(&str, &str)] = &[
+    ("space w", "file.save"),
+    ("space q", "app.quit"),
+    ("space space", "palette.open"),
+    ("space g", "view.go_to_line"),
+    ("space o", "outline.go_to_symbol"),
+    ("space i", "navigate.definition"),
+    ("space shift+i", "navigate.references"),
+    ("space t", "workspace.symbols"),
+    ("space c", "edit.rename"),
+    ("space f", "format.document"),
+    ("space shift+f", "format.selection"),
+    ("space a", "lsp.code_actions"),
+    ("space h", "lsp.hover"),
+    ("space shift+h", "lsp.signature_help"),
+    ("space e", "lsp.diagnostics"),
+    ("space b", "tasks.run"),
+    ("space shift+b", "tasks.rerun"),
+    ("space x", "tasks.cancel"),
+    ("space shift+o", "tasks.output"),
+    ("space shift+e", "tasks.problems"),
+    ("f5", "debug.continue"),
+    ("shift+f5", "debug.stop"),
+    ("ctrl+shift+f5", "debug.restart"),
+    ("f6", "debug.pause"),
+    ("f10", "debug.step_over"),
+    ("f11", "debug.step_into"),
+    ("shift+f11", "debug.step_out"),
+    ("space shift+d", "debug.toggle_breakpoint"),
+    ("space shift+c", "debug.conditional_breakpoint"),
+    ("space bracketright", "git.next_change"),
+    ("space bracketleft", "git.previous_change"),
+    ("space shift+g", "git.show_change"),
+    ("space shift+u", "git.revert_change"),
+    ("space shift+a", "git.stage_change"),
+    ("space shift+l", "git.blame"),
+    ("space l", "git.history"),
+    ("space shift+n", "conflict.next"),
+    ("space shift+p", "conflict.previous"),
+    ("space shift+w", "diff.with_saved"),
+    ("space d", "edit.duplicate_lines"),
+    ("space k", "edit.move_lines_up"),
+    ("space j", "edit.move_lines_down"),
+    ("space s", "edit.sort_lines"),
+    ("space shift+s", "edit.sort_lines_natural"),
+    ("space u", "edit.sort_lines_unique"),
+    ("space r", "edit.reverse_lines"),
+    ("space m", "bookmarks.toggle"),
+    ("space n", "bookmarks.next"),
+    ("space p", "bookmarks.previous"),
+    ("space shift+r", "keymap.reload"),
+    ("space v", "vim.toggle"),
+    ("ctrl+grave", "terminal.toggle"),
+    ("ctrl+alt+grave", "terminal.new"),
+    ("ctrl+page_down", "terminal.next"),
+    ("ctrl+page_up", "terminal.previous"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyStroke {
+    key: gdk::Key,
+    modifiers: gdk::ModifierType,
+}
+
+fn binding_modifiers() -> gdk::ModifierType {
+    gdk::ModifierType::CONTROL_MASK
+        | gdk::ModifierType::SHIFT_MASK
+        | gdk::ModifierType::ALT_MASK
+        | gdk::ModifierType::SUPER_MASK
+}
+
+fn key_from_name(name: &str) -> Option<gdk::Key> {
+    let lowercase = name.to_lowercase();
+    let alias = match lowercase.as_str() {
+        "up" => "Up",
+        "down" => "Down",
+        "left" => "Left",
+        "right" => "Right",
+        "pageup" | "page_up" | "pgup" => "Page_Up",
+        "pagedown" | "page_down" | "pgdn" => "Page_Down",
+        "home" => "Home",
+        "end" => "End",
+        "enter" | "return" => "Return",
+        "escape" | "esc" => "Escape",
+        "tab" => "Tab",
+        "space" => "space",
+        "delete" | "del" => "Delete",
+        "backspace" => "BackSpace",
+        "insert" | "ins" => "Insert",
+        _ => "",
+    };
+    if !alias.is_empty() {
+        return gdk::Key::from_name(alias);
+    }
+    if let Some(number) = lowercase.strip_prefix('f')
+        && number.parse::<u32>().is_ok()
+    {
+        return gdk::Key::from_name(format!("F{}", number));
+    }
+    gdk::Key::from_name(lowercase.as_str()).or_else(|| gdk::Key::from_name(name))
+}
+
+impl KeyStroke {
+    pub fn parse(text: &str) -> Option<KeyStroke> {
+        let mut modifiers = gdk::ModifierType::empty();
+        let mut parts: Vec<&str> = text.split('+').collect();
+        if text.ends_with("++") {
+            parts.truncate(parts.len() - 2);
+            parts.push("plus");
+        }
+        let key_name = parts.pop()?;
+        for part in parts {
+            modifiers |= match part.trim().to_lowercase().as_str() {
+                "ctrl" | "control" => gdk::ModifierType::CONTROL_MASK,
+                "shift" => gdk::ModifierType::SHIFT_MASK,
+                "alt" | "meta" => gdk::ModifierType::ALT_MASK,
+                "super" | "cmd" => gdk::ModifierType::SUPER_MASK,
+                _ => return None,
+            };
+        }
+        let key = key_from_name(key_name.trim())?;
+        Some(KeyStroke {
+            key: key.to_lower(),
+            modifiers,
+        })
+    }
+
+    fn from_event(keyval: gdk::Key, modifiers: gdk::ModifierType) -> Option<KeyStroke> {
+        let name = keyval.name()?;
+        if [
+            "Shift",
+            "Control",
+            "Alt",
+            "Super",
+            "Meta",
+            "Hyper",
+            "ISO_Level",
+        ]
+        .iter()
+        .any(|modifier| name.starts_with(modifier))
+        {
+            return None;
+        }
+        Some(KeyStroke {
+            key: keyval.to_lower(),
+            modifiers: modifiers & binding_modifiers(),
+        })
+    }
+
+    pub fn label(&self) -> String {
+        let mut label = String::new();
+        for (modifier, name) in [
+            (gdk::ModifierType::CONTROL_MASK, "Ctrl+"),
+            (gdk::ModifierType::SUPER_MASK, "Super+"),
+            (gdk::ModifierType::ALT_MASK, "Alt+"),
+            (gdk::ModifierType::SHIFT_MASK, "Shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                label.push_str(name);
+            }
+        }
+        let name = self
+            .key
+            .name()
+            .map(|name| name.to_string())
+            .unwrap_or_default();
+        match name.chars().count() {
+            1 => label.push_str(&name.to_uppercase()),
+            _ => label.push_str(&name),
+        }
+        label
+    }
+}
+
+pub fn sequence_label(keys: &[KeyStroke]) -> String {
+    keys.iter()
+        .map(KeyStroke::label)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Clone)]
+pub struct Binding {
+    pub keys: Vec<KeyStroke>,
+    pub command: String,
+}
+
+pub fn preset(name: &str) -> Option<Vec<(&'static str, &'static str)>> {
+    match name {
+        "default" => Some(DEFAULT_PRESET.to_vec()),
+        "emacs" => Some(EMACS_PRESET.to_vec()),
+        "vim-leader" => Some([DEFAULT_PRESET, VIM_LEADER_PRESET].concat()),
+        _ => None,
+    }
+}
+
+fn parse_sequence(text: &str) -> Option<Vec<KeyStroke>> {
+    let keys: Option<Vec<KeyStroke>> = text.split_whitespace().map(KeyStroke::parse).collect();
+    keys.filter(|keys| !keys.is_empty())
+}
+
+pub fn keymap_path() -> PathBuf {
+    settings::config_dir().join("keymap.toml")
+}
+
+pub fn load_bindings(commands: &CommandRegistry) -> (Vec<Binding>, Vec<String>) {
+    let mut problems = Vec::new();
+    let table = match fs::read_to_string(keymap_path()) {
+        Ok(content) => match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(error) => {
+                problems.push(format!("Could not parse keymap.toml: {}", error));
+                toml::Table::new()
+            }
+        },
+        Err(_) => toml::Table::new(),
+    };
+    let preset_name = table
+        .get("preset")
+        .and_then(|value| value.as_str())
+        .unwrap_or("default");
+    let preset_bindings = preset(preset_name).unwrap_or_else(|| {
+        problems.push(format!("Unknown keymap preset \"{}\"", preset_name));
+        DEFAULT_PRESET.to_vec()
+    });
+    let mut bindings: Vec<Binding> = preset_bindings
+        .iter()
+        .filter_map(|(keys, command)| {
+            Some(Binding {
+                keys: parse_sequence(keys)?,
+                command: command.to_string(),
+            })
+        })
+        .collect();
+    let mut user_bindings: Vec<Binding> = Vec::new();
+    let entries = table
+        .get("bindings")
+        .and_then(|value| value.as_array())
+        .cloned()
+        .unwrap_or_default();
+    for entry in entries {
+        let keys_text = entry.get("keys").and_then(|value| value.as_str());
+        let command = entry.get("command").and_then(|value| value.as_str());
+        let (Some(keys_text), Some(command)) = (keys_text, command) else {
+            problems.push("Keymap entries need both \"keys\" and \"command\"".to_owned());
+            continue;
+        };
+        let Some(keys) = parse_sequence(keys_text) else {
+            problems.push(format!("Could not parse key sequence \"{}\"", keys_text));
+            continue;
+        };
+        if !command.is_empty() && !commands.contains(command) {
+            problems.push(format!(
+                "Unknown command \"{}\" bound to {}",
+                command, keys_text
+            ));
+            continue;
+        }
+        if let Some(existing) = user_bindings.iter().find(|binding| binding.keys == keys)
+            && existing.command != command
+        {
+            problems.push(format!(
+                "{} is bound to both \"{}\" and \"{}\"",
+                sequence_label(&keys),
+                existing.command,
+                command
+            ));
+        }
+        user_bindings.push(Binding {
+            keys,
+            command: command.to_owned(),
+        });
+    }
+    for user_binding in user_bindings {
+        bindings.retain(|binding| binding.keys != user_binding.keys);
+        if !user_binding.command.is_empty() {
+            bindings.push(user_binding);
+        }
+    }
+    for binding in &bindings {
+        for other in &bindings {
+            if other.keys.len() > binding.keys.len() && other.keys.starts_with(&binding.keys) {
+                problems.push(format!(
+                    "{} (\"{}\") shadows {} (\"{}\")",
+                    sequence_label(&other.keys),
+                    other.command,
+                    sequence_label(&binding.keys),
+                    binding.command
+                ));
+            }
+        }
+    }
+    (bindings, problems)
+}
+
+type BindingFilter = Box<dyn Fn(&str) -> bool>;
+
+pub struct Keymap {
+    commands: CommandRegistry,
+    status: Label,
+    bindings: RefCell<Vec<Binding>>,
+    pending: RefCell<Vec<KeyStroke>>,
+    plain_keys_allowed: RefCell<Option<Box<dyn Fn() -> bool>>>,
+    binding_filter: RefCell<Option<BindingFilter>>,
+    monitor: RefCell<Option<gio::FileMonitor>>,
+}
+
+impl Keymap {
+    pub fn new(commands: &CommandRegistry, status: &Label) -> Rc<Keymap> {
+        let keymap = Rc::new(Keymap {
+            commands: commands.clone(),
+            status: status.clone(),
+            bindings: RefCell::new(Vec::new()),
+            pending: RefCell::new(Vec::new()),
+            plain_keys_allowed: RefCell::new(None),
+            binding_filter: RefCell::new(None),
+            monitor: RefCell::new(None),
+        });
+        let reloading = Rc::downgrade(&keymap);
+        commands.register("keymap.reload", "Reload Keymap", move || {
+            if let Some(keymap) = reloading.upgrade() {
+                keymap.reload();
+            }
+        });
+        let path = keymap_path();
+        if let Ok(monitor) = gio::File::for_path(&path)
+            .monitor_file(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE)
+        {
+            let watching = Rc::downgrade(&keymap);
+            monitor.connect_changed(move |_, _, _, event| {
+                if matches!(
+                    event,
+                    gio::FileMonitorEvent::ChangesDoneHint
+                        | gio::FileMonitorEvent::Created
+                        | gio::FileMonitorEvent::Deleted
+                ) && let Some(keymap) = watching.upgrade()
+                {
+                    keymap.reload();
+                }
+            });
+            keymap.monitor.replace(Some(monitor));
+        }
+        keymap
+    }
+
+    pub fn attach(self: &Rc<Self>, widget: &impl IsA<Widget>) {
+        let controller = EventControllerKey::new();
+        controller.set_propagation_phase(PropagationPhase::Capture);
+        let keymap = self.clone();
+        controller.connect_key_pressed(move |_, keyval, _, modifiers| {
+            keymap.key_pressed(keyval, modifiers)
+        });
+        widget.add_controller(controller);
+    }
+
+    pub fn set_plain_keys_allowed(&self, allowed: impl Fn() -> bool + 'static) {
+        self.plain_keys_allowed.replace(Some(Box::new(allowed)));
+    }
+
+    pub fn set_binding_filter(&self, filter: impl Fn(&str) -> bool + 'static) {
+        self.binding_filter.replace(Some(Box::new(filter)));
+    }
+
+    pub fn reload(&self) {
+        let (bindings, problems) = load_bindings(&self.commands);
+        for problem in &problems {
+            eprintln!("keymap: {}", problem);
+        }
+        self.bindings.replace(bindings);
+        self.pending.borrow_mut().clear();
+        match problems.len() {
+            0 => self.status.set_text(""),
+            1 => self.status.set_text(&problems[0]),
+            count => self.status.set_text(&format!(
+                "{} (and {} more keymap problems)",
+                problems[0],
+                count - 1
+            )),
+        }
+    }
+
+    pub fn bindings_for(&self, command: &str) -> Vec<String> {
+        self.bindings
+            .borrow()
+            .iter()
+            .filter(|binding| binding.command == command)
+            .map(|binding| sequence_label(&binding.keys))
+            .collect()
+    }
+
+    fn key_pressed(&self, keyval: gdk::Key, modifiers: gdk::ModifierType) -> Propagation {
+        let Some(stroke) = KeyStroke::from_event(keyval, modifiers) else {
+            return Propagation::Proceed;
+        };
+        let mut sequence = self.pending.borrow().clone();
+        if !sequence.is_empty() && stroke.key == gdk::Key::Escape && stroke.modifiers.is_empty() {
+            self.pending.borrow_mut().clear();
+            self.status.set_text("");
+            return Propagation::Stop;
+        }
+        if sequence.is_empty()
+            && (stroke.modifiers - gdk::ModifierType::SHIFT_MASK).is_empty()
+            && keyval.to_unicode().is_some()
+            && !self
+                .plain_keys_allowed
+                .borrow()
+                .as_ref()
+                .is_some_and(|allowed| allowed())
+        {
+            return Propagation::Proceed;
+        }
+        sequence.push(stroke);
+        let (is_prefix, command) = {
+            let bindings = self.bindings.borrow();
+            let filter = self.binding_filter.borrow();
+            let bindings: Vec<&Binding> = bindings
+                .iter()
+                .filter(|binding| {
+                    filter
+                        .as_ref()
+                        .is_none_or(|allowed| allowed(&binding.command))
+                })
+                .collect();
+            let is_prefix = bindings.iter().any(|binding| {
+                binding.keys.len() > sequence.len() && binding.keys.starts_with(&sequence)
+            });
+            let command = bindings
+                .iter()
+                .find(|binding| binding.keys == sequence)
+                .map(|binding| binding.command.clone());
+            (is_prefix, command)
+        };
+        if is_prefix {
+            self.status
+                .set_text(&format!("{} —", sequence_label(&sequence)));
+            self.pending.replace(sequence);
+            return Propagation::Stop;
+        }
+        let was_pending = sequence.len() > 1;
+        self.pending.borrow_mut().clear();
+        match command {
+            Some(command) => {
+                if was_pending {
+                    let title = self.commands.title(&command).unwrap_or(command.clone());
+                    self.status
+                        .set_text(&format!("{}: {}", sequence_label(&sequence), title));
+                }
+                self.commands.run(&command);
+                Propagation::Stop
+            }
+            None if was_pending => {
+                self.status
+                    .set_text(&format!("{} is not bound", sequence_label(&sequence)));
+                Propagation::Stop
+            }
+            None => Propagation::Proceed,
+        }
+    }
+}
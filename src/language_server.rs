@@ -0,0 +1,1642 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+    path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
+
+use gtk4::{
+    EventControllerKey, Label, PolicyType, Popover, PositionType, PropagationPhase, ScrolledWindow,
+    TextIter, TextView, TextWindowType, gdk,
+    glib::{self, Propagation},
+    pango,
+    prelude::*,
+};
+use serde_json::{Value, json};
+
+use crate::{
+    commands::{Argument, CommandRegistry},
+    completion::{Completion, ProvidedItem},
+    document::Document,
+    format,
+    lsp::{self, Client},
+    notification::Notification,
+    results::{self, Location, ResultItem, ResultsPanel},
+    semantic_tokens, settings, workspace,
+};
+
+const TAB_SIZE: i32 = 3;
+const SEMANTIC_TOKENS_DELAY: Duration = Duration::from_millis(300);
+const DIAGNOSTIC_TAGS: &[&str] = &["lsp_error", "lsp_warning", "lsp_information"];
+
+type ServerKey = (String, PathBuf);
+
+struct OpenDocument {
+    client: Rc<Client>,
+    uri: String,
+    language: String,
+    version: i64,
+    opened: bool,
+    changes: Vec<Value>,
+}
+
+struct CompletionCache {
+    anchor: i32,
+    prefix: String,
+    items: Vec<ProvidedItem>,
+    incomplete: bool,
+}
+
+pub struct LanguageServers {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    results: Rc<ResultsPanel>,
+    completion: Rc<Completion>,
+    commands: CommandRegistry,
+    servers: HashMap<String, Vec<String>>,
+    formatters: HashSet<String>,
+    clients: RefCell<HashMap<ServerKey, Rc<Client>>>,
+    failed: RefCell<HashSet<ServerKey>>,
+    open: RefCell<Option<OpenDocument>>,
+    edits: Cell<u64>,
+    flush_pending: Rc<Cell<bool>>,
+    diagnostics: RefCell<HashMap<String, Vec<Value>>>,
+    completion_cache: RefCell<Option<CompletionCache>>,
+    completion_requested: RefCell<Option<(i32, String)>>,
+    hover: RefCell<Option<(i32, String)>>,
+    hover_requested: Cell<Option<i32>>,
+    info: Popover,
+    info_label: Label,
+    signature: Popover,
+    signature_label: Label,
+    signature_line: Cell<i32>,
+    signature_pending: Rc<Cell<bool>>,
+    semantic_tokens_pending: Rc<Cell<bool>>,
+    code_actions: RefCell<Vec<Value>>,
+}
+
+fn is_identifier_char(character: char) -> bool {
+    character.is_alphanumeric() || character == '_'
+}
+
+fn word_bounds(iter: &TextIter) -> (TextIter, TextIter) {
+    let mut start = *iter;
+    while start.backward_char() {
+        if !is_identifier_char(start.char()) {
+            start.forward_char();
+            break;
+        }
+    }
+    let mut end = *iter;
+    while is_identifier_char(end.char()) && end.forward_char() {}
+    (start, end)
+}
+
+fn sync_kind(client: &Client) -> i64 {
+    let sync = client.capability("textDocumentSync");
+    sync.as_i64()
+        .or_else(|| sync["change"].as_i64())
+        .unwrap_or(0)
+}
+
+fn markup_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(markup_text)
+            .filter(|text| !text.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        Value::Object(_) => value["value"].as_str().unwrap_or("").to_owned(),
+        _ => String::new(),
+    }
+}
+
+fn plain_text(markup: &str) -> String {
+    markup
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned()
+}
+
+fn completion_kind(kind: i64) -> &'static str {
+    match kind {
+        2..=4 => "function",
+        5 | 10 => "field",
+        6 => "variable",
+        7..=9 | 13 | 22 | 25 => "type",
+        11 | 12 | 20 | 21 => "constant",
+        14 => "keyword",
+        15 => "snippet",
+        _ => "word",
+    }
+}
+
+fn severity_name(diagnostic: &Value) -> &'static str {
+    match diagnostic["severity"].as_i64() {
+        Some(2) => "warning",
+        Some(3) => "information",
+        Some(4) => "hint",
+        _ => "error",
+    }
+}
+
+fn as_list(value: Value) -> Vec<Value> {
+    match value {
+        Value::Null => Vec::new(),
+        Value::Array(items) => items,
+        value => vec![value],
+    }
+}
+
+impl LanguageServers {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        results: &Rc<ResultsPanel>,
+        completion: &Rc<Completion>,
+        commands: &CommandRegistry,
+        editor_settings: &settings::EditorSettings,
+    ) -> Rc<LanguageServers> {
+        let buffer = text_view.buffer();
+        for (name, color) in DIAGNOSTIC_TAGS.iter().zip([
+            gdk::RGBA::new(1.0, 0.35, 0.35, 1.0),
+            gdk::RGBA::new(1.0, 0.8, 0.3, 1.0),
+            gdk::RGBA::new(0.45, 0.7, 1.0, 1.0),
+        ]) {
+            buffer.create_tag(
+                Some(name),
+                &[
+                    ("underline", &pango::Underline::Error),
+                    ("underline-rgba", &color),
+                ],
+            );
+        }
+        let info_label = Label::builder()
+            .xalign(0.0)
+            .wrap(true)
+            .selectable(true)
+            .max_width_chars(80)
+            .build();
+        let info = Popover::builder()
+            .child(
+                &ScrolledWindow::builder()
+                    .child(&info_label)
+                    .hscrollbar_policy(PolicyType::Never)
+                    .propagate_natural_height(true)
+                    .propagate_natural_width(true)
+                    .max_content_height(300)
+                    .build(),
+            )
+            .position(PositionType::Bottom)
+            .build();
+        info.set_parent(text_view);
+        semantic_tokens::create_tags(text_view);
+        let signature_label = Label::builder().xalign(0.0).build();
+        let signature = Popover::builder()
+            .child(&signature_label)
+            .has_arrow(false)
+            .position(PositionType::Top)
+            .autohide(false)
+            .build();
+        signature.set_parent(text_view);
+        let servers = Rc::new(LanguageServers {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            results: results.clone(),
+            completion: completion.clone(),
+            commands: commands.clone(),
+            servers: editor_settings.language_servers.clone(),
+            formatters: editor_settings.formatters.keys().cloned().collect(),
+            clients: RefCell::new(HashMap::new()),
+            failed: RefCell::new(HashSet::new()),
+            open: RefCell::new(None),
+            edits: Cell::new(0),
+            flush_pending: Rc::new(Cell::new(false)),
+            diagnostics: RefCell::new(HashMap::new()),
+            completion_cache: RefCell::new(None),
+            completion_requested: RefCell::new(None),
+            hover: RefCell::new(None),
+            hover_requested: Cell::new(None),
+            info,
+            info_label,
+            signature,
+            signature_label,
+            signature_line: Cell::new(0),
+            signature_pending: Rc::new(Cell::new(false)),
+            semantic_tokens_pending: Rc::new(Cell::new(false)),
+            code_actions: RefCell::new(Vec::new()),
+        });
+        let weak = Rc::downgrade(&servers);
+        buffer.connect_insert_text(move |_, iter, text| {
+            if let Some(servers) = weak.upgrade() {
+                servers.record_change(iter, iter, text);
+                servers.typed(text);
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        buffer.connect_delete_range(move |_, start, end| {
+            if let Some(servers) = weak.upgrade() {
+                servers.record_change(start, end, "");
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        buffer.connect_mark_set(move |buffer, iter, mark| {
+            if mark == &buffer.get_insert()
+                && let Some(servers) = weak.upgrade()
+                && servers.signature.is_visible()
+                && iter.line() != servers.signature_line.get()
+            {
+                servers.signature.popdown();
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        document.connect_language_changed(move |_| {
+            if let Some(servers) = weak.upgrade() {
+                servers.sync_document();
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        document.connect_opened(move |_| {
+            if let Some(servers) = weak.upgrade() {
+                servers.sync_document();
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        document.connect_saved(move |_| {
+            if let Some(servers) = weak.upgrade() {
+                servers.did_save();
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        completion.set_provider(move |cursor| {
+            weak.upgrade()
+                .map(|servers| servers.completion_items(cursor))
+                .unwrap_or_default()
+        });
+        text_view.set_has_tooltip(true);
+        let weak = Rc::downgrade(&servers);
+        text_view.connect_query_tooltip(move |text_view, x, y, keyboard, tooltip| {
+            let Some(servers) = weak.upgrade() else {
+                return false;
+            };
+            if keyboard {
+                return false;
+            }
+            let (x, y) = text_view.window_to_buffer_coords(TextWindowType::Widget, x, y);
+            let Some(iter) = text_view.iter_at_location(x, y) else {
+                return false;
+            };
+            match servers.tooltip_text(&iter) {
+                Some(text) => {
+                    tooltip.set_text(Some(&text));
+                    true
+                }
+                None => false,
+            }
+        });
+        let weak = Rc::downgrade(&servers);
+        text_view.connect_has_focus_notify(move |text_view| {
+            if !text_view.has_focus()
+                && let Some(servers) = weak.upgrade()
+            {
+                servers.signature.popdown();
+            }
+        });
+        let controller = EventControllerKey::new();
+        controller.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&servers);
+        controller.connect_key_pressed(move |_, keyval, _, _| {
+            if keyval == gdk::Key::Escape
+                && let Some(servers) = weak.upgrade()
+            {
+                servers.signature.popdown();
+            }
+            Propagation::Proceed
+        });
+        text_view.add_controller(controller);
+        servers.register_commands();
+        servers.sync_document();
+        servers
+    }
+
+    fn register_commands(self: &Rc<Self>) {
+        let commands = &self.commands;
+        let previous = commands.action("navigate.definition");
+        let servers = self.clone();
+        commands.register("navigate.definition", "Go to Definition", move || {
+            if servers.supports("definitionProvider") {
+                servers.definition();
+            } else if let Some(previous) = &previous {
+                previous("");
+            }
+        });
+        let previous = commands.action("navigate.references");
+        let servers = self.clone();
+        commands.register("navigate.references", "Find All References", move || {
+            if servers.supports("referencesProvider") {
+                servers.references();
+            } else if let Some(previous) = &previous {
+                previous("");
+            }
+        });
+        let previous = commands.action("edit.rename");
+        let servers = self.clone();
+        commands.register("edit.rename", "Rename Symbol", move || {
+            if servers.supports("renameProvider") {
+                servers.start_rename();
+            } else if let Some(previous) = &previous {
+                previous("");
+            }
+        });
+        let servers = self.clone();
+        commands.register_with_argument(
+            "lsp.rename",
+            "Rename Symbol with Language Server",
+            Argument {
+                prompt: "New name".to_owned(),
+                choices: Vec::new(),
+            },
+            move |name| servers.rename(name),
+        );
+        for (name, title, selection) in [
+            ("format.document", "Format Document", false),
+            ("format.selection", "Format Selection", true),
+        ] {
+            let previous = commands.action(name);
+            let servers = self.clone();
+            commands.register(name, title, move || {
+                let language = servers.document.language();
+                if language != "qat"
+                    && !servers.formatters.contains(&language)
+                    && servers.supports("documentFormattingProvider")
+                {
+                    let lines = if selection {
+                        format::selected_lines(&servers.text_view.buffer())
+                    } else {
+                        None
+                    };
+                    servers.format(lines);
+                } else if let Some(previous) = &previous {
+                    previous("");
+                }
+            });
+        }
+        let servers = self.clone();
+        commands.register("lsp.hover", "Show Hover Information", move || {
+            servers.show_hover()
+        });
+        let servers = self.clone();
+        commands.register("lsp.signature_help", "Show Signature Help", move || {
+            servers.request_signature()
+        });
+        let servers = self.clone();
+        commands.register("lsp.code_actions", "Show Code Actions", move || {
+            servers.code_actions()
+        });
+        let servers = self.clone();
+        commands.register("lsp.diagnostics", "Show Diagnostics", move || {
+            servers.show_diagnostics()
+        });
+        let servers = self.clone();
+        commands.register("lsp.restart", "Restart Language Server", move || {
+            servers.restart()
+        });
+    }
+
+    fn active(&self) -> Option<(Rc<Client>, String)> {
+        let open = self.open.borrow();
+        let open = open.as_ref()?;
+        (open.opened && open.client.is_initialized() && open.client.is_running())
+            .then(|| (open.client.clone(), open.uri.clone()))
+    }
+
+    fn supports(&self, capability: &str) -> bool {
+        self.active()
+            .is_some_and(|(client, _)| client.supports(capability))
+    }
+
+    fn cursor(&self) -> TextIter {
+        let buffer = self.text_view.buffer();
+        buffer.iter_at_mark(&buffer.get_insert())
+    }
+
+    fn text_document_position(&self, iter: &TextIter) -> Value {
+        let (uri, encoding) = match self.active() {
+            Some((client, uri)) => (uri, client.encoding()),
+            None => (String::new(), lsp::Encoding::Utf16),
+        };
+        json!({
+            "textDocument": { "uri": uri },
+            "position": lsp::position(iter, encoding),
+        })
+    }
+
+    fn request(
+        self: &Rc<Self>,
+        method: &str,
+        params: Value,
+        handler: impl FnOnce(&Rc<Self>, Value, bool) + 'static,
+    ) {
+        let Some((client, uri)) = self.active() else {
+            self.status.set_text("No language server is running");
+            return;
+        };
+        self.flush();
+        let edits = self.edits.get();
+        let weak = Rc::downgrade(self);
+        client.request(method, params, move |result| {
+            let Some(servers) = weak.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(result) => {
+                    let stale = servers.edits.get() != edits
+                        || servers.active().is_none_or(|(_, current)| current != uri);
+                    handler(&servers, result, stale);
+                }
+                Err(error) => servers.status.set_text(&error),
+            }
+        });
+    }
+
+    fn sync_document(self: &Rc<Self>) {
+        let path = self.document.path();
+        let language = self.document.language();
+        let uri = lsp::uri_from_path(&path);
+        if let Some(open) = self.open.borrow().as_ref()
+            && open.uri == uri
+            && open.language == language
+            && open.client.is_running()
+        {
+            return;
+        }
+        if let Some(open) = self.open.take()
+            && open.opened
+        {
+            open.client.notify(
+                "textDocument/didClose",
+                json!({ "textDocument": { "uri": open.uri } }),
+            );
+        }
+        self.clear_diagnostic_tags();
+        semantic_tokens::clear(&self.text_view.buffer());
+        self.completion_cache.replace(None);
+        self.hover.replace(None);
+        let Some(command) = self.servers.get(&language) else {
+            return;
+        };
+        let root = workspace::find_root(&path);
+        let Some(client) = self.client(&language, root, command) else {
+            return;
+        };
+        let initialized = client.is_initialized();
+        self.open.replace(Some(OpenDocument {
+            client,
+            uri,
+            language,
+            version: 0,
+            opened: false,
+            changes: Vec::new(),
+        }));
+        if initialized {
+            self.did_open();
+        }
+    }
+
+    fn client(
+        self: &Rc<Self>,
+        language: &str,
+        root: PathBuf,
+        command: &[String],
+    ) -> Option<Rc<Client>> {
+        let key = (language.to_owned(), root);
+        if let Some(client) = self.clients.borrow().get(&key)
+            && client.is_running()
+        {
+            return Some(client.clone());
+        }
+        if self.failed.borrow().contains(&key) {
+            return None;
+        }
+        let client = match Client::start(&key.1, command) {
+            Ok(client) => client,
+            Err(error) => {
+                self.failed.borrow_mut().insert(key);
+                self.notification
+                    .show(&format!("Could not start {}", command[0]), &error);
+                return None;
+            }
+        };
+        self.status.set_text(&format!("Starting {}…", client.name));
+        let weak = Rc::downgrade(self);
+        let weak_client = Rc::downgrade(&client);
+        client.connect_initialized(move || {
+            if let (Some(servers), Some(client)) = (weak.upgrade(), weak_client.upgrade()) {
+                servers.client_initialized(&client);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        client.connect_notification(move |method, params| {
+            if let Some(servers) = weak.upgrade() {
+                servers.notification(method, params);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let weak_client = Rc::downgrade(&client);
+        client.set_request_handler(move |method, params| {
+            match (weak.upgrade(), weak_client.upgrade()) {
+                (Some(servers), Some(client)) => servers.server_request(&client, method, params),
+                _ => Err("The editor is closing".to_owned()),
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let weak_client = Rc::downgrade(&client);
+        let exited_key = key.clone();
+        client.connect_exited(move || {
+            if let (Some(servers), Some(client)) = (weak.upgrade(), weak_client.upgrade()) {
+                servers.client_exited(&exited_key, &client);
+            }
+        });
+        self.clients.borrow_mut().insert(key, client.clone());
+        Some(client)
+    }
+
+    fn client_initialized(self: &Rc<Self>, client: &Rc<Client>) {
+        self.status.set_text("");
+        let uses_client = self
+            .open
+            .borrow()
+            .as_ref()
+            .is_some_and(|open| Rc::ptr_eq(&open.client, client) && !open.opened);
+        if uses_client {
+            self.did_open();
+        }
+    }
+
+    fn client_exited(&self, key: &ServerKey, client: &Rc<Client>) {
+        let mut clients = self.clients.borrow_mut();
+        if clients
+            .get(key)
+            .is_some_and(|existing| Rc::ptr_eq(existing, client))
+        {
+            clients.remove(key);
+        }
+        drop(clients);
+        let uses_client = self
+            .open
+            .borrow()
+            .as_ref()
+            .is_some_and(|open| Rc::ptr_eq(&open.client, client));
+        if uses_client {
+            self.open.replace(None);
+            self.clear_diagnostic_tags();
+            semantic_tokens::clear(&self.text_view.buffer());
+        }
+        if !client.is_stopping() {
+            self.failed.borrow_mut().insert(key.clone());
+            self.notification.show(
+                &format!("{} exited", client.name),
+                "Run Restart Language Server to start it again.",
+            );
+        }
+    }
+
+    fn did_open(self: &Rc<Self>) {
+        let message = {
+            let mut open = self.open.borrow_mut();
+            let Some(open) = open.as_mut() else {
+                return;
+            };
+            open.opened = true;
+            open.version += 1;
+            open.changes.clear();
+            (
+                open.client.clone(),
+                json!({
+                    "textDocument": {
+                        "uri": open.uri,
+                        "languageId": open.language,
+                        "version": open.version,
+                        "text": self.document.text(),
+                    },
+                }),
+            )
+        };
+        message.0.notify("textDocument/didOpen", message.1);
+        self.apply_diagnostics();
+        self.schedule_semantic_tokens();
+    }
+
+    fn did_save(&self) {
+        self.flush();
+        if let Some((client, uri)) = self.active()
+            && !matches!(
+                client.capability("textDocumentSync")["save"],
+                Value::Null | Value::Bool(false)
+            )
+        {
+            client.notify(
+                "textDocument/didSave",
+                json!({ "textDocument": { "uri": uri } }),
+            );
+        }
+    }
+
+    fn record_change(self: &Rc<Self>, start: &TextIter, end: &TextIter, text: &str) {
+        self.edits.set(self.edits.get() + 1);
+        self.hover.replace(None);
+        self.hover_requested.set(None);
+        let mut guard = self.open.borrow_mut();
+        let Some(open) = guard.as_mut().filter(|open| open.opened) else {
+            return;
+        };
+        match sync_kind(&open.client) {
+            0 => return,
+            2 => {
+                let encoding = open.client.encoding();
+                open.changes.push(json!({
+                    "range": {
+                        "start": lsp::position(start, encoding),
+                        "end": lsp::position(end, encoding),
+                    },
+                    "text": text,
+                }));
+            }
+            _ => open.changes.push(Value::Null),
+        }
+        drop(guard);
+        self.schedule_flush();
+        self.schedule_semantic_tokens();
+    }
+
+    fn schedule_flush(self: &Rc<Self>) {
+        if self.flush_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(servers) = weak.upgrade() {
+                servers.flush_pending.set(false);
+                servers.flush();
+            }
+        });
+    }
+
+    fn flush(&self) {
+        let message = {
+            let mut open = self.open.borrow_mut();
+            let Some(open) = open
+                .as_mut()
+                .filter(|open| open.opened && !open.changes.is_empty())
+            else {
+                return;
+            };
+            let changes = std::mem::take(&mut open.changes);
+            open.version += 1;
+            let content_changes = if sync_kind(&open.client) == 2 {
+                changes
+            } else {
+                vec![json!({ "text": self.document.text() })]
+            };
+            (
+                open.client.clone(),
+                json!({
+                    "textDocument": { "uri": open.uri, "version": open.version },
+                    "contentChanges": content_changes,
+                }),
+            )
+        };
+        message.0.notify("textDocument/didChange", message.1);
+    }
+
+    fn schedule_semantic_tokens(self: &Rc<Self>) {
+        if self.semantic_tokens_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::timeout_add_local_once(SEMANTIC_TOKENS_DELAY, move || {
+            if let Some(servers) = weak.upgrade() {
+                servers.semantic_tokens_pending.set(false);
+                servers.request_semantic_tokens();
+            }
+        });
+    }
+
+    fn request_semantic_tokens(self: &Rc<Self>) {
+        let Some((client, uri)) = self.active() else {
+            return;
+        };
+        if matches!(
+            client.capability("semanticTokensProvider")["full"],
+            Value::Null | Value::Bool(false)
+        ) {
+            return;
+        }
+        self.flush();
+        let edits = self.edits.get();
+        let weak = Rc::downgrade(self);
+        let weak_client = Rc::downgrade(&client);
+        client.request(
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": { "uri": uri } }),
+            move |result| {
+                let (Some(servers), Some(client), Ok(result)) =
+                    (weak.upgrade(), weak_client.upgrade(), result)
+                else {
+                    return;
+                };
+                if servers.edits.get() != edits
+                    || servers.active().is_none_or(|(_, current)| current != uri)
+                {
+                    return;
+                }
+                semantic_tokens::apply(
+                    &servers.text_view.buffer(),
+                    result["data"].as_array().map_or(&[], Vec::as_slice),
+                    &client.capability("semanticTokensProvider")["legend"],
+                    client.encoding(),
+                );
+            },
+        );
+    }
+
+    fn typed(self: &Rc<Self>, text: &str) {
+        if !self.text_view.has_focus() {
+            return;
+        }
+        let Some((client, _)) = self.active() else {
+            return;
+        };
+        let mut characters = text.chars();
+        let (Some(character), None) = (characters.next(), characters.next()) else {
+            return;
+        };
+        let character = character.to_string();
+        let has = |capability: &Value, field: &str| {
+            capability[field]
+                .as_array()
+                .is_some_and(|list| list.iter().any(|value| *value == character))
+        };
+        let signature = client.capability("signatureHelpProvider");
+        if self.signature.is_visible()
+            || has(&signature, "triggerCharacters")
+            || has(&signature, "retriggerCharacters")
+        {
+            self.schedule_signature();
+        }
+        if has(
+            &client.capability("completionProvider"),
+            "triggerCharacters",
+        ) {
+            let completion = self.completion.clone();
+            glib::idle_add_local_once(move || completion.trigger());
+        }
+    }
+
+    fn notification(self: &Rc<Self>, method: &str, params: &Value) {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let uri = params["uri"].as_str().unwrap_or("").to_owned();
+                let diagnostics = params["diagnostics"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default();
+                let current = self.active().is_some_and(|(_, current)| current == uri);
+                if diagnostics.is_empty() {
+                    self.diagnostics.borrow_mut().remove(&uri);
+                } else {
+                    self.diagnostics.borrow_mut().insert(uri, diagnostics);
+                }
+                if current {
+                    self.apply_diagnostics();
+                }
+            }
+            "window/showMessage" => {
+                let message = params["message"].as_str().unwrap_or("");
+                if params["type"].as_i64().is_some_and(|kind| kind <= 2) {
+                    self.notification.show("Language server", message);
+                } else {
+                    self.status.set_text(message);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn server_request(
+        self: &Rc<Self>,
+        client: &Client,
+        method: &str,
+        params: &Value,
+    ) -> Result<Value, String> {
+        match method {
+            "workspace/applyEdit" => Ok(match self.apply_workspace_edit(&params["edit"]) {
+                Ok(_) => json!({ "applied": true }),
+                Err(error) => json!({ "applied": false, "failureReason": error }),
+            }),
+            "workspace/configuration" => Ok(Value::Array(vec![
+                Value::Null;
+                params["items"]
+                    .as_array()
+                    .map_or(0, Vec::len)
+            ])),
+            "workspace/workspaceFolders" => Ok(json!([{
+                "uri": lsp::uri_from_path(&client.root),
+                "name": client.root.file_name().map(|name| name.to_string_lossy()),
+            }])),
+            "window/showMessageRequest" => {
+                self.notification
+                    .show(&client.name, params["message"].as_str().unwrap_or(""));
+                Ok(Value::Null)
+            }
+            "client/registerCapability"
+            | "client/unregisterCapability"
+            | "window/workDoneProgress/create" => Ok(Value::Null),
+            _ => Err(format!("Unsupported request {}", method)),
+        }
+    }
+
+    fn restart(self: &Rc<Self>) {
+        let language = self.document.language();
+        if !self.servers.contains_key(&language) {
+            self.status
+                .set_text("No language server is configured for this language");
+            return;
+        }
+        let key = (language, workspace::find_root(&self.document.path()));
+        self.failed.borrow_mut().remove(&key);
+        let client = self.clients.borrow_mut().remove(&key);
+        if let Some(client) = client {
+            client.shutdown();
+        }
+        self.open.replace(None);
+        self.sync_document();
+    }
+
+    pub fn stop(&self) {
+        for client in self.clients.borrow().values() {
+            client.stop();
+        }
+    }
+
+    fn clear_diagnostic_tags(&self) {
+        let buffer = self.text_view.buffer();
+        let (start, end) = buffer.bounds();
+        for tag in DIAGNOSTIC_TAGS {
+            buffer.remove_tag_by_name(tag, &start, &end);
+        }
+    }
+
+    fn diagnostic_range(
+        &self,
+        diagnostic: &Value,
+        encoding: lsp::Encoding,
+    ) -> (TextIter, TextIter) {
+        let buffer = self.text_view.buffer();
+        let mut start = lsp::iter_at_position(&buffer, &diagnostic["range"]["start"], encoding);
+        let mut end = lsp::iter_at_position(&buffer, &diagnostic["range"]["end"], encoding);
+        if end <= start {
+            end = start;
+            if !end.ends_line() {
+                end.forward_char();
+            } else if !start.starts_line() {
+                start.backward_char();
+            }
+        }
+        (start, end)
+    }
+
+    fn apply_diagnostics(&self) {
+        self.clear_diagnostic_tags();
+        let Some((client, uri)) = self.active() else {
+            return;
+        };
+        let buffer = self.text_view.buffer();
+        for diagnostic in self.diagnostics.borrow().get(&uri).into_iter().flatten() {
+            let (start, end) = self.diagnostic_range(diagnostic, client.encoding());
+            let tag = match diagnostic["severity"].as_i64() {
+                Some(2) => "lsp_warning",
+                Some(3) | Some(4) => "lsp_information",
+                _ => "lsp_error",
+            };
+            buffer.apply_tag_by_name(tag, &start, &end);
+        }
+    }
+
+    fn tooltip_text(self: &Rc<Self>, iter: &TextIter) -> Option<String> {
+        let (client, uri) = self.active()?;
+        let mut parts = Vec::new();
+        for diagnostic in self.diagnostics.borrow().get(&uri).into_iter().flatten() {
+            let (start, end) = self.diagnostic_range(diagnostic, client.encoding());
+            if start <= *iter && *iter < end {
+                let source = diagnostic["source"]
+                    .as_str()
+                    .map(|source| format!(" ({})", source))
+                    .unwrap_or_default();
+                parts.push(format!(
+                    "{}{}: {}",
+                    severity_name(diagnostic),
+                    source,
+                    diagnostic["message"].as_str().unwrap_or("")
+                ));
+            }
+        }
+        let (start, end) = word_bounds(iter);
+        if start != end && client.supports("hoverProvider") {
+            let cached = self.hover.borrow().clone();
+            match cached {
+                Some((offset, text)) if offset == start.offset() && !text.is_empty() => {
+                    parts.push(text);
+                }
+                _ if self.hover_requested.get() != Some(start.offset()) => {
+                    let offset = start.offset();
+                    self.hover_requested.set(Some(offset));
+                    self.request(
+                        "textDocument/hover",
+                        self.text_document_position(iter),
+                        move |servers, result, stale| {
+                            if stale || servers.hover_requested.get() != Some(offset) {
+                                return;
+                            }
+                            let text = plain_text(&markup_text(&result["contents"]));
+                            servers.hover.replace(Some((offset, text)));
+                            servers.text_view.trigger_tooltip_query();
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    }
+
+    fn popup_at(&self, popover: &Popover, iter: &TextIter) {
+        let location = self.text_view.iter_location(iter);
+        let y = if popover.position() == PositionType::Top {
+            location.y()
+        } else {
+            location.y() + location.height()
+        };
+        let (x, y) =
+            self.text_view
+                .buffer_to_window_coords(TextWindowType::Widget, location.x(), y);
+        popover.set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, 1)));
+        popover.popup();
+    }
+
+    fn show_hover(self: &Rc<Self>) {
+        let cursor = self.cursor();
+        self.request(
+            "textDocument/hover",
+            self.text_document_position(&cursor),
+            |servers, result, stale| {
+                if stale {
+                    return;
+                }
+                let text = plain_text(&markup_text(&result["contents"]));
+                if text.is_empty() {
+                    servers.status.set_text("No information available");
+                    return;
+                }
+                servers.info_label.set_text(&text);
+                servers.popup_at(&servers.info, &servers.cursor());
+            },
+        );
+    }
+
+    fn schedule_signature(self: &Rc<Self>) {
+        if self.signature_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(servers) = weak.upgrade() {
+                servers.signature_pending.set(false);
+                servers.request_signature();
+            }
+        });
+    }
+
+    fn request_signature(self: &Rc<Self>) {
+        if !self.supports("signatureHelpProvider") {
+            return;
+        }
+        let cursor = self.cursor();
+        self.request(
+            "textDocument/signatureHelp",
+            self.text_document_position(&cursor),
+            |servers, help, stale| {
+                if stale {
+                    return;
+                }
+                let signatures = help["signatures"].as_array().cloned().unwrap_or_default();
+                let active = help["activeSignature"].as_u64().unwrap_or(0) as usize;
+                let Some(signature) = signatures.get(active).or(signatures.first()) else {
+                    servers.signature.popdown();
+                    return;
+                };
+                let label = signature["label"].as_str().unwrap_or("");
+                let parameter = signature["activeParameter"]
+                    .as_u64()
+                    .or(help["activeParameter"].as_u64())
+                    .unwrap_or(0) as usize;
+                let range = match &signature["parameters"][parameter]["label"] {
+                    Value::String(name) => label
+                        .find(name.as_str())
+                        .map(|start| start..start + name.len()),
+                    Value::Array(offsets) => {
+                        let offset = |index: usize| {
+                            lsp::column_to_index(
+                                label,
+                                offsets[index].as_u64().unwrap_or(0),
+                                lsp::Encoding::Utf16,
+                            )
+                        };
+                        (offsets.len() == 2).then(|| offset(0)..offset(1))
+                    }
+                    _ => None,
+                };
+                let markup = match range {
+                    Some(range) => format!(
+                        "{}<b>{}</b>{}",
+                        glib::markup_escape_text(&label[..range.start]),
+                        glib::markup_escape_text(&label[range.clone()]),
+                        glib::markup_escape_text(&label[range.end..])
+                    ),
+                    None => glib::markup_escape_text(label).to_string(),
+                };
+                servers.signature_label.set_markup(&markup);
+                let cursor = servers.cursor();
+                servers.signature_line.set(cursor.line());
+                servers.popup_at(&servers.signature, &cursor);
+            },
+        );
+    }
+
+    fn completion_items(self: &Rc<Self>, cursor: &TextIter) -> Vec<ProvidedItem> {
+        let Some((client, _)) = self.active() else {
+            return Vec::new();
+        };
+        let provider = client.capability("completionProvider");
+        if provider.is_null() {
+            return Vec::new();
+        }
+        let (start, _) = word_bounds(cursor);
+        let anchor = start.offset();
+        let prefix = start.slice(cursor).to_string();
+        if let Some(cache) = self.completion_cache.borrow().as_ref()
+            && cache.anchor == anchor
+            && (!cache.incomplete || cache.prefix == prefix)
+        {
+            return cache.items.clone();
+        }
+        let requested = Some((anchor, prefix.clone()));
+        if *self.completion_requested.borrow() == requested {
+            return Vec::new();
+        }
+        self.completion_requested.replace(requested);
+        let mut before = start;
+        let trigger = (before.backward_char() && prefix.is_empty())
+            .then(|| before.char().to_string())
+            .filter(|character| {
+                provider["triggerCharacters"]
+                    .as_array()
+                    .is_some_and(|list| list.iter().any(|value| value == character))
+            });
+        let mut params = self.text_document_position(cursor);
+        params["context"] = match trigger {
+            Some(character) => json!({ "triggerKind": 2, "triggerCharacter": character }),
+            None => json!({ "triggerKind": 1 }),
+        };
+        self.request(
+            "textDocument/completion",
+            params,
+            move |servers, result, _| {
+                servers.completion_requested.replace(None);
+                let incomplete = result["isIncomplete"].as_bool().unwrap_or(false);
+                let items = match result {
+                    Value::Array(items) => items,
+                    result => result["items"].as_array().cloned().unwrap_or_default(),
+                };
+                let items = items
+                    .iter()
+                    .map(|item| {
+                        let label = item["label"].as_str().unwrap_or("").to_owned();
+                        let text = item["textEdit"]["newText"]
+                            .as_str()
+                            .or(item["insertText"].as_str())
+                            .unwrap_or(&label)
+                            .to_owned();
+                        ProvidedItem {
+                            kind: completion_kind(item["kind"].as_i64().unwrap_or(1)),
+                            detail: item["detail"].as_str().unwrap_or("").to_owned(),
+                            snippet: item["insertTextFormat"].as_i64() == Some(2),
+                            label,
+                            text,
+                        }
+                    })
+                    .collect();
+                servers.completion_cache.replace(Some(CompletionCache {
+                    anchor,
+                    prefix,
+                    items,
+                    incomplete,
+                }));
+                servers.completion.provider_updated();
+            },
+        );
+        Vec::new()
+    }
+
+    fn locations(&self, values: &[Value]) -> Vec<(Location, String)> {
+        let encoding = self
+            .active()
+            .map(|(client, _)| client.encoding())
+            .unwrap_or(lsp::Encoding::Utf16);
+        let current = self.document.path();
+        let mut files: HashMap<PathBuf, Option<String>> = HashMap::new();
+        let mut locations = Vec::new();
+        for value in values {
+            let uri = value["uri"].as_str().or(value["targetUri"].as_str());
+            let Some(path) = uri.and_then(lsp::path_from_uri) else {
+                continue;
+            };
+            let range = if value["targetSelectionRange"].is_object() {
+                &value["targetSelectionRange"]
+            } else {
+                &value["range"]
+            };
+            let text = files.entry(path.clone()).or_insert_with(|| {
+                if path == current {
+                    Some(self.document.text())
+                } else {
+                    fs::read_to_string(&path).ok()
+                }
+            });
+            let line = range["start"]["line"].as_u64().unwrap_or(0) as usize;
+            let line_text = text
+                .as_deref()
+                .and_then(|text| text.lines().nth(line))
+                .unwrap_or("");
+            let column = lsp::column_to_index(
+                line_text,
+                range["start"]["character"].as_u64().unwrap_or(0),
+                encoding,
+            );
+            locations.push((
+                Location {
+                    path,
+                    line: line as i32,
+                    column: column as i32,
+                },
+                line_text.to_owned(),
+            ));
+        }
+        locations
+    }
+
+    fn location_detail(&self, location: &Location) -> String {
+        let root = workspace::find_root(&self.document.path());
+        format!(
+            "{}:{}",
+            location
+                .path
+                .strip_prefix(&root)
+                .unwrap_or(&location.path)
+                .display(),
+            location.line + 1
+        )
+    }
+
+    fn show_locations(&self, title: &str, values: Vec<Value>, empty: &str) {
+        let locations = self.locations(&values);
+        match locations.as_slice() {
+            [] => self.status.set_text(empty),
+            [(location, _)] => {
+                results::open_location(&self.document, &self.text_view, &self.status, location);
+            }
+            _ => {
+                let items = locations
+                    .into_iter()
+                    .map(|(location, text)| ResultItem {
+                        detail: self.location_detail(&location),
+                        location,
+                        text,
+                    })
+                    .collect();
+                self.results.show(title, items);
+            }
+        }
+    }
+
+    fn definition(self: &Rc<Self>) {
+        let cursor = self.cursor();
+        self.request(
+            "textDocument/definition",
+            self.text_document_position(&cursor),
+            |servers, result, _| {
+                servers.show_locations("Definitions", as_list(result), "No definition found")
+            },
+        );
+    }
+
+    fn references(self: &Rc<Self>) {
+        let cursor = self.cursor();
+        let (start, end) = word_bounds(&cursor);
+        let title = format!("References to {}", start.slice(&end));
+        let mut params = self.text_document_position(&cursor);
+        params["context"] = json!({ "includeDeclaration": true });
+        self.request(
+            "textDocument/references",
+            params,
+            move |servers, result, _| {
+                let locations = servers.locations(&as_list(result));
+                let items: Vec<ResultItem> = locations
+                    .into_iter()
+                    .map(|(location, text)| ResultItem {
+                        detail: servers.location_detail(&location),
+                        location,
+                        text,
+                    })
+                    .collect();
+                if items.is_empty() {
+                    servers.status.set_text("No references found");
+                } else {
+                    servers.results.show(&title, items);
+                }
+            },
+        );
+    }
+
+    fn start_rename(self: &Rc<Self>) {
+        let (start, end) = word_bounds(&self.cursor());
+        if start == end {
+            self.status
+                .set_text("Place the cursor on a symbol to rename");
+            return;
+        }
+        let word = start.slice(&end);
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "lsp.rename",
+            "Rename Symbol with Language Server",
+            Argument {
+                prompt: format!("Rename {} to", word),
+                choices: Vec::new(),
+            },
+            move |name| {
+                if let Some(servers) = weak.upgrade() {
+                    servers.rename(name);
+                }
+            },
+        );
+        self.commands.run("lsp.rename");
+    }
+
+    fn rename(self: &Rc<Self>, name: &str) {
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        let mut params = self.text_document_position(&self.cursor());
+        params["newName"] = json!(name);
+        self.request("textDocument/rename", params, |servers, edit, stale| {
+            if stale {
+                servers
+                    .status
+                    .set_text("The document changed before the rename finished");
+                return;
+            }
+            match servers.apply_workspace_edit(&edit) {
+                Ok(files) => servers
+                    .status
+                    .set_text(&format!("Renamed in {} file(s)", files)),
+                Err(error) => servers.notification.show("Rename failed", &error),
+            }
+        });
+    }
+
+    fn code_actions(self: &Rc<Self>) {
+        let Some((client, uri)) = self.active() else {
+            self.status.set_text("No language server is running");
+            return;
+        };
+        let buffer = self.text_view.buffer();
+        let (start, end) = buffer
+            .selection_bounds()
+            .unwrap_or_else(|| (self.cursor(), self.cursor()));
+        let encoding = client.encoding();
+        let lines = start.line() as i64..end.line() as i64 + 1;
+        let diagnostics: Vec<Value> = self
+            .diagnostics
+            .borrow()
+            .get(&uri)
+            .into_iter()
+            .flatten()
+            .filter(|diagnostic| {
+                let first = diagnostic["range"]["start"]["line"].as_i64().unwrap_or(0);
+                let last = diagnostic["range"]["end"]["line"].as_i64().unwrap_or(0);
+                first < lines.end && last >= lines.start
+            })
+            .cloned()
+            .collect();
+        let params = json!({
+            "textDocument": { "uri": uri },
+            "range": {
+                "start": lsp::position(&start, encoding),
+                "end": lsp::position(&end, encoding),
+            },
+            "context": { "diagnostics": diagnostics },
+        });
+        self.request("textDocument/codeAction", params, |servers, result, _| {
+            let mut seen = HashSet::new();
+            let actions: Vec<Value> = as_list(result)
+                .into_iter()
+                .filter(|action| {
+                    action["title"]
+                        .as_str()
+                        .is_some_and(|title| seen.insert(title.to_owned()))
+                })
+                .collect();
+            if actions.is_empty() {
+                servers.status.set_text("No code actions available");
+                return;
+            }
+            let choices = actions
+                .iter()
+                .map(|action| {
+                    (
+                        action["title"].as_str().unwrap_or("").to_owned(),
+                        action["kind"].as_str().unwrap_or("").to_owned(),
+                    )
+                })
+                .collect();
+            servers.code_actions.replace(actions);
+            let applying = Rc::downgrade(servers);
+            servers.commands.register_with_argument(
+                "lsp.apply_code_action",
+                "Apply Code Action",
+                Argument {
+                    prompt: "Code action".to_owned(),
+                    choices,
+                },
+                move |title| {
+                    if let Some(servers) = applying.upgrade() {
+                        servers.apply_code_action(title);
+                    }
+                },
+            );
+            servers.commands.run("lsp.apply_code_action");
+        });
+    }
+
+    fn apply_code_action(self: &Rc<Self>, title: &str) {
+        let Some(action) = self
+            .code_actions
+            .borrow()
+            .iter()
+            .find(|action| action["title"] == title)
+            .cloned()
+        else {
+            return;
+        };
+        if action["command"].is_string() {
+            self.execute_command(&action);
+            return;
+        }
+        let resolvable = self.active().is_some_and(|(client, _)| {
+            client.capability("codeActionProvider")["resolveProvider"] == true
+        });
+        if action["edit"].is_null() && resolvable {
+            self.request("codeAction/resolve", action, |servers, resolved, _| {
+                servers.finish_code_action(&resolved)
+            });
+        } else {
+            self.finish_code_action(&action);
+        }
+    }
+
+    fn finish_code_action(self: &Rc<Self>, action: &Value) {
+        if !action["edit"].is_null()
+            && let Err(error) = self.apply_workspace_edit(&action["edit"])
+        {
+            self.notification.show("Code action failed", &error);
+            return;
+        }
+        if action["command"].is_object() {
+            self.execute_command(&action["command"]);
+        }
+    }
+
+    fn execute_command(self: &Rc<Self>, command: &Value) {
+        let params = json!({
+            "command": command["command"],
+            "arguments": command.get("arguments").cloned().unwrap_or(json!([])),
+        });
+        self.request("workspace/executeCommand", params, |_, _, _| {});
+    }
+
+    fn apply_workspace_edit(&self, edit: &Value) -> Result<usize, String> {
+        let mut files: Vec<(String, Vec<Value>)> = Vec::new();
+        if let Some(changes) = edit["documentChanges"].as_array() {
+            for change in changes {
+                if change.get("kind").is_some() {
+                    return Err("Creating, renaming or deleting files is not supported".to_owned());
+                }
+                files.push((
+                    change["textDocument"]["uri"]
+                        .as_str()
+                        .unwrap_or("")
+                        .to_owned(),
+                    change["edits"].as_array().cloned().unwrap_or_default(),
+                ));
+            }
+        } else if let Some(changes) = edit["changes"].as_object() {
+            for (uri, edits) in changes {
+                files.push((uri.clone(), edits.as_array().cloned().unwrap_or_default()));
+            }
+        }
+        let (encoding, current) = match self.active() {
+            Some((client, uri)) => (client.encoding(), Some(uri)),
+            None => (lsp::Encoding::Utf16, None),
+        };
+        for (uri, edits) in &files {
+            if Some(uri) == current.as_ref() {
+                self.flush();
+                let text = lsp::apply_edits(&self.document.text(), edits, encoding);
+                format::apply_text(&self.document.buffer, &text, None);
+                continue;
+            }
+            let path = lsp::path_from_uri(uri).ok_or(format!("Cannot edit {}", uri))?;
+            let text = fs::read_to_string(&path)
+                .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+            fs::write(&path, lsp::apply_edits(&text, edits, encoding))
+                .map_err(|error| format!("Could not write {}: {}", path.display(), error))?;
+        }
+        Ok(files.len())
+    }
+
+    fn format(self: &Rc<Self>, lines: Option<Range<usize>>) {
+        let Some((_, uri)) = self.active() else {
+            return;
+        };
+        let params = json!({
+            "textDocument": { "uri": uri },
+            "options": { "tabSize": TAB_SIZE, "insertSpaces": false },
+        });
+        self.request(
+            "textDocument/formatting",
+            params,
+            move |servers, edits, stale| {
+                if stale {
+                    servers
+                        .status
+                        .set_text("The buffer changed while formatting");
+                    return;
+                }
+                let encoding = servers
+                    .active()
+                    .map(|(client, _)| client.encoding())
+                    .unwrap_or(lsp::Encoding::Utf16);
+                let text = lsp::apply_edits(&servers.document.text(), &as_list(edits), encoding);
+                let changed = format::apply_text(&servers.document.buffer, &text, lines);
+                servers.status.set_text(if changed == 0 {
+                    "Already formatted"
+                } else {
+                    "Formatted"
+                });
+            },
+        );
+    }
+
+    fn show_diagnostics(&self) {
+        let mut values = Vec::new();
+        for (uri, diagnostics) in self.diagnostics.borrow().iter() {
+            for diagnostic in diagnostics {
+                values.push((
+                    json!({ "uri": uri, "range": diagnostic["range"] }),
+                    diagnostic.clone(),
+                ));
+            }
+        }
+        let locations = self.locations(
+            &values
+                .iter()
+                .map(|(location, _)| location.clone())
+                .collect::<Vec<_>>(),
+        );
+        let mut items: Vec<ResultItem> = locations
+            .into_iter()
+            .zip(values)
+            .map(|((location, _), (_, diagnostic))| ResultItem {
+                detail: format!(
+                    "{} {}",
+                    self.location_detail(&location),
+                    severity_name(&diagnostic)
+                ),
+                text: diagnostic["message"].as_str().unwrap_or("").to_owned(),
+                location,
+            })
+            .collect();
+        if items.is_empty() {
+            self.status.set_text("No diagnostics");
+            return;
+        }
+        items.sort_by(|item, other| {
+            (&item.location.path, item.location.line)
+                .cmp(&(&other.location.path, other.location.line))
+        });
+        self.results.show("Diagnostics", items);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use gtk4::TextBuffer;
+
+    use super::*;
+    use crate::{fake_server, snippets::Snippets, word_index::WordIndex};
+
+    fn served_text(servers: &LanguageServers, uri: &str) -> Option<String> {
+        servers.diagnostics.borrow().get(uri)?[0]["message"]
+            .as_str()
+            .map(str::to_owned)
+    }
+
+    #[gtk4::test]
+    fn buffer_edits_are_sent_as_incremental_changes() {
+        let path = env::temp_dir().join(format!("moon-language-server-{}.qat", process::id()));
+        fs::write(&path, "let a = 1.\nsay \"😀\" a.\n").expect("Could not write the test file");
+        let buffer = TextBuffer::new(None);
+        let document = Document::open(&buffer, &path).expect("Could not open the test file");
+        let text_view = TextView::with_buffer(&buffer);
+        let commands = CommandRegistry::default();
+        let snippets = Snippets::new(&text_view, &document, &commands);
+        let word_index = WordIndex::new();
+        let completion = Completion::new(
+            &text_view,
+            &text_view,
+            &document,
+            &snippets,
+            &word_index,
+            &commands,
+        );
+        let settings = settings::EditorSettings {
+            language_servers: HashMap::from([(
+                "qat".to_owned(),
+                fake_server::command("lsp::tests::language_server"),
+            )]),
+            ..Default::default()
+        };
+        let servers = LanguageServers::new(
+            &text_view,
+            &document,
+            &Label::new(None),
+            &Notification::new(&commands),
+            &ResultsPanel::new(&commands),
+            &completion,
+            &commands,
+            &settings,
+        );
+        let uri = lsp::uri_from_path(&path);
+        fake_server::run_until(|| served_text(&servers, &uri).is_some());
+        assert_eq!(served_text(&servers, &uri), Some(document.text()));
+
+        buffer.insert(&mut buffer.iter_at_line_offset(1, 4).unwrap(), "😀");
+        buffer.insert(&mut buffer.end_iter(), "say a + 1.\n");
+        let mut start = buffer.iter_at_line_offset(0, 4).unwrap();
+        let mut end = buffer.iter_at_line_offset(1, 3).unwrap();
+        buffer.delete(&mut start, &mut end);
+        fake_server::run_until(|| served_text(&servers, &uri) == Some(document.text()));
+        assert_eq!(document.text(), "let  \"😀😀\" a.\nsay a + 1.\n");
+
+        let mut start = buffer.iter_at_line_offset(0, 7).unwrap();
+        let mut end = buffer.iter_at_line_offset(0, 8).unwrap();
+        buffer.delete(&mut start, &mut end);
+        fake_server::run_until(|| served_text(&servers, &uri) == Some(document.text()));
+        buffer.insert(&mut buffer.iter_at_line_offset(0, 7).unwrap(), "x\ny");
+        fake_server::run_until(|| served_text(&servers, &uri) == Some(document.text()));
+        assert_eq!(document.text(), "let  \"😀x\ny\" a.\nsay a + 1.\n");
+
+        servers.stop();
+        fake_server::run_until(|| servers.clients.borrow().is_empty());
+        fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,336 @@
+use std::cmp::Ordering;
+
+use gtk4::{TextBuffer, TextIter, TextView, prelude::*};
+
+use crate::commands::CommandRegistry;
+
+fn selected_lines(buffer: &TextBuffer) -> (i32, i32) {
+    let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        (cursor, cursor)
+    });
+    let first = start.line();
+    let mut last = end.line();
+    if last > first && end.starts_line() {
+        last -= 1;
+    }
+    (first, last)
+}
+
+fn iter_at(buffer: &TextBuffer, line: i32, offset: i32) -> TextIter {
+    if let Some(iter) = buffer.iter_at_line_offset(line, offset) {
+        return iter;
+    }
+    match buffer.iter_at_line(line) {
+        Some(mut iter) => {
+            if !iter.ends_line() {
+                iter.forward_to_line_end();
+            }
+            iter
+        }
+        None => buffer.end_iter(),
+    }
+}
+
+fn line_span(buffer: &TextBuffer, first: i32, last: i32) -> (TextIter, TextIter) {
+    let start = iter_at(buffer, first, 0);
+    let mut end = iter_at(buffer, last, 0);
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    (start, end)
+}
+
+fn lines_text(buffer: &TextBuffer, first: i32, last: i32) -> Vec<String> {
+    let (start, end) = line_span(buffer, first, last);
+    buffer
+        .text(&start, &end, true)
+        .split('\n')
+        .map(String::from)
+        .collect()
+}
+
+fn replace_lines(buffer: &TextBuffer, first: i32, last: i32, lines: &[String]) {
+    let (mut start, mut end) = line_span(buffer, first, last);
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &lines.join("\n"));
+}
+
+fn selection_positions(buffer: &TextBuffer) -> [(i32, i32); 2] {
+    let insert = buffer.iter_at_mark(&buffer.get_insert());
+    let bound = buffer.iter_at_mark(&buffer.selection_bound());
+    [
+        (insert.line(), insert.line_offset()),
+        (bound.line(), bound.line_offset()),
+    ]
+}
+
+fn restore_selection(buffer: &TextBuffer, positions: [(i32, i32); 2], line_shift: i32) {
+    let [(insert_line, insert_offset), (bound_line, bound_offset)] = positions;
+    let insert = iter_at(buffer, insert_line + line_shift, insert_offset);
+    let bound = iter_at(buffer, bound_line + line_shift, bound_offset);
+    buffer.select_range(&insert, &bound);
+}
+
+fn user_action(buffer: &TextBuffer, edit: impl FnOnce()) {
+    buffer.begin_user_action();
+    edit();
+    buffer.end_user_action();
+}
+
+pub fn move_lines_up(buffer: &TextBuffer) {
+    let (first, last) = selected_lines(buffer);
+    if first == 0 {
+        return;
+    }
+    let positions = selection_positions(buffer);
+    let mut lines = lines_text(buffer, first - 1, last);
+    lines.rotate_left(1);
+    user_action(buffer, || {
+        replace_lines(buffer, first - 1, last, &lines);
+        restore_selection(buffer, positions, -1);
+    });
+}
+
+pub fn move_lines_down(buffer: &TextBuffer) {
+    let (first, last) = selected_lines(buffer);
+    if last + 1 >= buffer.line_count() {
+        return;
+    }
+    let positions = selection_positions(buffer);
+    let mut lines = lines_text(buffer, first, last + 1);
+    lines.rotate_right(1);
+    user_action(buffer, || {
+        replace_lines(buffer, first, last + 1, &lines);
+        restore_selection(buffer, positions, 1);
+    });
+}
+
+pub fn duplicate_lines(buffer: &TextBuffer) {
+    let (first, last) = selected_lines(buffer);
+    let positions = selection_positions(buffer);
+    let block = lines_text(buffer, first, last).join("\n");
+    user_action(buffer, || {
+        let (_, mut end) = line_span(buffer, first, last);
+        buffer.insert(&mut end, &("\n".to_owned() + &block));
+        restore_selection(buffer, positions, last - first + 1);
+    });
+}
+
+pub fn delete_lines(buffer: &TextBuffer) {
+    let (first, last) = selected_lines(buffer);
+    let column = buffer.iter_at_mark(&buffer.get_insert()).line_offset();
+    user_action(buffer, || {
+        let (mut start, mut end) = if last + 1 < buffer.line_count() {
+            (iter_at(buffer, first, 0), iter_at(buffer, last + 1, 0))
+        } else if first > 0 {
+            (line_span(buffer, first - 1, first - 1).1, buffer.end_iter())
+        } else {
+            (buffer.start_iter(), buffer.end_iter())
+        };
+        buffer.delete(&mut start, &mut end);
+        let line = first.min(buffer.line_count() - 1);
+        buffer.place_cursor(&iter_at(buffer, line, column));
+    });
+}
+
+pub fn join_lines(buffer: &TextBuffer) {
+    let (first, mut last) = selected_lines(buffer);
+    if first == last {
+        if last + 1 >= buffer.line_count() {
+            return;
+        }
+        last += 1;
+    }
+    let lines = lines_text(buffer, first, last);
+    let join_point = lines[0].trim_end().chars().count() as i32;
+    let joined = join_trimmed(&lines);
+    user_action(buffer, || {
+        replace_lines(buffer, first, last, &[joined]);
+        buffer.place_cursor(&iter_at(buffer, first, join_point));
+    });
+}
+
+fn lines_to_reorder(buffer: &TextBuffer) -> (i32, i32) {
+    let (first, last) = selected_lines(buffer);
+    if first != last {
+        return (first, last);
+    }
+    let mut last = buffer.line_count() - 1;
+    if last > 0 && buffer.end_iter().starts_line() {
+        last -= 1;
+    }
+    (0, last)
+}
+
+fn reorder_lines(buffer: &TextBuffer, reorder: impl FnOnce(&mut Vec<String>)) {
+    let (first, last) = lines_to_reorder(buffer);
+    let mut lines = lines_text(buffer, first, last);
+    reorder(&mut lines);
+    user_action(buffer, || {
+        replace_lines(buffer, first, last, &lines);
+        let last = first + lines.len() as i32 - 1;
+        let (start, end) = line_span(buffer, first, last);
+        buffer.select_range(&start, &end);
+    });
+}
+
+pub fn sort_lines(buffer: &TextBuffer, natural: bool, unique: bool) {
+    reorder_lines(buffer, |lines| sort_strings(lines, natural, unique));
+}
+
+pub fn reverse_lines(buffer: &TextBuffer) {
+    reorder_lines(buffer, |lines| lines.reverse());
+}
+
+pub fn join_trimmed(lines: &[String]) -> String {
+    let mut joined = lines[0].trim_end().to_owned();
+    for line in &lines[1..] {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !joined.trim_start().is_empty() {
+            joined.push(' ');
+        }
+        joined.push_str(line);
+    }
+    joined
+}
+
+pub fn sort_strings(lines: &mut Vec<String>, natural: bool, unique: bool) {
+    if natural {
+        lines.sort_by(|a, b| natural_cmp(a, b).then_with(|| a.cmp(b)));
+    } else {
+        lines.sort();
+    }
+    if unique {
+        lines.dedup();
+    }
+}
+
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut x_digits = String::new();
+                while let Some(digit) = a_chars.next_if(char::is_ascii_digit) {
+                    x_digits.push(digit);
+                }
+                let mut y_digits = String::new();
+                while let Some(digit) = b_chars.next_if(char::is_ascii_digit) {
+                    y_digits.push(digit);
+                }
+                let x_value = x_digits.trim_start_matches('0');
+                let y_value = y_digits.trim_start_matches('0');
+                let ordering = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn register(
+    commands: &CommandRegistry,
+    name: &str,
+    title: &str,
+    text_view: &TextView,
+    operation: fn(&TextBuffer),
+) {
+    let text_view = text_view.clone();
+    commands.register(name, title, move || {
+        operation(&text_view.buffer());
+        text_view.scroll_mark_onscreen(&text_view.buffer().get_insert());
+    });
+}
+
+pub fn register_line_commands(commands: &CommandRegistry, text_view: &TextView) {
+    register(
+        commands,
+        "edit.move_lines_up",
+        "Move Lines Up",
+        text_view,
+        move_lines_up,
+    );
+    register(
+        commands,
+        "edit.move_lines_down",
+        "Move Lines Down",
+        text_view,
+        move_lines_down,
+    );
+    register(
+        commands,
+        "edit.duplicate_lines",
+        "Duplicate Lines",
+        text_view,
+        duplicate_lines,
+    );
+    register(
+        commands,
+        "edit.delete_lines",
+        "Delete Lines",
+        text_view,
+        delete_lines,
+    );
+    register(
+        commands,
+        "edit.join_lines",
+        "Join Lines",
+        text_view,
+        join_lines,
+    );
+    register(
+        commands,
+        "edit.sort_lines",
+        "Sort Lines",
+        text_view,
+        |buffer| sort_lines(buffer, false, false),
+    );
+    register(
+        commands,
+        "edit.sort_lines_natural",
+        "Sort Lines (Natural Order)",
+        text_view,
+        |buffer| sort_lines(buffer, true, false),
+    );
+    register(
+        commands,
+        "edit.sort_lines_unique",
+        "Sort Lines and Remove Duplicates",
+        text_view,
+        |buffer| sort_lines(buffer, false, true),
+    );
+    register(
+        commands,
+        "edit.sort_lines_natural_unique",
+        "Sort Lines (Natural Order) and Remove Duplicates",
+        text_view,
+        |buffer| sort_lines(buffer, true, true),
+    );
+    register(
+        commands,
+        "edit.reverse_lines",
+        "Reverse Lines",
+        text_view,
+        reverse_lines,
+    );
+}
@@ -0,0 +1,831 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use gtk4::{TextBuffer, TextIter, gio, glib, prelude::*};
+use serde_json::{Value, json};
+
+use crate::{framing, semantic_tokens};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+type ResponseHandler = Box<dyn FnOnce(Result<Value, String>)>;
+type NotificationHandler = Rc<dyn Fn(&str, &Value)>;
+type RequestHandler = Rc<dyn Fn(&str, &Value) -> Result<Value, String>>;
+type Handler = Rc<dyn Fn()>;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16,
+}
+
+pub struct Client {
+    pub root: PathBuf,
+    pub name: String,
+    process: gio::Subprocess,
+    writer: Rc<framing::Writer>,
+    next_id: Cell<i64>,
+    pending: RefCell<HashMap<i64, ResponseHandler>>,
+    queued: RefCell<Vec<Value>>,
+    initialized: Cell<bool>,
+    running: Cell<bool>,
+    stopping: Cell<bool>,
+    capabilities: RefCell<Value>,
+    encoding: Cell<Encoding>,
+    initialized_handlers: RefCell<Vec<Handler>>,
+    notification_handlers: RefCell<Vec<NotificationHandler>>,
+    request_handler: RefCell<Option<RequestHandler>>,
+    exited_handlers: RefCell<Vec<Handler>>,
+}
+
+pub fn uri_from_path(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+pub fn path_from_uri(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+    while index < encoded.len() {
+        if encoded[index] == b'%'
+            && let Some(byte) = encoded
+                .get(index + 1..index + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            bytes.push(byte);
+            index += 3;
+        } else {
+            bytes.push(encoded[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+pub fn column_to_index(line: &str, character: u64, encoding: Encoding) -> usize {
+    if encoding == Encoding::Utf8 {
+        let mut index = (character as usize).min(line.len());
+        while !line.is_char_boundary(index) {
+            index -= 1;
+        }
+        return index;
+    }
+    let mut units = 0;
+    for (index, character_at) in line.char_indices() {
+        if units >= character {
+            return index;
+        }
+        units += character_at.len_utf16() as u64;
+    }
+    line.len()
+}
+
+pub fn index_to_column(line: &str, index: usize, encoding: Encoding) -> u64 {
+    let prefix = line.get(..index).unwrap_or(line);
+    match encoding {
+        Encoding::Utf8 => prefix.len() as u64,
+        Encoding::Utf16 => prefix.encode_utf16().count() as u64,
+    }
+}
+
+pub fn position(iter: &TextIter, encoding: Encoding) -> Value {
+    let mut start = *iter;
+    start.set_line_offset(0);
+    let prefix = start.slice(iter);
+    json!({
+        "line": iter.line(),
+        "character": index_to_column(&prefix, prefix.len(), encoding),
+    })
+}
+
+pub fn iter_at_position(buffer: &TextBuffer, position: &Value, encoding: Encoding) -> TextIter {
+    let line = position["line"].as_i64().unwrap_or(0) as i32;
+    let Some(start) = buffer.iter_at_line(line) else {
+        return buffer.end_iter();
+    };
+    let mut end = start;
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    let text = start.slice(&end);
+    let index = column_to_index(&text, position["character"].as_u64().unwrap_or(0), encoding);
+    buffer
+        .iter_at_line_index(line, index as i32)
+        .unwrap_or(start)
+}
+
+pub fn offset_in_text(text: &str, position: &Value, encoding: Encoding) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let mut start = 0;
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(newline) => start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let end = text[start..]
+        .find('\n')
+        .map(|newline| start + newline)
+        .unwrap_or(text.len());
+    start
+        + column_to_index(
+            &text[start..end],
+            position["character"].as_u64().unwrap_or(0),
+            encoding,
+        )
+}
+
+pub fn apply_edits(text: &str, edits: &[Value], encoding: Encoding) -> String {
+    let mut ranges: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| {
+            let start = offset_in_text(text, &edit["range"]["start"], encoding);
+            let end = offset_in_text(text, &edit["range"]["end"], encoding);
+            (
+                start,
+                end.max(start),
+                edit["newText"].as_str().unwrap_or(""),
+            )
+        })
+        .collect();
+    ranges.sort_by_key(|(start, end, _)| (*start, *end));
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, end, new_text) in ranges {
+        if start < copied {
+            continue;
+        }
+        result.push_str(&text[copied..start]);
+        result.push_str(new_text);
+        copied = end;
+    }
+    result.push_str(&text[copied..]);
+    result
+}
+
+async fn read_messages(weak: Weak<Client>, input: gio::DataInputStream) {
+    while let Some(message) = framing::read_message(&input).await {
+        let Some(client) = weak.upgrade() else {
+            return;
+        };
+        match message {
+            Ok(message) => client.dispatch(message),
+            Err(error) => eprintln!("Invalid message from {}: {}", client.name, error),
+        }
+    }
+    if let Some(client) = weak.upgrade() {
+        client.exited();
+    }
+}
+
+impl Client {
+    pub fn start(root: &Path, command: &[String]) -> Result<Rc<Client>, String> {
+        let launcher = gio::SubprocessLauncher::new(
+            gio::SubprocessFlags::STDIN_PIPE | gio::SubprocessFlags::STDOUT_PIPE,
+        );
+        if root.is_dir() {
+            launcher.set_cwd(root);
+        }
+        let arguments: Vec<&OsStr> = command.iter().map(OsStr::new).collect();
+        let process = launcher
+            .spawn(&arguments)
+            .map_err(|error| error.message().to_owned())?;
+        let (Some(stdin), Some(stdout)) = (process.stdin_pipe(), process.stdout_pipe()) else {
+            return Err("The language server has no standard streams".to_owned());
+        };
+        let client = Rc::new(Client {
+            root: root.to_path_buf(),
+            name: command[0].clone(),
+            process,
+            writer: framing::Writer::new(stdin),
+            next_id: Cell::new(1),
+            pending: RefCell::new(HashMap::new()),
+            queued: RefCell::new(Vec::new()),
+            initialized: Cell::new(false),
+            running: Cell::new(true),
+            stopping: Cell::new(false),
+            capabilities: RefCell::new(Value::Null),
+            encoding: Cell::new(Encoding::Utf16),
+            initialized_handlers: RefCell::new(Vec::new()),
+            notification_handlers: RefCell::new(Vec::new()),
+            request_handler: RefCell::new(None),
+            exited_handlers: RefCell::new(Vec::new()),
+        });
+        glib::spawn_future_local(read_messages(
+            Rc::downgrade(&client),
+            gio::DataInputStream::new(&stdout),
+        ));
+        client.initialize();
+        Ok(client)
+    }
+
+    fn initialize(self: &Rc<Self>) {
+        let root_uri = uri_from_path(&self.root);
+        let name = self
+            .root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let params = json!({
+            "processId": std::process::id(),
+            "clientInfo": { "name": "moon" },
+            "rootPath": self.root.to_string_lossy(),
+            "rootUri": root_uri,
+            "workspaceFolders": [{ "uri": root_uri, "name": name }],
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-8", "utf-16"] },
+                "workspace": {
+                    "applyEdit": true,
+                    "configuration": true,
+                    "workspaceFolders": true,
+                    "workspaceEdit": { "documentChanges": true },
+                },
+                "window": { "showMessage": {} },
+                "textDocument": {
+                    "synchronization": { "didSave": true },
+                    "publishDiagnostics": {},
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "completion": {
+                        "completionItem": { "snippetSupport": true },
+                        "contextSupport": true,
+                    },
+                    "signatureHelp": {
+                        "signatureInformation": {
+                            "parameterInformation": { "labelOffsetSupport": true },
+                        },
+                    },
+                    "definition": {},
+                    "references": {},
+                    "rename": {},
+                    "codeAction": {
+                        "codeActionLiteralSupport": {
+                            "codeActionKind": {
+                                "valueSet": [
+                                    "",
+                                    "quickfix",
+                                    "refactor",
+                                    "refactor.extract",
+                                    "refactor.inline",
+                                    "refactor.rewrite",
+                                    "source",
+                                    "source.organizeImports",
+                                ],
+                            },
+                        },
+                        "resolveSupport": { "properties": ["edit"] },
+                    },
+                    "formatting": {},
+                    "semanticTokens": {
+                        "requests": { "full": true },
+                        "tokenTypes": semantic_tokens::TOKEN_TYPES,
+                        "tokenModifiers": semantic_tokens::TOKEN_MODIFIERS,
+                        "formats": ["relative"],
+                    },
+                },
+            },
+        });
+        let weak = Rc::downgrade(self);
+        let id = self.register(Box::new(move |result| {
+            let Some(client) = weak.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(result) => {
+                    let capabilities = result["capabilities"].clone();
+                    if capabilities["positionEncoding"] == "utf-8" {
+                        client.encoding.set(Encoding::Utf8);
+                    }
+                    client.capabilities.replace(capabilities);
+                    client.send(json!({
+                        "jsonrpc": "2.0",
+                        "method": "initialized",
+                        "params": {},
+                    }));
+                    client.initialized.set(true);
+                    for message in client.queued.take() {
+                        client.send(message);
+                    }
+                    let handlers = client.initialized_handlers.borrow().clone();
+                    for handler in handlers {
+                        handler();
+                    }
+                }
+                Err(error) => {
+                    eprintln!("Could not initialize {}: {}", client.name, error);
+                    client.kill();
+                }
+            }
+        }));
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "initialize",
+            "params": params,
+        }));
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.get()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.get()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.get()
+    }
+
+    pub fn capability(&self, name: &str) -> Value {
+        self.capabilities.borrow()[name].clone()
+    }
+
+    pub fn supports(&self, name: &str) -> bool {
+        !matches!(self.capability(name), Value::Null | Value::Bool(false))
+    }
+
+    pub fn connect_initialized(&self, handler: impl Fn() + 'static) {
+        self.initialized_handlers
+            .borrow_mut()
+            .push(Rc::new(handler));
+    }
+
+    pub fn connect_notification(&self, handler: impl Fn(&str, &Value) + 'static) {
+        self.notification_handlers
+            .borrow_mut()
+            .push(Rc::new(handler));
+    }
+
+    pub fn set_request_handler(
+        &self,
+        handler: impl Fn(&str, &Value) -> Result<Value, String> + 'static,
+    ) {
+        self.request_handler.replace(Some(Rc::new(handler)));
+    }
+
+    pub fn connect_exited(&self, handler: impl Fn() + 'static) {
+        self.exited_handlers.borrow_mut().push(Rc::new(handler));
+    }
+
+    fn register(&self, handler: ResponseHandler) -> i64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.pending.borrow_mut().insert(id, handler);
+        id
+    }
+
+    pub fn request(
+        self: &Rc<Self>,
+        method: &str,
+        params: Value,
+        handler: impl FnOnce(Result<Value, String>) + 'static,
+    ) {
+        if !self.running.get() {
+            handler(Err(format!("{} is not running", self.name)));
+            return;
+        }
+        let id = self.register(Box::new(handler));
+        self.post(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }));
+    }
+
+    pub fn notify(self: &Rc<Self>, method: &str, params: Value) {
+        self.post(json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }));
+    }
+
+    fn post(self: &Rc<Self>, message: Value) {
+        if self.initialized.get() {
+            self.send(message);
+        } else {
+            self.queued.borrow_mut().push(message);
+        }
+    }
+
+    fn send(&self, message: Value) {
+        if !self.running.get() {
+            return;
+        }
+        self.writer.send(&message);
+    }
+
+    fn dispatch(self: &Rc<Self>, message: Value) {
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        match (message["method"].as_str(), message.get("id")) {
+            (Some(method), Some(id)) => {
+                let handler = self.request_handler.borrow().clone();
+                let result = match handler {
+                    Some(handler) => handler(method, &params),
+                    None => Err(format!("Unsupported request {}", method)),
+                };
+                self.send(match result {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err(error) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": error },
+                    }),
+                });
+            }
+            (Some(method), None) => {
+                let handlers = self.notification_handlers.borrow().clone();
+                for handler in handlers {
+                    handler(method, &params);
+                }
+            }
+            (None, Some(id)) => {
+                let handler = id
+                    .as_i64()
+                    .and_then(|id| self.pending.borrow_mut().remove(&id));
+                if let Some(handler) = handler {
+                    handler(match message.get("error") {
+                        Some(error) => Err(error["message"]
+                            .as_str()
+                            .unwrap_or("The language server reported an error")
+                            .to_owned()),
+                        None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                    });
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    fn exited(&self) {
+        if !self.running.replace(false) {
+            return;
+        }
+        let pending: Vec<ResponseHandler> = self.pending.take().into_values().collect();
+        for handler in pending {
+            handler(Err(format!("{} exited", self.name)));
+        }
+        let handlers = self.exited_handlers.borrow().clone();
+        for handler in handlers {
+            handler();
+        }
+    }
+
+    pub fn shutdown(self: &Rc<Self>) {
+        if !self.running.get() {
+            return;
+        }
+        self.stopping.set(true);
+        let weak = Rc::downgrade(self);
+        self.request("shutdown", Value::Null, move |_| {
+            if let Some(client) = weak.upgrade() {
+                client.notify("exit", Value::Null);
+            }
+        });
+        let process = self.process.clone();
+        glib::timeout_add_local_once(SHUTDOWN_TIMEOUT, move || process.force_exit());
+    }
+
+    pub fn stop(&self) {
+        self.stopping.set(true);
+        if !self.running.get() || self.writer.is_writing() {
+            self.kill();
+            return;
+        }
+        for message in [
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "shutdown", "params": null }),
+            json!({ "jsonrpc": "2.0", "method": "exit", "params": null }),
+        ] {
+            if !self.writer.write_now(&message) {
+                self.kill();
+                return;
+            }
+        }
+    }
+
+    pub fn kill(&self) {
+        self.stopping.set(true);
+        self.process.force_exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, env, rc::Rc};
+
+    use super::*;
+    use crate::fake_server::{self, Peer};
+
+    const TEXT: &str = "let a = 1.\nsay \"😀\" a.\n";
+
+    fn at(line: u64, character: u64) -> Value {
+        json!({ "line": line, "character": character })
+    }
+
+    fn edit(start: Value, end: Value, text: &str) -> Value {
+        json!({ "range": { "start": start, "end": end }, "newText": text })
+    }
+
+    fn is_word(character: char) -> bool {
+        character.is_alphanumeric() || character == '_'
+    }
+
+    fn rename_edits(text: &str, position: &Value, new_name: &str) -> Vec<Value> {
+        let line = text
+            .lines()
+            .nth(position["line"].as_u64().unwrap_or(0) as usize)
+            .unwrap_or("");
+        let index = column_to_index(
+            line,
+            position["character"].as_u64().unwrap_or(0),
+            Encoding::Utf16,
+        );
+        let start = line[..index]
+            .rfind(|character| !is_word(character))
+            .map_or(0, |found| found + 1);
+        let end = line[index..]
+            .find(|character| !is_word(character))
+            .map_or(line.len(), |found| index + found);
+        let name = &line[start..end];
+        let mut edits = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            for (index, _) in line.match_indices(name) {
+                let before = line[..index].chars().next_back();
+                let after = line[index + name.len()..].chars().next();
+                if before.is_some_and(is_word) || after.is_some_and(is_word) {
+                    continue;
+                }
+                let column = |index| index_to_column(line, index, Encoding::Utf16);
+                edits.push(edit(
+                    at(number as u64, column(index)),
+                    at(number as u64, column(index + name.len())),
+                    new_name,
+                ));
+            }
+        }
+        edits
+    }
+
+    #[test]
+    fn language_server() {
+        if !fake_server::is_server() {
+            return;
+        }
+        let mut peer = Peer::connect();
+        let initialize = peer.receive();
+        assert_eq!(initialize["method"], "initialize");
+        assert!(initialize["params"]["rootUri"].is_string());
+        peer.send(json!({
+            "jsonrpc": "2.0",
+            "id": initialize["id"],
+            "result": {
+                "capabilities": {
+                    "positionEncoding": "utf-16",
+                    "textDocumentSync": 2,
+                    "completionProvider": {},
+                    "renameProvider": true,
+                },
+            },
+        }));
+        let mut text = String::new();
+        loop {
+            let message = peer.receive();
+            let params = &message["params"];
+            let result = match message["method"].as_str().unwrap_or("") {
+                "textDocument/didOpen" => {
+                    text = params["textDocument"]["text"]
+                        .as_str()
+                        .unwrap_or("")
+                        .to_owned();
+                    None
+                }
+                "textDocument/didChange" => {
+                    for change in params["contentChanges"].as_array().into_iter().flatten() {
+                        let edit = json!({ "range": change["range"], "newText": change["text"] });
+                        text = apply_edits(&text, &[edit], Encoding::Utf16);
+                    }
+                    None
+                }
+                "textDocument/completion" => Some(json!([{
+                    "label": format!(
+                        "{}:{}",
+                        params["position"]["line"],
+                        params["position"]["character"]
+                    ),
+                }])),
+                "textDocument/rename" => Some(json!({
+                    "changes": {
+                        params["textDocument"]["uri"].as_str().unwrap_or(""): rename_edits(
+                            &text,
+                            &params["position"],
+                            params["newName"].as_str().unwrap_or(""),
+                        ),
+                    },
+                })),
+                "shutdown" => Some(Value::Null),
+                "exit" => std::process::exit(0),
+                _ => continue,
+            };
+            match result {
+                Some(result) => peer.send(json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": result,
+                })),
+                None => peer.send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {
+                        "uri": params["textDocument"]["uri"],
+                        "diagnostics": [{
+                            "range": { "start": at(0, 0), "end": at(0, 0) },
+                            "message": text,
+                        }],
+                    },
+                })),
+            }
+        }
+    }
+
+    #[gtk4::test]
+    fn client_talks_to_a_language_server() {
+        let root = env::temp_dir();
+        let uri = uri_from_path(&root.join("main.qat"));
+        let client = Client::start(&root, &fake_server::command("lsp::tests::language_server"))
+            .expect("Could not start the fake language server");
+        let diagnostics = Rc::new(RefCell::new(Vec::new()));
+        let publishing = diagnostics.clone();
+        client.connect_notification(move |method, params| {
+            if method == "textDocument/publishDiagnostics" {
+                publishing
+                    .borrow_mut()
+                    .push(params["diagnostics"][0]["message"].clone());
+            }
+        });
+        let exited = Rc::new(Cell::new(false));
+        let exiting = exited.clone();
+        client.connect_exited(move || exiting.set(true));
+
+        client.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": uri, "languageId": "qat", "version": 1, "text": TEXT },
+            }),
+        );
+        fake_server::run_until(|| client.is_initialized());
+        assert!(client.encoding() == Encoding::Utf16);
+        assert!(client.supports("renameProvider"));
+        assert!(!client.supports("hoverProvider"));
+        fake_server::run_until(|| diagnostics.borrow().len() == 1);
+        assert_eq!(diagnostics.borrow()[0], TEXT);
+
+        client.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [
+                    { "range": { "start": at(0, 8), "end": at(0, 9) }, "text": "42" },
+                    { "range": { "start": at(1, 10), "end": at(1, 10) }, "text": " + 1" },
+                ],
+            }),
+        );
+        fake_server::run_until(|| diagnostics.borrow().len() == 2);
+        let text = "let a = 42.\nsay \"😀\" a + 1.\n";
+        assert_eq!(diagnostics.borrow()[1], text);
+
+        let completion = Rc::new(RefCell::new(None));
+        let completing = completion.clone();
+        client.request(
+            "textDocument/completion",
+            json!({ "textDocument": { "uri": uri }, "position": at(1, 9) }),
+            move |result| {
+                completing.replace(Some(result));
+            },
+        );
+        fake_server::run_until(|| completion.borrow().is_some());
+        let items = completion.take().unwrap().expect("Completion failed");
+        assert_eq!(items[0]["label"], "1:9");
+
+        let rename = Rc::new(RefCell::new(None));
+        let renaming = rename.clone();
+        client.request(
+            "textDocument/rename",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": at(1, 9),
+                "newName": "count",
+            }),
+            move |result| {
+                renaming.replace(Some(result));
+            },
+        );
+        fake_server::run_until(|| rename.borrow().is_some());
+        let workspace_edit = rename.take().unwrap().expect("Rename failed");
+        let edits = workspace_edit["changes"][&uri]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        assert_eq!(
+            apply_edits(text, &edits, client.encoding()),
+            "let count = 42.\nsay \"😀\" count + 1.\n"
+        );
+
+        client.shutdown();
+        fake_server::run_until(|| exited.get());
+        assert!(client.is_stopping());
+        assert!(!client.is_running());
+    }
+
+    #[test]
+    fn uris_round_trip_paths() {
+        let path = Path::new("/home/user/my project/naïve #1.qat");
+        let uri = uri_from_path(path);
+        assert_eq!(uri, "file:///home/user/my%20project/na%C3%AFve%20%231.qat");
+        assert_eq!(path_from_uri(&uri), Some(path.to_path_buf()));
+        assert_eq!(
+            path_from_uri("file:///tmp/a%2"),
+            Some(PathBuf::from("/tmp/a%2"))
+        );
+        assert_eq!(path_from_uri("https://example.com/a.qat"), None);
+    }
+
+    #[test]
+    fn columns_convert_to_byte_indices() {
+        assert_eq!(column_to_index("abc", 2, Encoding::Utf16), 2);
+        assert_eq!(column_to_index("abc", 9, Encoding::Utf16), 3);
+        assert_eq!(column_to_index("abc", 9, Encoding::Utf8), 3);
+        let line = "aé😀b";
+        assert_eq!(column_to_index(line, 2, Encoding::Utf16), 3);
+        assert_eq!(column_to_index(line, 4, Encoding::Utf16), 7);
+        assert_eq!(column_to_index(line, 3, Encoding::Utf8), 3);
+        assert_eq!(index_to_column(line, 7, Encoding::Utf16), 4);
+        assert_eq!(index_to_column(line, 7, Encoding::Utf8), 7);
+    }
+
+    #[test]
+    fn utf8_columns_inside_a_character_stay_on_a_boundary() {
+        let line = "aé😀b";
+        assert_eq!(column_to_index(line, 2, Encoding::Utf8), 1);
+        assert_eq!(column_to_index(line, 5, Encoding::Utf8), 3);
+        assert_eq!(offset_in_text("x\naé😀b", &at(1, 6), Encoding::Utf8), 5);
+        assert_eq!(
+            apply_edits("é😀", &[edit(at(0, 1), at(0, 5), "-")], Encoding::Utf8),
+            "-😀"
+        );
+    }
+
+    #[test]
+    fn positions_convert_to_offsets() {
+        let text = "ab\né😀\nx";
+        assert_eq!(offset_in_text(text, &at(0, 1), Encoding::Utf16), 1);
+        assert_eq!(offset_in_text(text, &at(0, 9), Encoding::Utf16), 2);
+        assert_eq!(offset_in_text(text, &at(1, 3), Encoding::Utf16), 9);
+        assert_eq!(offset_in_text(text, &at(2, 0), Encoding::Utf16), 10);
+        assert_eq!(offset_in_text(text, &at(7, 0), Encoding::Utf16), text.len());
+    }
+
+    #[test]
+    fn edits_apply_in_position_order() {
+        let edits = [
+            edit(at(1, 9), at(1, 10), "b"),
+            edit(at(0, 4), at(0, 5), "b"),
+            edit(at(2, 0), at(2, 0), "give b.\n"),
+        ];
+        assert_eq!(
+            apply_edits(TEXT, &edits, Encoding::Utf16),
+            "let b = 1.\nsay \"😀\" b.\ngive b.\n"
+        );
+        assert_eq!(
+            apply_edits(TEXT, &[edit(at(0, 0), at(1, 0), "")], Encoding::Utf16),
+            "say \"😀\" a.\n"
+        );
+        assert_eq!(
+            apply_edits(
+                "abcdefg",
+                &[edit(at(0, 0), at(0, 5), "X"), edit(at(0, 2), at(0, 3), "Y")],
+                Encoding::Utf16
+            ),
+            "Xfg"
+        );
+    }
+}
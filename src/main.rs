@@ -1,19 +1,55 @@
-use std::collections::HashSet;
+mod blame;
+mod bookmarks;
+mod breadcrumbs;
+mod commands;
+mod completion;
+mod conflicts;
+mod dap;
+mod debugger;
+mod diff;
+mod diff_view;
+mod document;
+#[cfg(test)]
+mod fake_server;
+mod format;
+mod framing;
+mod fuzzy;
+mod git;
+mod git_gutter;
+mod gutter;
+mod highlight;
+mod history;
+mod keymap;
+mod language_server;
+mod line_ops;
+mod lsp;
+mod minimap;
+mod notification;
+mod outline;
+mod palette;
+mod rename;
+mod results;
+mod semantic_tokens;
+mod settings;
+mod snippets;
+mod syntax;
+mod tasks;
+mod terminal;
+mod terminal_screen;
+mod view;
+mod vim;
+mod word_index;
+mod workspace;
+
+use std::{path::Path, rc::Rc};
 
 use gtk4::{
-    Application, ApplicationWindow, Box, CallbackAction, CssProvider, Orientation, PolicyType,
-    STYLE_PROVIDER_PRIORITY_APPLICATION, ScrolledWindow, Settings, Shortcut, ShortcutController,
-    ShortcutTrigger, TextBuffer, TextView, gdk, gio::prelude::ApplicationExt, glib::Propagation,
-    pango, prelude::*, style_context_add_provider_for_display,
+    Application, ApplicationWindow, Box, CssProvider, Label, MovementStep, Orientation, Overlay,
+    PolicyType, STYLE_PROVIDER_PRIORITY_APPLICATION, ScrolledWindow, Settings, TextBuffer,
+    TextView, gdk, gio::prelude::ApplicationExt, pango, prelude::*,
+    style_context_add_provider_for_display,
 };
 
-use tree_sitter::{Language, Parser};
-use unicode_segmentation::UnicodeSegmentation;
-
-unsafe extern "C" {
-    fn tree_sitter_qat() -> Language;
-}
-
 fn set_tab_width(text_view: &TextView, font_description: &pango::FontDescription) {
     let layout = text_view.create_pango_layout(Some(" "));
     layout.set_font_description(Some(font_description));
@@ -26,6 +62,138 @@ fn set_tab_width(text_view: &TextView, font_description: &pango::FontDescription
     text_view.set_tabs(&tabs);
 }
 
+fn register_editor_commands(
+    commands: &commands::CommandRegistry,
+    window: &ApplicationWindow,
+    text_view: &TextView,
+    document: &Rc<document::Document>,
+) {
+    let saving = document.clone();
+    commands.register("file.save", "Save File", move || {
+        if let Err(error) = saving.save() {
+            eprintln!("Could not save {}: {}", saving.path().display(), error);
+        }
+    });
+    let closing = window.clone();
+    commands.register("app.quit", "Quit", move || closing.close());
+    let jumping = text_view.clone();
+    commands.register_with_argument(
+        "view.go_to_line",
+        "Go to Line",
+        commands::Argument {
+            prompt: "Line number, optionally followed by :column".to_owned(),
+            choices: Vec::new(),
+        },
+        move |argument| {
+            let mut parts = argument.trim().splitn(2, ':');
+            let Some(Ok(line)) = parts.next().map(|line| line.trim().parse::<i32>()) else {
+                return;
+            };
+            let column = parts
+                .next()
+                .and_then(|column| column.trim().parse::<i32>().ok())
+                .unwrap_or(1);
+            let buffer = jumping.buffer();
+            let line = (line - 1).clamp(0, buffer.line_count() - 1);
+            let iter = buffer
+                .iter_at_line_offset(line, column - 1)
+                .or_else(|| buffer.iter_at_line(line))
+                .unwrap_or(buffer.end_iter());
+            buffer.place_cursor(&iter);
+            jumping.scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.5);
+            jumping.grab_focus();
+        },
+    );
+    let setting_language = document.clone();
+    commands.register_with_argument(
+        "editor.set_language",
+        "Set Language",
+        commands::Argument {
+            prompt: "Language".to_owned(),
+            choices: document::LANGUAGES
+                .iter()
+                .map(|language| (language.to_string(), String::new()))
+                .collect(),
+        },
+        move |language| {
+            if document::LANGUAGES.contains(&language) {
+                setting_language.set_language(language);
+            }
+        },
+    );
+    let undoing = text_view.clone();
+    commands.register("edit.undo", "Undo", move || undoing.buffer().undo());
+    let redoing = text_view.clone();
+    commands.register("edit.redo", "Redo", move || redoing.buffer().redo());
+    for (name, title, step, count) in [
+        (
+            "cursor.line_start",
+            "Go to Line Start",
+            MovementStep::DisplayLineEnds,
+            -1,
+        ),
+        (
+            "cursor.line_end",
+            "Go to Line End",
+            MovementStep::DisplayLineEnds,
+            1,
+        ),
+        (
+            "cursor.next_line",
+            "Go to Next Line",
+            MovementStep::DisplayLines,
+            1,
+        ),
+        (
+            "cursor.previous_line",
+            "Go to Previous Line",
+            MovementStep::DisplayLines,
+            -1,
+        ),
+        (
+            "cursor.forward_char",
+            "Go Forward One Character",
+            MovementStep::VisualPositions,
+            1,
+        ),
+        (
+            "cursor.backward_char",
+            "Go Back One Character",
+            MovementStep::VisualPositions,
+            -1,
+        ),
+        (
+            "cursor.forward_word",
+            "Go Forward One Word",
+            MovementStep::Words,
+            1,
+        ),
+        (
+            "cursor.backward_word",
+            "Go Back One Word",
+            MovementStep::Words,
+            -1,
+        ),
+        (
+            "cursor.buffer_start",
+            "Go to Start of File",
+            MovementStep::BufferEnds,
+            -1,
+        ),
+        (
+            "cursor.buffer_end",
+            "Go to End of File",
+            MovementStep::BufferEnds,
+            1,
+        ),
+    ] {
+        let moving = text_view.clone();
+        commands.register(name, title, move || {
+            moving.emit_move_cursor(step, count, false)
+        });
+    }
+}
+
 fn main() {
     gtk4::init().expect("Failed to initialise GTK4");
     let app = Application::builder()
@@ -41,33 +209,82 @@ fn main() {
             .default_width(1920)
             .default_height(1080)
             .build();
-        let shortcut_manager = ShortcutController::new();
-        shortcut_manager.set_scope(gtk4::ShortcutScope::Global);
-        let save_trigger = ShortcutTrigger::parse_string("<Control>s").unwrap();
-        let save_action = CallbackAction::new(move |_, _| {
-            println!("Ctrl+S pressed");
-            Propagation::Stop
-        });
-        let shortcut_save = Shortcut::new(Some(save_trigger), Some(save_action));
-        shortcut_manager.add_shortcut(shortcut_save);
-        window.add_controller(shortcut_manager);
+        let commands = commands::CommandRegistry::default();
         let css = CssProvider::new();
         css.load_from_data(
-            "#text_field {
+            "#text_field, #revision_view, #diff_view {
             background-color: #222528;
             font-size: 14pt;
             line-height: 1.5;
             font-family: 'Agave Nerd Font';
-        }",
+        }
+        status_bar {
+            background-color: #1b1d20;
+            padding: 2px 10px;
+            font-family: 'Agave Nerd Font';
+        }
+        breadcrumbs {
+            background-color: #1b1d20;
+            padding: 0 6px;
+        }
+        #sticky_header {
+            background-color: #222528;
+            border-bottom: 1px solid #33373b;
+            font-size: 14pt;
+            font-family: 'Agave Nerd Font';
+        }
+        #notification {
+            background-color: #2b2f33;
+            border: 1px solid #33373b;
+            border-radius: 6px;
+            padding: 6px 8px;
+        }
+        #terminal_view text {
+            background-color: #222528;
+            color: #ffffff;
+        }
+        #terminal_view {
+            font-family: 'Agave Nerd Font';
+            font-size: 12pt;
+        }
+        #results_panel, #task_panel, #debug_panel, #history_panel {
+            border-top: 1px solid #33373b;
+            padding: 0 6px;
+        }
+        #task_panel textview, #debug_panel textview {
+            font-family: 'Agave Nerd Font';
+        }
+        #conflict_actions button {
+            padding: 0 6px;
+            min-height: 0;
+            font-size: 10pt;
+        }
+        #sticky_header button {
+            padding: 0 4px;
+            min-height: 0;
+        }
+        .completion_icon {
+            font-family: 'Agave Nerd Font';
+            min-width: 1.2em;
+        }
+        .completion_keyword { color: #ff88cd; }
+        .completion_type { color: #fbd37d; }
+        .completion_builtin { color: #b29bff; }
+        .completion_constant { color: #ffb293; }
+        .completion_variable { color: #ffffff; }
+        .completion_field { color: #ff7272; }
+        .completion_function { color: #69a5ff; }
+        .completion_snippet { color: #a5ff8e; }
+        .completion_word { color: #c5ffff; }",
         );
         style_context_add_provider_for_display(
             &gdk::Display::default().expect("Could not get GDK Display"),
             &css,
             STYLE_PROVIDER_PRIORITY_APPLICATION,
         );
-        let text_view = TextView::new();
+        let text_view = view::EditorView::new();
         set_tab_width(
-            &text_view,
+            text_view.upcast_ref(),
             &pango::FontDescription::from_string("Agave Nerd Font 14"),
         );
         let main_col = Box::builder()
@@ -83,6 +300,8 @@ fn main() {
         text_view.set_right_margin(field_margin);
         text_view.set_vexpand(true);
         text_view.set_hexpand(true);
+        let editor_settings = settings::load_settings();
+        text_view.apply_settings(&editor_settings);
         let scrolled_window = ScrolledWindow::builder()
             .child(&text_view)
             .hscrollbar_policy(PolicyType::Automatic)
@@ -93,538 +312,201 @@ fn main() {
             .vexpand(true)
             .build();
         let buffer = text_view.buffer();
-        buffer.set_text(
-            std::fs::read_to_string("/mnt/main/dev/qatlang/qat/test/main.qat")
-                .expect("Failed to read source file")
-                .as_str(),
-        );
-        let tag_keyword = buffer
-            .create_tag(Some("keyword"), &[("foreground", &"#ff88cd")])
-            .expect("Could not create tag for keywords");
-        let tag_function = buffer
-            .create_tag(
-                Some("function"),
-                &[("foreground", &"#69a5ff"), ("weight", &700)],
-            )
-            .expect("Could not create tag for function names");
-        let tag_type = buffer
-            .create_tag(
-                Some("type"),
-                &[("foreground", &"#fbd37d"), ("weight", &700)],
-            )
-            .expect("Could not create tag for type");
-        let tag_type_builtin = buffer
-            .create_tag(
-                Some("type_builtin"),
-                &[("foreground", &"#b29bff"), ("weight", &700)],
-            )
-            .expect("Could not create tag for builtin types");
-        let tag_constant = buffer
-            .create_tag(Some("constant"), &[("foreground", &"#ffb293")])
-            .expect("Could not create tag for constants");
-        let tag_string = buffer
-            .create_tag(Some("string"), &[("foreground", &"#a5ff8e")])
-            .expect("Could not create tag for strings");
-        let tag_escape_string = buffer
-            .create_tag(
-                Some("escape_string"),
-                &[("foreground", &"#c5ffff"), ("weight", &700)],
-            )
-            .expect("Could not create tag for escape strings");
-        let tag_dead = buffer
-            .create_tag(Some("dead"), &[("foreground", &"#535353")])
-            .expect("Could not create tag for dead code");
-        let tag_field = buffer
-            .create_tag(Some("field"), &[("foreground", &"#ff7272")])
-            .expect("Could not create tag for fields");
-        let tag_important = buffer
-            .create_tag(
-                Some("important"),
-                &[("foreground", &"#ffffff"), ("weight", &700)],
-            )
-            .expect("Could not create tag for important entities");
-        let keyword_list: HashSet<&str> = [
-            "pub", "give", "loop", "struct", "mix", "toggle", "choice", "region", "heap", "is",
-            "in", "own", "let", "meta", "define", "if", "where", "use", "copy", "move", "swap",
-            "pre", "say", "not", "or", "and", "do", "skill", "type", "for", "else", "match", "var",
-            "variadic", "assembly", "from", "to", "flag", "opaque", "end", "operator", "spawn",
-            "ignore", "_", "default", "as", "volatile", "ok", "try",
-        ]
-        .iter()
-        .cloned()
-        .collect();
-        let builtin_types: HashSet<&str> = [
-            "atomic",
-            "i8",
-            "i16",
-            "i32",
-            "i64",
-            "i128",
-            "u1",
-            "u8",
-            "u16",
-            "u32",
-            "u64",
-            "u128",
-            "f32",
-            "f64",
-            "f80",
-            "f128",
-            "f128ppc",
-            "fbrain",
-            "int",
-            "uint",
-            "bytestring",
-            "float",
-            "double",
-            "longdouble",
-            "usize",
-            "isize",
-            "self",
-            "bool",
-            "byte",
-            "char",
-            "uchar",
-            "poly",
-            "maybe",
-            "result",
-            "error",
-            "ref",
-            "ptr",
-            "multi",
-            "text",
-            "slice",
-            "future",
-            "integer",
-            "vec",
-        ]
-        .iter()
-        .cloned()
-        .collect();
-        let constant_list: HashSet<&str> = ["none", "null"].iter().cloned().collect();
-        let change_fn = move |buf: &'_ TextBuffer| {
-            let content = buf.text(&buf.start_iter(), &buf.end_iter(), true);
-            let mut grapheme_indices: Vec<i32> = vec![0; content.len()];
-            for (grapheme_index, (byte_index, grapheme)) in
-                content.grapheme_indices(true).enumerate()
-            {
-                for offset in 0..grapheme.len() {
-                    grapheme_indices[byte_index + offset] = grapheme_index as i32;
-                }
-            }
-            let keyword_list = &keyword_list;
-            let tag_keyword = &tag_keyword;
-            let tag_type = &tag_type;
-            let tag_type_builtin = &tag_type_builtin;
-            let tag_function = &tag_function;
-            let tag_dead = &tag_dead;
-            let tag_constant = &tag_constant;
-            let tag_string = &tag_string;
-            let tag_field = &tag_field;
-            let tag_important = &tag_important;
-            let tag_escape_string = &tag_escape_string;
-            let language = unsafe { tree_sitter_qat() };
-            let mut parser = Parser::new();
-            parser
-                .set_language(&language)
-                .expect("Could not set language");
-            let (start, end) = buf.bounds();
-            let changed_content = buf.text(&start, &end, false);
-            if let Some(tree) = parser.parse(changed_content, None) {
-                let mut cursor = tree.walk();
-                buf.remove_all_tags(&buf.start_iter(), &buf.end_iter());
-                'outer: loop {
-                    let node = cursor.node();
-                    if !node.is_named() {
-                        if keyword_list.contains(&node.kind()) {
-                            let range = node.byte_range();
-                            let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                            let end = buf
-                                .iter_at_offset(grapheme_indices[range.end.min(content.len() - 1)]);
-                            buf.apply_tag(tag_keyword, &start, &end);
-                        } else if builtin_types.contains(&node.kind()) {
-                            let range = node.byte_range();
-                            let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                            let end = buf
-                                .iter_at_offset(grapheme_indices[range.end.min(content.len() - 1)]);
-                            buf.apply_tag(tag_type_builtin, &start, &end);
-                        } else if constant_list.contains(&node.kind()) {
-                            let range = node.byte_range();
-                            let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                            let end = buf
-                                .iter_at_offset(grapheme_indices[range.end.min(content.len() - 1)]);
-                            buf.apply_tag(tag_constant, &start, &end);
-                        } else if node.kind() == "'" {
-                            let range = node.byte_range();
-                            let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                            let end = buf
-                                .iter_at_offset(grapheme_indices[range.end.min(content.len() - 1)]);
-                            buf.apply_tag(tag_important, &start, &end);
-                        }
-                    } else {
-                        let range = node.byte_range();
-                        let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                        let end =
-                            buf.iter_at_offset(grapheme_indices[range.end.min(content.len() - 1)]);
-                        match node.kind() {
-                            "self_instance" => {
-                                let range = node.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                buf.apply_tag(tag_important, &start, &end);
-                            }
-                            "comment_line" | "comment_multi" => {
-                                buf.apply_tag(tag_dead, &start, &end);
-                            }
-                            "literal_string" | "multiline_string" => {
-                                buf.apply_tag(tag_string, &start, &end);
-                            }
-                            "escape_sequence" => {
-                                buf.apply_tag(tag_escape_string, &start, &end);
-                            }
-                            "constants" | "literal_integer" => {
-                                buf.apply_tag(tag_constant, &start, &end);
-                            }
-                            "type" => {
-                                let child =
-                                    node.child(0).expect("Could not find child node in type");
-                                if child.kind() == "entity" {
-                                    let name_field = child.child_by_field_name("name").expect(
-                                        ("Could not get name field in ".to_owned() + &node.kind())
-                                            .as_str(),
-                                    );
-                                    let range = name_field.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    if builtin_types.contains(
-                                        &content[node.byte_range().start..node.byte_range().end],
-                                    ) {
-                                        buf.apply_tag(tag_type_builtin, &start, &end);
-                                    } else if node.parent().is_none() {
-                                        buf.apply_tag(tag_type, &start, &end);
-                                    }
-                                }
-                            }
-                            "type_subtype" => {
-                                let mut tag_value = tag_type;
-                                if let Some(parent1) = node.parent()
-                                    && parent1.kind() == "type_without_entity"
-                                    && node.prev_sibling().is_none()
-                                {
-                                    if let Some(parent2) = parent1.parent()
-                                        && parent1.prev_sibling().is_none()
-                                    {
-                                        if parent2.kind() == "function_call" {
-                                            tag_value = tag_function;
-                                        } else if parent2.kind() == "type" {
-                                            if let Some(parent3) = parent2.parent()
-                                                && parent3.kind() == "type_generic"
-                                                && parent2.prev_sibling().is_none()
-                                            {
-                                                if let Some(parent4) = parent3.parent()
-                                                    && parent4.kind() == "type_without_entity"
-                                                    && parent3.prev_sibling().is_none()
-                                                {
-                                                    if let Some(parent5) = parent4.parent()
-                                                        && parent5.kind() == "function_call"
-                                                        && parent4.prev_sibling().is_none()
-                                                    {
-                                                        tag_value = tag_function;
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                let mut cursor = tree.walk();
-                                if let Some(id) = node.children(&mut cursor).last() {
-                                    let range = id.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    buf.apply_tag(tag_value, &start, &end);
-                                }
-                            }
-                            "type_primitive" | "type_signed_integer" | "type_unsigned_integer" => {
-                                buf.apply_tag(tag_type_builtin, &start, &end);
-                            }
-                            "type_generic" => {
-                                let child =
-                                    node.child(0).expect("Cannot find child in type_generic");
-                                let mut tag_value = tag_type;
-                                if let Some(type_without_entity) = node.parent()
-                                    && node.prev_sibling().is_none()
-                                    && type_without_entity.kind() == "type_without_entity"
-                                {
-                                    if let Some(parent) = type_without_entity.parent()
-                                        && type_without_entity.prev_sibling().is_none()
-                                        && parent.kind() == "function_call"
-                                    {
-                                        tag_value = tag_function;
-                                    }
-                                }
-                                if let Some(second_child) = child.child(0) {
-                                    if second_child.kind() == "entity" {
-                                        let mut cursor = tree.walk();
-                                        let last = second_child
-                                            .children(&mut cursor)
-                                            .last()
-                                            .expect("Could not get last child in entity");
-                                        let range = last.byte_range();
-                                        let start =
-                                            buf.iter_at_offset(grapheme_indices[range.start]);
-                                        let end = buf.iter_at_offset(
-                                            grapheme_indices[range.end.min(content.len() - 1)],
-                                        );
-                                        buf.apply_tag(tag_value, &start, &end);
-                                    }
-                                }
-                            }
-                            "function_definition" | "prerun_function_definition" | "method" => {
-                                let name_field = node.child_by_field_name("name").expect(
-                                    ("Could not get name field in ".to_owned() + &node.kind())
-                                        .as_str(),
-                                );
-                                let range = name_field.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                buf.apply_tag(tag_function, &start, &end);
-                            }
-                            "struct_definition" | "mix_definition" | "toggle_definition"
-                            | "choice_definition" | "flag_definition" | "type_definition"
-                            | "skill_definition" => {
-                                let name_field = node.child_by_field_name("name").expect(
-                                    ("Could not get name field in ".to_owned() + &node.kind())
-                                        .as_str(),
-                                );
-                                let range = name_field.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                buf.apply_tag(tag_type, &start, &end);
-                            }
-                            "struct_field"
-                            | "flag_field"
-                            | "statement_declaration"
-                            | "mix_field"
-                            | "choice_field_name"
-                            | "toggle_field"
-                            | "function_parameter_single" => {
-                                if let Some(name_field) = node.child_by_field_name("name") {
-                                    let range = name_field.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    buf.apply_tag(tag_field, &start, &end);
-                                }
-                            }
-                            "method_arg_single" => {
-                                if let Some(name_field) = node.child_by_field_name("member") {
-                                    let range = name_field.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    buf.apply_tag(tag_field, &start, &end);
-                                }
-                            }
-                            "generic_parameter_single" => {
-                                if let Some(name_field) = node.child_by_field_name("type_parameter")
-                                {
-                                    let range = name_field.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    buf.apply_tag(tag_type, &start, &end);
-                                }
-                                if let Some(name_field) =
-                                    node.child_by_field_name("prerun_parameter")
-                                {
-                                    let range = name_field.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    buf.apply_tag(tag_constant, &start, &end);
-                                }
-                            }
-                            "flag_is_variant" | "flag_initialiser" => {
-                                let mut cursor = tree.walk();
-                                for name in node.children_by_field_name("name", &mut cursor) {
-                                    let range = name.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    buf.apply_tag(tag_field, &start, &end);
-                                }
-                            }
-                            "entity" => {
-                                let name_field = node.child_by_field_name("name").expect(
-                                    ("Could not get name field in ".to_owned() + &node.kind())
-                                        .as_str(),
-                                );
-                                let range = name_field.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                if builtin_types.contains(
-                                    &content[node.byte_range().start..node.byte_range().end],
-                                ) {
-                                    buf.apply_tag(tag_type_builtin, &start, &end);
-                                } else if keyword_list.contains(
-                                    &content[node.byte_range().start..node.byte_range().end],
-                                ) {
-                                    buf.apply_tag(tag_keyword, &start, &end);
-                                } else if constant_list.contains(
-                                    &content[node.byte_range().start..node.byte_range().end],
-                                ) {
-                                    buf.apply_tag(tag_constant, &start, &end);
-                                } else {
-                                    if let Some(parent) = node.parent() {
-                                        match parent.kind() {
-                                            "function_call" | "type" | "type_generic" => {}
-                                            _ => {
-                                                if node.child_count() == 1 {
-                                                    buf.apply_tag(tag_field, &start, &end);
-                                                }
-                                            }
-                                        }
-                                    } else if node.child_count() == 1 {
-                                        buf.apply_tag(tag_field, &start, &end);
-                                    }
-                                }
-                            }
-                            "function_call" => {
-                                let child = node
-                                    .child(0)
-                                    .expect("Could not get child node in function_call");
-                                if child.kind() == "entity" {
-                                    let value =
-                                        &content[child.byte_range().start..child.byte_range().end];
-                                    let name = child.child_by_field_name("name").expect(
-                                        "Could not get name field in entity in function_call",
-                                    );
-                                    let range = name.byte_range();
-                                    let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                    let end = buf.iter_at_offset(
-                                        grapheme_indices[range.end.min(content.len() - 1)],
-                                    );
-                                    if builtin_types.contains(value) {
-                                        buf.apply_tag(tag_type_builtin, &start, &end);
-                                    } else if keyword_list.contains(value) {
-                                        buf.apply_tag(tag_keyword, &start, &end);
-                                    } else {
-                                        buf.apply_tag(tag_function, &start, &end);
-                                    }
-                                } else {
-                                    println!(
-                                        "Function call {} child is {}",
-                                        &content[child.byte_range().start..child.byte_range().end],
-                                        child.kind()
-                                    );
-                                }
-                            }
-                            "constructor_call" => {
-                                let child = node
-                                    .child(0)
-                                    .expect("Could not get child of constructor call");
-                                println!(
-                                    "Child of constructor call {} is {}",
-                                    &content[node.byte_range().start..node.byte_range().end],
-                                    child.kind()
-                                );
-                            }
-                            "mix_initialiser" | "choice_initialiser" => {
-                                let name_field = node.child_by_field_name("name").expect(
-                                    ("Could not get name field in ".to_owned() + &node.kind())
-                                        .as_str(),
-                                );
-                                let range = name_field.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                buf.apply_tag(
-                                    if node.kind() == "mix_initialiser" {
-                                        tag_type
-                                    } else {
-                                        tag_field
-                                    },
-                                    &start,
-                                    &end,
-                                );
-                            }
-                            "heap_get" | "heap_put" | "heap_grow" => {
-                                let name_field = node.child_by_field_name("name").expect(
-                                    ("Could not get name field in ".to_owned() + &node.kind())
-                                        .as_str(),
-                                );
-                                let range = name_field.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                buf.apply_tag(tag_function, &start, &end);
-                            }
-                            "member_access" => {
-                                let name_field = node.child_by_field_name("name").expect(
-                                    ("Could not get name field in ".to_owned() + &node.kind())
-                                        .as_str(),
-                                );
-                                let range = name_field.byte_range();
-                                let start = buf.iter_at_offset(grapheme_indices[range.start]);
-                                let end = buf.iter_at_offset(
-                                    grapheme_indices[range.end.min(content.len() - 1)],
-                                );
-                                if let Some(parent) = node.parent() {
-                                    if parent.kind() == "function_call" {
-                                        buf.apply_tag(tag_function, &start, &end);
-                                    } else {
-                                        buf.apply_tag(tag_field, &start, &end);
-                                    }
-                                } else {
-                                    buf.apply_tag(tag_field, &start, &end);
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    if cursor.goto_first_child() {
-                        continue;
-                    }
-                    if cursor.goto_next_sibling() {
-                        continue;
-                    }
-                    loop {
-                        if cursor.goto_parent() {
-                            if cursor.goto_next_sibling() {
-                                continue 'outer;
-                            } else {
-                                continue;
-                            }
-                        } else {
-                            break 'outer;
-                        }
-                    }
-                }
-            }
-        };
+        let document = document::Document::open(
+            &buffer,
+            Path::new("/mnt/main/dev/qatlang/qat/test/main.qat"),
+        )
+        .expect("Failed to read source file");
+        register_editor_commands(&commands, &window, text_view.upcast_ref(), &document);
+        line_ops::register_line_commands(&commands, text_view.upcast_ref());
+        bookmarks::register_bookmark_commands(&commands, text_view.upcast_ref());
+        highlight::create_tags(&buffer);
+        let highlight_document = document.clone();
+        let change_fn =
+            move |buf: &'_ TextBuffer| highlight::highlight(buf, &highlight_document.language());
         change_fn(&buffer);
-        buffer.connect_changed(change_fn);
-        main_row.append(&scrolled_window);
-        main_col.append(&main_row);
+        buffer.connect_changed(change_fn.clone());
+        let highlight_buffer = buffer.clone();
+        document.connect_language_changed(move |_| change_fn(&highlight_buffer));
+        let breadcrumbs = breadcrumbs::Breadcrumbs::new(
+            text_view.upcast_ref(),
+            &scrolled_window,
+            &document,
+            &commands,
+            editor_settings.breadcrumbs,
+            editor_settings.sticky_scroll,
+        );
+        let overlay = Overlay::builder()
+            .child(&scrolled_window)
+            .hexpand(true)
+            .vexpand(true)
+            .build();
+        overlay.add_overlay(breadcrumbs.sticky_header());
+        let notification = notification::Notification::new(&commands);
+        overlay.add_overlay(notification.widget());
+        let editor_col = Box::builder()
+            .orientation(Orientation::Vertical)
+            .hexpand(true)
+            .build();
+        editor_col.append(breadcrumbs.bar());
+        editor_col.append(&overlay);
+        main_row.append(&editor_col);
+        if editor_settings.minimap {
+            let minimap = minimap::Minimap::new(text_view.upcast_ref(), &scrolled_window);
+            minimap.set_saved_text(document.text());
+            let saved_minimap = minimap.clone();
+            document.connect_saved(move |text| saved_minimap.set_saved_text(text.to_owned()));
+            let opened_minimap = minimap.clone();
+            document.connect_opened(move |text| opened_minimap.set_saved_text(text.to_owned()));
+            main_row.append(minimap.widget());
+        }
+        let outline = outline::Outline::new(
+            text_view.upcast_ref(),
+            &document,
+            &commands,
+            editor_settings.outline,
+        );
+        main_row.prepend(outline.widget());
+        let status_bar = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .css_name("status_bar")
+            .build();
+        let mode_label = Label::builder().xalign(0.0).build();
+        let keymap_label = Label::builder().hexpand(true).xalign(1.0).build();
+        status_bar.append(&mode_label);
+        status_bar.append(&keymap_label);
+        let terminal = terminal::Terminal::new(
+            &main_row,
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &commands,
+            &editor_settings,
+        );
+        main_col.append(terminal.widget());
+        let gutter = gutter::Gutter::new(text_view.upcast_ref(), &scrolled_window);
+        let debugger = debugger::Debugger::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &commands,
+            &gutter,
+            &editor_settings,
+        );
+        main_col.append(debugger.widget());
+        git_gutter::GitGutter::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &commands,
+            &gutter,
+            &editor_settings,
+        );
+        let diff_viewer =
+            diff_view::DiffViewer::new(text_view.upcast_ref(), &document, &keymap_label, &commands);
+        let history = history::History::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &commands,
+            &diff_viewer,
+        );
+        main_col.append(history.widget());
+        blame::Blame::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &commands,
+            &gutter,
+            &history,
+        );
+        conflicts::Conflicts::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &commands,
+        );
+        let results = results::ResultsPanel::new(&commands);
+        let tasks = tasks::Tasks::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &results,
+            &commands,
+        );
+        main_col.append(tasks.widget());
+        main_col.append(results.widget());
+        main_col.append(&status_bar);
+        workspace::Workspace::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &results,
+            &commands,
+        );
+        rename::Rename::new(text_view.upcast_ref(), &document, &keymap_label, &commands);
+        format::Formatter::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &commands,
+            &editor_settings,
+        );
+        let vim = vim::setup_vim(
+            text_view.upcast_ref(),
+            &mode_label,
+            &document,
+            editor_settings.vim_mode,
+        );
+        let toggling = vim.clone();
+        commands.register("vim.toggle", "Toggle Vim Mode", move || {
+            toggling.set_enabled(!toggling.is_enabled())
+        });
+        let snippets = snippets::Snippets::new(text_view.upcast_ref(), &document, &commands);
+        let word_index = word_index::WordIndex::new();
+        word_index.add_buffer(&buffer);
+        let completion = completion::Completion::new(
+            text_view.upcast_ref(),
+            &scrolled_window,
+            &document,
+            &snippets,
+            &word_index,
+            &commands,
+        );
+        let language_servers = language_server::LanguageServers::new(
+            text_view.upcast_ref(),
+            &document,
+            &keymap_label,
+            &notification,
+            &results,
+            &completion,
+            &commands,
+            &editor_settings,
+        );
+        let focused_terminal = terminal.clone();
+        window.connect_close_request(move |_| {
+            language_servers.stop();
+            tasks.cancel();
+            debugger.stop();
+            terminal.stop();
+            gtk4::glib::Propagation::Proceed
+        });
+        let keymap = keymap::Keymap::new(&commands, &keymap_label);
+        keymap.set_plain_keys_allowed(move || vim.accepts_plain_keys());
+        keymap.set_binding_filter(move |command| {
+            !focused_terminal.has_focus()
+                || command.starts_with("terminal.")
+                || command == "palette.open"
+        });
+        keymap.attach(&window);
+        palette::Palette::new(&main_col, &text_view, &commands, &keymap);
+        keymap.reload();
         window.set_child(Some(&main_col));
         window.present();
     });
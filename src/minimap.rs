@@ -0,0 +1,337 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use gtk4::{
+    Adjustment, DrawingArea, GestureDrag, ScrolledWindow, TextBuffer, TextTag, TextView, cairo,
+    gdk, glib, prelude::*,
+};
+
+use crate::{bookmarks, diff, syntax};
+
+const LINE_HEIGHT: f64 = 2.0;
+const CHAR_WIDTH: f64 = 1.0;
+const MARKER_WIDTH: f64 = 4.0;
+const TAB_COLUMNS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarkerKind {
+    SearchMatch,
+    SyntaxError,
+    Bookmark,
+    Unsaved,
+}
+
+impl MarkerKind {
+    fn color(&self) -> gdk::RGBA {
+        match self {
+            MarkerKind::SearchMatch => gdk::RGBA::new(0.98, 0.83, 0.49, 0.9),
+            MarkerKind::SyntaxError => gdk::RGBA::new(1.0, 0.45, 0.45, 0.9),
+            MarkerKind::Bookmark => gdk::RGBA::new(0.41, 0.65, 1.0, 0.9),
+            MarkerKind::Unsaved => gdk::RGBA::new(0.65, 1.0, 0.56, 0.7),
+        }
+    }
+}
+
+struct Run {
+    column: f64,
+    length: f64,
+    color: gdk::RGBA,
+}
+
+#[derive(Default)]
+struct MinimapState {
+    lines: Vec<Vec<Run>>,
+    markers: HashMap<MarkerKind, Vec<i32>>,
+    saved_text: Option<String>,
+    search_text: String,
+    drag_scroll: f64,
+}
+
+#[derive(Clone)]
+pub struct Minimap {
+    area: DrawingArea,
+    text_view: TextView,
+    adjustment: Adjustment,
+    state: Rc<RefCell<MinimapState>>,
+    refresh_pending: Rc<Cell<bool>>,
+}
+
+impl Minimap {
+    pub fn new(text_view: &TextView, scrolled_window: &ScrolledWindow) -> Self {
+        let area = DrawingArea::builder()
+            .width_request(120)
+            .vexpand(true)
+            .name("minimap")
+            .build();
+        let minimap = Minimap {
+            area: area.clone(),
+            text_view: text_view.clone(),
+            adjustment: scrolled_window.vadjustment(),
+            state: Rc::new(RefCell::new(MinimapState::default())),
+            refresh_pending: Rc::new(Cell::new(false)),
+        };
+        let drawing = minimap.clone();
+        area.set_draw_func(move |_, context, width, height| {
+            drawing.draw(context, width as f64, height as f64);
+        });
+        let weak_area = area.downgrade();
+        let redraw = move |_: &Adjustment| {
+            if let Some(area) = weak_area.upgrade() {
+                area.queue_draw();
+            }
+        };
+        minimap.adjustment.connect_value_changed(redraw.clone());
+        minimap.adjustment.connect_changed(redraw);
+        let drag = GestureDrag::new();
+        let dragging = minimap.clone();
+        drag.connect_drag_begin(move |_, x, y| {
+            let height = dragging.area.height() as f64;
+            dragging.state.borrow_mut().drag_scroll = dragging.scroll_offset(height);
+            dragging.scroll_to(x, y);
+        });
+        let dragging = minimap.clone();
+        drag.connect_drag_update(move |gesture, _, offset_y| {
+            if let Some((x, y)) = gesture.start_point() {
+                dragging.scroll_to(x, y + offset_y);
+            }
+        });
+        area.add_controller(drag);
+        let buffer = text_view.buffer();
+        let changed = minimap.clone();
+        buffer.connect_changed(move |_| changed.schedule_refresh());
+        let selection = minimap.clone();
+        buffer.connect_mark_set(move |buffer, _, mark| {
+            if mark == &buffer.get_insert() || mark == &buffer.selection_bound() {
+                selection.update_search_matches(false);
+            }
+        });
+        let bookmark_changed = minimap.clone();
+        let on_tag = move |buffer: &TextBuffer, tag: &TextTag, _: &_, _: &_| {
+            if tag == &bookmarks::bookmark_tag(buffer) {
+                bookmark_changed.schedule_refresh();
+            }
+        };
+        buffer.connect_apply_tag(on_tag.clone());
+        buffer.connect_remove_tag(on_tag);
+        minimap.schedule_refresh();
+        minimap
+    }
+
+    pub fn widget(&self) -> &DrawingArea {
+        &self.area
+    }
+
+    pub fn set_saved_text(&self, text: String) {
+        self.state.borrow_mut().saved_text = Some(text);
+        self.schedule_refresh();
+    }
+
+    pub fn set_markers(&self, kind: MarkerKind, lines: Vec<i32>) {
+        self.state.borrow_mut().markers.insert(kind, lines);
+        self.area.queue_draw();
+    }
+
+    fn schedule_refresh(&self) {
+        if self.refresh_pending.replace(true) {
+            return;
+        }
+        let minimap = self.clone();
+        glib::idle_add_local_once(move || {
+            minimap.refresh_pending.set(false);
+            minimap.refresh();
+        });
+    }
+
+    fn refresh(&self) {
+        let buffer = self.text_view.buffer();
+        let content = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        let lines = color_runs(&buffer);
+        let error_lines = syntax::parse_qat(&content)
+            .map(|tree| syntax::error_lines(&tree))
+            .unwrap_or_default();
+        let unsaved_lines = match &self.state.borrow().saved_text {
+            Some(saved_text) => {
+                let old: Vec<&str> = saved_text.split('\n').collect();
+                let new: Vec<&str> = content.split('\n').collect();
+                diff::diff(&old, &new)
+                    .iter()
+                    .flat_map(|hunk| {
+                        let start = hunk.new_start as i32;
+                        start..start + hunk.new_len.max(1) as i32
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        {
+            let mut state = self.state.borrow_mut();
+            state.lines = lines;
+            state.markers.insert(MarkerKind::SyntaxError, error_lines);
+            state.markers.insert(MarkerKind::Unsaved, unsaved_lines);
+            state
+                .markers
+                .insert(MarkerKind::Bookmark, bookmarks::bookmark_lines(&buffer));
+        }
+        self.update_search_matches(true);
+        self.area.queue_draw();
+    }
+
+    fn update_search_matches(&self, force: bool) {
+        let buffer = self.text_view.buffer();
+        let selected = buffer
+            .selection_bounds()
+            .map(|(start, end)| buffer.text(&start, &end, true).to_string())
+            .filter(|text| text.trim().len() >= 2 && !text.contains('\n'))
+            .unwrap_or_default();
+        if !force && self.state.borrow().search_text == selected {
+            return;
+        }
+        let mut lines = Vec::new();
+        if !selected.is_empty() {
+            let content = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+            let mut line = 0;
+            let mut counted = 0;
+            for (index, _) in content.match_indices(selected.as_str()) {
+                line += content[counted..index].matches('\n').count() as i32;
+                counted = index;
+                lines.push(line);
+            }
+            lines.dedup();
+        }
+        self.state.borrow_mut().search_text = selected;
+        self.set_markers(MarkerKind::SearchMatch, lines);
+    }
+
+    fn scroll_offset(&self, height: f64) -> f64 {
+        let total_height = self.state.borrow().lines.len() as f64 * LINE_HEIGHT;
+        let range = self.adjustment.upper() - self.adjustment.page_size();
+        if range <= 0.0 || total_height <= height {
+            return 0.0;
+        }
+        (self.adjustment.value() / range) * (total_height - height)
+    }
+
+    fn scroll_to(&self, x: f64, y: f64) {
+        let width = self.area.width() as f64;
+        let height = self.area.height() as f64;
+        let state = self.state.borrow();
+        let total_height = state.lines.len().max(1) as f64 * LINE_HEIGHT;
+        let fraction = if x >= width - MARKER_WIDTH * 2.0 {
+            y / height
+        } else {
+            (y + state.drag_scroll) / total_height
+        };
+        let adjustment = &self.adjustment;
+        let value = fraction.clamp(0.0, 1.0) * adjustment.upper() - adjustment.page_size() / 2.0;
+        adjustment.set_value(value.clamp(
+            adjustment.lower(),
+            adjustment.upper() - adjustment.page_size(),
+        ));
+    }
+
+    fn draw(&self, context: &cairo::Context, width: f64, height: f64) {
+        let scroll = self.scroll_offset(height);
+        let state = self.state.borrow();
+        context.set_source_rgb(0.133, 0.145, 0.157);
+        let _ = context.paint();
+        let first_line = ((scroll / LINE_HEIGHT) as usize).min(state.lines.len());
+        let last_line =
+            (((scroll + height) / LINE_HEIGHT).ceil() as usize + 1).min(state.lines.len());
+        for (index, runs) in state.lines[first_line..last_line].iter().enumerate() {
+            let y = (first_line + index) as f64 * LINE_HEIGHT - scroll;
+            for run in runs {
+                context.set_source_rgba(
+                    run.color.red() as f64,
+                    run.color.green() as f64,
+                    run.color.blue() as f64,
+                    0.8,
+                );
+                context.rectangle(
+                    MARKER_WIDTH + run.column * CHAR_WIDTH,
+                    y,
+                    run.length * CHAR_WIDTH,
+                    LINE_HEIGHT - 0.5,
+                );
+                let _ = context.fill();
+            }
+        }
+        let total_height = state.lines.len().max(1) as f64 * LINE_HEIGHT;
+        let upper = self.adjustment.upper();
+        if upper > 0.0 {
+            let viewport_y = self.adjustment.value() / upper * total_height - scroll;
+            let viewport_height = self.adjustment.page_size() / upper * total_height;
+            context.set_source_rgba(1.0, 1.0, 1.0, 0.08);
+            context.rectangle(0.0, viewport_y, width - MARKER_WIDTH, viewport_height);
+            let _ = context.fill();
+        }
+        let line_count = state.lines.len().max(1) as f64;
+        for kind in [
+            MarkerKind::Unsaved,
+            MarkerKind::SearchMatch,
+            MarkerKind::Bookmark,
+            MarkerKind::SyntaxError,
+        ] {
+            let color = kind.color();
+            context.set_source_rgba(
+                color.red() as f64,
+                color.green() as f64,
+                color.blue() as f64,
+                color.alpha() as f64,
+            );
+            for line in state.markers.get(&kind).into_iter().flatten() {
+                let y = *line as f64 / line_count * height;
+                context.rectangle(width - MARKER_WIDTH, y, MARKER_WIDTH, 2.0);
+            }
+            let _ = context.fill();
+        }
+    }
+}
+
+fn color_runs(buffer: &TextBuffer) -> Vec<Vec<Run>> {
+    let default_color = gdk::RGBA::new(0.87, 0.94, 1.0, 0.6);
+    let mut lines: Vec<Vec<Run>> = vec![Vec::new()];
+    let mut column = 0;
+    let mut iter = buffer.start_iter();
+    while !iter.is_end() {
+        let color = iter
+            .tags()
+            .iter()
+            .rev()
+            .filter(|tag| tag.is_foreground_set())
+            .find_map(|tag| tag.foreground_rgba())
+            .unwrap_or(default_color);
+        let mut next = iter;
+        next.forward_to_tag_toggle(None::<&TextTag>);
+        for character in buffer.text(&iter, &next, true).chars() {
+            match character {
+                '\n' => {
+                    lines.push(Vec::new());
+                    column = 0;
+                }
+                ' ' => column += 1,
+                '\t' => column += TAB_COLUMNS - column % TAB_COLUMNS,
+                _ => {
+                    let line = lines.last_mut().expect("Minimap lines are never empty");
+                    match line.last_mut() {
+                        Some(run)
+                            if run.column + run.length == column as f64 && run.color == color =>
+                        {
+                            run.length += 1.0;
+                        }
+                        _ => line.push(Run {
+                            column: column as f64,
+                            length: 1.0,
+                            color,
+                        }),
+                    }
+                    column += 1;
+                }
+            }
+        }
+        iter = next;
+    }
+    lines
+}
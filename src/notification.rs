@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use gtk4::{
+    Align, Box, Button, Label, Orientation, PolicyType, Revealer, RevealerTransitionType,
+    ScrolledWindow, prelude::*,
+};
+
+use crate::commands::CommandRegistry;
+
+pub struct Notification {
+    revealer: Revealer,
+    title: Label,
+    message: Label,
+}
+
+impl Notification {
+    pub fn new(commands: &CommandRegistry) -> Rc<Notification> {
+        let title = Label::builder().hexpand(true).xalign(0.0).build();
+        title.add_css_class("heading");
+        let close = Button::builder().label("✕").has_frame(false).build();
+        let header = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&title);
+        header.append(&close);
+        let message = Label::builder()
+            .xalign(0.0)
+            .yalign(0.0)
+            .selectable(true)
+            .wrap(true)
+            .max_width_chars(80)
+            .build();
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&message)
+            .hscrollbar_policy(PolicyType::Never)
+            .propagate_natural_height(true)
+            .max_content_height(240)
+            .build();
+        let content = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(4)
+            .name("notification")
+            .build();
+        content.append(&header);
+        content.append(&scrolled_window);
+        let revealer = Revealer::builder()
+            .child(&content)
+            .transition_type(RevealerTransitionType::SlideDown)
+            .halign(Align::End)
+            .valign(Align::Start)
+            .margin_top(6)
+            .margin_end(18)
+            .build();
+        let notification = Rc::new(Notification {
+            revealer,
+            title,
+            message,
+        });
+        let weak = Rc::downgrade(&notification);
+        close.connect_clicked(move |_| {
+            if let Some(notification) = weak.upgrade() {
+                notification.dismiss();
+            }
+        });
+        let dismissing = notification.clone();
+        commands.register("notification.dismiss", "Dismiss Notification", move || {
+            dismissing.dismiss()
+        });
+        notification
+    }
+
+    pub fn widget(&self) -> &Revealer {
+        &self.revealer
+    }
+
+    pub fn show(&self, title: &str, message: &str) {
+        self.title.set_text(title);
+        self.message.set_text(message.trim_end());
+        self.message.set_visible(!message.trim().is_empty());
+        self.revealer.set_reveal_child(true);
+    }
+
+    pub fn dismiss(&self) {
+        self.revealer.set_reveal_child(false);
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Label, ListBox, Orientation, PolicyType, ScrolledWindow, SelectionMode, TextView, glib,
+    prelude::*,
+};
+use tree_sitter::{Node, Point, Tree};
+
+use crate::{
+    commands::{Argument, CommandRegistry},
+    document::Document,
+    syntax,
+};
+
+#[derive(Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: &'static str,
+    pub start: Point,
+    pub end: Point,
+    pub name_start: Point,
+    pub children: Vec<Symbol>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    symbol: Symbol,
+    path: String,
+    depth: i32,
+}
+
+fn kind_label(kind: &str) -> Option<&'static str> {
+    Some(match kind {
+        "function_definition" => "function",
+        "prerun_function_definition" => "prerun function",
+        "method" => "method",
+        "struct_definition" => "struct",
+        "mix_definition" => "mix",
+        "choice_definition" => "choice",
+        "flag_definition" => "flag",
+        "toggle_definition" => "toggle",
+        "type_definition" => "type",
+        "skill_definition" => "skill",
+        _ => return None,
+    })
+}
+
+pub fn kind_icon(kind: &str) -> &'static str {
+    match kind {
+        "function" | "prerun function" | "method" => "λ",
+        "skill" => "S",
+        _ => "T",
+    }
+}
+
+fn symbol_at(node: Node, content: &str) -> Option<Symbol> {
+    let kind = kind_label(node.kind())?;
+    let name = node.child_by_field_name("name")?;
+    Some(Symbol {
+        name: content.get(name.byte_range())?.to_owned(),
+        kind,
+        start: node.start_position(),
+        end: node.end_position(),
+        name_start: name.start_position(),
+        children: Vec::new(),
+    })
+}
+
+fn collect(node: Node, content: &str, symbols: &mut Vec<Symbol>) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        match symbol_at(child, content) {
+            Some(mut symbol) => {
+                collect(child, content, &mut symbol.children);
+                symbols.push(symbol);
+            }
+            None => collect(child, content, symbols),
+        }
+    }
+}
+
+pub fn nested_symbols(tree: &Tree, content: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    collect(tree.root_node(), content, &mut symbols);
+    symbols
+}
+
+pub fn enclosing(symbols: &[Symbol], point: Point) -> Vec<(Symbol, Vec<Symbol>)> {
+    let mut chain = Vec::new();
+    let mut level = symbols.to_vec();
+    while let Some(found) = level
+        .iter()
+        .find(|symbol| symbol.start <= point && point <= symbol.end)
+        .cloned()
+    {
+        let children = found.children.clone();
+        chain.push((found, level));
+        level = children;
+    }
+    chain
+}
+
+pub fn jump_to(text_view: &TextView, symbol: &Symbol) {
+    let buffer = text_view.buffer();
+    let Some(iter) = buffer.iter_at_line_index(
+        symbol.name_start.row as i32,
+        symbol.name_start.column as i32,
+    ) else {
+        return;
+    };
+    buffer.place_cursor(&iter);
+    text_view.scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.3);
+    text_view.grab_focus();
+}
+
+pub fn symbols(tree: &Tree, content: &str) -> Vec<Symbol> {
+    let collected = nested_symbols(tree, content);
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut skills = Vec::new();
+    for symbol in collected {
+        if symbol.kind == "skill" {
+            skills.push(symbol);
+        } else {
+            symbols.push(symbol);
+        }
+    }
+    for skill in skills {
+        match symbols.iter_mut().find(|symbol| {
+            !matches!(symbol.kind, "function" | "prerun function") && symbol.name == skill.name
+        }) {
+            Some(owner) => {
+                owner.children.extend(skill.children);
+                owner.children.sort_by_key(|child| child.start);
+            }
+            None => symbols.push(skill),
+        }
+    }
+    symbols.sort_by_key(|symbol| symbol.start);
+    symbols
+}
+
+fn flatten(symbols: &[Symbol], depth: i32, parent: &str, entries: &mut Vec<Entry>) {
+    for symbol in symbols {
+        let path = if parent.is_empty() {
+            symbol.name.clone()
+        } else {
+            format!("{}.{}", parent, symbol.name)
+        };
+        entries.push(Entry {
+            symbol: symbol.clone(),
+            path: path.clone(),
+            depth,
+        });
+        flatten(&symbol.children, depth + 1, &path, entries);
+    }
+}
+
+pub struct Outline {
+    text_view: TextView,
+    document: Rc<Document>,
+    commands: CommandRegistry,
+    sidebar: ScrolledWindow,
+    list: ListBox,
+    entries: RefCell<Vec<Entry>>,
+    refresh_pending: Rc<Cell<bool>>,
+}
+
+impl Outline {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        commands: &CommandRegistry,
+        visible: bool,
+    ) -> Rc<Outline> {
+        let list = ListBox::builder()
+            .selection_mode(SelectionMode::Single)
+            .build();
+        let sidebar = ScrolledWindow::builder()
+            .child(&list)
+            .hscrollbar_policy(PolicyType::Never)
+            .width_request(220)
+            .name("outline")
+            .visible(visible)
+            .build();
+        let outline = Rc::new(Outline {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            commands: commands.clone(),
+            sidebar,
+            list: list.clone(),
+            entries: RefCell::new(Vec::new()),
+            refresh_pending: Rc::new(Cell::new(false)),
+        });
+        let weak = Rc::downgrade(&outline);
+        list.connect_row_activated(move |_, row| {
+            if let Some(outline) = weak.upgrade() {
+                let entry = outline.entries.borrow().get(row.index() as usize).cloned();
+                if let Some(entry) = entry {
+                    jump_to(&outline.text_view, &entry.symbol);
+                }
+            }
+        });
+        let buffer = text_view.buffer();
+        let weak = Rc::downgrade(&outline);
+        buffer.connect_changed(move |_| {
+            if let Some(outline) = weak.upgrade() {
+                outline.schedule_refresh();
+            }
+        });
+        let weak = Rc::downgrade(&outline);
+        buffer.connect_mark_set(move |buffer, _, mark| {
+            if mark != &buffer.get_insert() {
+                return;
+            }
+            if let Some(outline) = weak.upgrade() {
+                outline.follow_cursor();
+            }
+        });
+        let weak = Rc::downgrade(&outline);
+        document.connect_language_changed(move |_| {
+            if let Some(outline) = weak.upgrade() {
+                outline.schedule_refresh();
+            }
+        });
+        let toggling = outline.clone();
+        commands.register("outline.toggle", "Toggle Outline", move || {
+            toggling.sidebar.set_visible(!toggling.sidebar.is_visible());
+        });
+        outline.refresh();
+        outline
+    }
+
+    pub fn widget(&self) -> &ScrolledWindow {
+        &self.sidebar
+    }
+
+    fn schedule_refresh(self: &Rc<Self>) {
+        if self.refresh_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(outline) = weak.upgrade() {
+                outline.refresh_pending.set(false);
+                outline.refresh();
+            }
+        });
+    }
+
+    fn refresh(self: &Rc<Self>) {
+        let mut entries = Vec::new();
+        if self.document.language() == "qat" {
+            let content = self.document.text();
+            if let Some(tree) = syntax::parse_qat(&content) {
+                flatten(&symbols(&tree, &content), 0, "", &mut entries);
+            }
+        }
+        self.list.remove_all();
+        for entry in &entries {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(6)
+                .margin_start(6 + entry.depth * 14)
+                .build();
+            let glyph = kind_icon(entry.symbol.kind);
+            let icon = Label::new(Some(glyph));
+            icon.add_css_class("completion_icon");
+            icon.add_css_class(if glyph == "λ" {
+                "completion_function"
+            } else {
+                "completion_type"
+            });
+            row.append(&icon);
+            row.append(
+                &Label::builder()
+                    .label(&entry.symbol.name)
+                    .hexpand(true)
+                    .xalign(0.0)
+                    .build(),
+            );
+            let kind = Label::new(Some(entry.symbol.kind));
+            kind.add_css_class("dim-label");
+            row.append(&kind);
+            self.list.append(&row);
+        }
+        let choices = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.path.clone(),
+                    format!("{}, line {}", entry.symbol.kind, entry.symbol.start.row + 1),
+                )
+            })
+            .collect();
+        self.entries.replace(entries);
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "outline.go_to_symbol",
+            "Go to Symbol in File",
+            Argument {
+                prompt: "Symbol".to_owned(),
+                choices,
+            },
+            move |path| {
+                let Some(outline) = weak.upgrade() else {
+                    return;
+                };
+                let entry = outline
+                    .entries
+                    .borrow()
+                    .iter()
+                    .find(|entry| entry.path == path)
+                    .cloned();
+                if let Some(entry) = entry {
+                    jump_to(&outline.text_view, &entry.symbol);
+                }
+            },
+        );
+        self.follow_cursor();
+    }
+
+    fn follow_cursor(&self) {
+        let buffer = self.text_view.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let point = Point {
+            row: cursor.line() as usize,
+            column: cursor.line_index() as usize,
+        };
+        let innermost = self
+            .entries
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.symbol.start <= point && point <= entry.symbol.end)
+            .max_by_key(|(_, entry)| entry.symbol.start)
+            .map(|(index, _)| index as i32);
+        match innermost.and_then(|index| self.list.row_at_index(index)) {
+            Some(row) => {
+                if self.list.selected_row().as_ref() != Some(&row) {
+                    self.list.select_row(Some(&row));
+                    if let Some(bounds) = row.compute_bounds(&self.list) {
+                        let adjustment = self.sidebar.vadjustment();
+                        let top = bounds.y() as f64;
+                        let bottom = top + bounds.height() as f64;
+                        if top < adjustment.value() {
+                            adjustment.set_value(top);
+                        } else if bottom > adjustment.value() + adjustment.page_size() {
+                            adjustment.set_value(bottom - adjustment.page_size());
+                        }
+                    }
+                }
+            }
+            None => self.list.unselect_all(),
+        }
+    }
+}
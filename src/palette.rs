@@ -0,0 +1,326 @@
+use std::{cell::RefCell, rc::Rc};
+
+use gtk4::{
+    Box, Entry, EventControllerKey, Label, ListBox, Orientation, PolicyType, Popover, PositionType,
+    PropagationPhase, ScrolledWindow, SelectionMode, Widget, gdk, glib::Propagation, prelude::*,
+};
+
+use crate::{commands::CommandRegistry, fuzzy, keymap::Keymap};
+
+const MAX_RECENT: usize = 20;
+const MAX_ROWS: usize = 50;
+
+#[derive(Clone)]
+struct Item {
+    value: String,
+    title: String,
+    positions: Vec<usize>,
+    detail: String,
+    binding: String,
+}
+
+pub struct Palette {
+    popover: Popover,
+    entry: Entry,
+    list: ListBox,
+    scrolled_window: ScrolledWindow,
+    parent: Widget,
+    focus_return: Widget,
+    commands: CommandRegistry,
+    keymap: Rc<Keymap>,
+    recent: RefCell<Vec<String>>,
+    argument_for: RefCell<Option<String>>,
+    items: RefCell<Vec<Item>>,
+}
+
+impl Palette {
+    pub fn new(
+        parent: &impl IsA<Widget>,
+        focus_return: &impl IsA<Widget>,
+        commands: &CommandRegistry,
+        keymap: &Rc<Keymap>,
+    ) -> Rc<Palette> {
+        let entry = Entry::builder().width_chars(60).build();
+        let list = ListBox::builder()
+            .selection_mode(SelectionMode::Browse)
+            .build();
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&list)
+            .hscrollbar_policy(PolicyType::Never)
+            .max_content_height(400)
+            .propagate_natural_height(true)
+            .build();
+        let content = Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(6)
+            .build();
+        content.append(&entry);
+        content.append(&scrolled_window);
+        let popover = Popover::builder()
+            .child(&content)
+            .has_arrow(false)
+            .position(PositionType::Bottom)
+            .build();
+        popover.set_parent(parent);
+        let palette = Rc::new(Palette {
+            popover: popover.clone(),
+            entry: entry.clone(),
+            list: list.clone(),
+            scrolled_window,
+            parent: parent.clone().upcast(),
+            focus_return: focus_return.clone().upcast(),
+            commands: commands.clone(),
+            keymap: keymap.clone(),
+            recent: RefCell::new(Vec::new()),
+            argument_for: RefCell::new(None),
+            items: RefCell::new(Vec::new()),
+        });
+        let weak = Rc::downgrade(&palette);
+        entry.connect_changed(move |_| {
+            if let Some(palette) = weak.upgrade() {
+                palette.refresh();
+            }
+        });
+        let weak = Rc::downgrade(&palette);
+        entry.connect_activate(move |_| {
+            if let Some(palette) = weak.upgrade() {
+                let index = palette
+                    .list
+                    .selected_row()
+                    .map(|row| row.index())
+                    .unwrap_or(0);
+                palette.activate(index as usize);
+            }
+        });
+        let weak = Rc::downgrade(&palette);
+        list.connect_row_activated(move |_, row| {
+            if let Some(palette) = weak.upgrade() {
+                palette.activate(row.index() as usize);
+            }
+        });
+        let controller = EventControllerKey::new();
+        controller.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&palette);
+        controller.connect_key_pressed(move |_, keyval, _, _| {
+            let Some(palette) = weak.upgrade() else {
+                return Propagation::Proceed;
+            };
+            match keyval {
+                gdk::Key::Up => palette.move_selection(-1),
+                gdk::Key::Down => palette.move_selection(1),
+                gdk::Key::Page_Up => palette.move_selection(-10),
+                gdk::Key::Page_Down => palette.move_selection(10),
+                gdk::Key::Escape => palette.popover.popdown(),
+                _ => return Propagation::Proceed,
+            }
+            Propagation::Stop
+        });
+        entry.add_controller(controller);
+        let weak = Rc::downgrade(&palette);
+        popover.connect_closed(move |_| {
+            if let Some(palette) = weak.upgrade() {
+                palette.argument_for.replace(None);
+                palette.focus_return.grab_focus();
+            }
+        });
+        let opening = palette.clone();
+        commands.register("palette.open", "Show All Commands", move || opening.open());
+        let prompting = palette.clone();
+        commands.set_argument_prompter(move |name| prompting.prompt(name));
+        let weak = Rc::downgrade(&palette);
+        commands.connect_run(move |name| {
+            if name != "palette.open"
+                && let Some(palette) = weak.upgrade()
+            {
+                let mut recent = palette.recent.borrow_mut();
+                recent.retain(|recent_name| recent_name != name);
+                recent.insert(0, name.to_owned());
+                recent.truncate(MAX_RECENT);
+            }
+        });
+        palette
+    }
+
+    fn show(&self) {
+        self.entry.set_text("");
+        self.refresh();
+        self.popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(self.parent.width() / 2, 0, 1, 1)));
+        self.popover.popup();
+        self.entry.grab_focus();
+    }
+
+    pub fn open(&self) {
+        self.argument_for.replace(None);
+        self.entry.set_placeholder_text(Some("Type a command"));
+        self.show();
+    }
+
+    pub fn prompt(&self, command: &str) {
+        let Some(argument) = self.commands.argument(command) else {
+            return;
+        };
+        self.argument_for.replace(Some(command.to_owned()));
+        self.entry.set_placeholder_text(Some(&argument.prompt));
+        self.show();
+    }
+
+    fn command_items(&self, query: &str) -> Vec<(i32, Item)> {
+        let recent = self.recent.borrow();
+        self.commands
+            .list()
+            .into_iter()
+            .filter_map(|(name, title)| {
+                let title_match = fuzzy::fuzzy_match(query, &title);
+                let name_match = fuzzy::fuzzy_match(query, &name);
+                let (score, positions) = match (title_match, name_match) {
+                    (Some(title_match), Some(name_match))
+                        if name_match.score > title_match.score =>
+                    {
+                        (name_match.score, Vec::new())
+                    }
+                    (Some(title_match), _) => (title_match.score, title_match.positions),
+                    (None, Some(name_match)) => (name_match.score, Vec::new()),
+                    (None, None) => return None,
+                };
+                let recency = recent
+                    .iter()
+                    .position(|recent_name| *recent_name == name)
+                    .map(|index| (MAX_RECENT - index) as i32 * 3)
+                    .unwrap_or(0);
+                let binding = self.keymap.bindings_for(&name).join(", ");
+                Some((
+                    score + recency,
+                    Item {
+                        value: name.clone(),
+                        title,
+                        positions,
+                        detail: name,
+                        binding,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn argument_items(&self, command: &str, query: &str) -> Vec<(i32, Item)> {
+        let Some(argument) = self.commands.argument(command) else {
+            return Vec::new();
+        };
+        let mut items: Vec<(i32, Item)> = argument
+            .choices
+            .iter()
+            .filter_map(|(choice, detail)| {
+                let choice_match = fuzzy::fuzzy_match(query, choice)?;
+                Some((
+                    choice_match.score,
+                    Item {
+                        value: choice.clone(),
+                        title: choice.clone(),
+                        positions: choice_match.positions,
+                        detail: detail.clone(),
+                        binding: String::new(),
+                    },
+                ))
+            })
+            .collect();
+        let query = query.trim();
+        if !query.is_empty() && !argument.choices.iter().any(|(choice, _)| choice == query) {
+            items.push((
+                if argument.choices.is_empty() {
+                    i32::MAX
+                } else {
+                    i32::MIN
+                },
+                Item {
+                    value: query.to_owned(),
+                    title: query.to_owned(),
+                    positions: Vec::new(),
+                    detail: argument.prompt.clone(),
+                    binding: String::new(),
+                },
+            ));
+        }
+        items
+    }
+
+    fn refresh(&self) {
+        let query = self.entry.text().to_string();
+        let argument_for = self.argument_for.borrow().clone();
+        let mut items = match &argument_for {
+            Some(command) => self.argument_items(command, &query),
+            None => self.command_items(&query),
+        };
+        items.sort_by(|(score, item), (other_score, other)| {
+            other_score
+                .cmp(score)
+                .then_with(|| item.title.cmp(&other.title))
+        });
+        items.truncate(MAX_ROWS);
+        self.list.remove_all();
+        for (_, item) in &items {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(12)
+                .build();
+            let title = Label::builder().hexpand(true).xalign(0.0).build();
+            title.set_markup(&fuzzy::highlighted_markup(&item.title, &item.positions));
+            row.append(&title);
+            let detail = Label::new(Some(&item.detail));
+            detail.add_css_class("dim-label");
+            row.append(&detail);
+            row.append(&Label::new(Some(&item.binding)));
+            self.list.append(&row);
+        }
+        self.list.select_row(self.list.row_at_index(0).as_ref());
+        self.scrolled_window.vadjustment().set_value(0.0);
+        self.items
+            .replace(items.into_iter().map(|(_, item)| item).collect());
+    }
+
+    fn move_selection(&self, delta: i32) {
+        let count = self.items.borrow().len() as i32;
+        if count == 0 {
+            return;
+        }
+        let current = self.list.selected_row().map(|row| row.index()).unwrap_or(0);
+        let Some(row) = self
+            .list
+            .row_at_index((current + delta).clamp(0, count - 1))
+        else {
+            return;
+        };
+        self.list.select_row(Some(&row));
+        if let Some(bounds) = row.compute_bounds(&self.list) {
+            let adjustment = self.scrolled_window.vadjustment();
+            let top = bounds.y() as f64;
+            let bottom = top + bounds.height() as f64;
+            if top < adjustment.value() {
+                adjustment.set_value(top);
+            } else if bottom > adjustment.value() + adjustment.page_size() {
+                adjustment.set_value(bottom - adjustment.page_size());
+            }
+        }
+    }
+
+    fn activate(&self, index: usize) {
+        let Some(item) = self.items.borrow().get(index).cloned() else {
+            return;
+        };
+        let argument_for = self.argument_for.borrow().clone();
+        match argument_for {
+            Some(command) => {
+                self.popover.popdown();
+                self.commands.run_with_argument(&command, &item.value);
+            }
+            None => {
+                if self.commands.argument(&item.value).is_some() {
+                    self.prompt(&item.value);
+                } else {
+                    self.popover.popdown();
+                    self.commands.run(&item.value);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use std::{cell::RefCell, collections::HashSet, ops::Range, rc::Rc};
+
+use gtk4::{
+    Entry, Label, Popover, PositionType, TextMark, TextTag, TextView, TextWindowType, gdk,
+    prelude::*,
+};
+use tree_sitter::{Node, Point};
+
+use crate::{commands::CommandRegistry, conflicts, document::Document, syntax};
+
+const DECLARATION_KINDS: &[&str] = &[
+    "statement_declaration",
+    "function_parameter_single",
+    "struct_field",
+    "mix_field",
+    "toggle_field",
+];
+
+struct Target {
+    name: String,
+    ranges: Vec<(Point, Point)>,
+    taken: HashSet<String>,
+}
+
+struct Session {
+    marks: Vec<(TextMark, TextMark)>,
+    original: String,
+    taken: HashSet<String>,
+    changed: bool,
+}
+
+pub struct Rename {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    popover: Popover,
+    entry: Entry,
+    tag: TextTag,
+    session: RefCell<Option<Session>>,
+}
+
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|first: char| first.is_numeric())
+        && text
+            .chars()
+            .all(|character| character.is_alphanumeric() || character == '_')
+        && !syntax::KEYWORDS.contains(&text)
+        && !syntax::BUILTIN_TYPES.contains(&text)
+}
+
+fn text<'content>(node: Node, content: &'content str) -> &'content str {
+    content.get(node.byte_range()).unwrap_or("")
+}
+
+fn leaves<'tree>(node: Node<'tree>, range: &Range<usize>, found: &mut Vec<Node<'tree>>) {
+    if node.end_byte() < range.start || node.start_byte() > range.end {
+        return;
+    }
+    if node.child_count() == 0 {
+        if node.start_byte() >= range.start && node.end_byte() <= range.end {
+            found.push(node);
+        }
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        leaves(child, range, found);
+    }
+}
+
+fn descendants<'tree>(node: Node<'tree>, found: &mut Vec<Node<'tree>>) {
+    found.push(node);
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        descendants(child, found);
+    }
+}
+
+fn is_member_name(node: Node) -> bool {
+    node.parent().is_some_and(|parent| {
+        parent.kind() == "member_access" && parent.child_by_field_name("name") == Some(node)
+    })
+}
+
+fn is_field(node: Node) -> bool {
+    matches!(node.kind(), "struct_field" | "mix_field" | "toggle_field")
+}
+
+fn scope_of(declaration: Node) -> Option<Range<usize>> {
+    match declaration.kind() {
+        "statement_declaration" => declaration
+            .parent()
+            .map(|parent| declaration.start_byte()..parent.end_byte()),
+        "function_parameter_single" => {
+            let mut ancestor = declaration.parent();
+            while let Some(current) = ancestor {
+                if syntax::FUNCTION_KINDS.contains(&current.kind()) {
+                    return Some(current.byte_range());
+                }
+                ancestor = current.parent();
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+fn find_target(content: &str, point: Point) -> Result<Target, String> {
+    if !conflicts::find(content).is_empty() {
+        return Err("Resolve the merge conflicts before renaming".to_owned());
+    }
+    let tree = syntax::parse_qat(content).ok_or("Could not parse the file")?;
+    let root = tree.root_node();
+    let before = Point {
+        row: point.row,
+        column: point.column.saturating_sub(1),
+    };
+    let node = [point, before]
+        .into_iter()
+        .filter_map(|point| root.descendant_for_point_range(point, point))
+        .find(|node| node.child_count() == 0 && is_identifier(text(*node, content)))
+        .ok_or("Place the cursor on an identifier to rename it")?;
+    let name = text(node, content).to_owned();
+    let mut nodes = Vec::new();
+    descendants(root, &mut nodes);
+    let declarations: Vec<Node> = nodes
+        .into_iter()
+        .filter(|candidate| DECLARATION_KINDS.contains(&candidate.kind()))
+        .filter(|candidate| {
+            candidate
+                .child_by_field_name("name")
+                .is_some_and(|declared| text(declared, content) == name)
+        })
+        .collect();
+    let declaration = declarations
+        .iter()
+        .find(|declaration| declaration.child_by_field_name("name") == Some(node))
+        .copied();
+    let declaration = match declaration {
+        Some(declaration) => declaration,
+        None if is_member_name(node) => {
+            let fields: Vec<Node> = declarations
+                .iter()
+                .filter(|declaration| is_field(**declaration))
+                .copied()
+                .collect();
+            match fields.as_slice() {
+                [field] => *field,
+                [] => return Err(format!("Field {} is not declared in this file", name)),
+                _ => return Err(format!("Field {} is declared more than once", name)),
+            }
+        }
+        None => declarations
+            .iter()
+            .filter(|declaration| !is_field(**declaration))
+            .filter_map(|declaration| {
+                let scope = scope_of(*declaration)?;
+                scope
+                    .contains(&node.start_byte())
+                    .then_some((scope.start, *declaration))
+            })
+            .max_by_key(|(start, _)| *start)
+            .map(|(_, declaration)| declaration)
+            .ok_or(format!("Cannot determine the scope of {}", name))?,
+    };
+    let declared = declaration
+        .child_by_field_name("name")
+        .ok_or(format!("Cannot determine the scope of {}", name))?;
+    let mut ranges = Vec::new();
+    let mut taken = HashSet::new();
+    if is_field(declaration) {
+        if declarations
+            .iter()
+            .filter(|other| is_field(**other))
+            .count()
+            > 1
+        {
+            return Err(format!("Field {} is declared more than once", name));
+        }
+        if root.has_error() {
+            return Err("The file contains syntax errors".to_owned());
+        }
+        let mut found = Vec::new();
+        leaves(root, &(0..content.len()), &mut found);
+        for leaf in found {
+            if leaf == declared || (is_member_name(leaf) && text(leaf, content) == name) {
+                ranges.push((leaf.start_position(), leaf.end_position()));
+            }
+        }
+        if let Some(owner) = declaration.parent() {
+            let mut siblings = Vec::new();
+            descendants(owner, &mut siblings);
+            taken.extend(
+                siblings
+                    .into_iter()
+                    .filter(|sibling| is_field(*sibling))
+                    .filter_map(|sibling| sibling.child_by_field_name("name"))
+                    .map(|sibling| text(sibling, content).to_owned()),
+            );
+        }
+    } else {
+        let scope =
+            scope_of(declaration).ok_or(format!("Cannot determine the scope of {}", name))?;
+        if declarations.iter().any(|other| {
+            *other != declaration && !is_field(*other) && scope.contains(&other.start_byte())
+        }) {
+            return Err(format!("{} is declared again inside its scope", name));
+        }
+        let scope_node = root
+            .descendant_for_byte_range(scope.start, scope.end)
+            .unwrap_or(root);
+        if scope_node.has_error() {
+            return Err(format!("The scope of {} contains syntax errors", name));
+        }
+        let mut found = Vec::new();
+        leaves(root, &scope, &mut found);
+        for leaf in found {
+            if leaf.kind() != declared.kind() || is_member_name(leaf) {
+                continue;
+            }
+            let leaf_text = text(leaf, content);
+            if leaf_text == name {
+                ranges.push((leaf.start_position(), leaf.end_position()));
+            } else {
+                taken.insert(leaf_text.to_owned());
+            }
+        }
+    }
+    Ok(Target {
+        name,
+        ranges,
+        taken,
+    })
+}
+
+impl Rename {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        commands: &CommandRegistry,
+    ) -> Rc<Rename> {
+        let entry = Entry::builder().width_chars(24).build();
+        let popover = Popover::builder()
+            .child(&entry)
+            .has_arrow(false)
+            .position(PositionType::Bottom)
+            .build();
+        popover.set_parent(text_view);
+        let tag = text_view
+            .buffer()
+            .create_tag(Some("rename_preview"), &[("background", &"#3a4250")])
+            .expect("Could not create tag for rename preview");
+        let rename = Rc::new(Rename {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            popover: popover.clone(),
+            entry: entry.clone(),
+            tag,
+            session: RefCell::new(None),
+        });
+        let weak = Rc::downgrade(&rename);
+        entry.connect_changed(move |entry| {
+            if let Some(rename) = weak.upgrade() {
+                rename.preview(&entry.text());
+            }
+        });
+        let weak = Rc::downgrade(&rename);
+        entry.connect_activate(move |entry| {
+            if let Some(rename) = weak.upgrade() {
+                rename.commit(&entry.text());
+            }
+        });
+        let weak = Rc::downgrade(&rename);
+        popover.connect_closed(move |_| {
+            if let Some(rename) = weak.upgrade() {
+                rename.cancel();
+            }
+        });
+        let starting = rename.clone();
+        commands.register("edit.rename", "Rename Symbol", move || starting.start());
+        rename
+    }
+
+    pub fn start(&self) {
+        if self.session.borrow().is_some() {
+            return;
+        }
+        if self.document.language() != "qat" {
+            self.status
+                .set_text("Rename is only available in qat files");
+            return;
+        }
+        let buffer = self.text_view.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let point = Point {
+            row: cursor.line() as usize,
+            column: cursor.line_index() as usize,
+        };
+        let target = match find_target(&self.document.text(), point) {
+            Ok(target) => target,
+            Err(reason) => {
+                self.status.set_text(&reason);
+                return;
+            }
+        };
+        let mut marks = Vec::new();
+        for (start, end) in &target.ranges {
+            let (Some(start), Some(end)) = (
+                buffer.iter_at_line_index(start.row as i32, start.column as i32),
+                buffer.iter_at_line_index(end.row as i32, end.column as i32),
+            ) else {
+                continue;
+            };
+            buffer.apply_tag(&self.tag, &start, &end);
+            marks.push((
+                buffer.create_mark(None, &start, true),
+                buffer.create_mark(None, &end, false),
+            ));
+        }
+        buffer.begin_user_action();
+        self.session.replace(Some(Session {
+            marks,
+            original: target.name.clone(),
+            taken: target.taken,
+            changed: false,
+        }));
+        self.entry.set_text(&target.name);
+        self.entry.select_region(0, -1);
+        let location = self.text_view.iter_location(&cursor);
+        let (x, y) = self.text_view.buffer_to_window_coords(
+            TextWindowType::Widget,
+            location.x(),
+            location.y() + location.height(),
+        );
+        self.popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, 1)));
+        self.popover.popup();
+        self.entry.grab_focus();
+    }
+
+    fn replace_all(&self, session: &Session, name: &str) {
+        let buffer = self.text_view.buffer();
+        for (start, end) in &session.marks {
+            let mut start_iter = buffer.iter_at_mark(start);
+            let mut end_iter = buffer.iter_at_mark(end);
+            if buffer.text(&start_iter, &end_iter, true) == name {
+                continue;
+            }
+            buffer.delete(&mut start_iter, &mut end_iter);
+            buffer.insert(&mut start_iter, name);
+            buffer.apply_tag(
+                &self.tag,
+                &buffer.iter_at_mark(start),
+                &buffer.iter_at_mark(end),
+            );
+        }
+    }
+
+    fn preview(&self, name: &str) {
+        let mut session = self.session.borrow_mut();
+        let Some(session) = session.as_mut() else {
+            return;
+        };
+        if !is_identifier(name) {
+            self.entry.add_css_class("error");
+            return;
+        }
+        self.entry.remove_css_class("error");
+        self.replace_all(session, name);
+        session.changed |= name != session.original;
+    }
+
+    fn finish(&self, session: Session) {
+        let buffer = self.text_view.buffer();
+        for (start, end) in &session.marks {
+            buffer.remove_tag(
+                &self.tag,
+                &buffer.iter_at_mark(start),
+                &buffer.iter_at_mark(end),
+            );
+            buffer.delete_mark(start);
+            buffer.delete_mark(end);
+        }
+        buffer.end_user_action();
+    }
+
+    fn commit(&self, name: &str) {
+        {
+            let session = self.session.borrow();
+            let Some(session) = session.as_ref() else {
+                return;
+            };
+            if !is_identifier(name) {
+                self.status
+                    .set_text(&format!("{} is not a valid identifier", name));
+                return;
+            }
+            if name != session.original && session.taken.contains(name) {
+                self.status
+                    .set_text(&format!("{} is already used in this scope", name));
+                return;
+            }
+        }
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        self.replace_all(&session, name);
+        let count = session.marks.len();
+        self.finish(session);
+        self.status.set_text(&format!(
+            "Renamed {} occurrence{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+        self.popover.popdown();
+        self.text_view.grab_focus();
+    }
+
+    fn cancel(&self) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        let changed = session.changed;
+        self.finish(session);
+        if changed {
+            self.text_view.buffer().undo();
+        }
+        self.text_view.grab_focus();
+    }
+}
@@ -0,0 +1,158 @@
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use gtk4::{
+    Box, Button, Label, ListBox, Orientation, PolicyType, ScrolledWindow, SelectionMode, TextView,
+    pango, prelude::*,
+};
+
+use crate::{commands::CommandRegistry, document::Document};
+
+#[derive(Clone, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: i32,
+    pub column: i32,
+}
+
+#[derive(Clone)]
+pub struct ResultItem {
+    pub location: Location,
+    pub detail: String,
+    pub text: String,
+}
+
+type Opener = Rc<dyn Fn(&Location)>;
+
+pub struct ResultsPanel {
+    widget: Box,
+    title: Label,
+    list: ListBox,
+    items: RefCell<Vec<ResultItem>>,
+    opener: RefCell<Option<Opener>>,
+}
+
+pub fn open_location(
+    document: &Document,
+    text_view: &TextView,
+    status: &Label,
+    location: &Location,
+) -> bool {
+    if location.path != document.path() {
+        if document.buffer.is_modified() {
+            status.set_text(&format!(
+                "Save changes before opening {}",
+                location.path.display()
+            ));
+            return false;
+        }
+        if let Err(error) = document.load(&location.path) {
+            eprintln!("Could not open {}: {}", location.path.display(), error);
+            return false;
+        }
+    }
+    let buffer = text_view.buffer();
+    let Some(iter) = buffer
+        .iter_at_line_index(location.line, location.column)
+        .or_else(|| buffer.iter_at_line(location.line))
+    else {
+        return false;
+    };
+    buffer.place_cursor(&iter);
+    text_view.scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.3);
+    text_view.grab_focus();
+    true
+}
+
+impl ResultsPanel {
+    pub fn new(commands: &CommandRegistry) -> Rc<ResultsPanel> {
+        let title = Label::builder().hexpand(true).xalign(0.0).build();
+        let close = Button::builder().label("✕").has_frame(false).build();
+        let header = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&title);
+        header.append(&close);
+        let list = ListBox::builder()
+            .selection_mode(SelectionMode::Browse)
+            .build();
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&list)
+            .hscrollbar_policy(PolicyType::Automatic)
+            .vexpand(true)
+            .build();
+        let widget = Box::builder()
+            .orientation(Orientation::Vertical)
+            .height_request(180)
+            .name("results_panel")
+            .visible(false)
+            .build();
+        widget.append(&header);
+        widget.append(&scrolled_window);
+        let panel = Rc::new(ResultsPanel {
+            widget,
+            title,
+            list: list.clone(),
+            items: RefCell::new(Vec::new()),
+            opener: RefCell::new(None),
+        });
+        let weak = Rc::downgrade(&panel);
+        close.connect_clicked(move |_| {
+            if let Some(panel) = weak.upgrade() {
+                panel.hide();
+            }
+        });
+        let weak = Rc::downgrade(&panel);
+        list.connect_row_activated(move |_, row| {
+            let Some(panel) = weak.upgrade() else {
+                return;
+            };
+            let item = panel.items.borrow().get(row.index() as usize).cloned();
+            let opener = panel.opener.borrow().clone();
+            if let (Some(item), Some(opener)) = (item, opener) {
+                opener(&item.location);
+            }
+        });
+        let closing = panel.clone();
+        commands.register("results.close", "Close Results Panel", move || {
+            closing.hide()
+        });
+        panel
+    }
+
+    pub fn widget(&self) -> &Box {
+        &self.widget
+    }
+
+    pub fn set_opener(&self, opener: impl Fn(&Location) + 'static) {
+        self.opener.replace(Some(Rc::new(opener)));
+    }
+
+    pub fn show(&self, title: &str, items: Vec<ResultItem>) {
+        self.title.set_text(title);
+        self.list.remove_all();
+        for item in &items {
+            let row = Box::builder()
+                .orientation(Orientation::Horizontal)
+                .spacing(12)
+                .build();
+            let detail = Label::new(Some(&item.detail));
+            detail.add_css_class("dim-label");
+            row.append(&detail);
+            row.append(
+                &Label::builder()
+                    .label(item.text.trim())
+                    .xalign(0.0)
+                    .ellipsize(pango::EllipsizeMode::End)
+                    .build(),
+            );
+            self.list.append(&row);
+        }
+        self.items.replace(items);
+        self.widget.set_visible(true);
+    }
+
+    pub fn hide(&self) {
+        self.widget.set_visible(false);
+    }
+}
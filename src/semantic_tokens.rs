@@ -0,0 +1,184 @@
+use gtk4::{TextBuffer, TextTag, TextView, prelude::*};
+use serde_json::Value;
+
+use crate::lsp::{self, Encoding};
+
+pub const TOKEN_TYPES: &[&str] = &[
+    "namespace",
+    "type",
+    "class",
+    "enum",
+    "interface",
+    "struct",
+    "typeParameter",
+    "parameter",
+    "variable",
+    "property",
+    "enumMember",
+    "event",
+    "function",
+    "method",
+    "macro",
+    "keyword",
+    "modifier",
+    "comment",
+    "string",
+    "number",
+    "regexp",
+    "operator",
+    "decorator",
+];
+pub const TOKEN_MODIFIERS: &[&str] = &[
+    "declaration",
+    "definition",
+    "readonly",
+    "static",
+    "deprecated",
+    "abstract",
+    "async",
+    "modification",
+    "documentation",
+    "defaultLibrary",
+];
+const CAPTURES: &[&str] = &[
+    "keyword",
+    "function",
+    "type",
+    "type_builtin",
+    "constant",
+    "string",
+    "dead",
+    "field",
+    "important",
+    "variable",
+];
+const DEPRECATED_TAG: &str = "semantic_deprecated";
+
+fn capture(token_type: &str, modifiers: &[&str]) -> Option<&'static str> {
+    let builtin = modifiers.contains(&"defaultLibrary");
+    Some(match token_type {
+        "namespace" | "type" | "class" | "enum" | "interface" | "struct" | "typeParameter"
+            if builtin =>
+        {
+            "type_builtin"
+        }
+        "namespace" | "type" | "class" | "enum" | "interface" | "struct" | "typeParameter" => {
+            "type"
+        }
+        "variable" if modifiers.contains(&"readonly") && modifiers.contains(&"static") => {
+            "constant"
+        }
+        "parameter" | "variable" => "variable",
+        "property" | "event" => "field",
+        "enumMember" | "number" => "constant",
+        "function" | "method" | "macro" => "function",
+        "keyword" | "modifier" => "keyword",
+        "comment" => "dead",
+        "string" | "regexp" => "string",
+        "decorator" => "important",
+        _ => return None,
+    })
+}
+
+pub fn create_tags(text_view: &TextView) {
+    let table = text_view.buffer().tag_table();
+    for name in CAPTURES {
+        let tag = TextTag::new(Some(&format!("semantic_{}", name)));
+        match table.lookup(name) {
+            Some(theme) => {
+                if theme.is_foreground_set() {
+                    tag.set_foreground_rgba(theme.foreground_rgba().as_ref());
+                }
+                tag.set_weight(if theme.is_weight_set() {
+                    theme.weight()
+                } else {
+                    400
+                });
+            }
+            None => {
+                tag.set_foreground_rgba(Some(&text_view.color()));
+                tag.set_weight(400);
+            }
+        }
+        table.add(&tag);
+    }
+    let deprecated = TextTag::new(Some(DEPRECATED_TAG));
+    deprecated.set_strikethrough(true);
+    table.add(&deprecated);
+}
+
+pub fn clear(buffer: &TextBuffer) {
+    let (start, end) = buffer.bounds();
+    for name in CAPTURES {
+        buffer.remove_tag_by_name(&format!("semantic_{}", name), &start, &end);
+    }
+    buffer.remove_tag_by_name(DEPRECATED_TAG, &start, &end);
+}
+
+pub fn apply(buffer: &TextBuffer, data: &[Value], legend: &Value, encoding: Encoding) {
+    clear(buffer);
+    let names = |field: &str| -> Vec<String> {
+        legend[field]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|name| name.as_str().unwrap_or("").to_owned())
+            .collect()
+    };
+    let types = names("tokenTypes");
+    let modifier_names = names("tokenModifiers");
+    let numbers: Vec<u64> = data.iter().filter_map(Value::as_u64).collect();
+    let mut line = 0;
+    let mut column = 0;
+    let mut line_text = String::new();
+    let mut text_line = None;
+    for token in numbers.chunks_exact(5) {
+        let (delta_line, delta_start, length, token_type, modifier_bits) =
+            (token[0], token[1], token[2], token[3], token[4]);
+        if delta_line > 0 {
+            line += delta_line;
+            column = delta_start;
+        } else {
+            column += delta_start;
+        }
+        let modifiers: Vec<&str> = modifier_names
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| *bit < 64 && modifier_bits & (1 << bit) != 0)
+            .map(|(_, name)| name.as_str())
+            .collect();
+        let Some(token_type) = types.get(token_type as usize) else {
+            continue;
+        };
+        let deprecated = modifiers.contains(&"deprecated");
+        let capture = capture(token_type, &modifiers);
+        if capture.is_none() && !deprecated {
+            continue;
+        }
+        if text_line != Some(line) {
+            let Some(start) = buffer.iter_at_line(line as i32) else {
+                break;
+            };
+            let mut end = start;
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            line_text = start.slice(&end).to_string();
+            text_line = Some(line);
+        }
+        let start_index = lsp::column_to_index(&line_text, column, encoding);
+        let end_index = lsp::column_to_index(&line_text, column + length, encoding);
+        let (Some(start), Some(end)) = (
+            buffer.iter_at_line_index(line as i32, start_index as i32),
+            buffer.iter_at_line_index(line as i32, end_index as i32),
+        ) else {
+            continue;
+        };
+        if let Some(capture) = capture {
+            buffer.apply_tag_by_name(&format!("semantic_{}", capture), &start, &end);
+        }
+        if deprecated {
+            buffer.apply_tag_by_name(DEPRECATED_TAG, &start, &end);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum WrapMode {
+    #[default]
+    None,
+    Window,
+    Column(i32),
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum WhitespaceMode {
+    #[default]
+    None,
+    Trailing,
+    All,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum TerminalDock {
+    #[default]
+    Bottom,
+    Right,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum DiffBase {
+    #[default]
+    Index,
+    Head,
+}
+
+#[derive(Clone)]
+pub struct EditorSettings {
+    pub wrap_mode: WrapMode,
+    pub rulers: Vec<i32>,
+    pub show_whitespace: WhitespaceMode,
+    pub indent_guides: bool,
+    pub highlight_current_line: bool,
+    pub minimap: bool,
+    pub outline: bool,
+    pub breadcrumbs: bool,
+    pub sticky_scroll: bool,
+    pub format_on_save: bool,
+    pub formatters: HashMap<String, Vec<String>>,
+    pub formatter_timeout: u64,
+    pub language_servers: HashMap<String, Vec<String>>,
+    pub debug_adapters: HashMap<String, Vec<String>>,
+    pub diff_base: DiffBase,
+    pub terminal_dock: TerminalDock,
+    pub terminal_scrollback: usize,
+    pub vim_mode: bool,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        EditorSettings {
+            wrap_mode: WrapMode::None,
+            rulers: Vec::new(),
+            show_whitespace: WhitespaceMode::None,
+            indent_guides: false,
+            highlight_current_line: true,
+            minimap: false,
+            outline: false,
+            breadcrumbs: true,
+            sticky_scroll: false,
+            format_on_save: false,
+            formatters: HashMap::new(),
+            formatter_timeout: 5,
+            language_servers: HashMap::new(),
+            debug_adapters: HashMap::new(),
+            diff_base: DiffBase::Index,
+            terminal_dock: TerminalDock::Bottom,
+            terminal_scrollback: 10000,
+            vim_mode: false,
+        }
+    }
+}
+
+pub fn config_dir() -> PathBuf {
+    if let Ok(config_home) = env::var("XDG_CONFIG_HOME") {
+        return PathBuf::from(config_home).join("moon");
+    }
+    PathBuf::from(env::var("HOME").unwrap_or_default())
+        .join(".config")
+        .join("moon")
+}
+
+pub fn cache_dir() -> PathBuf {
+    if let Ok(cache_home) = env::var("XDG_CACHE_HOME") {
+        return PathBuf::from(cache_home).join("moon");
+    }
+    PathBuf::from(env::var("HOME").unwrap_or_default())
+        .join(".cache")
+        .join("moon")
+}
+
+fn commands(table: &toml::Table, name: &str) -> HashMap<String, Vec<String>> {
+    let mut commands = HashMap::new();
+    let Some(entries) = table.get(name).and_then(|value| value.as_table()) else {
+        return commands;
+    };
+    for (language, command) in entries {
+        let command: Vec<String> = match command {
+            toml::Value::String(command) => command.split_whitespace().map(str::to_owned).collect(),
+            toml::Value::Array(arguments) => arguments
+                .iter()
+                .filter_map(|argument| argument.as_str())
+                .map(str::to_owned)
+                .collect(),
+            _ => Vec::new(),
+        };
+        if command.is_empty() {
+            eprintln!("Ignoring invalid {} entry for {}", name, language);
+        } else {
+            commands.insert(language.clone(), command);
+        }
+    }
+    commands
+}
+
+pub fn load_settings() -> EditorSettings {
+    let mut settings = EditorSettings::default();
+    let Ok(content) = fs::read_to_string(config_dir().join("settings.toml")) else {
+        return settings;
+    };
+    let table = match content.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(error) => {
+            eprintln!("Could not parse settings.toml: {}", error);
+            return settings;
+        }
+    };
+    let wrap_column = table
+        .get("wrap_column")
+        .and_then(|value| value.as_integer())
+        .unwrap_or(80) as i32;
+    settings.wrap_mode = match table.get("wrap").and_then(|value| value.as_str()) {
+        Some("window") => WrapMode::Window,
+        Some("column") => WrapMode::Column(wrap_column),
+        _ => WrapMode::None,
+    };
+    if let Some(rulers) = table.get("rulers").and_then(|value| value.as_array()) {
+        settings.rulers = rulers
+            .iter()
+            .filter_map(|ruler| ruler.as_integer())
+            .map(|ruler| ruler as i32)
+            .collect();
+    }
+    settings.show_whitespace = match table
+        .get("show_whitespace")
+        .and_then(|value| value.as_str())
+    {
+        Some("trailing") => WhitespaceMode::Trailing,
+        Some("all") => WhitespaceMode::All,
+        _ => WhitespaceMode::None,
+    };
+    if let Some(indent_guides) = table.get("indent_guides").and_then(|value| value.as_bool()) {
+        settings.indent_guides = indent_guides;
+    }
+    if let Some(highlight) = table
+        .get("highlight_current_line")
+        .and_then(|value| value.as_bool())
+    {
+        settings.highlight_current_line = highlight;
+    }
+    if let Some(minimap) = table.get("minimap").and_then(|value| value.as_bool()) {
+        settings.minimap = minimap;
+    }
+    if let Some(outline) = table.get("outline").and_then(|value| value.as_bool()) {
+        settings.outline = outline;
+    }
+    if let Some(breadcrumbs) = table.get("breadcrumbs").and_then(|value| value.as_bool()) {
+        settings.breadcrumbs = breadcrumbs;
+    }
+    if let Some(sticky_scroll) = table.get("sticky_scroll").and_then(|value| value.as_bool()) {
+        settings.sticky_scroll = sticky_scroll;
+    }
+    if let Some(format_on_save) = table
+        .get("format_on_save")
+        .and_then(|value| value.as_bool())
+    {
+        settings.format_on_save = format_on_save;
+    }
+    settings.formatters = commands(&table, "formatters");
+    settings.language_servers = commands(&table, "language_servers");
+    settings.debug_adapters = commands(&table, "debug_adapters");
+    if let Some(timeout) = table
+        .get("formatter_timeout")
+        .and_then(|value| value.as_integer())
+    {
+        settings.formatter_timeout = timeout.max(1) as u64;
+    }
+    settings.diff_base = match table.get("git_diff_base").and_then(|value| value.as_str()) {
+        Some("head") => DiffBase::Head,
+        _ => DiffBase::Index,
+    };
+    settings.terminal_dock = match table.get("terminal_dock").and_then(|value| value.as_str()) {
+        Some("right") => TerminalDock::Right,
+        _ => TerminalDock::Bottom,
+    };
+    if let Some(scrollback) = table
+        .get("terminal_scrollback")
+        .and_then(|value| value.as_integer())
+    {
+        settings.terminal_scrollback = scrollback.max(0) as usize;
+    }
+    if let Some(vim_mode) = table.get("vim_mode").and_then(|value| value.as_bool()) {
+        settings.vim_mode = vim_mode;
+    }
+    settings
+}
@@ -0,0 +1,981 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    rc::Rc,
+};
+
+use gtk4::{
+    EventControllerKey, ListBox, Popover, PositionType, PropagationPhase, SelectionMode,
+    TextBuffer, TextIter, TextMark, TextView, TextWindowType, gdk,
+    glib::{self, Propagation},
+    prelude::*,
+};
+
+use crate::{
+    commands::{Argument, CommandRegistry},
+    document::Document,
+    settings,
+};
+
+const QAT_SNIPPETS: &str = include_str!("snippets/qat.toml");
+
+#[derive(Clone)]
+pub struct Snippet {
+    pub name: String,
+    pub prefix: String,
+    pub description: String,
+    pub body: String,
+}
+
+enum Segment {
+    Text(String),
+    Field {
+        number: u32,
+        placeholder: Vec<Segment>,
+        choices: Vec<String>,
+    },
+    Variable {
+        name: String,
+        default: Vec<Segment>,
+    },
+}
+
+struct Parser {
+    characters: Vec<char>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.characters.get(self.index).copied()
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.index;
+        while self
+            .peek()
+            .is_some_and(|character| character.is_ascii_digit())
+        {
+            self.index += 1;
+        }
+        self.characters[start..self.index]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.index;
+        while self
+            .peek()
+            .is_some_and(|character| character.is_ascii_alphanumeric() || character == '_')
+        {
+            self.index += 1;
+        }
+        self.characters[start..self.index].iter().collect()
+    }
+
+    fn choices(&mut self) -> Vec<String> {
+        let mut choices = vec![String::new()];
+        while let Some(character) = self.peek() {
+            self.index += 1;
+            match character {
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        self.index += 1;
+                        choices.last_mut().unwrap().push(escaped);
+                    }
+                }
+                ',' => choices.push(String::new()),
+                '|' if self.peek() == Some('}') => {
+                    self.index += 1;
+                    break;
+                }
+                _ => choices.last_mut().unwrap().push(character),
+            }
+        }
+        choices
+    }
+
+    fn segments(&mut self, nested: bool) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        while let Some(character) = self.peek() {
+            if nested && character == '}' {
+                break;
+            }
+            self.index += 1;
+            if character == '\\'
+                && let Some(escaped) = self
+                    .peek()
+                    .filter(|escaped| matches!(escaped, '$' | '}' | '\\'))
+            {
+                self.index += 1;
+                text.push(escaped);
+                continue;
+            }
+            if character != '$' {
+                text.push(character);
+                continue;
+            }
+            let segment = match self.peek() {
+                Some(next) if next.is_ascii_digit() => Segment::Field {
+                    number: self.number().unwrap_or(0),
+                    placeholder: Vec::new(),
+                    choices: Vec::new(),
+                },
+                Some(next) if next.is_ascii_alphabetic() || next == '_' => Segment::Variable {
+                    name: self.name(),
+                    default: Vec::new(),
+                },
+                Some('{') => {
+                    self.index += 1;
+                    match self.braced() {
+                        Some(segment) => segment,
+                        None => {
+                            text.push_str("${");
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    text.push('$');
+                    continue;
+                }
+            };
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(segment);
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        segments
+    }
+
+    fn braced(&mut self) -> Option<Segment> {
+        let start = self.index;
+        let segment = if self.peek().is_some_and(|next| next.is_ascii_digit()) {
+            let number = self.number()?;
+            match self.peek() {
+                Some(':') => {
+                    self.index += 1;
+                    let placeholder = self.segments(true);
+                    Segment::Field {
+                        number,
+                        placeholder,
+                        choices: Vec::new(),
+                    }
+                }
+                Some('|') => {
+                    self.index += 1;
+                    let choices = self.choices();
+                    return Some(Segment::Field {
+                        number,
+                        placeholder: Vec::new(),
+                        choices,
+                    });
+                }
+                _ => Segment::Field {
+                    number,
+                    placeholder: Vec::new(),
+                    choices: Vec::new(),
+                },
+            }
+        } else {
+            let name = self.name();
+            if name.is_empty() {
+                self.index = start;
+                return None;
+            }
+            let default = if self.peek() == Some(':') {
+                self.index += 1;
+                self.segments(true)
+            } else {
+                Vec::new()
+            };
+            Segment::Variable { name, default }
+        };
+        if self.peek() != Some('}') {
+            self.index = start;
+            return None;
+        }
+        self.index += 1;
+        Some(segment)
+    }
+}
+
+fn parse_body(body: &str) -> Vec<Segment> {
+    Parser {
+        characters: body.chars().collect(),
+        index: 0,
+    }
+    .segments(false)
+}
+
+struct FieldRange {
+    number: u32,
+    start: usize,
+    end: usize,
+    choices: Vec<String>,
+}
+
+struct Renderer<'a> {
+    variables: &'a HashMap<&'static str, String>,
+    indent: String,
+    placeholders: HashMap<u32, String>,
+    text: String,
+    length: usize,
+    fields: Vec<FieldRange>,
+}
+
+impl Renderer<'_> {
+    fn push(&mut self, text: &str) {
+        for character in text.chars() {
+            self.text.push(character);
+            self.length += 1;
+            if character == '\n' {
+                self.text.push_str(&self.indent);
+                self.length += self.indent.chars().count();
+            }
+        }
+    }
+
+    fn render(&mut self, segments: &[Segment]) {
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => self.push(text),
+                Segment::Variable { name, default } => {
+                    match self
+                        .variables
+                        .get(name.as_str())
+                        .filter(|value| !value.is_empty())
+                    {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.push(&value);
+                        }
+                        None if self.variables.contains_key(name.as_str()) => self.render(default),
+                        None if default.is_empty() => self.push(name),
+                        None => self.render(default),
+                    }
+                }
+                Segment::Field {
+                    number,
+                    placeholder,
+                    choices,
+                } => {
+                    let start = self.length;
+                    if let Some(first_choice) = choices.first() {
+                        self.push(first_choice);
+                    } else if !placeholder.is_empty() {
+                        self.render(placeholder);
+                        let text: String = self.text.chars().skip(start).collect();
+                        self.placeholders.entry(*number).or_insert(text);
+                    } else if let Some(mirrored) = self.placeholders.get(number).cloned() {
+                        self.push(&mirrored);
+                    }
+                    self.fields.push(FieldRange {
+                        number: *number,
+                        start,
+                        end: self.length,
+                        choices: choices.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn collect_placeholders(
+    segments: &[Segment],
+    variables: &HashMap<&'static str, String>,
+    placeholders: &mut HashMap<u32, String>,
+) {
+    for segment in segments {
+        if let Segment::Field {
+            number,
+            placeholder,
+            choices,
+        } = segment
+        {
+            if placeholders.contains_key(number) {
+                continue;
+            }
+            if let Some(first_choice) = choices.first() {
+                placeholders.insert(*number, first_choice.clone());
+            } else if !placeholder.is_empty() {
+                let mut renderer = Renderer {
+                    variables,
+                    indent: String::new(),
+                    placeholders: HashMap::new(),
+                    text: String::new(),
+                    length: 0,
+                    fields: Vec::new(),
+                };
+                renderer.render(placeholder);
+                placeholders.insert(*number, renderer.text);
+            }
+        }
+    }
+}
+
+struct Field {
+    number: u32,
+    start: TextMark,
+    end: TextMark,
+    choices: Vec<String>,
+}
+
+struct Session {
+    fields: Vec<Field>,
+    stops: Vec<u32>,
+    current: usize,
+    start: TextMark,
+    end: TextMark,
+}
+
+pub struct Snippets {
+    text_view: TextView,
+    document: Rc<Document>,
+    snippets: RefCell<HashMap<String, Vec<Snippet>>>,
+    session: RefCell<Option<Session>>,
+    syncing: Rc<Cell<bool>>,
+    sync_pending: Rc<Cell<bool>>,
+    choice_popover: Popover,
+    choice_list: ListBox,
+}
+
+fn parse_snippet_file(content: &str) -> Result<Vec<Snippet>, toml::de::Error> {
+    let table = content.parse::<toml::Table>()?;
+    Ok(table
+        .iter()
+        .filter_map(|(name, value)| {
+            let body = match value.get("body")? {
+                toml::Value::Array(lines) => lines
+                    .iter()
+                    .filter_map(|line| line.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                body => body.as_str()?.to_owned(),
+            };
+            Some(Snippet {
+                name: name.clone(),
+                prefix: value
+                    .get("prefix")
+                    .and_then(|prefix| prefix.as_str())
+                    .unwrap_or(name)
+                    .to_owned(),
+                description: value
+                    .get("description")
+                    .and_then(|description| description.as_str())
+                    .unwrap_or_default()
+                    .to_owned(),
+                body,
+            })
+        })
+        .collect())
+}
+
+pub fn load_snippets() -> HashMap<String, Vec<Snippet>> {
+    let mut snippets: HashMap<String, Vec<Snippet>> = HashMap::new();
+    snippets.insert(
+        "qat".to_owned(),
+        parse_snippet_file(QAT_SNIPPETS).expect("Built-in qat snippets are valid"),
+    );
+    let Ok(entries) = fs::read_dir(settings::config_dir().join("snippets")) else {
+        return snippets;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("toml") {
+            continue;
+        }
+        let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        match parse_snippet_file(&content) {
+            Ok(user_snippets) => {
+                let language_snippets = snippets.entry(language.to_owned()).or_default();
+                for snippet in user_snippets {
+                    language_snippets.retain(|existing| existing.name != snippet.name);
+                    language_snippets.push(snippet);
+                }
+            }
+            Err(error) => eprintln!("Could not parse {}: {}", path.display(), error),
+        }
+    }
+    snippets
+}
+
+fn prefix_start(cursor: &TextIter) -> TextIter {
+    let mut start = *cursor;
+    while !start.starts_line() {
+        let mut previous = start;
+        previous.backward_char();
+        if previous.char().is_whitespace() {
+            break;
+        }
+        start = previous;
+    }
+    start
+}
+
+impl Snippets {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        commands: &CommandRegistry,
+    ) -> Rc<Snippets> {
+        let choice_list = ListBox::builder()
+            .selection_mode(SelectionMode::Browse)
+            .build();
+        let choice_popover = Popover::builder()
+            .child(&choice_list)
+            .has_arrow(false)
+            .position(PositionType::Bottom)
+            .autohide(false)
+            .build();
+        choice_popover.set_parent(text_view);
+        let snippets = Rc::new(Snippets {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            snippets: RefCell::new(load_snippets()),
+            session: RefCell::new(None),
+            syncing: Rc::new(Cell::new(false)),
+            sync_pending: Rc::new(Cell::new(false)),
+            choice_popover,
+            choice_list: choice_list.clone(),
+        });
+        let controller = EventControllerKey::new();
+        controller.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&snippets);
+        controller.connect_key_pressed(move |_, keyval, _, modifiers| {
+            let Some(snippets) = weak.upgrade() else {
+                return Propagation::Proceed;
+            };
+            snippets.key_pressed(keyval, modifiers)
+        });
+        text_view.add_controller(controller);
+        let weak = Rc::downgrade(&snippets);
+        choice_list.connect_row_activated(move |_, row| {
+            if let Some(snippets) = weak.upgrade() {
+                snippets.pick_choice(row.index() as usize);
+            }
+        });
+        let buffer = text_view.buffer();
+        let weak = Rc::downgrade(&snippets);
+        buffer.connect_changed(move |_| {
+            if let Some(snippets) = weak.upgrade() {
+                snippets.schedule_sync();
+            }
+        });
+        let weak = Rc::downgrade(&snippets);
+        buffer.connect_mark_set(move |buffer, iter, mark| {
+            if mark != &buffer.get_insert() {
+                return;
+            }
+            if let Some(snippets) = weak.upgrade() {
+                snippets.cursor_moved(iter);
+            }
+        });
+        let weak = Rc::downgrade(&snippets);
+        let choices = snippets.snippet_choices();
+        commands.register_with_argument(
+            "snippets.insert",
+            "Insert Snippet",
+            Argument {
+                prompt: "Snippet".to_owned(),
+                choices,
+            },
+            move |name| {
+                if let Some(snippets) = weak.upgrade() {
+                    snippets.insert_named(name);
+                }
+            },
+        );
+        let reloading = snippets.clone();
+        commands.register("snippets.reload", "Reload Snippets", move || {
+            reloading.snippets.replace(load_snippets());
+        });
+        snippets
+    }
+
+    fn snippet_choices(&self) -> Vec<(String, String)> {
+        let mut choices: Vec<(String, String)> = self
+            .snippets
+            .borrow()
+            .values()
+            .flatten()
+            .map(|snippet| (snippet.name.clone(), snippet.description.clone()))
+            .collect();
+        choices.sort();
+        choices.dedup_by(|choice, other| choice.0 == other.0);
+        choices
+    }
+
+    pub fn snippets_for(&self, language: &str) -> Vec<Snippet> {
+        self.snippets
+            .borrow()
+            .get(language)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn insert_named(&self, name: &str) {
+        let language = self.document.language();
+        let Some(snippet) = self
+            .snippets_for(&language)
+            .into_iter()
+            .find(|snippet| snippet.name == name)
+        else {
+            return;
+        };
+        let buffer = self.text_view.buffer();
+        let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            (cursor, cursor)
+        });
+        let selected = buffer.text(&start, &end, true).to_string();
+        self.expand(&start, &end, &snippet.body, &selected);
+    }
+
+    fn key_pressed(&self, keyval: gdk::Key, modifiers: gdk::ModifierType) -> Propagation {
+        let shift = modifiers.contains(gdk::ModifierType::SHIFT_MASK);
+        let plain = (modifiers - gdk::ModifierType::SHIFT_MASK).is_empty();
+        let in_session = self.session.borrow().is_some();
+        if self.choice_popover.is_visible() {
+            match keyval {
+                gdk::Key::Up | gdk::Key::Down => {
+                    let count = self.choice_count();
+                    let current = self
+                        .choice_list
+                        .selected_row()
+                        .map(|row| row.index())
+                        .unwrap_or(0);
+                    let next = if keyval == gdk::Key::Up {
+                        (current - 1).max(0)
+                    } else {
+                        (current + 1).min(count - 1)
+                    };
+                    self.choice_list
+                        .select_row(self.choice_list.row_at_index(next).as_ref());
+                    return Propagation::Stop;
+                }
+                gdk::Key::Return | gdk::Key::KP_Enter => {
+                    let index = self
+                        .choice_list
+                        .selected_row()
+                        .map(|row| row.index())
+                        .unwrap_or(0);
+                    self.pick_choice(index as usize);
+                    return Propagation::Stop;
+                }
+                _ => self.choice_popover.popdown(),
+            }
+        }
+        match keyval {
+            gdk::Key::Tab if plain && !shift => {
+                if in_session {
+                    self.next_stop(1);
+                    return Propagation::Stop;
+                }
+                if self.expand_prefix() {
+                    return Propagation::Stop;
+                }
+                Propagation::Proceed
+            }
+            gdk::Key::ISO_Left_Tab | gdk::Key::Tab if plain && shift && in_session => {
+                self.next_stop(-1);
+                Propagation::Stop
+            }
+            gdk::Key::Escape if in_session => {
+                self.end_session();
+                Propagation::Proceed
+            }
+            _ => Propagation::Proceed,
+        }
+    }
+
+    fn expand_prefix(&self) -> bool {
+        let buffer = self.text_view.buffer();
+        if buffer.has_selection() {
+            return false;
+        }
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let token_start = prefix_start(&cursor);
+        let token = buffer.text(&token_start, &cursor, true).to_string();
+        if token.is_empty() {
+            return false;
+        }
+        let snippets = self.snippets_for(&self.document.language());
+        let found = snippets
+            .iter()
+            .filter(|snippet| !snippet.prefix.is_empty() && token.ends_with(&snippet.prefix))
+            .filter(|snippet| {
+                let before = token[..token.len() - snippet.prefix.len()].chars().last();
+                before.is_none_or(|character| {
+                    !(character.is_alphanumeric() || character == '_')
+                        || !snippet
+                            .prefix
+                            .starts_with(|first: char| first.is_alphanumeric() || first == '_')
+                })
+            })
+            .max_by_key(|snippet| snippet.prefix.len());
+        let Some(snippet) = found else {
+            return false;
+        };
+        let mut start = cursor;
+        start.backward_chars(snippet.prefix.chars().count() as i32);
+        self.expand(&start, &cursor, &snippet.body, "");
+        true
+    }
+
+    fn variables(&self, cursor: &TextIter, selected: &str) -> HashMap<&'static str, String> {
+        let path = self.document.path();
+        let now = glib::DateTime::now_local().ok();
+        let format = |pattern: &str| {
+            now.as_ref()
+                .and_then(|now| now.format(pattern).ok())
+                .map(|text| text.to_string())
+                .unwrap_or_default()
+        };
+        let buffer = cursor.buffer();
+        let mut line_start = *cursor;
+        line_start.set_line_offset(0);
+        let mut line_end = *cursor;
+        if !line_end.ends_line() {
+            line_end.forward_to_line_end();
+        }
+        HashMap::from([
+            (
+                "TM_FILENAME",
+                path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "TM_FILENAME_BASE",
+                path.file_stem()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "TM_DIRECTORY",
+                path.parent()
+                    .map(|directory| directory.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            ("TM_FILEPATH", path.display().to_string()),
+            ("TM_SELECTED_TEXT", selected.to_owned()),
+            ("TM_LINE_INDEX", cursor.line().to_string()),
+            ("TM_LINE_NUMBER", (cursor.line() + 1).to_string()),
+            (
+                "TM_CURRENT_LINE",
+                buffer.text(&line_start, &line_end, true).to_string(),
+            ),
+            ("CURRENT_YEAR", format("%Y")),
+            ("CURRENT_YEAR_SHORT", format("%y")),
+            ("CURRENT_MONTH", format("%m")),
+            ("CURRENT_MONTH_NAME", format("%B")),
+            ("CURRENT_DATE", format("%d")),
+            ("CURRENT_DAY_NAME", format("%A")),
+            ("CURRENT_HOUR", format("%H")),
+            ("CURRENT_MINUTE", format("%M")),
+            ("CURRENT_SECOND", format("%S")),
+        ])
+    }
+
+    pub fn expand(&self, start: &TextIter, end: &TextIter, body: &str, selected: &str) {
+        self.end_session();
+        let buffer = self.text_view.buffer();
+        let segments = parse_body(body);
+        let variables = self.variables(start, selected);
+        let mut line_start = *start;
+        line_start.set_line_offset(0);
+        let mut indent_end = line_start;
+        while !indent_end.ends_line()
+            && indent_end.offset() < start.offset()
+            && indent_end.char().is_whitespace()
+        {
+            indent_end.forward_char();
+        }
+        let mut placeholders = HashMap::new();
+        collect_placeholders(&segments, &variables, &mut placeholders);
+        let mut renderer = Renderer {
+            variables: &variables,
+            indent: buffer.text(&line_start, &indent_end, true).to_string(),
+            placeholders,
+            text: String::new(),
+            length: 0,
+            fields: Vec::new(),
+        };
+        renderer.render(&segments);
+        let (mut start, mut end) = (*start, *end);
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        let offset = start.offset();
+        buffer.insert(&mut start, &renderer.text);
+        buffer.end_user_action();
+        let iter_at = |relative: usize| buffer.iter_at_offset(offset + relative as i32);
+        let fields: Vec<Field> = renderer
+            .fields
+            .into_iter()
+            .map(|field| Field {
+                number: field.number,
+                start: buffer.create_mark(None, &iter_at(field.start), true),
+                end: buffer.create_mark(None, &iter_at(field.end), false),
+                choices: field.choices,
+            })
+            .collect();
+        let mut stops: Vec<u32> = fields
+            .iter()
+            .map(|field| field.number)
+            .filter(|number| *number != 0)
+            .collect();
+        stops.sort();
+        stops.dedup();
+        if fields.iter().any(|field| field.number == 0) {
+            stops.push(0);
+        }
+        let session = Session {
+            fields,
+            stops,
+            current: 0,
+            start: buffer.create_mark(None, &iter_at(0), true),
+            end: buffer.create_mark(None, &iter_at(renderer.length), false),
+        };
+        if session.stops.is_empty() {
+            buffer.place_cursor(&buffer.iter_at_mark(&session.end));
+            self.delete_session_marks(&session);
+            return;
+        }
+        self.session.replace(Some(session));
+        self.select_stop();
+    }
+
+    fn select_stop(&self) {
+        let buffer = self.text_view.buffer();
+        let (start, end, number, choices) = {
+            let session = self.session.borrow();
+            let Some(session) = session.as_ref() else {
+                return;
+            };
+            let number = session.stops[session.current];
+            let Some(field) = session.fields.iter().find(|field| field.number == number) else {
+                return;
+            };
+            (
+                buffer.iter_at_mark(&field.start),
+                buffer.iter_at_mark(&field.end),
+                number,
+                field.choices.clone(),
+            )
+        };
+        buffer.select_range(&end, &start);
+        self.text_view.scroll_mark_onscreen(&buffer.get_insert());
+        if number == 0 {
+            buffer.place_cursor(&end);
+            self.end_session();
+            return;
+        }
+        if choices.len() > 1 {
+            self.show_choices(&start, &choices);
+        }
+    }
+
+    fn next_stop(&self, direction: i32) {
+        let finished = {
+            let mut session = self.session.borrow_mut();
+            let Some(session) = session.as_mut() else {
+                return;
+            };
+            let next = session.current as i32 + direction;
+            if next < 0 {
+                return;
+            }
+            if next as usize >= session.stops.len() {
+                true
+            } else {
+                session.current = next as usize;
+                false
+            }
+        };
+        if finished {
+            let buffer = self.text_view.buffer();
+            let end = self
+                .session
+                .borrow()
+                .as_ref()
+                .map(|session| buffer.iter_at_mark(&session.end));
+            if let Some(end) = end {
+                buffer.place_cursor(&end);
+            }
+            self.end_session();
+            return;
+        }
+        self.select_stop();
+    }
+
+    fn delete_session_marks(&self, session: &Session) {
+        let buffer = self.text_view.buffer();
+        for field in &session.fields {
+            buffer.delete_mark(&field.start);
+            buffer.delete_mark(&field.end);
+        }
+        buffer.delete_mark(&session.start);
+        buffer.delete_mark(&session.end);
+    }
+
+    fn end_session(&self) {
+        self.choice_popover.popdown();
+        if let Some(session) = self.session.take() {
+            self.delete_session_marks(&session);
+        }
+    }
+
+    fn cursor_moved(&self, iter: &TextIter) {
+        if self.syncing.get() {
+            return;
+        }
+        let outside = {
+            let session = self.session.borrow();
+            let Some(session) = session.as_ref() else {
+                return;
+            };
+            let buffer = self.text_view.buffer();
+            let start = buffer.iter_at_mark(&session.start);
+            let end = buffer.iter_at_mark(&session.end);
+            iter.offset() < start.offset() || iter.offset() > end.offset()
+        };
+        if outside {
+            self.end_session();
+        }
+    }
+
+    fn schedule_sync(&self) {
+        if self.syncing.get() || self.sync_pending.get() {
+            return;
+        }
+        let marks = {
+            let session = self.session.borrow();
+            let Some(session) = session.as_ref() else {
+                return;
+            };
+            let number = session.stops[session.current];
+            session
+                .fields
+                .iter()
+                .filter(|field| field.number == number)
+                .map(|field| (field.start.clone(), field.end.clone()))
+                .collect::<Vec<_>>()
+        };
+        if marks.len() < 2 {
+            return;
+        }
+        self.sync_pending.set(true);
+        let buffer = self.text_view.buffer();
+        let syncing = self.syncing.clone();
+        let sync_pending = self.sync_pending.clone();
+        glib::idle_add_local_once(move || {
+            sync_pending.set(false);
+            if marks
+                .iter()
+                .any(|(start, end)| start.is_deleted() || end.is_deleted())
+            {
+                return;
+            }
+            let (primary_start, primary_end) = &marks[0];
+            let text = buffer.text(
+                &buffer.iter_at_mark(primary_start),
+                &buffer.iter_at_mark(primary_end),
+                true,
+            );
+            syncing.set(true);
+            for (start, end) in &marks[1..] {
+                let mut start_iter = buffer.iter_at_mark(start);
+                let mut end_iter = buffer.iter_at_mark(end);
+                if buffer.text(&start_iter, &end_iter, true) == text {
+                    continue;
+                }
+                buffer.delete(&mut start_iter, &mut end_iter);
+                buffer.insert(&mut start_iter, &text);
+            }
+            syncing.set(false);
+        });
+    }
+
+    fn show_choices(&self, at: &TextIter, choices: &[String]) {
+        self.choice_list.remove_all();
+        for choice in choices {
+            let label = if choice.is_empty() { "(empty)" } else { choice };
+            let row = gtk4::Label::builder().label(label).xalign(0.0).build();
+            self.choice_list.append(&row);
+        }
+        self.choice_list
+            .select_row(self.choice_list.row_at_index(0).as_ref());
+        let location = self.text_view.iter_location(at);
+        let (x, y) = self.text_view.buffer_to_window_coords(
+            TextWindowType::Widget,
+            location.x(),
+            location.y() + location.height(),
+        );
+        self.choice_popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, 1)));
+        self.choice_popover.popup();
+    }
+
+    fn choice_count(&self) -> i32 {
+        let session = self.session.borrow();
+        let Some(session) = session.as_ref() else {
+            return 0;
+        };
+        let number = session.stops[session.current];
+        session
+            .fields
+            .iter()
+            .find(|field| field.number == number)
+            .map(|field| field.choices.len() as i32)
+            .unwrap_or(0)
+    }
+
+    fn pick_choice(&self, index: usize) {
+        self.choice_popover.popdown();
+        let buffer: TextBuffer = self.text_view.buffer();
+        let picked = {
+            let session = self.session.borrow();
+            let Some(session) = session.as_ref() else {
+                return;
+            };
+            let number = session.stops[session.current];
+            session
+                .fields
+                .iter()
+                .find(|field| field.number == number)
+                .and_then(|field| {
+                    Some((
+                        field.start.clone(),
+                        field.end.clone(),
+                        field.choices.get(index)?.clone(),
+                    ))
+                })
+        };
+        let Some((start, end, choice)) = picked else {
+            return;
+        };
+        let mut start_iter = buffer.iter_at_mark(&start);
+        let mut end_iter = buffer.iter_at_mark(&end);
+        buffer.begin_user_action();
+        buffer.delete(&mut start_iter, &mut end_iter);
+        buffer.insert(&mut start_iter, &choice);
+        buffer.end_user_action();
+        buffer.select_range(&buffer.iter_at_mark(&end), &buffer.iter_at_mark(&start));
+        self.text_view.grab_focus();
+    }
+}
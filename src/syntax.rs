@@ -0,0 +1,114 @@
+use tree_sitter::{Language, Node, Parser, Tree};
+
+use crate::conflicts::{self, Side};
+
+pub const KEYWORDS: &[&str] = &[
+    "pub", "give", "loop", "struct", "mix", "toggle", "choice", "region", "heap", "is", "in",
+    "own", "let", "meta", "define", "if", "where", "use", "copy", "move", "swap", "pre", "say",
+    "not", "or", "and", "do", "skill", "type", "for", "else", "match", "var", "variadic",
+    "assembly", "from", "to", "flag", "opaque", "end", "operator", "spawn", "ignore", "_",
+    "default", "as", "volatile", "ok", "try",
+];
+
+pub const BUILTIN_TYPES: &[&str] = &[
+    "atomic",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "u1",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "f32",
+    "f64",
+    "f80",
+    "f128",
+    "f128ppc",
+    "fbrain",
+    "int",
+    "uint",
+    "bytestring",
+    "float",
+    "double",
+    "longdouble",
+    "usize",
+    "isize",
+    "self",
+    "bool",
+    "byte",
+    "char",
+    "uchar",
+    "poly",
+    "maybe",
+    "result",
+    "error",
+    "ref",
+    "ptr",
+    "multi",
+    "text",
+    "slice",
+    "future",
+    "integer",
+    "vec",
+];
+
+pub const CONSTANTS: &[&str] = &["none", "null"];
+
+pub const FUNCTION_KINDS: &[&str] = &[
+    "function_definition",
+    "prerun_function_definition",
+    "method",
+];
+
+pub const TYPE_KINDS: &[&str] = &[
+    "struct_definition",
+    "mix_definition",
+    "toggle_definition",
+    "choice_definition",
+    "flag_definition",
+    "type_definition",
+];
+
+unsafe extern "C" {
+    fn tree_sitter_qat() -> Language;
+}
+
+pub fn qat_language() -> Language {
+    unsafe { tree_sitter_qat() }
+}
+
+pub fn parse_qat(content: &str) -> Option<Tree> {
+    parse_qat_side(content, Side::Ours)
+}
+
+pub fn parse_qat_side(content: &str, side: Side) -> Option<Tree> {
+    let masked = conflicts::mask(content, side);
+    let mut parser = Parser::new();
+    parser
+        .set_language(&qat_language())
+        .expect("Could not set language");
+    parser.parse(masked.as_deref().unwrap_or(content), None)
+}
+
+pub fn error_lines(tree: &Tree) -> Vec<i32> {
+    let mut lines = Vec::new();
+    let mut pending: Vec<Node> = vec![tree.root_node()];
+    while let Some(node) = pending.pop() {
+        if node.is_error() || node.is_missing() {
+            lines.push(node.start_position().row as i32);
+            continue;
+        }
+        if !node.has_error() {
+            continue;
+        }
+        let mut cursor = node.walk();
+        pending.extend(node.children(&mut cursor));
+    }
+    lines.sort();
+    lines.dedup();
+    lines
+}
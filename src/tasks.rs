@@ -0,0 +1,756 @@
+use std::{
+    cell::{Cell, RefCell},
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Button, EventControllerMotion, GestureClick, Label, Orientation, PolicyType,
+    ScrolledWindow, TextIter, TextMark, TextView, TextWindowType, gdk, gio, glib, pango,
+    prelude::*,
+};
+use regex::Regex;
+
+use crate::{
+    commands::{Argument, CommandRegistry},
+    document::Document,
+    notification::Notification,
+    results::{self, Location, ResultItem, ResultsPanel},
+    workspace,
+};
+
+const TASKS_FILE: &str = "tasks.toml";
+const DEFAULT_PROBLEM_MATCHER: &str = r"^(?<file>[^\s:][^:]*):(?<line>\d+):(?:(?<column>\d+):)?\s*(?:(?<severity>error|warning|note|info|hint)[^:]*:)?\s*(?<message>.*)$";
+const ESCAPE_SEQUENCES: &str =
+    r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[()][0-9A-Za-z]";
+const PROBLEM_TAGS: &[&str] = &["task_error", "task_warning", "task_information"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+    Information,
+}
+
+impl Severity {
+    fn parse(text: &str) -> Severity {
+        let text = text.to_lowercase();
+        if text.starts_with("warn") {
+            Severity::Warning
+        } else if ["note", "info", "hint"]
+            .iter()
+            .any(|prefix| text.starts_with(prefix))
+        {
+            Severity::Information
+        } else {
+            Severity::Error
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Information => "note",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Severity::Error => PROBLEM_TAGS[0],
+            Severity::Warning => PROBLEM_TAGS[1],
+            Severity::Information => PROBLEM_TAGS[2],
+        }
+    }
+}
+
+struct Task {
+    name: String,
+    command: Vec<String>,
+    cwd: PathBuf,
+    env: Vec<(String, String)>,
+    matcher: Regex,
+}
+
+struct Problem {
+    location: Location,
+    severity: Severity,
+    message: String,
+    output_line: i32,
+}
+
+struct Run {
+    name: String,
+    process: gio::Subprocess,
+    group: Option<libc::pid_t>,
+    generation: u64,
+    cancelled: Cell<bool>,
+    cancellable: gio::Cancellable,
+}
+
+fn load_tasks(root: &Path) -> Result<Vec<Task>, String> {
+    let path = root.join(TASKS_FILE);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+    let mut tasks = Vec::new();
+    for entry in table
+        .get("task")
+        .and_then(|value| value.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.as_table())
+    {
+        let Some(name) = entry.get("name").and_then(|value| value.as_str()) else {
+            return Err(format!("A task in {} has no name", path.display()));
+        };
+        let command: Vec<String> = match entry.get("command") {
+            Some(toml::Value::String(command)) => {
+                vec!["sh".to_owned(), "-c".to_owned(), command.clone()]
+            }
+            Some(toml::Value::Array(arguments)) => arguments
+                .iter()
+                .filter_map(|argument| argument.as_str())
+                .map(str::to_owned)
+                .collect(),
+            _ => Vec::new(),
+        };
+        if command.is_empty() {
+            return Err(format!("Task {} has no command", name));
+        }
+        let cwd = entry
+            .get("cwd")
+            .and_then(|value| value.as_str())
+            .map_or(root.to_path_buf(), |cwd| root.join(cwd));
+        let env = entry
+            .get("env")
+            .and_then(|value| value.as_table())
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| {
+                let value = match value {
+                    toml::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect();
+        let pattern = entry
+            .get("problem_matcher")
+            .and_then(|value| value.as_str())
+            .unwrap_or(DEFAULT_PROBLEM_MATCHER);
+        let matcher = Regex::new(pattern)
+            .map_err(|error| format!("Invalid problem_matcher for task {}: {}", name, error))?;
+        tasks.push(Task {
+            name: name.to_owned(),
+            command,
+            cwd,
+            env,
+            matcher,
+        });
+    }
+    Ok(tasks)
+}
+
+fn same_file(first: &Path, second: &Path) -> bool {
+    first == second
+        || matches!(
+            (fs::canonicalize(first), fs::canonicalize(second)),
+            (Ok(first), Ok(second)) if first == second
+        )
+}
+
+fn parse_problem(matcher: &Regex, line: &str, cwd: &Path) -> Option<(Location, Severity, String)> {
+    let captures = matcher.captures(line)?;
+    let path = cwd.join(captures.name("file")?.as_str().trim());
+    if !path.is_file() {
+        return None;
+    }
+    let line_number = captures.name("line")?.as_str().parse::<i32>().ok()?;
+    let column = captures
+        .name("column")
+        .and_then(|column| column.as_str().parse::<i32>().ok())
+        .unwrap_or(1);
+    let severity = captures
+        .name("severity")
+        .map_or(Severity::Error, |severity| {
+            Severity::parse(severity.as_str())
+        });
+    let message = captures
+        .name("message")
+        .map_or(line, |message| message.as_str())
+        .trim()
+        .to_owned();
+    Some((
+        Location {
+            path: fs::canonicalize(&path).unwrap_or(path),
+            line: (line_number - 1).max(0),
+            column: (column - 1).max(0),
+        },
+        severity,
+        message,
+    ))
+}
+
+pub struct Tasks {
+    text_view: TextView,
+    document: Rc<Document>,
+    status: Label,
+    notification: Rc<Notification>,
+    results: Rc<ResultsPanel>,
+    commands: CommandRegistry,
+    widget: Box,
+    title: Label,
+    cancel: Button,
+    output: TextView,
+    end: TextMark,
+    escape_sequences: Regex,
+    problems: RefCell<Vec<Problem>>,
+    inline: RefCell<Vec<(TextMark, TextMark, usize)>>,
+    running: RefCell<Option<Run>>,
+    generation: Cell<u64>,
+    last: RefCell<Option<String>>,
+}
+
+impl Tasks {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        notification: &Rc<Notification>,
+        results: &Rc<ResultsPanel>,
+        commands: &CommandRegistry,
+    ) -> Rc<Tasks> {
+        let buffer = text_view.buffer();
+        for (name, color) in PROBLEM_TAGS.iter().zip([
+            gdk::RGBA::new(1.0, 0.35, 0.35, 1.0),
+            gdk::RGBA::new(1.0, 0.8, 0.3, 1.0),
+            gdk::RGBA::new(0.45, 0.7, 1.0, 1.0),
+        ]) {
+            buffer.create_tag(
+                Some(name),
+                &[
+                    ("underline", &pango::Underline::Error),
+                    ("underline-rgba", &color),
+                ],
+            );
+        }
+        let title = Label::builder().hexpand(true).xalign(0.0).build();
+        let cancel = Button::builder()
+            .label("Stop")
+            .has_frame(false)
+            .sensitive(false)
+            .build();
+        let close = Button::builder().label("✕").has_frame(false).build();
+        let header = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        header.append(&title);
+        header.append(&cancel);
+        header.append(&close);
+        let output = TextView::builder()
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .wrap_mode(gtk4::WrapMode::WordChar)
+            .left_margin(6)
+            .build();
+        let output_buffer = output.buffer();
+        output_buffer.create_tag(Some("task_command"), &[("foreground", &"#8a8f94")]);
+        for (name, color) in PROBLEM_TAGS.iter().zip(["#ff7272", "#fbd37d", "#69a5ff"]) {
+            output_buffer.create_tag(
+                Some(name),
+                &[
+                    ("foreground", &color),
+                    ("underline", &pango::Underline::Single),
+                ],
+            );
+        }
+        let end = output_buffer.create_mark(None, &output_buffer.end_iter(), false);
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&output)
+            .hscrollbar_policy(PolicyType::Never)
+            .vexpand(true)
+            .build();
+        let widget = Box::builder()
+            .orientation(Orientation::Vertical)
+            .height_request(200)
+            .name("task_panel")
+            .visible(false)
+            .build();
+        widget.append(&header);
+        widget.append(&scrolled_window);
+        let tasks = Rc::new(Tasks {
+            text_view: text_view.clone(),
+            document: document.clone(),
+            status: status.clone(),
+            notification: notification.clone(),
+            results: results.clone(),
+            commands: commands.clone(),
+            widget,
+            title,
+            cancel: cancel.clone(),
+            output: output.clone(),
+            end,
+            escape_sequences: Regex::new(ESCAPE_SEQUENCES)
+                .expect("Could not compile escape sequence pattern"),
+            problems: RefCell::new(Vec::new()),
+            inline: RefCell::new(Vec::new()),
+            running: RefCell::new(None),
+            generation: Cell::new(0),
+            last: RefCell::new(None),
+        });
+        let weak = Rc::downgrade(&tasks);
+        cancel.connect_clicked(move |_| {
+            if let Some(tasks) = weak.upgrade() {
+                tasks.cancel();
+            }
+        });
+        let weak = Rc::downgrade(&tasks);
+        close.connect_clicked(move |_| {
+            if let Some(tasks) = weak.upgrade() {
+                tasks.widget.set_visible(false);
+            }
+        });
+        let gesture = GestureClick::builder().button(1).build();
+        let weak = Rc::downgrade(&tasks);
+        gesture.connect_released(move |_, presses, x, y| {
+            let Some(tasks) = weak.upgrade() else {
+                return;
+            };
+            if presses != 1 || tasks.output.buffer().has_selection() {
+                return;
+            }
+            if let Some(index) = tasks.problem_at(x, y) {
+                tasks.open_problem(index);
+            }
+        });
+        output.add_controller(gesture);
+        let motion = EventControllerMotion::new();
+        let weak = Rc::downgrade(&tasks);
+        motion.connect_motion(move |_, x, y| {
+            if let Some(tasks) = weak.upgrade() {
+                let cursor = tasks.problem_at(x, y).map_or("text", |_| "pointer");
+                tasks.output.set_cursor_from_name(Some(cursor));
+            }
+        });
+        output.add_controller(motion);
+        text_view.set_has_tooltip(true);
+        let weak = Rc::downgrade(&tasks);
+        text_view.connect_query_tooltip(move |text_view, x, y, keyboard, tooltip| {
+            let Some(tasks) = weak.upgrade() else {
+                return false;
+            };
+            if keyboard {
+                return false;
+            }
+            let (x, y) = text_view.window_to_buffer_coords(TextWindowType::Widget, x, y);
+            let Some(iter) = text_view.iter_at_location(x, y) else {
+                return false;
+            };
+            match tasks.tooltip_text(&iter) {
+                Some(text) => {
+                    tooltip.set_text(Some(&text));
+                    true
+                }
+                None => false,
+            }
+        });
+        let weak = Rc::downgrade(&tasks);
+        document.connect_opened(move |_| {
+            if let Some(tasks) = weak.upgrade() {
+                tasks.apply_problem_tags();
+                tasks.update_choices();
+            }
+        });
+        let weak = Rc::downgrade(&tasks);
+        document.connect_saved(move |_| {
+            if let Some(tasks) = weak.upgrade()
+                && tasks.document.path().file_name() == Some(OsStr::new(TASKS_FILE))
+            {
+                tasks.update_choices();
+            }
+        });
+        tasks.register_commands();
+        tasks.update_choices();
+        tasks
+    }
+
+    pub fn widget(&self) -> &Box {
+        &self.widget
+    }
+
+    fn register_commands(self: &Rc<Self>) {
+        let tasks = self.clone();
+        self.commands
+            .register("tasks.rerun", "Run Last Task", move || {
+                let last = tasks.last.borrow().clone();
+                match last {
+                    Some(name) => tasks.run(&name),
+                    None => {
+                        tasks.commands.run("tasks.run");
+                    }
+                }
+            });
+        let tasks = self.clone();
+        self.commands
+            .register("tasks.cancel", "Stop Task", move || tasks.cancel());
+        let tasks = self.clone();
+        self.commands
+            .register("tasks.output", "Toggle Task Output", move || {
+                tasks.widget.set_visible(!tasks.widget.is_visible())
+            });
+        let tasks = self.clone();
+        self.commands
+            .register("tasks.problems", "Show Task Problems", move || {
+                tasks.show_problems()
+            });
+    }
+
+    fn update_choices(self: &Rc<Self>) {
+        let root = workspace::find_root(&self.document.path());
+        let choices = load_tasks(&root)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|task| {
+                let command = match task.command.as_slice() {
+                    [shell, flag, command] if shell == "sh" && flag == "-c" => command.clone(),
+                    command => command.join(" "),
+                };
+                (task.name, command)
+            })
+            .collect();
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "tasks.run",
+            "Run Task",
+            Argument {
+                prompt: "Task".to_owned(),
+                choices,
+            },
+            move |name| {
+                if let Some(tasks) = weak.upgrade() {
+                    tasks.run(name.trim());
+                }
+            },
+        );
+    }
+
+    fn run(self: &Rc<Self>, name: &str) {
+        let root = workspace::find_root(&self.document.path());
+        let tasks = match load_tasks(&root) {
+            Ok(tasks) => tasks,
+            Err(error) => {
+                self.notification.show("Could not load tasks", &error);
+                return;
+            }
+        };
+        let Some(task) = tasks.into_iter().find(|task| task.name == name) else {
+            self.status.set_text(&format!(
+                "No task named {} in {}",
+                name,
+                root.join(TASKS_FILE).display()
+            ));
+            return;
+        };
+        self.cancel();
+        if self.document.buffer.is_modified()
+            && let Err(error) = self.document.save()
+        {
+            eprintln!(
+                "Could not save {}: {}",
+                self.document.path().display(),
+                error
+            );
+        }
+        let launcher = gio::SubprocessLauncher::new(
+            gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_MERGE,
+        );
+        launcher.set_cwd(&task.cwd);
+        for (key, value) in &task.env {
+            launcher.setenv(key, value, true);
+        }
+        launcher.set_child_setup(|| unsafe {
+            libc::setsid();
+        });
+        let arguments: Vec<&OsStr> = task.command.iter().map(OsStr::new).collect();
+        let process = match launcher.spawn(&arguments) {
+            Ok(process) => process,
+            Err(error) => {
+                self.notification
+                    .show(&format!("Could not run {}", task.name), error.message());
+                return;
+            }
+        };
+        let Some(stdout) = process.stdout_pipe() else {
+            process.force_exit();
+            return;
+        };
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+        self.last.replace(Some(task.name.clone()));
+        self.problems.borrow_mut().clear();
+        self.apply_problem_tags();
+        let buffer = self.output.buffer();
+        buffer.set_text("");
+        let mut end = buffer.end_iter();
+        buffer.insert_with_tags_by_name(
+            &mut end,
+            &format!("$ {}\n", task.command.join(" ")),
+            &["task_command"],
+        );
+        self.title.set_text(&format!("{} — running", task.name));
+        self.status.set_text(&format!("Running {}…", task.name));
+        self.cancel.set_sensitive(true);
+        self.widget.set_visible(true);
+        let cancellable = gio::Cancellable::new();
+        self.running.replace(Some(Run {
+            name: task.name,
+            process: process.clone(),
+            group: process
+                .identifier()
+                .and_then(|pid| pid.parse::<libc::pid_t>().ok()),
+            generation,
+            cancelled: Cell::new(false),
+            cancellable: cancellable.clone(),
+        }));
+        let weak = Rc::downgrade(self);
+        let matcher = task.matcher;
+        let cwd = task.cwd;
+        glib::spawn_future_local(async move {
+            let input = gio::DataInputStream::new(&stdout);
+            while let Ok(Ok(Some(line))) = gio::CancellableFuture::new(
+                input.read_line_future(glib::Priority::DEFAULT),
+                cancellable.clone(),
+            )
+            .await
+            {
+                let Some(tasks) = weak.upgrade() else {
+                    return;
+                };
+                if tasks.generation.get() != generation {
+                    return;
+                }
+                tasks.append_line(&String::from_utf8_lossy(&line), &matcher, &cwd);
+            }
+            let _ = process.wait_future().await;
+            if let Some(tasks) = weak.upgrade() {
+                tasks.finished(generation);
+            }
+        });
+    }
+
+    pub fn cancel(&self) {
+        if let Some(run) = self.running.borrow().as_ref() {
+            run.cancelled.set(true);
+            run.cancellable.cancel();
+            if let Some(group) = run.group {
+                unsafe { libc::kill(-group, libc::SIGKILL) };
+            }
+            run.process.force_exit();
+        }
+    }
+
+    fn finished(&self, generation: u64) {
+        if self
+            .running
+            .borrow()
+            .as_ref()
+            .is_none_or(|run| run.generation != generation)
+        {
+            return;
+        }
+        let Some(run) = self.running.take() else {
+            return;
+        };
+        self.cancel.set_sensitive(false);
+        let outcome = if run.cancelled.get() {
+            "stopped".to_owned()
+        } else if !run.process.has_exited() {
+            "was terminated".to_owned()
+        } else if run.process.is_successful() {
+            "succeeded".to_owned()
+        } else {
+            format!("failed with exit status {}", run.process.exit_status())
+        };
+        let problems = self.problems.borrow();
+        let count = |severity| {
+            problems
+                .iter()
+                .filter(|problem| problem.severity == severity)
+                .count()
+        };
+        let summary = format!(
+            "{} {} — {} errors, {} warnings",
+            run.name,
+            outcome,
+            count(Severity::Error),
+            count(Severity::Warning)
+        );
+        self.title.set_text(&summary);
+        self.status.set_text(&summary);
+    }
+
+    fn append_line(&self, line: &str, matcher: &Regex, cwd: &Path) {
+        let line = self
+            .escape_sequences
+            .replace_all(line.trim_end_matches(['\r', '\n']), "");
+        let buffer = self.output.buffer();
+        let output_line = buffer.line_count() - 1;
+        let mut end = buffer.end_iter();
+        buffer.insert(&mut end, &format!("{}\n", line));
+        if let Some((location, severity, message)) = parse_problem(matcher, &line, cwd)
+            && let Some(start) = buffer.iter_at_line(output_line)
+        {
+            let mut end = start;
+            end.forward_to_line_end();
+            buffer.apply_tag_by_name(severity.tag(), &start, &end);
+            let index = self.problems.borrow().len();
+            self.problems.borrow_mut().push(Problem {
+                location,
+                severity,
+                message,
+                output_line,
+            });
+            self.apply_problem_tag(index);
+        }
+        self.output.scroll_mark_onscreen(&self.end);
+    }
+
+    fn problem_at(&self, x: f64, y: f64) -> Option<usize> {
+        let (x, y) =
+            self.output
+                .window_to_buffer_coords(TextWindowType::Widget, x as i32, y as i32);
+        let line = self.output.iter_at_location(x, y)?.line();
+        self.problems
+            .borrow()
+            .iter()
+            .position(|problem| problem.output_line == line)
+    }
+
+    fn open_problem(&self, index: usize) {
+        let inline = self
+            .inline
+            .borrow()
+            .iter()
+            .find(|(_, _, problem)| *problem == index)
+            .map(|(start, _, _)| start.clone());
+        if let Some(mark) = inline {
+            let buffer = self.text_view.buffer();
+            buffer.place_cursor(&buffer.iter_at_mark(&mark));
+            self.text_view
+                .scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.3);
+            self.text_view.grab_focus();
+            return;
+        }
+        let Some(location) = self
+            .problems
+            .borrow()
+            .get(index)
+            .map(|problem| problem.location.clone())
+        else {
+            return;
+        };
+        results::open_location(&self.document, &self.text_view, &self.status, &location);
+    }
+
+    fn apply_problem_tags(&self) {
+        let buffer = self.text_view.buffer();
+        let (start, end) = buffer.bounds();
+        for tag in PROBLEM_TAGS {
+            buffer.remove_tag_by_name(tag, &start, &end);
+        }
+        for (start, end, _) in self.inline.take() {
+            buffer.delete_mark(&start);
+            buffer.delete_mark(&end);
+        }
+        for index in 0..self.problems.borrow().len() {
+            self.apply_problem_tag(index);
+        }
+    }
+
+    fn apply_problem_tag(&self, index: usize) {
+        let problems = self.problems.borrow();
+        let Some(problem) = problems.get(index) else {
+            return;
+        };
+        if !same_file(&problem.location.path, &self.document.path()) {
+            return;
+        }
+        let buffer = self.text_view.buffer();
+        let Some(mut start) = buffer
+            .iter_at_line_offset(problem.location.line, problem.location.column)
+            .or_else(|| buffer.iter_at_line(problem.location.line))
+        else {
+            return;
+        };
+        let mut end = start;
+        if !end.ends_line() {
+            end.forward_word_end();
+            if end.line() != start.line() || end == start {
+                end = start;
+                end.forward_to_line_end();
+            }
+        } else if !start.starts_line() {
+            start.backward_char();
+        }
+        buffer.apply_tag_by_name(problem.severity.tag(), &start, &end);
+        self.inline.borrow_mut().push((
+            buffer.create_mark(None, &start, true),
+            buffer.create_mark(None, &end, false),
+            index,
+        ));
+    }
+
+    fn tooltip_text(&self, iter: &TextIter) -> Option<String> {
+        let buffer = self.text_view.buffer();
+        let problems = self.problems.borrow();
+        let parts: Vec<String> = self
+            .inline
+            .borrow()
+            .iter()
+            .filter(|(start, end, _)| {
+                buffer.iter_at_mark(start) <= *iter && *iter < buffer.iter_at_mark(end)
+            })
+            .filter_map(|(_, _, index)| problems.get(*index))
+            .map(|problem| format!("{}: {}", problem.severity.name(), problem.message))
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    }
+
+    fn show_problems(&self) {
+        let root = workspace::find_root(&self.document.path());
+        let items: Vec<ResultItem> = self
+            .problems
+            .borrow()
+            .iter()
+            .map(|problem| ResultItem {
+                location: problem.location.clone(),
+                detail: format!(
+                    "{} {}:{}",
+                    problem.severity.name(),
+                    problem
+                        .location
+                        .path
+                        .strip_prefix(&root)
+                        .unwrap_or(&problem.location.path)
+                        .display(),
+                    problem.location.line + 1
+                ),
+                text: problem.message.clone(),
+            })
+            .collect();
+        if items.is_empty() {
+            self.status.set_text("No task problems");
+            return;
+        }
+        self.results
+            .show(&format!("Task problems ({})", items.len()), items);
+    }
+}
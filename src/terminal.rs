@@ -0,0 +1,887 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    env, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    ptr,
+    rc::Rc,
+};
+
+use gtk4::{
+    Box, Button, EventControllerKey, Label, Notebook, Orientation, Paned, PolicyType,
+    PropagationPhase, ScrolledWindow, TextBuffer, TextTag, TextView, Widget, gdk,
+    glib::{self, ControlFlow, Propagation},
+    pango,
+    prelude::*,
+};
+
+use crate::{
+    commands::CommandRegistry,
+    document::Document,
+    settings::{self, TerminalDock},
+    terminal_screen::{Cell as ScreenCell, Color, Screen, Style},
+};
+
+const FOREGROUND: (u8, u8, u8) = (0xff, 0xff, 0xff);
+const BACKGROUND: (u8, u8, u8) = (0x22, 0x25, 0x28);
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x1b, 0x1d, 0x20),
+    (0xff, 0x72, 0x72),
+    (0xa5, 0xff, 0x8e),
+    (0xfb, 0xd3, 0x7d),
+    (0x69, 0xa5, 0xff),
+    (0xff, 0x88, 0xcd),
+    (0xc5, 0xff, 0xff),
+    (0xd0, 0xd0, 0xd0),
+    (0x53, 0x53, 0x53),
+    (0xff, 0xb2, 0x93),
+    (0xc8, 0xff, 0xba),
+    (0xff, 0xe4, 0xa8),
+    (0x9a, 0xc3, 0xff),
+    (0xb2, 0x9b, 0xff),
+    (0xe2, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+const CURSOR_TAG: &str = "terminal_cursor";
+const READ_SIZE: usize = 65536;
+
+fn rgb(color: Color, default: (u8, u8, u8)) -> (u8, u8, u8) {
+    match color {
+        Color::Default => default,
+        Color::Indexed(index @ 0..=15) => PALETTE[index as usize],
+        Color::Indexed(index @ 16..=231) => {
+            let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        Color::Indexed(index) => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+        Color::Rgb(red, green, blue) => (red, green, blue),
+    }
+}
+
+fn hex((red, green, blue): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", red, green, blue)
+}
+
+fn key_bytes(
+    keyval: gdk::Key,
+    modifiers: gdk::ModifierType,
+    application_cursor: bool,
+) -> Option<Vec<u8>> {
+    let shift = modifiers.contains(gdk::ModifierType::SHIFT_MASK);
+    let alt = modifiers.contains(gdk::ModifierType::ALT_MASK);
+    let control = modifiers.contains(gdk::ModifierType::CONTROL_MASK);
+    let modifier = 1 + shift as u8 + alt as u8 * 2 + control as u8 * 4;
+    let cursor = |letter: char| -> Vec<u8> {
+        if modifier > 1 {
+            format!("\x1b[1;{}{}", modifier, letter).into_bytes()
+        } else if application_cursor {
+            format!("\x1bO{}", letter).into_bytes()
+        } else {
+            format!("\x1b[{}", letter).into_bytes()
+        }
+    };
+    let tilde = |code: u8| -> Vec<u8> {
+        if modifier > 1 {
+            format!("\x1b[{};{}~", code, modifier).into_bytes()
+        } else {
+            format!("\x1b[{}~", code).into_bytes()
+        }
+    };
+    let bytes = match keyval {
+        gdk::Key::Return | gdk::Key::KP_Enter => b"\r".to_vec(),
+        gdk::Key::BackSpace if control => b"\x08".to_vec(),
+        gdk::Key::BackSpace => b"\x7f".to_vec(),
+        gdk::Key::Tab => b"\t".to_vec(),
+        gdk::Key::ISO_Left_Tab => b"\x1b[Z".to_vec(),
+        gdk::Key::Escape => b"\x1b".to_vec(),
+        gdk::Key::Up | gdk::Key::KP_Up => cursor('A'),
+        gdk::Key::Down | gdk::Key::KP_Down => cursor('B'),
+        gdk::Key::Right | gdk::Key::KP_Right => cursor('C'),
+        gdk::Key::Left | gdk::Key::KP_Left => cursor('D'),
+        gdk::Key::Home | gdk::Key::KP_Home => cursor('H'),
+        gdk::Key::End | gdk::Key::KP_End => cursor('F'),
+        gdk::Key::Insert => tilde(2),
+        gdk::Key::Delete | gdk::Key::KP_Delete => tilde(3),
+        gdk::Key::Page_Up | gdk::Key::KP_Page_Up => tilde(5),
+        gdk::Key::Page_Down | gdk::Key::KP_Page_Down => tilde(6),
+        gdk::Key::F1 => b"\x1bOP".to_vec(),
+        gdk::Key::F2 => b"\x1bOQ".to_vec(),
+        gdk::Key::F3 => b"\x1bOR".to_vec(),
+        gdk::Key::F4 => b"\x1bOS".to_vec(),
+        gdk::Key::F5 => tilde(15),
+        gdk::Key::F6 => tilde(17),
+        gdk::Key::F7 => tilde(18),
+        gdk::Key::F8 => tilde(19),
+        gdk::Key::F9 => tilde(20),
+        gdk::Key::F10 => tilde(21),
+        gdk::Key::F11 => tilde(23),
+        gdk::Key::F12 => tilde(24),
+        _ => {
+            let character = keyval.to_unicode()?;
+            let mut bytes = Vec::new();
+            if alt {
+                bytes.push(0x1b);
+            }
+            match character {
+                '@'..='_' | 'a'..='z' if control => {
+                    bytes.push(character.to_ascii_uppercase() as u8 & 0x1f)
+                }
+                ' ' | '2' if control => bytes.push(0),
+                '/' if control => bytes.push(0x1f),
+                character => {
+                    let mut encoded = [0; 4];
+                    bytes.extend_from_slice(character.encode_utf8(&mut encoded).as_bytes());
+                }
+            }
+            bytes
+        }
+    };
+    Some(bytes)
+}
+
+fn spawn_shell(directory: &Path, columns: usize, rows: usize) -> io::Result<(OwnedFd, i32)> {
+    let size = libc::winsize {
+        ws_row: rows as u16,
+        ws_col: columns as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let mut master = -1;
+    let mut slave = -1;
+    if unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    unsafe {
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        let flags = libc::fcntl(master.as_raw_fd(), libc::F_GETFL);
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+    let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_owned());
+    let mut command = Command::new(shell);
+    command
+        .current_dir(directory)
+        .env("TERM", "xterm-256color")
+        .env("COLORTERM", "truecolor")
+        .env("TERM_PROGRAM", "moon")
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    Ok((master, child.id() as i32))
+}
+
+struct TerminalTab {
+    view: TextView,
+    scrolled_window: ScrolledWindow,
+    page: Box,
+    label: Label,
+    shell: String,
+    master: OwnedFd,
+    pid: i32,
+    parser: RefCell<vte::Parser>,
+    screen: RefCell<Screen>,
+    tags: RefCell<HashMap<Style, TextTag>>,
+    rendered_total: Cell<u64>,
+    scrollback_lines: Cell<usize>,
+    render_pending: Rc<Cell<bool>>,
+    resize_pending: Rc<Cell<bool>>,
+    reader: RefCell<Option<glib::SourceId>>,
+    outgoing: RefCell<VecDeque<u8>>,
+    writer: RefCell<Option<glib::SourceId>>,
+    exited: Cell<bool>,
+}
+
+impl TerminalTab {
+    fn write(self: &Rc<Self>, bytes: &[u8]) {
+        if self.exited.get() || bytes.is_empty() {
+            return;
+        }
+        self.outgoing.borrow_mut().extend(bytes);
+        if self.writer.borrow().is_some() || self.flush() {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        let writer = glib::unix_fd_add_local(
+            self.master.as_raw_fd(),
+            glib::IOCondition::OUT | glib::IOCondition::HUP | glib::IOCondition::ERR,
+            move |_, _| {
+                let Some(tab) = weak.upgrade() else {
+                    return ControlFlow::Break;
+                };
+                if tab.flush() {
+                    tab.writer.take();
+                    ControlFlow::Break
+                } else {
+                    ControlFlow::Continue
+                }
+            },
+        );
+        self.writer.replace(Some(writer));
+    }
+
+    fn flush(&self) -> bool {
+        let mut outgoing = self.outgoing.borrow_mut();
+        while !outgoing.is_empty() && !self.exited.get() {
+            let (bytes, _) = outgoing.as_slices();
+            let written = unsafe {
+                libc::write(
+                    self.master.as_raw_fd(),
+                    bytes.as_ptr() as *const libc::c_void,
+                    bytes.len(),
+                )
+            };
+            if written < 0 {
+                match io::Error::last_os_error().kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return false,
+                    _ => break,
+                }
+            }
+            outgoing.drain(..written as usize);
+        }
+        outgoing.clear();
+        true
+    }
+
+    fn read(self: &Rc<Self>) -> ControlFlow {
+        let mut data = vec![0u8; READ_SIZE];
+        let read = unsafe {
+            libc::read(
+                self.master.as_raw_fd(),
+                data.as_mut_ptr() as *mut libc::c_void,
+                data.len(),
+            )
+        };
+        if read < 0
+            && matches!(
+                io::Error::last_os_error().kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+            )
+        {
+            return ControlFlow::Continue;
+        }
+        if read <= 0 {
+            return ControlFlow::Break;
+        }
+        let responses = {
+            let mut screen = self.screen.borrow_mut();
+            self.parser
+                .borrow_mut()
+                .advance(&mut *screen, &data[..read as usize]);
+            screen.take_responses()
+        };
+        self.write(&responses);
+        self.schedule_render();
+        ControlFlow::Continue
+    }
+
+    fn schedule_render(self: &Rc<Self>) {
+        if self.render_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(tab) = weak.upgrade() {
+                tab.render_pending.set(false);
+                tab.render();
+            }
+        });
+    }
+
+    fn schedule_resize(self: &Rc<Self>) {
+        if self.resize_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local_once(move || {
+            if let Some(tab) = weak.upgrade() {
+                tab.resize_pending.set(false);
+                tab.resize();
+            }
+        });
+    }
+
+    fn cell_size(&self) -> (i32, i32) {
+        let layout = self.view.create_pango_layout(Some("M"));
+        let (width, height) = layout.pixel_size();
+        (width.max(1), height.max(1))
+    }
+
+    fn resize(self: &Rc<Self>) {
+        let (cell_width, cell_height) = self.cell_size();
+        let width = self.scrolled_window.hadjustment().page_size() as i32
+            - self.view.left_margin()
+            - self.view.right_margin();
+        let height = self.scrolled_window.vadjustment().page_size() as i32
+            - self.view.top_margin()
+            - self.view.bottom_margin();
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        let columns = (width / cell_width - 1).max(2) as usize;
+        let rows = (height / cell_height).max(1) as usize;
+        if self.screen.borrow().size() == (columns, rows) {
+            return;
+        }
+        self.screen.borrow_mut().resize(columns, rows);
+        let size = libc::winsize {
+            ws_row: rows as u16,
+            ws_col: columns as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+        self.schedule_render();
+    }
+
+    fn tag(&self, buffer: &TextBuffer, style: &Style) -> Option<TextTag> {
+        if *style == Style::default() {
+            return None;
+        }
+        if let Some(tag) = self.tags.borrow().get(style) {
+            return Some(tag.clone());
+        }
+        let mut foreground = rgb(style.foreground, FOREGROUND);
+        let mut background = rgb(style.background, BACKGROUND);
+        if style.bold
+            && let Color::Indexed(index @ 0..=7) = style.foreground
+        {
+            foreground = PALETTE[index as usize + 8];
+        }
+        if style.inverse {
+            std::mem::swap(&mut foreground, &mut background);
+        }
+        if style.dim {
+            let dim = |value: u8| (value as u16 * 2 / 3) as u8;
+            foreground = (dim(foreground.0), dim(foreground.1), dim(foreground.2));
+        }
+        let tag = TextTag::new(None);
+        tag.set_foreground(Some(&hex(foreground)));
+        if style.background != Color::Default || style.inverse {
+            tag.set_background(Some(&hex(background)));
+        }
+        if style.bold {
+            tag.set_weight(700);
+        }
+        if style.italic {
+            tag.set_style(pango::Style::Italic);
+        }
+        if style.underline {
+            tag.set_underline(pango::Underline::Single);
+        }
+        if style.strikethrough {
+            tag.set_strikethrough(true);
+        }
+        let table = buffer.tag_table();
+        table.add(&tag);
+        if let Some(cursor) = table.lookup(CURSOR_TAG) {
+            cursor.set_priority(table.size() - 1);
+        }
+        self.tags.borrow_mut().insert(*style, tag.clone());
+        Some(tag)
+    }
+
+    fn insert_line(
+        &self,
+        buffer: &TextBuffer,
+        line: &[ScreenCell],
+        cursor: Option<usize>,
+        newline: bool,
+    ) {
+        let length = line
+            .iter()
+            .rposition(|cell| cell.character != ' ' || cell.style != Style::default())
+            .map_or(0, |index| index + 1)
+            .max(cursor.map_or(0, |column| column + 1))
+            .min(line.len());
+        let mut iter = buffer.end_iter();
+        let line_start = iter.offset();
+        let mut cursor_offset = None;
+        let mut run = String::new();
+        let mut run_style = Style::default();
+        let mut offset = 0;
+        for (column, cell) in line.iter().take(length).enumerate() {
+            if cursor == Some(column) {
+                cursor_offset = Some(offset);
+            }
+            if cell.is_continuation() {
+                continue;
+            }
+            if cell.style != run_style && !run.is_empty() {
+                self.insert_run(buffer, &mut iter, &run, &run_style);
+                run.clear();
+            }
+            run_style = cell.style;
+            run.push(cell.character);
+            offset += 1;
+        }
+        self.insert_run(buffer, &mut iter, &run, &run_style);
+        if let Some(cursor_offset) = cursor_offset {
+            let start = buffer.iter_at_offset(line_start + cursor_offset);
+            let mut end = start;
+            if end.ends_line() {
+                buffer.insert(&mut iter, " ");
+                end = buffer.iter_at_offset(line_start + cursor_offset);
+            }
+            end.forward_char();
+            buffer.apply_tag_by_name(CURSOR_TAG, &start, &end);
+            iter = buffer.end_iter();
+        }
+        if newline {
+            buffer.insert(&mut iter, "\n");
+        }
+    }
+
+    fn insert_run(
+        &self,
+        buffer: &TextBuffer,
+        iter: &mut gtk4::TextIter,
+        text: &str,
+        style: &Style,
+    ) {
+        if text.is_empty() {
+            return;
+        }
+        match self.tag(buffer, style) {
+            Some(tag) => buffer.insert_with_tags(iter, text, &[&tag]),
+            None => buffer.insert(iter, text),
+        }
+    }
+
+    fn render(&self) {
+        let buffer = self.view.buffer();
+        let adjustment = self.scrolled_window.vadjustment();
+        let at_bottom = adjustment.value() + adjustment.page_size() >= adjustment.upper() - 1.0;
+        let mut screen = self.screen.borrow_mut();
+        if screen.take_scrollback_cleared() {
+            buffer.set_text("");
+            self.scrollback_lines.set(0);
+            self.rendered_total.set(screen.scrollback_total());
+        }
+        let mut start = buffer
+            .iter_at_line(self.scrollback_lines.get() as i32)
+            .unwrap_or(buffer.end_iter());
+        buffer.delete(&mut start, &mut buffer.end_iter());
+        let scrollback = screen.scrollback();
+        let new_lines = ((screen.scrollback_total() - self.rendered_total.get()) as usize)
+            .min(scrollback.len());
+        for line in scrollback.range(scrollback.len() - new_lines..) {
+            self.insert_line(&buffer, line, None, true);
+        }
+        self.rendered_total.set(screen.scrollback_total());
+        let mut lines = self.scrollback_lines.get() + new_lines;
+        if lines > scrollback.len() {
+            let mut start = buffer.start_iter();
+            let mut end = buffer
+                .iter_at_line((lines - scrollback.len()) as i32)
+                .unwrap_or(buffer.end_iter());
+            buffer.delete(&mut start, &mut end);
+            lines = scrollback.len();
+        }
+        self.scrollback_lines.set(lines);
+        let cursor = screen.cursor();
+        let rows = screen.grid().len();
+        for (row, line) in screen.grid().iter().enumerate() {
+            let cursor = cursor
+                .filter(|(cursor_row, _)| *cursor_row == row)
+                .map(|(_, column)| column);
+            self.insert_line(&buffer, line, cursor, row + 1 < rows);
+        }
+        let title = screen.title().unwrap_or(&self.shell).to_owned();
+        drop(screen);
+        self.label.set_text(&title);
+        if at_bottom {
+            self.scroll_to_bottom();
+        }
+    }
+
+    fn scroll_to_bottom(&self) {
+        let buffer = self.view.buffer();
+        let mark = buffer.create_mark(None, &buffer.end_iter(), false);
+        self.view.scroll_to_mark(&mark, 0.0, false, 0.0, 1.0);
+        buffer.delete_mark(&mark);
+    }
+
+    fn copy(&self) {
+        self.view.buffer().copy_clipboard(&self.view.clipboard());
+    }
+
+    fn paste(self: &Rc<Self>) {
+        let weak = Rc::downgrade(self);
+        self.view
+            .clipboard()
+            .read_text_async(None::<&gtk4::gio::Cancellable>, move |result| {
+                let (Some(tab), Ok(Some(text))) = (weak.upgrade(), result) else {
+                    return;
+                };
+                let text = text.replace("\r\n", "\r").replace('\n', "\r");
+                if tab.screen.borrow().bracketed_paste() {
+                    tab.write(b"\x1b[200~");
+                    tab.write(text.as_bytes());
+                    tab.write(b"\x1b[201~");
+                } else {
+                    tab.write(text.as_bytes());
+                }
+                tab.scroll_to_bottom();
+            });
+    }
+
+    fn hang_up(&self) {
+        if !self.exited.get() {
+            unsafe { libc::kill(self.pid, libc::SIGHUP) };
+        }
+    }
+}
+
+pub struct Terminal {
+    document: Rc<Document>,
+    text_view: TextView,
+    status: Label,
+    paned: Paned,
+    panel: Box,
+    notebook: Notebook,
+    tabs: RefCell<Vec<Rc<TerminalTab>>>,
+    scrollback: usize,
+}
+
+impl Terminal {
+    pub fn new(
+        editor: &impl IsA<Widget>,
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        commands: &CommandRegistry,
+        editor_settings: &settings::EditorSettings,
+    ) -> Rc<Terminal> {
+        let notebook = Notebook::builder()
+            .scrollable(true)
+            .hexpand(true)
+            .vexpand(true)
+            .build();
+        let new_tab = Button::builder().label("+").has_frame(false).build();
+        notebook.set_action_widget(&new_tab, gtk4::PackType::End);
+        let panel = Box::builder()
+            .orientation(Orientation::Vertical)
+            .name("terminal_panel")
+            .visible(false)
+            .build();
+        panel.append(&notebook);
+        let paned = Paned::builder()
+            .orientation(match editor_settings.terminal_dock {
+                TerminalDock::Bottom => Orientation::Vertical,
+                TerminalDock::Right => Orientation::Horizontal,
+            })
+            .start_child(editor)
+            .end_child(&panel)
+            .resize_start_child(true)
+            .resize_end_child(false)
+            .shrink_end_child(false)
+            .vexpand(true)
+            .build();
+        let terminal = Rc::new(Terminal {
+            document: document.clone(),
+            text_view: text_view.clone(),
+            status: status.clone(),
+            paned,
+            panel,
+            notebook,
+            tabs: RefCell::new(Vec::new()),
+            scrollback: editor_settings.terminal_scrollback,
+        });
+        let weak = Rc::downgrade(&terminal);
+        new_tab.connect_clicked(move |_| {
+            if let Some(terminal) = weak.upgrade() {
+                terminal.open_tab();
+            }
+        });
+        let toggling = terminal.clone();
+        commands.register("terminal.toggle", "Toggle Terminal", move || {
+            toggling.toggle()
+        });
+        let opening = terminal.clone();
+        commands.register("terminal.new", "New Terminal", move || opening.open_tab());
+        let closing = terminal.clone();
+        commands.register("terminal.close", "Close Terminal", move || {
+            if let Some(tab) = closing.current() {
+                tab.hang_up();
+                closing.remove_tab(&tab);
+            }
+        });
+        let switching = terminal.clone();
+        commands.register("terminal.next", "Next Terminal", move || {
+            switching.notebook.next_page();
+            switching.focus();
+        });
+        let switching = terminal.clone();
+        commands.register("terminal.previous", "Previous Terminal", move || {
+            switching.notebook.prev_page();
+            switching.focus();
+        });
+        let docking = terminal.clone();
+        commands.register("terminal.move", "Move Terminal Panel", move || {
+            docking.paned.set_position(-1);
+            docking
+                .paned
+                .set_orientation(match docking.paned.orientation() {
+                    Orientation::Vertical => Orientation::Horizontal,
+                    _ => Orientation::Vertical,
+                });
+        });
+        terminal
+    }
+
+    pub fn widget(&self) -> &Paned {
+        &self.paned
+    }
+
+    pub fn has_focus(&self) -> bool {
+        self.tabs.borrow().iter().any(|tab| tab.view.has_focus())
+    }
+
+    pub fn stop(&self) {
+        for tab in self.tabs.borrow().iter() {
+            tab.hang_up();
+        }
+    }
+
+    fn current(&self) -> Option<Rc<TerminalTab>> {
+        let page = self.notebook.current_page()?;
+        let widget = self.notebook.nth_page(Some(page))?;
+        self.tabs
+            .borrow()
+            .iter()
+            .find(|tab| tab.page.upcast_ref::<Widget>() == &widget)
+            .cloned()
+    }
+
+    fn focus(&self) {
+        if let Some(tab) = self.current() {
+            tab.view.grab_focus();
+        }
+    }
+
+    fn toggle(self: &Rc<Self>) {
+        if self.tabs.borrow().is_empty() {
+            self.open_tab();
+        } else if !self.panel.is_visible() {
+            self.panel.set_visible(true);
+            self.focus();
+        } else if self.has_focus() {
+            self.panel.set_visible(false);
+            self.text_view.grab_focus();
+        } else {
+            self.focus();
+        }
+    }
+
+    fn open_tab(self: &Rc<Self>) {
+        let directory: PathBuf = self
+            .document
+            .path()
+            .parent()
+            .filter(|directory| directory.is_dir())
+            .map(Path::to_path_buf)
+            .or_else(|| env::var("HOME").ok().map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let (master, pid) = match spawn_shell(&directory, 80, 24) {
+            Ok(spawned) => spawned,
+            Err(error) => {
+                eprintln!("Could not start a terminal: {}", error);
+                self.status
+                    .set_text(&format!("Could not start a terminal: {}", error));
+                return;
+            }
+        };
+        let view = TextView::builder()
+            .editable(false)
+            .monospace(true)
+            .wrap_mode(gtk4::WrapMode::Char)
+            .left_margin(6)
+            .right_margin(6)
+            .top_margin(4)
+            .bottom_margin(4)
+            .name("terminal_view")
+            .build();
+        view.buffer().create_tag(
+            Some(CURSOR_TAG),
+            &[
+                ("background", &hex(FOREGROUND)),
+                ("foreground", &hex(BACKGROUND)),
+            ],
+        );
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&view)
+            .hscrollbar_policy(PolicyType::Never)
+            .vexpand(true)
+            .build();
+        let page = Box::builder().orientation(Orientation::Vertical).build();
+        page.append(&scrolled_window);
+        let shell = env::var("SHELL")
+            .ok()
+            .and_then(|shell| {
+                Path::new(&shell)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "sh".to_owned());
+        let label = Label::new(Some(&shell));
+        let close = Button::builder().label("✕").has_frame(false).build();
+        let tab_label = Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(4)
+            .build();
+        tab_label.append(&label);
+        tab_label.append(&close);
+        let tab = Rc::new(TerminalTab {
+            view: view.clone(),
+            scrolled_window: scrolled_window.clone(),
+            page: page.clone(),
+            label,
+            shell,
+            master,
+            pid,
+            parser: RefCell::new(vte::Parser::new()),
+            screen: RefCell::new(Screen::new(80, 24, self.scrollback)),
+            tags: RefCell::new(HashMap::new()),
+            rendered_total: Cell::new(0),
+            scrollback_lines: Cell::new(0),
+            render_pending: Rc::new(Cell::new(false)),
+            resize_pending: Rc::new(Cell::new(false)),
+            reader: RefCell::new(None),
+            outgoing: RefCell::new(VecDeque::new()),
+            writer: RefCell::new(None),
+            exited: Cell::new(false),
+        });
+        let weak = Rc::downgrade(&tab);
+        let reader = glib::unix_fd_add_local(
+            tab.master.as_raw_fd(),
+            glib::IOCondition::IN | glib::IOCondition::HUP | glib::IOCondition::ERR,
+            move |_, _| {
+                let Some(tab) = weak.upgrade() else {
+                    return ControlFlow::Break;
+                };
+                let flow = tab.read();
+                if flow.is_break() {
+                    tab.reader.take();
+                }
+                flow
+            },
+        );
+        tab.reader.replace(Some(reader));
+        let weak = Rc::downgrade(self);
+        let weak_tab = Rc::downgrade(&tab);
+        glib::child_watch_add_local(glib::Pid(pid), move |_, _| {
+            if let (Some(terminal), Some(tab)) = (weak.upgrade(), weak_tab.upgrade()) {
+                tab.exited.set(true);
+                terminal.remove_tab(&tab);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let weak_tab = Rc::downgrade(&tab);
+        close.connect_clicked(move |_| {
+            if let (Some(terminal), Some(tab)) = (weak.upgrade(), weak_tab.upgrade()) {
+                tab.hang_up();
+                terminal.remove_tab(&tab);
+            }
+        });
+        for adjustment in [scrolled_window.hadjustment(), scrolled_window.vadjustment()] {
+            let weak = Rc::downgrade(&tab);
+            adjustment.connect_page_size_notify(move |_| {
+                if let Some(tab) = weak.upgrade() {
+                    tab.schedule_resize();
+                }
+            });
+        }
+        let controller = EventControllerKey::new();
+        controller.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&tab);
+        controller.connect_key_pressed(move |_, keyval, _, modifiers| {
+            let Some(tab) = weak.upgrade() else {
+                return Propagation::Proceed;
+            };
+            let control_shift = gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK;
+            if modifiers & (control_shift | gdk::ModifierType::ALT_MASK) == control_shift {
+                match keyval.to_lower() {
+                    gdk::Key::c => {
+                        tab.copy();
+                        return Propagation::Stop;
+                    }
+                    gdk::Key::v => {
+                        tab.paste();
+                        return Propagation::Stop;
+                    }
+                    _ => {}
+                }
+            }
+            if modifiers.contains(gdk::ModifierType::SHIFT_MASK)
+                && matches!(keyval, gdk::Key::Page_Up | gdk::Key::Page_Down)
+            {
+                let adjustment = tab.scrolled_window.vadjustment();
+                let step = adjustment.page_size() * 0.9;
+                adjustment.set_value(if keyval == gdk::Key::Page_Up {
+                    adjustment.value() - step
+                } else {
+                    adjustment.value() + step
+                });
+                return Propagation::Stop;
+            }
+            let application_cursor = tab.screen.borrow().application_cursor();
+            match key_bytes(keyval, modifiers, application_cursor) {
+                Some(bytes) => {
+                    tab.write(&bytes);
+                    tab.scroll_to_bottom();
+                    Propagation::Stop
+                }
+                None => Propagation::Proceed,
+            }
+        });
+        view.add_controller(controller);
+        let index = self.notebook.append_page(&page, Some(&tab_label));
+        self.notebook.set_tab_reorderable(&page, true);
+        self.tabs.borrow_mut().push(tab.clone());
+        self.panel.set_visible(true);
+        self.notebook.set_current_page(Some(index));
+        tab.render();
+        view.grab_focus();
+    }
+
+    fn remove_tab(&self, tab: &Rc<TerminalTab>) {
+        if let Some(reader) = tab.reader.take() {
+            reader.remove();
+        }
+        if let Some(writer) = tab.writer.take() {
+            writer.remove();
+        }
+        self.tabs
+            .borrow_mut()
+            .retain(|other| !Rc::ptr_eq(other, tab));
+        if let Some(page) = self.notebook.page_num(&tab.page) {
+            self.notebook.remove_page(Some(page));
+        }
+        if self.tabs.borrow().is_empty() {
+            self.panel.set_visible(false);
+            self.text_view.grab_focus();
+        } else {
+            self.focus();
+        }
+    }
+}
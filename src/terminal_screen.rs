@@ -0,0 +1,585 @@
+use std::collections::VecDeque;
+
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, Perform};
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Color {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub character: char,
+    pub style: Style,
+}
+
+impl Cell {
+    fn blank(style: Style) -> Cell {
+        Cell {
+            character: ' ',
+            style: Style {
+                background: style.background,
+                ..Style::default()
+            },
+        }
+    }
+
+    pub fn is_continuation(&self) -> bool {
+        self.character == '\0'
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct SavedCursor {
+    row: usize,
+    column: usize,
+    style: Style,
+}
+
+pub struct Screen {
+    columns: usize,
+    rows: usize,
+    grid: Vec<Vec<Cell>>,
+    primary: Option<Vec<Vec<Cell>>>,
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_limit: usize,
+    scrollback_total: u64,
+    scrollback_cleared: bool,
+    row: usize,
+    column: usize,
+    wrap_pending: bool,
+    style: Style,
+    saved: SavedCursor,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    auto_wrap: bool,
+    insert_mode: bool,
+    cursor_visible: bool,
+    application_cursor: bool,
+    bracketed_paste: bool,
+    title: Option<String>,
+    responses: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(columns: usize, rows: usize, scrollback_limit: usize) -> Screen {
+        let columns = columns.max(1);
+        let rows = rows.max(1);
+        Screen {
+            columns,
+            rows,
+            grid: vec![vec![Cell::blank(Style::default()); columns]; rows],
+            primary: None,
+            scrollback: VecDeque::new(),
+            scrollback_limit,
+            scrollback_total: 0,
+            scrollback_cleared: false,
+            row: 0,
+            column: 0,
+            wrap_pending: false,
+            style: Style::default(),
+            saved: SavedCursor::default(),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            auto_wrap: true,
+            insert_mode: false,
+            cursor_visible: true,
+            application_cursor: false,
+            bracketed_paste: false,
+            title: None,
+            responses: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    pub fn grid(&self) -> &[Vec<Cell>] {
+        &self.grid
+    }
+
+    pub fn scrollback(&self) -> &VecDeque<Vec<Cell>> {
+        &self.scrollback
+    }
+
+    pub fn scrollback_total(&self) -> u64 {
+        self.scrollback_total
+    }
+
+    pub fn take_scrollback_cleared(&mut self) -> bool {
+        std::mem::take(&mut self.scrollback_cleared)
+    }
+
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor_visible.then_some((self.row, self.column))
+    }
+
+    pub fn application_cursor(&self) -> bool {
+        self.application_cursor
+    }
+
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    pub fn resize(&mut self, columns: usize, rows: usize) {
+        let columns = columns.max(1);
+        let rows = rows.max(1);
+        if (columns, rows) == (self.columns, self.rows) {
+            return;
+        }
+        for line in self
+            .grid
+            .iter_mut()
+            .chain(self.primary.iter_mut().flatten())
+        {
+            line.resize(columns, Cell::blank(Style::default()));
+        }
+        while self.grid.len() > rows {
+            if self.row >= rows {
+                let line = self.grid.remove(0);
+                if self.primary.is_none() {
+                    self.push_scrollback(line);
+                }
+                self.row -= 1;
+            } else {
+                self.grid.pop();
+            }
+        }
+        self.grid
+            .resize(rows, vec![Cell::blank(Style::default()); columns]);
+        if let Some(primary) = &mut self.primary {
+            primary.truncate(rows);
+            primary.resize(rows, vec![Cell::blank(Style::default()); columns]);
+        }
+        self.columns = columns;
+        self.rows = rows;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.row = self.row.min(rows - 1);
+        self.column = self.column.min(columns - 1);
+        self.wrap_pending = false;
+    }
+
+    fn push_scrollback(&mut self, line: Vec<Cell>) {
+        self.scrollback.push_back(line);
+        self.scrollback_total += 1;
+        while self.scrollback.len() > self.scrollback_limit {
+            self.scrollback.pop_front();
+        }
+    }
+
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![Cell::blank(self.style); self.columns]
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+            let line = self.grid.remove(self.scroll_top);
+            if self.scroll_top == 0 && self.primary.is_none() {
+                self.push_scrollback(line);
+            }
+            self.grid.insert(self.scroll_bottom, self.blank_line());
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+            self.grid.remove(self.scroll_bottom);
+            self.grid.insert(self.scroll_top, self.blank_line());
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+    }
+
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns - 1);
+        self.wrap_pending = false;
+    }
+
+    fn erase(&mut self, row: usize, columns: std::ops::Range<usize>) {
+        let blank = Cell::blank(self.style);
+        let end = columns.end.min(self.columns);
+        for cell in &mut self.grid[row][columns.start.min(end)..end] {
+            *cell = blank;
+        }
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        match mode {
+            0 => {
+                self.erase(self.row, self.column..self.columns);
+                for row in self.row + 1..self.rows {
+                    self.erase(row, 0..self.columns);
+                }
+            }
+            1 => {
+                for row in 0..self.row {
+                    self.erase(row, 0..self.columns);
+                }
+                self.erase(self.row, 0..self.column + 1);
+            }
+            2 => {
+                for row in 0..self.rows {
+                    self.erase(row, 0..self.columns);
+                }
+            }
+            3 => {
+                self.scrollback.clear();
+                self.scrollback_cleared = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn insert_lines(&mut self, count: usize) {
+        if self.row < self.scroll_top || self.row > self.scroll_bottom {
+            return;
+        }
+        for _ in 0..count.min(self.scroll_bottom - self.row + 1) {
+            self.grid.remove(self.scroll_bottom);
+            self.grid.insert(self.row, self.blank_line());
+        }
+        self.column = 0;
+    }
+
+    fn delete_lines(&mut self, count: usize) {
+        if self.row < self.scroll_top || self.row > self.scroll_bottom {
+            return;
+        }
+        for _ in 0..count.min(self.scroll_bottom - self.row + 1) {
+            self.grid.remove(self.row);
+            self.grid.insert(self.scroll_bottom, self.blank_line());
+        }
+        self.column = 0;
+    }
+
+    fn insert_cells(&mut self, count: usize) {
+        let blank = Cell::blank(self.style);
+        let line = &mut self.grid[self.row];
+        for _ in 0..count.min(self.columns - self.column) {
+            line.pop();
+            line.insert(self.column, blank);
+        }
+    }
+
+    fn delete_cells(&mut self, count: usize) {
+        let blank = Cell::blank(self.style);
+        let line = &mut self.grid[self.row];
+        for _ in 0..count.min(self.columns - self.column) {
+            line.remove(self.column);
+            line.push(blank);
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = SavedCursor {
+            row: self.row,
+            column: self.column,
+            style: self.style,
+        };
+    }
+
+    fn restore_cursor(&mut self) {
+        self.style = self.saved.style;
+        self.move_to(self.saved.row, self.saved.column);
+    }
+
+    fn set_alternate_screen(&mut self, enabled: bool, save_cursor: bool) {
+        if enabled == self.primary.is_some() {
+            return;
+        }
+        if enabled {
+            if save_cursor {
+                self.save_cursor();
+            }
+            let blank = vec![vec![Cell::blank(Style::default()); self.columns]; self.rows];
+            self.primary = Some(std::mem::replace(&mut self.grid, blank));
+        } else {
+            if let Some(primary) = self.primary.take() {
+                self.grid = primary;
+            }
+            if save_cursor {
+                self.restore_cursor();
+            }
+        }
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+    }
+
+    fn set_mode(&mut self, private: bool, mode: u16, enabled: bool) {
+        match (private, mode) {
+            (false, 4) => self.insert_mode = enabled,
+            (true, 1) => self.application_cursor = enabled,
+            (true, 7) => self.auto_wrap = enabled,
+            (true, 25) => self.cursor_visible = enabled,
+            (true, 47) | (true, 1047) => self.set_alternate_screen(enabled, false),
+            (true, 1048) if enabled => self.save_cursor(),
+            (true, 1048) => self.restore_cursor(),
+            (true, 1049) => self.set_alternate_screen(enabled, true),
+            (true, 2004) => self.bracketed_paste = enabled,
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        let (columns, rows, limit) = (self.columns, self.rows, self.scrollback_limit);
+        let scrollback = std::mem::take(&mut self.scrollback);
+        let total = self.scrollback_total;
+        *self = Screen::new(columns, rows, limit);
+        self.scrollback = scrollback;
+        self.scrollback_total = total;
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        let mut iter = params.iter();
+        if params.is_empty() {
+            self.style = Style::default();
+            return;
+        }
+        while let Some(param) = iter.next() {
+            match param[0] {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = param.get(1) != Some(&0),
+                7 => self.style.inverse = true,
+                9 => self.style.strikethrough = true,
+                21 | 22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.inverse = false,
+                29 => self.style.strikethrough = false,
+                code @ 30..=37 => self.style.foreground = Color::Indexed(code as u8 - 30),
+                38 => self.style.foreground = extended_color(param, &mut iter),
+                39 => self.style.foreground = Color::Default,
+                code @ 40..=47 => self.style.background = Color::Indexed(code as u8 - 40),
+                48 => self.style.background = extended_color(param, &mut iter),
+                49 => self.style.background = Color::Default,
+                code @ 90..=97 => self.style.foreground = Color::Indexed(code as u8 - 90 + 8),
+                code @ 100..=107 => self.style.background = Color::Indexed(code as u8 - 100 + 8),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn extended_color<'a>(param: &[u16], iter: &mut impl Iterator<Item = &'a [u16]>) -> Color {
+    let values: Vec<u16> = if param.len() > 1 {
+        param[1..].to_vec()
+    } else {
+        let mut values = Vec::new();
+        if let Some(kind) = iter.next() {
+            values.push(kind[0]);
+            let count = if kind[0] == 2 { 3 } else { 1 };
+            values.extend(iter.take(count).map(|value| value[0]));
+        }
+        values
+    };
+    match values.as_slice() {
+        [5, index, ..] => Color::Indexed(*index as u8),
+        [2, .., red, green, blue] => Color::Rgb(*red as u8, *green as u8, *blue as u8),
+        _ => Color::Default,
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, character: char) {
+        let width = character.width().unwrap_or(0);
+        if width == 0 {
+            return;
+        }
+        if self.wrap_pending && self.auto_wrap {
+            self.column = 0;
+            self.line_feed();
+        }
+        self.wrap_pending = false;
+        if self.column + width > self.columns {
+            if self.auto_wrap {
+                self.column = 0;
+                self.line_feed();
+            } else {
+                self.column = self.columns.saturating_sub(width);
+            }
+        }
+        if self.insert_mode {
+            self.insert_cells(width);
+        }
+        let style = self.style;
+        let line = &mut self.grid[self.row];
+        line[self.column] = Cell { character, style };
+        if width == 2 && self.column + 1 < self.columns {
+            line[self.column + 1] = Cell {
+                character: '\0',
+                style,
+            };
+        }
+        self.column += width;
+        if self.column >= self.columns {
+            self.column = self.columns - 1;
+            self.wrap_pending = true;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.column = self.column.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            0x09 => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column = next.min(self.columns - 1);
+            }
+            0x0a..=0x0c => self.line_feed(),
+            0x0d => {
+                self.column = 0;
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        if let [kind, title, ..] = params
+            && (*kind == b"0" || *kind == b"2")
+        {
+            self.title = Some(String::from_utf8_lossy(title).into_owned());
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+        let values: Vec<u16> = params.iter().map(|param| param[0]).collect();
+        let argument = |index: usize, default: usize| -> usize {
+            match values.get(index) {
+                Some(0) | None => default,
+                Some(value) => *value as usize,
+            }
+        };
+        let private = intermediates.first() == Some(&b'?');
+        match (action, intermediates) {
+            ('A', []) => self.move_to(self.row.saturating_sub(argument(0, 1)), self.column),
+            ('B' | 'e', []) => self.move_to(self.row + argument(0, 1), self.column),
+            ('C' | 'a', []) => self.move_to(self.row, self.column + argument(0, 1)),
+            ('D', []) => self.move_to(self.row, self.column.saturating_sub(argument(0, 1))),
+            ('E', []) => self.move_to(self.row + argument(0, 1), 0),
+            ('F', []) => self.move_to(self.row.saturating_sub(argument(0, 1)), 0),
+            ('G' | '`', []) => self.move_to(self.row, argument(0, 1) - 1),
+            ('d', []) => self.move_to(argument(0, 1) - 1, self.column),
+            ('H' | 'f', []) => self.move_to(argument(0, 1) - 1, argument(1, 1) - 1),
+            ('J', _) => self.erase_display(values.first().copied().unwrap_or(0)),
+            ('K', _) => match values.first().copied().unwrap_or(0) {
+                0 => self.erase(self.row, self.column..self.columns),
+                1 => self.erase(self.row, 0..self.column + 1),
+                2 => self.erase(self.row, 0..self.columns),
+                _ => {}
+            },
+            ('L', []) => self.insert_lines(argument(0, 1)),
+            ('M', []) => self.delete_lines(argument(0, 1)),
+            ('@', []) => self.insert_cells(argument(0, 1)),
+            ('P', []) => self.delete_cells(argument(0, 1)),
+            ('X', []) => {
+                let count = argument(0, 1);
+                self.erase(self.row, self.column..self.column + count);
+            }
+            ('S', []) => self.scroll_up(argument(0, 1)),
+            ('T', []) => self.scroll_down(argument(0, 1)),
+            ('m', []) => self.select_graphic_rendition(params),
+            ('r', []) => {
+                let top = argument(0, 1) - 1;
+                let bottom = argument(1, self.rows).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            ('s', []) => self.save_cursor(),
+            ('u', []) => self.restore_cursor(),
+            ('h' | 'l', _) => {
+                for mode in &values {
+                    self.set_mode(private, *mode, action == 'h');
+                }
+            }
+            ('n', []) => match argument(0, 0) {
+                5 => self.responses.extend_from_slice(b"\x1b[0n"),
+                6 => self.responses.extend_from_slice(
+                    format!("\x1b[{};{}R", self.row + 1, self.column + 1).as_bytes(),
+                ),
+                _ => {}
+            },
+            ('c', []) => self.responses.extend_from_slice(b"\x1b[?62;22c"),
+            ('c', [b'>']) => self.responses.extend_from_slice(b"\x1b[>0;0;0c"),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => self.reset(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::cell::{Cell, RefCell};
+
+use gtk4::{TextIter, TextTag, TextView, gdk, glib, graphene, prelude::*, subclass::prelude::*};
+
+use crate::settings::{EditorSettings, WhitespaceMode, WrapMode};
+
+mod imp {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct EditorView {
+        pub wrap_mode: Cell<WrapMode>,
+        pub rulers: RefCell<Vec<i32>>,
+        pub base_right_margin: Cell<i32>,
+        pub wrap_indent_tags: RefCell<Vec<TextTag>>,
+        pub changed_lines: Cell<Option<(i32, i32)>>,
+        pub show_whitespace: Cell<WhitespaceMode>,
+        pub indent_guides: Cell<bool>,
+        pub highlight_current_line: Cell<bool>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for EditorView {
+        const NAME: &'static str = "MoonEditorView";
+        type Type = super::EditorView;
+        type ParentType = TextView;
+    }
+
+    impl ObjectImpl for EditorView {}
+
+    impl WidgetImpl for EditorView {
+        fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(width, height, baseline);
+            self.obj().update_wrap_margin(width);
+        }
+    }
+
+    impl TextViewImpl for EditorView {
+        fn snapshot_layer(&self, layer: gtk4::TextViewLayer, snapshot: gtk4::Snapshot) {
+            match layer {
+                gtk4::TextViewLayer::BelowText => {
+                    self.obj().snapshot_current_line(&snapshot);
+                    self.obj().snapshot_rulers(&snapshot);
+                    self.obj().snapshot_indent_guides(&snapshot);
+                }
+                gtk4::TextViewLayer::AboveText => {
+                    self.obj().snapshot_whitespace(&snapshot);
+                }
+                _ => {}
+            }
+            self.parent_snapshot_layer(layer, snapshot);
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct EditorView(ObjectSubclass<imp::EditorView>)
+        @extends TextView, gtk4::Widget,
+        @implements gtk4::Accessible, gtk4::Buildable, gtk4::ConstraintTarget, gtk4::Scrollable;
+}
+
+impl EditorView {
+    pub fn new() -> Self {
+        let view: EditorView = glib::Object::new();
+        let weak_view = view.downgrade();
+        view.buffer().connect_insert_text(move |_, location, text| {
+            if let Some(view) = weak_view.upgrade() {
+                let line = location.line();
+                view.mark_changed_lines(line, line + text.matches('\n').count() as i32);
+            }
+        });
+        let weak_view = view.downgrade();
+        view.buffer().connect_delete_range(move |_, start, end| {
+            if let Some(view) = weak_view.upgrade() {
+                let line = start.line().min(end.line());
+                view.mark_changed_lines(line, line);
+            }
+        });
+        let weak_view = view.downgrade();
+        view.buffer().connect_changed(move |_| {
+            if let Some(view) = weak_view.upgrade()
+                && let Some((first, last)) = view.imp().changed_lines.take()
+            {
+                view.update_wrap_indent_lines(first, last);
+            }
+        });
+        let weak_view = view.downgrade();
+        view.buffer().connect_cursor_position_notify(move |_| {
+            if let Some(view) = weak_view.upgrade() {
+                view.queue_draw();
+            }
+        });
+        view
+    }
+
+    pub fn apply_settings(&self, settings: &EditorSettings) {
+        let imp = self.imp();
+        imp.wrap_mode.set(settings.wrap_mode);
+        imp.rulers.replace(settings.rulers.clone());
+        imp.base_right_margin.set(self.right_margin());
+        imp.show_whitespace.set(settings.show_whitespace);
+        imp.indent_guides.set(settings.indent_guides);
+        imp.highlight_current_line
+            .set(settings.highlight_current_line);
+        self.set_wrap_mode(match settings.wrap_mode {
+            WrapMode::None => gtk4::WrapMode::None,
+            WrapMode::Window | WrapMode::Column(_) => gtk4::WrapMode::WordChar,
+        });
+        self.update_wrap_margin(self.width());
+        self.update_wrap_indent();
+        self.queue_draw();
+    }
+
+    pub fn char_width(&self) -> f64 {
+        let layout = self.create_pango_layout(Some(&" ".repeat(10)));
+        layout.pixel_size().0 as f64 / 10.0
+    }
+
+    fn update_wrap_margin(&self, width: i32) {
+        let base_margin = self.imp().base_right_margin.get();
+        let right_margin = match self.imp().wrap_mode.get() {
+            WrapMode::Column(column) if width > 0 => {
+                let text_width = (column as f64 * self.char_width()).ceil() as i32;
+                (width - self.left_margin() - text_width).max(base_margin)
+            }
+            _ => base_margin,
+        };
+        if right_margin != self.right_margin() {
+            let weak_view = self.downgrade();
+            glib::idle_add_local_once(move || {
+                if let Some(view) = weak_view.upgrade() {
+                    view.set_right_margin(right_margin);
+                }
+            });
+        }
+    }
+
+    fn wrap_indent_tag(&self, columns: usize) -> TextTag {
+        let buffer = self.buffer();
+        let name = format!("wrap_indent_{}", columns);
+        if let Some(tag) = buffer.tag_table().lookup(&name) {
+            return tag;
+        }
+        let indent = (columns as f64 * self.char_width()).round() as i32;
+        let tag = buffer
+            .create_tag(
+                Some(&name),
+                &[
+                    ("left-margin", &(self.left_margin() + indent)),
+                    ("indent", &-indent),
+                ],
+            )
+            .expect("Could not create tag for wrapped line indentation");
+        self.imp().wrap_indent_tags.borrow_mut().push(tag.clone());
+        tag
+    }
+
+    fn mark_changed_lines(&self, first: i32, last: i32) {
+        let changed = &self.imp().changed_lines;
+        changed.set(Some(match changed.get() {
+            Some((changed_first, changed_last)) => {
+                (changed_first.min(first), changed_last.max(last))
+            }
+            None => (first, last),
+        }));
+    }
+
+    fn tab_columns(&self) -> usize {
+        let Some(tabs) = self.tabs() else {
+            return 8;
+        };
+        if tabs.size() < 2 {
+            return 8;
+        }
+        let tab_width = (tabs.tab(1).1 - tabs.tab(0).1) as f64;
+        ((tab_width / self.char_width()).round() as usize).max(1)
+    }
+
+    fn update_wrap_indent(&self) {
+        self.update_wrap_indent_lines(0, self.buffer().line_count() - 1);
+    }
+
+    fn update_wrap_indent_lines(&self, first: i32, last: i32) {
+        let buffer = self.buffer();
+        let wrapping = self.imp().wrap_mode.get() != WrapMode::None;
+        let tab_width = self.tab_columns();
+        for line in first..=last.min(buffer.line_count() - 1) {
+            let Some(line_start) = buffer.iter_at_line(line) else {
+                continue;
+            };
+            let mut line_end = line_start;
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            for tag in self.imp().wrap_indent_tags.borrow().iter() {
+                buffer.remove_tag(tag, &line_start, &line_end);
+            }
+            if !wrapping {
+                continue;
+            }
+            let text = buffer.text(&line_start, &line_end, true);
+            let mut columns = 0;
+            for character in text.chars() {
+                match character {
+                    ' ' => columns += 1,
+                    '\t' => columns += tab_width - columns % tab_width,
+                    _ => break,
+                }
+            }
+            if columns == 0 || text.trim().is_empty() {
+                continue;
+            }
+            let tag = self.wrap_indent_tag(columns);
+            buffer.apply_tag(&tag, &line_start, &line_end);
+        }
+    }
+
+    fn snapshot_rulers(&self, snapshot: &gtk4::Snapshot) {
+        let rulers = self.imp().rulers.borrow();
+        if rulers.is_empty() {
+            return;
+        }
+        let visible = self.visible_rect();
+        let char_width = self.char_width();
+        let color = gdk::RGBA::new(1.0, 1.0, 1.0, 0.08);
+        for column in rulers.iter() {
+            let x = self.left_margin() as f64 + *column as f64 * char_width;
+            snapshot.append_color(
+                &color,
+                &graphene::Rect::new(
+                    x.round() as f32,
+                    visible.y() as f32,
+                    1.0,
+                    visible.height() as f32,
+                ),
+            );
+        }
+    }
+
+    fn for_each_visible_line(&self, mut callback: impl FnMut(&TextIter, i32, i32)) {
+        let visible = self.visible_rect();
+        let bottom = visible.y() + visible.height();
+        let (mut line_start, _) = self.line_at_y(visible.y());
+        loop {
+            let (y, height) = self.line_yrange(&line_start);
+            if y > bottom {
+                break;
+            }
+            callback(&line_start, y, height);
+            if !line_start.forward_line() {
+                break;
+            }
+        }
+    }
+
+    fn snapshot_current_line(&self, snapshot: &gtk4::Snapshot) {
+        if !self.imp().highlight_current_line.get() {
+            return;
+        }
+        let buffer = self.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let (y, height) = self.line_yrange(&cursor);
+        let visible = self.visible_rect();
+        snapshot.append_color(
+            &gdk::RGBA::new(1.0, 1.0, 1.0, 0.04),
+            &graphene::Rect::new(
+                visible.x() as f32,
+                y as f32,
+                visible.width() as f32,
+                height as f32,
+            ),
+        );
+    }
+
+    fn indentation_end(line_start: &TextIter) -> TextIter {
+        let mut iter = *line_start;
+        while !iter.ends_line() && (iter.char() == ' ' || iter.char() == '\t') {
+            iter.forward_char();
+        }
+        iter
+    }
+
+    fn indentation_width(&self, line_start: &TextIter) -> i32 {
+        let mut line = *line_start;
+        for _ in 0..100 {
+            let indentation_end = Self::indentation_end(&line);
+            if !indentation_end.ends_line() {
+                return self.iter_location(&indentation_end).x() - self.iter_location(&line).x();
+            }
+            if !line.forward_line() {
+                break;
+            }
+        }
+        0
+    }
+
+    fn snapshot_indent_guides(&self, snapshot: &gtk4::Snapshot) {
+        if !self.imp().indent_guides.get() {
+            return;
+        }
+        let Some(tabs) = self.tabs() else {
+            return;
+        };
+        let tab_positions: Vec<i32> = (0..tabs.size()).map(|index| tabs.tab(index).1).collect();
+        let color = gdk::RGBA::new(1.0, 1.0, 1.0, 0.07);
+        self.for_each_visible_line(|line_start, y, height| {
+            let indentation = self.indentation_width(line_start);
+            let line_x = self.iter_location(line_start).x();
+            for position in tab_positions
+                .iter()
+                .take_while(|position| **position < indentation)
+            {
+                snapshot.append_color(
+                    &color,
+                    &graphene::Rect::new((line_x + position) as f32, y as f32, 1.0, height as f32),
+                );
+            }
+        });
+    }
+
+    fn snapshot_whitespace(&self, snapshot: &gtk4::Snapshot) {
+        let mode = self.imp().show_whitespace.get();
+        if mode == WhitespaceMode::None {
+            return;
+        }
+        let buffer = self.buffer();
+        let faint = gdk::RGBA::new(1.0, 1.0, 1.0, 0.18);
+        let trailing = gdk::RGBA::new(1.0, 0.45, 0.45, 0.45);
+        self.for_each_visible_line(|line_start, _, _| {
+            let mut line_end = *line_start;
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            let text = buffer.text(line_start, &line_end, true);
+            let trailing_start = text.trim_end().chars().count();
+            let mut iter = *line_start;
+            for (index, character) in text.chars().enumerate() {
+                let is_trailing = index >= trailing_start;
+                if (character == ' ' || character == '\t')
+                    && (mode == WhitespaceMode::All || is_trailing)
+                {
+                    let color = if is_trailing { &trailing } else { &faint };
+                    let location = self.iter_location(&iter);
+                    let center_y = (location.y() + location.height() / 2) as f32;
+                    if character == ' ' {
+                        let center_x = (location.x() + location.width() / 2) as f32;
+                        snapshot.append_color(
+                            color,
+                            &graphene::Rect::new(center_x - 1.0, center_y - 1.0, 2.0, 2.0),
+                        );
+                    } else {
+                        let start_x = (location.x() + 2) as f32;
+                        let end_x = (location.x() + location.width() - 2) as f32;
+                        snapshot.append_color(
+                            color,
+                            &graphene::Rect::new(start_x, center_y, end_x - start_x, 1.0),
+                        );
+                        snapshot.append_color(
+                            color,
+                            &graphene::Rect::new(end_x - 1.0, center_y - 2.0, 1.0, 5.0),
+                        );
+                    }
+                }
+                iter.forward_char();
+            }
+        });
+    }
+}
@@ -0,0 +1,1969 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use gtk4::{
+    EventControllerKey, Label, PropagationPhase, TextBuffer, TextIter, TextMark, TextTag, TextView,
+    gdk, glib::Propagation, prelude::*,
+};
+use regex::RegexBuilder;
+use tree_sitter::{Node, Point};
+
+use crate::{document::Document, line_ops, syntax};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    Command,
+}
+
+impl Mode {
+    fn label(&self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+            Mode::VisualBlock => "VISUAL BLOCK",
+            Mode::Command => "COMMAND",
+        }
+    }
+
+    fn is_visual(&self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine | Mode::VisualBlock)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Escape,
+    Enter,
+    Backspace,
+}
+
+impl Key {
+    fn display(&self) -> String {
+        match self {
+            Key::Char(character) => character.to_string(),
+            Key::Ctrl(character) => format!("^{}", character.to_ascii_uppercase()),
+            Key::Escape => "<Esc>".to_owned(),
+            Key::Enter => "<CR>".to_owned(),
+            Key::Backspace => "<BS>".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward(bool),
+    WordBackward(bool),
+    WordEnd(bool),
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find {
+        forward: bool,
+        till: bool,
+        target: char,
+    },
+    RepeatFind(bool),
+    MatchPair,
+    GotoLine(bool),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Dedent,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TextObject {
+    Word(bool),
+    Quote(char),
+    Pair(char, char),
+    Function,
+    Block,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Motion(Motion),
+    Object(TextObject, bool),
+    Lines,
+    Selection,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    Insert(InsertAt),
+    Visual(Mode),
+    SelectObject(TextObject, bool),
+    SwapAnchor,
+    BlockInsert(bool),
+    Put(bool),
+    Replace(char),
+    ToggleCase,
+    Join,
+    Undo,
+    Redo,
+    Repeat,
+    Ex,
+}
+
+struct Command {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+enum Parsed<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+#[derive(Clone, Default)]
+struct Register {
+    text: String,
+    linewise: bool,
+}
+
+struct BlockInsert {
+    first_line: i32,
+    last_line: i32,
+    column: i32,
+}
+
+struct VimState {
+    enabled: bool,
+    mode: Mode,
+    pending: Vec<Key>,
+    registers: HashMap<char, Register>,
+    last_find: Option<(bool, bool, char)>,
+    last_change: Vec<Key>,
+    last_insert: Option<String>,
+    change_keys: Vec<Key>,
+    insert_count: usize,
+    block_insert: Option<BlockInsert>,
+    command_line: String,
+    ex_range: Option<(i32, i32)>,
+    preferred_column: Option<i32>,
+    replaying: bool,
+    message: String,
+}
+
+pub struct Vim {
+    text_view: TextView,
+    status: Label,
+    document: Rc<Document>,
+    state: RefCell<VimState>,
+    anchor: TextMark,
+    cursor: TextMark,
+    insert_start: TextMark,
+    block_tag: TextTag,
+}
+
+fn char_class(character: char, big: bool) -> u8 {
+    if character.is_whitespace() || character == '\0' {
+        0
+    } else if big || character.is_alphanumeric() || character == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn parse_count(keys: &[Key], index: &mut usize) -> Option<usize> {
+    let mut count: Option<usize> = None;
+    while let Some(Key::Char(character)) = keys.get(*index) {
+        let Some(digit) = character.to_digit(10) else {
+            break;
+        };
+        if digit == 0 && count.is_none() {
+            break;
+        }
+        count = Some(count.unwrap_or(0) * 10 + digit as usize);
+        *index += 1;
+    }
+    count
+}
+
+fn parse_motion(keys: &[Key], index: usize) -> Parsed<Motion> {
+    let Some(key) = keys.get(index) else {
+        return Parsed::Incomplete;
+    };
+    let motion = match key {
+        Key::Char('h') | Key::Backspace => Motion::Left,
+        Key::Char('l') | Key::Char(' ') => Motion::Right,
+        Key::Char('j') | Key::Enter => Motion::Down,
+        Key::Char('k') => Motion::Up,
+        Key::Char('w') => Motion::WordForward(false),
+        Key::Char('W') => Motion::WordForward(true),
+        Key::Char('b') => Motion::WordBackward(false),
+        Key::Char('B') => Motion::WordBackward(true),
+        Key::Char('e') => Motion::WordEnd(false),
+        Key::Char('E') => Motion::WordEnd(true),
+        Key::Char('0') => Motion::LineStart,
+        Key::Char('^') => Motion::FirstNonBlank,
+        Key::Char('$') => Motion::LineEnd,
+        Key::Char('%') => Motion::MatchPair,
+        Key::Char('G') => Motion::GotoLine(true),
+        Key::Char(';') => Motion::RepeatFind(false),
+        Key::Char(',') => Motion::RepeatFind(true),
+        Key::Char('g') => {
+            return match keys.get(index + 1) {
+                None => Parsed::Incomplete,
+                Some(Key::Char('g')) => Parsed::Done(Motion::GotoLine(false)),
+                Some(_) => Parsed::Invalid,
+            };
+        }
+        Key::Char(kind @ ('f' | 't' | 'F' | 'T')) => {
+            return match keys.get(index + 1) {
+                None => Parsed::Incomplete,
+                Some(Key::Char(target)) => Parsed::Done(Motion::Find {
+                    forward: kind.is_lowercase(),
+                    till: *kind == 't' || *kind == 'T',
+                    target: *target,
+                }),
+                Some(_) => Parsed::Invalid,
+            };
+        }
+        _ => return Parsed::Invalid,
+    };
+    Parsed::Done(motion)
+}
+
+fn parse_object(keys: &[Key], index: usize) -> Parsed<(TextObject, bool)> {
+    let around = match keys.get(index) {
+        None => return Parsed::Incomplete,
+        Some(Key::Char('i')) => false,
+        Some(Key::Char('a')) => true,
+        Some(_) => return Parsed::Invalid,
+    };
+    let object = match keys.get(index + 1) {
+        None => return Parsed::Incomplete,
+        Some(Key::Char('w')) => TextObject::Word(false),
+        Some(Key::Char('W')) => TextObject::Word(true),
+        Some(Key::Char(quote @ ('"' | '\'' | '`'))) => TextObject::Quote(*quote),
+        Some(Key::Char('(' | ')' | 'b')) => TextObject::Pair('(', ')'),
+        Some(Key::Char('[' | ']')) => TextObject::Pair('[', ']'),
+        Some(Key::Char('{' | '}')) => TextObject::Pair('{', '}'),
+        Some(Key::Char('<' | '>')) => TextObject::Pair('<', '>'),
+        Some(Key::Char('B')) => TextObject::Block,
+        Some(Key::Char('f')) => TextObject::Function,
+        Some(_) => return Parsed::Invalid,
+    };
+    Parsed::Done((object, around))
+}
+
+fn parse(keys: &[Key], mode: Mode) -> Parsed<Command> {
+    let mut index = 0;
+    let mut register = None;
+    if keys.first() == Some(&Key::Char('"')) {
+        match keys.get(1) {
+            None => return Parsed::Incomplete,
+            Some(Key::Char(name)) => register = Some(*name),
+            Some(_) => return Parsed::Invalid,
+        }
+        index = 2;
+    }
+    let count = parse_count(keys, &mut index);
+    let Some(key) = keys.get(index) else {
+        return Parsed::Incomplete;
+    };
+    let done = |count: Option<usize>, action: Action| {
+        Parsed::Done(Command {
+            register,
+            count,
+            action,
+        })
+    };
+    let operator = match key {
+        Key::Char('d') => Some(Operator::Delete),
+        Key::Char('c') => Some(Operator::Change),
+        Key::Char('y') => Some(Operator::Yank),
+        Key::Char('>') => Some(Operator::Indent),
+        Key::Char('<') => Some(Operator::Dedent),
+        _ => None,
+    };
+    if let Some(operator) = operator {
+        if mode.is_visual() {
+            return done(count, Action::Operate(operator, Target::Selection));
+        }
+        let mut motion_index = index + 1;
+        let motion_count = parse_count(keys, &mut motion_index);
+        let count = match (count, motion_count) {
+            (None, None) => None,
+            (count, motion_count) => Some(count.unwrap_or(1) * motion_count.unwrap_or(1)),
+        };
+        return match keys.get(motion_index) {
+            None => Parsed::Incomplete,
+            Some(next) if next == key => done(count, Action::Operate(operator, Target::Lines)),
+            Some(Key::Char('i' | 'a')) => match parse_object(keys, motion_index) {
+                Parsed::Incomplete => Parsed::Incomplete,
+                Parsed::Invalid => Parsed::Invalid,
+                Parsed::Done((object, around)) => done(
+                    count,
+                    Action::Operate(operator, Target::Object(object, around)),
+                ),
+            },
+            Some(_) => match parse_motion(keys, motion_index) {
+                Parsed::Incomplete => Parsed::Incomplete,
+                Parsed::Invalid => Parsed::Invalid,
+                Parsed::Done(motion) => {
+                    done(count, Action::Operate(operator, Target::Motion(motion)))
+                }
+            },
+        };
+    }
+    let action = match key {
+        Key::Char('v') => Action::Visual(Mode::Visual),
+        Key::Char('V') => Action::Visual(Mode::VisualLine),
+        Key::Ctrl('v') => Action::Visual(Mode::VisualBlock),
+        Key::Char(':') => Action::Ex,
+        Key::Char('J') => Action::Join,
+        Key::Char('~') => Action::ToggleCase,
+        Key::Char('x') if mode.is_visual() => Action::Operate(Operator::Delete, Target::Selection),
+        Key::Char('s') if mode.is_visual() => Action::Operate(Operator::Change, Target::Selection),
+        Key::Char('o') if mode.is_visual() => Action::SwapAnchor,
+        Key::Char('I') if mode == Mode::VisualBlock => Action::BlockInsert(false),
+        Key::Char('A') if mode == Mode::VisualBlock => Action::BlockInsert(true),
+        Key::Char('i' | 'a') if mode.is_visual() => {
+            return match parse_object(keys, index) {
+                Parsed::Incomplete => Parsed::Incomplete,
+                Parsed::Invalid => Parsed::Invalid,
+                Parsed::Done((object, around)) => done(count, Action::SelectObject(object, around)),
+            };
+        }
+        _ if mode.is_visual() => {
+            return match parse_motion(keys, index) {
+                Parsed::Incomplete => Parsed::Incomplete,
+                Parsed::Invalid => Parsed::Invalid,
+                Parsed::Done(motion) => done(count, Action::Move(motion)),
+            };
+        }
+        Key::Char('x') => Action::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+        Key::Char('X') => Action::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        Key::Char('s') => Action::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        Key::Char('S') => Action::Operate(Operator::Change, Target::Lines),
+        Key::Char('D') => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        Key::Char('C') => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        Key::Char('Y') => Action::Operate(Operator::Yank, Target::Lines),
+        Key::Char('i') => Action::Insert(InsertAt::Cursor),
+        Key::Char('a') => Action::Insert(InsertAt::After),
+        Key::Char('I') => Action::Insert(InsertAt::LineStart),
+        Key::Char('A') => Action::Insert(InsertAt::LineEnd),
+        Key::Char('o') => Action::Insert(InsertAt::LineBelow),
+        Key::Char('O') => Action::Insert(InsertAt::LineAbove),
+        Key::Char('p') => Action::Put(false),
+        Key::Char('P') => Action::Put(true),
+        Key::Char('u') => Action::Undo,
+        Key::Ctrl('r') => Action::Redo,
+        Key::Char('.') => Action::Repeat,
+        Key::Char('r') => match keys.get(index + 1) {
+            None => return Parsed::Incomplete,
+            Some(Key::Char(character)) => Action::Replace(*character),
+            Some(_) => return Parsed::Invalid,
+        },
+        _ => {
+            return match parse_motion(keys, index) {
+                Parsed::Incomplete => Parsed::Incomplete,
+                Parsed::Invalid => Parsed::Invalid,
+                Parsed::Done(motion) => done(count, Action::Move(motion)),
+            };
+        }
+    };
+    done(count, action)
+}
+
+fn translate_key(keyval: gdk::Key, modifiers: gdk::ModifierType, mode: Mode) -> Option<Key> {
+    if modifiers.contains(gdk::ModifierType::ALT_MASK) {
+        return None;
+    }
+    let control = modifiers.contains(gdk::ModifierType::CONTROL_MASK);
+    match keyval {
+        gdk::Key::Escape => return Some(Key::Escape),
+        gdk::Key::Return | gdk::Key::KP_Enter => return Some(Key::Enter),
+        gdk::Key::BackSpace => return Some(Key::Backspace),
+        _ => {}
+    }
+    if mode != Mode::Insert && mode != Mode::Command && !control {
+        let mapped = match keyval {
+            gdk::Key::Left => Some('h'),
+            gdk::Key::Right => Some('l'),
+            gdk::Key::Up => Some('k'),
+            gdk::Key::Down => Some('j'),
+            gdk::Key::Home => Some('0'),
+            gdk::Key::End => Some('$'),
+            gdk::Key::Delete => Some('x'),
+            _ => None,
+        };
+        if let Some(character) = mapped {
+            return Some(Key::Char(character));
+        }
+    }
+    let character = keyval.to_unicode()?;
+    if control {
+        if character == '[' {
+            Some(Key::Escape)
+        } else {
+            Some(Key::Ctrl(character.to_ascii_lowercase()))
+        }
+    } else if character.is_control() {
+        None
+    } else {
+        Some(Key::Char(character))
+    }
+}
+
+fn iter_at_line_offset(buffer: &TextBuffer, line: i32, offset: i32) -> TextIter {
+    let line = line.clamp(0, buffer.line_count() - 1);
+    if let Some(iter) = buffer.iter_at_line_offset(line, offset) {
+        return iter;
+    }
+    let mut iter = buffer.iter_at_line(line).unwrap_or(buffer.end_iter());
+    if !iter.ends_line() {
+        iter.forward_to_line_end();
+    }
+    iter
+}
+
+fn line_end(iter: &TextIter) -> TextIter {
+    let mut end = *iter;
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    end
+}
+
+fn first_non_blank(iter: &TextIter) -> TextIter {
+    let mut first = *iter;
+    first.set_line_offset(0);
+    while !first.ends_line() && char_class(first.char(), false) == 0 {
+        first.forward_char();
+    }
+    first
+}
+
+fn indentation(iter: &TextIter) -> String {
+    let buffer = iter.buffer();
+    let mut start = *iter;
+    start.set_line_offset(0);
+    buffer
+        .text(&start, &first_non_blank(iter), true)
+        .to_string()
+}
+
+fn word_forward(iter: &mut TextIter, big: bool) {
+    let class = char_class(iter.char(), big);
+    if class != 0 {
+        while !iter.is_end() && char_class(iter.char(), big) == class {
+            iter.forward_char();
+        }
+    }
+    while !iter.is_end() && char_class(iter.char(), big) == 0 {
+        iter.forward_char();
+        if iter.starts_line() && iter.ends_line() {
+            break;
+        }
+    }
+}
+
+fn word_end(iter: &mut TextIter, big: bool) {
+    iter.forward_char();
+    while !iter.is_end() && char_class(iter.char(), big) == 0 {
+        iter.forward_char();
+    }
+    let class = char_class(iter.char(), big);
+    loop {
+        let mut next = *iter;
+        if !next.forward_char() || char_class(next.char(), big) != class {
+            break;
+        }
+        *iter = next;
+    }
+}
+
+fn word_backward(iter: &mut TextIter, big: bool) {
+    if !iter.backward_char() {
+        return;
+    }
+    while !iter.is_start() && char_class(iter.char(), big) == 0 {
+        iter.backward_char();
+    }
+    let class = char_class(iter.char(), big);
+    loop {
+        let mut previous = *iter;
+        if !previous.backward_char() || char_class(previous.char(), big) != class {
+            break;
+        }
+        *iter = previous;
+    }
+}
+
+fn match_pair(cursor: &TextIter) -> Option<TextIter> {
+    let pairs = [('(', ')'), ('[', ']'), ('{', '}')];
+    let mut iter = *cursor;
+    while !pairs
+        .iter()
+        .any(|(open, close)| iter.char() == *open || iter.char() == *close)
+    {
+        if iter.ends_line() {
+            return None;
+        }
+        iter.forward_char();
+    }
+    let character = iter.char();
+    let (open, close) = *pairs
+        .iter()
+        .find(|(open, close)| character == *open || character == *close)?;
+    let forward = character == open;
+    let mut depth = 0;
+    loop {
+        let moved = if forward {
+            iter.forward_char()
+        } else {
+            iter.backward_char()
+        };
+        if !moved || iter.is_end() {
+            return None;
+        }
+        let current = iter.char();
+        if current == character {
+            depth += 1;
+        } else if current == (if forward { close } else { open }) {
+            if depth == 0 {
+                return Some(iter);
+            }
+            depth -= 1;
+        }
+    }
+}
+
+fn trim_inner_block(start: &mut TextIter, end: &mut TextIter) {
+    if start.ends_line() && start.line() < end.line() {
+        start.forward_line();
+    }
+    let mut line_start = *end;
+    line_start.set_line_offset(0);
+    if line_start.line() > start.line()
+        && start
+            .buffer()
+            .text(&line_start, end, true)
+            .trim()
+            .is_empty()
+    {
+        *end = line_start;
+    }
+}
+
+fn iter_at_point(buffer: &TextBuffer, point: Point) -> TextIter {
+    buffer
+        .iter_at_line_index(point.row as i32, point.column as i32)
+        .unwrap_or(buffer.end_iter())
+}
+
+fn is_brace_block(node: &Node) -> bool {
+    node.child_count() >= 2
+        && node.child(0).is_some_and(|child| child.kind() == "{")
+        && node
+            .child(node.child_count() as u32 - 1)
+            .is_some_and(|child| child.kind() == "}")
+}
+
+fn inner_block(buffer: &TextBuffer, node: &Node) -> (TextIter, TextIter) {
+    let mut start = iter_at_point(buffer, node.start_position());
+    start.forward_char();
+    let mut end = iter_at_point(buffer, node.end_position());
+    end.backward_char();
+    trim_inner_block(&mut start, &mut end);
+    (start, end)
+}
+
+impl Vim {
+    fn buffer(&self) -> TextBuffer {
+        self.text_view.buffer()
+    }
+
+    fn mode(&self) -> Mode {
+        self.state.borrow().mode
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.enabled = enabled;
+            state.mode = Mode::Normal;
+            state.pending.clear();
+        }
+        self.text_view.set_overwrite(enabled);
+        self.status.set_visible(enabled);
+        self.update_status();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.borrow().enabled
+    }
+
+    pub fn accepts_plain_keys(&self) -> bool {
+        let state = self.state.borrow();
+        state.enabled
+            && self.text_view.has_focus()
+            && state.pending.is_empty()
+            && (state.mode == Mode::Normal || state.mode.is_visual())
+    }
+
+    fn update_status(&self) {
+        let state = self.state.borrow();
+        if state.mode == Mode::Command {
+            self.status.set_text(&format!(":{}", state.command_line));
+            return;
+        }
+        let pending: String = state.pending.iter().map(Key::display).collect();
+        self.status.set_text(
+            format!(
+                "-- {} --  {}  {}",
+                state.mode.label(),
+                pending,
+                state.message
+            )
+            .trim_end(),
+        );
+    }
+
+    fn set_message(&self, message: &str) {
+        self.state.borrow_mut().message = message.to_owned();
+    }
+
+    fn cursor_iter(&self) -> TextIter {
+        let buffer = self.buffer();
+        if self.mode().is_visual() {
+            buffer.iter_at_mark(&self.cursor)
+        } else {
+            buffer.iter_at_mark(&buffer.get_insert())
+        }
+    }
+
+    fn set_cursor(&self, iter: &TextIter) {
+        let buffer = self.buffer();
+        if self.mode().is_visual() {
+            buffer.move_mark(&self.cursor, iter);
+            self.update_selection();
+        } else {
+            buffer.place_cursor(iter);
+        }
+        self.text_view
+            .scroll_to_iter(&mut iter.clone(), 0.0, false, 0.0, 0.0);
+    }
+
+    fn clamp_cursor(&self) {
+        if self.mode() != Mode::Normal {
+            return;
+        }
+        let buffer = self.buffer();
+        let mut cursor = buffer.iter_at_mark(&buffer.get_insert());
+        if cursor.ends_line() && !cursor.starts_line() {
+            cursor.backward_char();
+            buffer.place_cursor(&cursor);
+        }
+    }
+
+    fn key_pressed(&self, keyval: gdk::Key, modifiers: gdk::ModifierType) -> Propagation {
+        let mode = {
+            let state = self.state.borrow();
+            if !state.enabled {
+                return Propagation::Proceed;
+            }
+            state.mode
+        };
+        let key = translate_key(keyval, modifiers, mode);
+        match mode {
+            Mode::Insert => {
+                if key == Some(Key::Escape) {
+                    self.leave_insert();
+                    Propagation::Stop
+                } else {
+                    Propagation::Proceed
+                }
+            }
+            Mode::Command => {
+                if let Some(key) = key {
+                    self.command_key(key);
+                }
+                Propagation::Stop
+            }
+            _ => match key {
+                Some(Key::Ctrl(character)) if character != 'r' && character != 'v' => {
+                    Propagation::Proceed
+                }
+                Some(key) => {
+                    self.normal_key(key);
+                    Propagation::Stop
+                }
+                None if modifiers
+                    .intersects(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::ALT_MASK) =>
+                {
+                    Propagation::Proceed
+                }
+                None if keyval.to_unicode().is_some() => Propagation::Stop,
+                None => Propagation::Proceed,
+            },
+        }
+    }
+
+    fn normal_key(&self, key: Key) {
+        if key == Key::Escape {
+            self.state.borrow_mut().pending.clear();
+            if self.mode().is_visual() {
+                self.exit_visual();
+            }
+            self.set_message("");
+            self.update_status();
+            return;
+        }
+        let parsed = {
+            let mut state = self.state.borrow_mut();
+            state.pending.push(key);
+            state.message.clear();
+            parse(&state.pending, state.mode)
+        };
+        match parsed {
+            Parsed::Incomplete => {}
+            Parsed::Invalid => self.state.borrow_mut().pending.clear(),
+            Parsed::Done(command) => {
+                let keys = std::mem::take(&mut self.state.borrow_mut().pending);
+                self.execute(command, keys);
+            }
+        }
+        self.update_status();
+    }
+
+    fn execute(&self, command: Command, keys: Vec<Key>) {
+        let buffer = self.buffer();
+        let count = command.count.unwrap_or(1);
+        let was_visual = self.mode().is_visual();
+        let mut changed = false;
+        if !matches!(command.action, Action::Move(Motion::Up | Motion::Down)) {
+            self.state.borrow_mut().preferred_column = None;
+        }
+        buffer.begin_user_action();
+        match command.action {
+            Action::Move(motion) => {
+                if let Some((target, _)) = self.motion_target(motion, command.count) {
+                    self.set_cursor(&target);
+                }
+            }
+            Action::Operate(operator, target) => {
+                changed = operator != Operator::Yank;
+                if target == Target::Selection && self.mode() == Mode::VisualBlock {
+                    self.block_operator(operator, command.register);
+                } else if let Some((start, end, linewise)) =
+                    self.operator_range(operator, target, command.count)
+                {
+                    if was_visual {
+                        self.exit_visual();
+                    }
+                    self.apply_operator(operator, start, end, linewise, command.register, count);
+                } else if was_visual {
+                    self.exit_visual();
+                }
+            }
+            Action::Insert(at) => {
+                changed = true;
+                self.state.borrow_mut().insert_count = count;
+                self.enter_insert(at);
+            }
+            Action::Visual(mode) => {
+                if self.mode() == mode {
+                    self.exit_visual();
+                } else {
+                    self.enter_visual(mode);
+                }
+            }
+            Action::SelectObject(object, around) => {
+                let cursor = self.cursor_iter();
+                if let Some((start, mut end)) = self.text_object(object, around, &cursor) {
+                    end.backward_char();
+                    self.state.borrow_mut().mode = Mode::Visual;
+                    buffer.move_mark(&self.anchor, &start);
+                    self.set_cursor(&end);
+                }
+            }
+            Action::SwapAnchor => {
+                let anchor = buffer.iter_at_mark(&self.anchor);
+                let cursor = self.cursor_iter();
+                buffer.move_mark(&self.anchor, &cursor);
+                self.set_cursor(&anchor);
+            }
+            Action::BlockInsert(append) => {
+                let (first_line, last_line, left, right) = self.block_bounds();
+                let column = if append { right + 1 } else { left };
+                self.exit_visual();
+                self.state.borrow_mut().block_insert = Some(BlockInsert {
+                    first_line: first_line + 1,
+                    last_line,
+                    column,
+                });
+                buffer.place_cursor(&iter_at_line_offset(&buffer, first_line, column));
+                self.start_insert();
+            }
+            Action::Put(before) => {
+                changed = true;
+                self.put(before, command.register, count);
+            }
+            Action::Replace(character) => {
+                changed = true;
+                let start = self.cursor_iter();
+                let mut end = start;
+                for _ in 0..count {
+                    if end.ends_line() {
+                        break;
+                    }
+                    end.forward_char();
+                }
+                if end.offset() - start.offset() == count as i32 {
+                    let mut start_mut = start;
+                    buffer.delete(&mut start_mut, &mut end);
+                    buffer.insert(&mut start_mut, &character.to_string().repeat(count));
+                    start_mut.backward_char();
+                    buffer.place_cursor(&start_mut);
+                }
+            }
+            Action::ToggleCase => {
+                changed = true;
+                self.toggle_case(count);
+            }
+            Action::Join => {
+                changed = true;
+                if was_visual {
+                    let (first_line, last_line) = self.visual_lines();
+                    self.exit_visual();
+                    buffer.place_cursor(&iter_at_line_offset(&buffer, first_line, 0));
+                    for _ in first_line..last_line.max(first_line + 1) {
+                        line_ops::join_lines(&buffer);
+                    }
+                } else {
+                    for _ in 0..(count - 1).max(1) {
+                        line_ops::join_lines(&buffer);
+                    }
+                }
+            }
+            Action::Undo => {
+                for _ in 0..count {
+                    buffer.undo();
+                }
+            }
+            Action::Redo => {
+                for _ in 0..count {
+                    buffer.redo();
+                }
+            }
+            Action::Repeat => {
+                buffer.end_user_action();
+                self.repeat();
+                buffer.begin_user_action();
+            }
+            Action::Ex => {
+                let range = if was_visual {
+                    let range = self.visual_lines();
+                    self.exit_visual();
+                    Some(range)
+                } else {
+                    None
+                };
+                let mut state = self.state.borrow_mut();
+                state.ex_range = range;
+                state.command_line = if range.is_some() {
+                    "'<,'>".to_owned()
+                } else {
+                    String::new()
+                };
+                state.mode = Mode::Command;
+            }
+        }
+        buffer.end_user_action();
+        if changed && !was_visual && !self.state.borrow().replaying {
+            let mut state = self.state.borrow_mut();
+            if state.mode == Mode::Insert {
+                state.change_keys = keys;
+            } else {
+                state.last_change = keys;
+                state.last_insert = None;
+            }
+        }
+        self.clamp_cursor();
+    }
+
+    fn motion_target(
+        &self,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> Option<(TextIter, MotionKind)> {
+        let buffer = self.buffer();
+        let times = count.unwrap_or(1);
+        let mut iter = self.cursor_iter();
+        let kind = match motion {
+            Motion::Left => {
+                for _ in 0..times {
+                    if iter.starts_line() {
+                        break;
+                    }
+                    iter.backward_char();
+                }
+                MotionKind::Exclusive
+            }
+            Motion::Right => {
+                for _ in 0..times {
+                    if iter.ends_line() {
+                        break;
+                    }
+                    iter.forward_char();
+                }
+                MotionKind::Exclusive
+            }
+            Motion::Up | Motion::Down => {
+                let column = *self
+                    .state
+                    .borrow_mut()
+                    .preferred_column
+                    .get_or_insert(iter.line_offset());
+                let line = if motion == Motion::Up {
+                    iter.line() - times as i32
+                } else {
+                    iter.line() + times as i32
+                };
+                iter = iter_at_line_offset(&buffer, line, column);
+                MotionKind::Linewise
+            }
+            Motion::WordForward(big) => {
+                for _ in 0..times {
+                    word_forward(&mut iter, big);
+                }
+                MotionKind::Exclusive
+            }
+            Motion::WordBackward(big) => {
+                for _ in 0..times {
+                    word_backward(&mut iter, big);
+                }
+                MotionKind::Exclusive
+            }
+            Motion::WordEnd(big) => {
+                for _ in 0..times {
+                    word_end(&mut iter, big);
+                }
+                MotionKind::Inclusive
+            }
+            Motion::LineStart => {
+                iter.set_line_offset(0);
+                MotionKind::Exclusive
+            }
+            Motion::FirstNonBlank => {
+                iter = first_non_blank(&iter);
+                MotionKind::Exclusive
+            }
+            Motion::LineEnd => {
+                iter = line_end(&iter_at_line_offset(
+                    &buffer,
+                    iter.line() + times as i32 - 1,
+                    0,
+                ));
+                if iter.starts_line() {
+                    MotionKind::Exclusive
+                } else {
+                    iter.backward_char();
+                    MotionKind::Inclusive
+                }
+            }
+            Motion::Find {
+                forward,
+                till,
+                target,
+            } => {
+                self.state.borrow_mut().last_find = Some((forward, till, target));
+                iter = self.find_on_line(&iter, forward, till, target, times)?;
+                MotionKind::Inclusive
+            }
+            Motion::RepeatFind(reverse) => {
+                let (forward, till, target) = self.state.borrow().last_find?;
+                iter = self.find_on_line(&iter, forward != reverse, till, target, times)?;
+                MotionKind::Inclusive
+            }
+            Motion::MatchPair => {
+                iter = match_pair(&iter)?;
+                MotionKind::Inclusive
+            }
+            Motion::GotoLine(last) => {
+                let line = match count {
+                    Some(line) => line as i32 - 1,
+                    None if last => buffer.line_count() - 1,
+                    None => 0,
+                };
+                iter = first_non_blank(&iter_at_line_offset(&buffer, line, 0));
+                MotionKind::Linewise
+            }
+        };
+        Some((iter, kind))
+    }
+
+    fn find_on_line(
+        &self,
+        cursor: &TextIter,
+        forward: bool,
+        till: bool,
+        target: char,
+        times: usize,
+    ) -> Option<TextIter> {
+        let mut iter = *cursor;
+        let mut found = 0;
+        while found < times {
+            if forward {
+                if iter.ends_line() || !iter.forward_char() || iter.ends_line() {
+                    return None;
+                }
+            } else if iter.starts_line() || !iter.backward_char() {
+                return None;
+            }
+            if iter.char() == target {
+                found += 1;
+            }
+        }
+        if till {
+            if forward {
+                iter.backward_char();
+            } else {
+                iter.forward_char();
+            }
+        }
+        Some(iter)
+    }
+
+    fn operator_range(
+        &self,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) -> Option<(TextIter, TextIter, bool)> {
+        let buffer = self.buffer();
+        let cursor = self.cursor_iter();
+        match target {
+            Target::Lines => {
+                let last =
+                    iter_at_line_offset(&buffer, cursor.line() + count.unwrap_or(1) as i32 - 1, 0);
+                Some((cursor, last, true))
+            }
+            Target::Selection => {
+                let anchor = buffer.iter_at_mark(&self.anchor);
+                let (start, mut end) = if anchor.offset() <= cursor.offset() {
+                    (anchor, cursor)
+                } else {
+                    (cursor, anchor)
+                };
+                if self.mode() == Mode::VisualLine {
+                    return Some((start, end, true));
+                }
+                end.forward_char();
+                Some((start, end, false))
+            }
+            Target::Object(object, around) => self
+                .text_object(object, around, &cursor)
+                .map(|(start, end)| (start, end, false)),
+            Target::Motion(motion) => {
+                let motion = match motion {
+                    Motion::WordForward(big)
+                        if operator == Operator::Change && char_class(cursor.char(), big) != 0 =>
+                    {
+                        Motion::WordEnd(big)
+                    }
+                    motion => motion,
+                };
+                let (mut target, kind) = self.motion_target(motion, count)?;
+                if matches!(motion, Motion::WordForward(_))
+                    && target.starts_line()
+                    && target.line() > cursor.line()
+                {
+                    target.backward_char();
+                    if target.offset() < cursor.offset() {
+                        target = cursor;
+                    }
+                }
+                let (start, mut end) = if cursor.offset() <= target.offset() {
+                    (cursor, target)
+                } else {
+                    (target, cursor)
+                };
+                match kind {
+                    MotionKind::Linewise => Some((start, end, true)),
+                    MotionKind::Inclusive => {
+                        end.forward_char();
+                        Some((start, end, false))
+                    }
+                    MotionKind::Exclusive => Some((start, end, false)),
+                }
+            }
+        }
+    }
+
+    fn store_register(&self, register: Option<char>, text: String, linewise: bool, yank: bool) {
+        let mut state = self.state.borrow_mut();
+        let value = Register { text, linewise };
+        match register {
+            Some('_') => return,
+            Some('+' | '*') => self.text_view.clipboard().set_text(&value.text),
+            Some(name) if name.is_ascii_uppercase() => {
+                let entry = state
+                    .registers
+                    .entry(name.to_ascii_lowercase())
+                    .or_default();
+                entry.text.push_str(&value.text);
+                entry.linewise |= linewise;
+            }
+            Some(name) => {
+                state.registers.insert(name, value.clone());
+            }
+            None => {}
+        }
+        if yank {
+            state.registers.insert('0', value.clone());
+        }
+        state.registers.insert('"', value);
+    }
+
+    fn apply_operator(
+        &self,
+        operator: Operator,
+        start: TextIter,
+        end: TextIter,
+        linewise: bool,
+        register: Option<char>,
+        count: usize,
+    ) {
+        let buffer = self.buffer();
+        let (mut start, mut end) = (start, end);
+        let mut leading_newline = false;
+        if linewise {
+            let first_line = start.line();
+            let last_line = end.line();
+            start = iter_at_line_offset(&buffer, first_line, 0);
+            end = iter_at_line_offset(&buffer, last_line, 0);
+            if !end.forward_line() && first_line > 0 && operator == Operator::Delete {
+                start.backward_char();
+                leading_newline = true;
+            }
+        }
+        if operator == Operator::Indent || operator == Operator::Dedent {
+            let last_line = if end.starts_line() && end.line() > start.line() {
+                end.line() - 1
+            } else {
+                end.line()
+            };
+            self.shift_lines(start.line(), last_line, operator == Operator::Indent, count);
+            return;
+        }
+        let mut text = buffer.text(&start, &end, true).to_string();
+        if leading_newline {
+            text.remove(0);
+        }
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.store_register(register, text, linewise, operator == Operator::Yank);
+        match operator {
+            Operator::Yank => {
+                buffer.place_cursor(&start);
+            }
+            Operator::Delete => {
+                buffer.delete(&mut start, &mut end);
+                if linewise {
+                    let line_start = iter_at_line_offset(&buffer, start.line(), 0);
+                    buffer.place_cursor(&first_non_blank(&line_start));
+                } else {
+                    buffer.place_cursor(&start);
+                }
+            }
+            Operator::Change => {
+                if linewise {
+                    let indent = indentation(&start);
+                    if end.starts_line() && end.line() > start.line() {
+                        end.backward_char();
+                    }
+                    buffer.delete(&mut start, &mut end);
+                    buffer.insert(&mut start, &indent);
+                } else {
+                    buffer.delete(&mut start, &mut end);
+                }
+                buffer.place_cursor(&start);
+                self.start_insert();
+            }
+            Operator::Indent | Operator::Dedent => {}
+        }
+    }
+
+    fn shift_lines(&self, first_line: i32, last_line: i32, indent: bool, count: usize) {
+        let buffer = self.buffer();
+        for line in first_line..=last_line {
+            let mut line_start = iter_at_line_offset(&buffer, line, 0);
+            if line_start.ends_line() {
+                continue;
+            }
+            for _ in 0..count {
+                if indent {
+                    buffer.insert(&mut line_start, "\t");
+                } else {
+                    let mut end = line_start;
+                    if end.char() == '\t' {
+                        end.forward_char();
+                    } else {
+                        while end.char() == ' ' && end.line_offset() < 3 {
+                            end.forward_char();
+                        }
+                    }
+                    buffer.delete(&mut line_start, &mut end);
+                }
+            }
+        }
+        let line_start = iter_at_line_offset(&buffer, first_line, 0);
+        buffer.place_cursor(&first_non_blank(&line_start));
+    }
+
+    fn block_bounds(&self) -> (i32, i32, i32, i32) {
+        let buffer = self.buffer();
+        let anchor = buffer.iter_at_mark(&self.anchor);
+        let cursor = buffer.iter_at_mark(&self.cursor);
+        (
+            anchor.line().min(cursor.line()),
+            anchor.line().max(cursor.line()),
+            anchor.line_offset().min(cursor.line_offset()),
+            anchor.line_offset().max(cursor.line_offset()),
+        )
+    }
+
+    fn visual_lines(&self) -> (i32, i32) {
+        let buffer = self.buffer();
+        let anchor = buffer.iter_at_mark(&self.anchor).line();
+        let cursor = buffer.iter_at_mark(&self.cursor).line();
+        (anchor.min(cursor), anchor.max(cursor))
+    }
+
+    fn block_operator(&self, operator: Operator, register: Option<char>) {
+        let buffer = self.buffer();
+        let (first_line, last_line, left, right) = self.block_bounds();
+        self.exit_visual();
+        if operator == Operator::Indent || operator == Operator::Dedent {
+            self.shift_lines(first_line, last_line, operator == Operator::Indent, 1);
+            return;
+        }
+        let mut pieces = Vec::new();
+        for line in (first_line..=last_line).rev() {
+            let Some(mut start) = buffer.iter_at_line_offset(line, left) else {
+                pieces.push(String::new());
+                continue;
+            };
+            let mut end = start;
+            while !end.ends_line() && end.line_offset() <= right {
+                end.forward_char();
+            }
+            pieces.push(buffer.text(&start, &end, true).to_string());
+            if operator != Operator::Yank {
+                buffer.delete(&mut start, &mut end);
+            }
+        }
+        pieces.reverse();
+        self.store_register(
+            register,
+            pieces.join("\n"),
+            false,
+            operator == Operator::Yank,
+        );
+        buffer.place_cursor(&iter_at_line_offset(&buffer, first_line, left));
+        if operator == Operator::Change {
+            self.state.borrow_mut().block_insert = Some(BlockInsert {
+                first_line: first_line + 1,
+                last_line,
+                column: left,
+            });
+            self.start_insert();
+        }
+    }
+
+    fn put(&self, before: bool, register: Option<char>, count: usize) {
+        let buffer = self.buffer();
+        if matches!(register, Some('+' | '*')) {
+            self.text_view.emit_paste_clipboard();
+            return;
+        }
+        let name = register
+            .map(|name| name.to_ascii_lowercase())
+            .unwrap_or('"');
+        let Some(value) = self.state.borrow().registers.get(&name).cloned() else {
+            return;
+        };
+        let text = value.text.repeat(count);
+        let cursor = self.cursor_iter();
+        if value.linewise {
+            let line = cursor.line();
+            let mut at = iter_at_line_offset(&buffer, line, 0);
+            let target_line = if before {
+                buffer.insert(&mut at, &text);
+                line
+            } else if at.forward_line() {
+                buffer.insert(&mut at, &text);
+                line + 1
+            } else {
+                let mut end = buffer.end_iter();
+                buffer.insert(&mut end, &("\n".to_owned() + text.trim_end_matches('\n')));
+                line + 1
+            };
+            let line_start = iter_at_line_offset(&buffer, target_line, 0);
+            buffer.place_cursor(&first_non_blank(&line_start));
+        } else {
+            let mut at = cursor;
+            if !before && !at.ends_line() {
+                at.forward_char();
+            }
+            buffer.insert(&mut at, &text);
+            at.backward_char();
+            buffer.place_cursor(&at);
+        }
+    }
+
+    fn toggle_case(&self, count: usize) {
+        let buffer = self.buffer();
+        let (mut start, mut end) = if self.mode().is_visual() {
+            let anchor = buffer.iter_at_mark(&self.anchor);
+            let cursor = self.cursor_iter();
+            let (start, mut end) = if anchor.offset() <= cursor.offset() {
+                (anchor, cursor)
+            } else {
+                (cursor, anchor)
+            };
+            end.forward_char();
+            self.exit_visual();
+            (start, end)
+        } else {
+            let start = self.cursor_iter();
+            let mut end = start;
+            for _ in 0..count {
+                if end.ends_line() {
+                    break;
+                }
+                end.forward_char();
+            }
+            (start, end)
+        };
+        let toggled: String = buffer
+            .text(&start, &end, true)
+            .chars()
+            .map(|character| {
+                if character.is_uppercase() {
+                    character.to_lowercase().collect::<String>()
+                } else {
+                    character.to_uppercase().collect::<String>()
+                }
+            })
+            .collect();
+        let offset = start.offset();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &toggled);
+        buffer.place_cursor(&buffer.iter_at_offset(offset + toggled.chars().count() as i32));
+    }
+
+    fn text_object(
+        &self,
+        object: TextObject,
+        around: bool,
+        cursor: &TextIter,
+    ) -> Option<(TextIter, TextIter)> {
+        match object {
+            TextObject::Word(big) => {
+                let class = char_class(cursor.char(), big);
+                let mut start = *cursor;
+                let mut end = *cursor;
+                loop {
+                    let mut previous = start;
+                    if start.starts_line()
+                        || !previous.backward_char()
+                        || char_class(previous.char(), big) != class
+                    {
+                        break;
+                    }
+                    start = previous;
+                }
+                while !end.ends_line() && char_class(end.char(), big) == class {
+                    end.forward_char();
+                }
+                if around {
+                    if class == 0 {
+                        let next_class = char_class(end.char(), big);
+                        while !end.ends_line() && char_class(end.char(), big) == next_class {
+                            end.forward_char();
+                        }
+                    } else {
+                        let mut trailing = end;
+                        while !trailing.ends_line() && char_class(trailing.char(), big) == 0 {
+                            trailing.forward_char();
+                        }
+                        if trailing != end {
+                            end = trailing;
+                        } else {
+                            loop {
+                                let mut previous = start;
+                                if start.starts_line()
+                                    || !previous.backward_char()
+                                    || char_class(previous.char(), big) != 0
+                                {
+                                    break;
+                                }
+                                start = previous;
+                            }
+                        }
+                    }
+                }
+                (start != end).then_some((start, end))
+            }
+            TextObject::Quote(quote) => {
+                let buffer = self.buffer();
+                let line_start = iter_at_line_offset(&buffer, cursor.line(), 0);
+                let text = buffer.text(&line_start, &line_end(cursor), true);
+                let mut quotes = Vec::new();
+                let mut escaped = false;
+                for (offset, character) in text.chars().enumerate() {
+                    if character == quote && !escaped {
+                        quotes.push(offset as i32);
+                    }
+                    escaped = character == '\\' && !escaped;
+                }
+                let column = cursor.line_offset();
+                let (open, close) = quotes
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .find(|(open, close)| *open <= column && column <= *close)
+                    .or_else(|| {
+                        quotes
+                            .chunks_exact(2)
+                            .map(|pair| (pair[0], pair[1]))
+                            .find(|(open, _)| *open > column)
+                    })?;
+                let line = cursor.line();
+                if around {
+                    Some((
+                        iter_at_line_offset(&buffer, line, open),
+                        iter_at_line_offset(&buffer, line, close + 1),
+                    ))
+                } else {
+                    Some((
+                        iter_at_line_offset(&buffer, line, open + 1),
+                        iter_at_line_offset(&buffer, line, close),
+                    ))
+                }
+            }
+            TextObject::Pair(open, close) => {
+                let mut start = *cursor;
+                let mut depth = 0;
+                loop {
+                    let character = start.char();
+                    if character == open {
+                        if depth == 0 {
+                            break;
+                        }
+                        depth -= 1;
+                    } else if character == close && start != *cursor {
+                        depth += 1;
+                    }
+                    if !start.backward_char() {
+                        return None;
+                    }
+                }
+                let mut end = start;
+                depth = 0;
+                loop {
+                    if !end.forward_char() || end.is_end() {
+                        return None;
+                    }
+                    let character = end.char();
+                    if character == open {
+                        depth += 1;
+                    } else if character == close {
+                        if depth == 0 {
+                            break;
+                        }
+                        depth -= 1;
+                    }
+                }
+                if around {
+                    end.forward_char();
+                } else {
+                    start.forward_char();
+                    trim_inner_block(&mut start, &mut end);
+                }
+                Some((start, end))
+            }
+            TextObject::Function | TextObject::Block => {
+                let buffer = self.buffer();
+                let content = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+                let Some(tree) = syntax::parse_qat(&content) else {
+                    return self.text_object(TextObject::Pair('{', '}'), around, cursor);
+                };
+                let point = Point {
+                    row: cursor.line() as usize,
+                    column: cursor.line_index() as usize,
+                };
+                let mut node = tree.root_node().descendant_for_point_range(point, point);
+                while let Some(current) = node {
+                    let found = match object {
+                        TextObject::Function => matches!(
+                            current.kind(),
+                            "function_definition" | "prerun_function_definition" | "method"
+                        ),
+                        _ => is_brace_block(&current),
+                    };
+                    if found {
+                        break;
+                    }
+                    node = current.parent();
+                }
+                let Some(node) = node else {
+                    if object == TextObject::Block {
+                        return self.text_object(TextObject::Pair('{', '}'), around, cursor);
+                    }
+                    return None;
+                };
+                if around {
+                    return Some((
+                        iter_at_point(&buffer, node.start_position()),
+                        iter_at_point(&buffer, node.end_position()),
+                    ));
+                }
+                if is_brace_block(&node) {
+                    return Some(inner_block(&buffer, &node));
+                }
+                let mut tree_cursor = node.walk();
+                let body = node
+                    .children(&mut tree_cursor)
+                    .filter(is_brace_block)
+                    .last();
+                match body {
+                    Some(body) => Some(inner_block(&buffer, &body)),
+                    None => Some((
+                        iter_at_point(&buffer, node.start_position()),
+                        iter_at_point(&buffer, node.end_position()),
+                    )),
+                }
+            }
+        }
+    }
+
+    fn enter_visual(&self, mode: Mode) {
+        let buffer = self.buffer();
+        let cursor = self.cursor_iter();
+        if !self.mode().is_visual() {
+            buffer.move_mark(&self.anchor, &cursor);
+            buffer.move_mark(&self.cursor, &cursor);
+        }
+        self.state.borrow_mut().mode = mode;
+        self.update_selection();
+    }
+
+    fn exit_visual(&self) {
+        let buffer = self.buffer();
+        let cursor = buffer.iter_at_mark(&self.cursor);
+        buffer.remove_tag(&self.block_tag, &buffer.start_iter(), &buffer.end_iter());
+        self.state.borrow_mut().mode = Mode::Normal;
+        buffer.place_cursor(&cursor);
+    }
+
+    fn update_selection(&self) {
+        let buffer = self.buffer();
+        let anchor = buffer.iter_at_mark(&self.anchor);
+        let cursor = buffer.iter_at_mark(&self.cursor);
+        buffer.remove_tag(&self.block_tag, &buffer.start_iter(), &buffer.end_iter());
+        match self.mode() {
+            Mode::Visual => {
+                let (mut insert, mut bound) = (cursor, anchor);
+                if cursor.offset() >= anchor.offset() {
+                    insert.forward_char();
+                } else {
+                    bound.forward_char();
+                }
+                buffer.select_range(&insert, &bound);
+            }
+            Mode::VisualLine => {
+                let (mut insert, mut bound) = (cursor, anchor);
+                insert.set_line_offset(0);
+                bound.set_line_offset(0);
+                if cursor.line() >= anchor.line() {
+                    insert.forward_line();
+                } else {
+                    bound.forward_line();
+                }
+                buffer.select_range(&insert, &bound);
+            }
+            Mode::VisualBlock => {
+                buffer.place_cursor(&cursor);
+                let (first_line, last_line, left, right) = self.block_bounds();
+                for line in first_line..=last_line {
+                    if let Some(start) = buffer.iter_at_line_offset(line, left) {
+                        let mut end = start;
+                        while !end.ends_line() && end.line_offset() <= right {
+                            end.forward_char();
+                        }
+                        buffer.apply_tag(&self.block_tag, &start, &end);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn enter_insert(&self, at: InsertAt) {
+        let buffer = self.buffer();
+        let mut cursor = self.cursor_iter();
+        match at {
+            InsertAt::Cursor => {}
+            InsertAt::After => {
+                if !cursor.ends_line() {
+                    cursor.forward_char();
+                }
+            }
+            InsertAt::LineStart => cursor = first_non_blank(&cursor),
+            InsertAt::LineEnd => cursor = line_end(&cursor),
+            InsertAt::LineBelow => {
+                let indent = indentation(&cursor);
+                cursor = line_end(&cursor);
+                buffer.insert(&mut cursor, &("\n".to_owned() + &indent));
+            }
+            InsertAt::LineAbove => {
+                let indent = indentation(&cursor);
+                cursor.set_line_offset(0);
+                buffer.insert(&mut cursor, &(indent + "\n"));
+                cursor.backward_char();
+            }
+        }
+        buffer.place_cursor(&cursor);
+        self.start_insert();
+    }
+
+    fn start_insert(&self) {
+        let buffer = self.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        buffer.move_mark(&self.insert_start, &cursor);
+        self.state.borrow_mut().mode = Mode::Insert;
+        self.text_view.set_overwrite(false);
+        self.update_status();
+    }
+
+    fn leave_insert(&self) {
+        let buffer = self.buffer();
+        let start = buffer.iter_at_mark(&self.insert_start);
+        let mut cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let inserted = if start.offset() <= cursor.offset() {
+            buffer.text(&start, &cursor, true).to_string()
+        } else {
+            String::new()
+        };
+        let (count, block_insert, replaying) = {
+            let mut state = self.state.borrow_mut();
+            (
+                std::mem::replace(&mut state.insert_count, 1),
+                state.block_insert.take(),
+                state.replaying,
+            )
+        };
+        buffer.begin_user_action();
+        if count > 1 && !inserted.is_empty() {
+            buffer.insert(&mut cursor, &inserted.repeat(count - 1));
+        }
+        if let Some(block) = block_insert
+            && !inserted.contains('\n')
+        {
+            for line in block.first_line..=block.last_line {
+                if let Some(mut at) = buffer.iter_at_line_offset(line, block.column) {
+                    buffer.insert(&mut at, &inserted);
+                }
+            }
+        }
+        buffer.end_user_action();
+        {
+            let mut state = self.state.borrow_mut();
+            if !replaying {
+                state.last_change = std::mem::take(&mut state.change_keys);
+                state.last_insert = Some(inserted);
+            }
+            state.mode = Mode::Normal;
+        }
+        let mut cursor = buffer.iter_at_mark(&buffer.get_insert());
+        if !cursor.starts_line() {
+            cursor.backward_char();
+            buffer.place_cursor(&cursor);
+        }
+        self.text_view.set_overwrite(true);
+        self.update_status();
+    }
+
+    fn repeat(&self) {
+        let (keys, insert) = {
+            let mut state = self.state.borrow_mut();
+            state.replaying = true;
+            (state.last_change.clone(), state.last_insert.clone())
+        };
+        for key in keys {
+            self.normal_key(key);
+        }
+        if self.mode() == Mode::Insert {
+            if let Some(text) = insert {
+                self.buffer().insert_at_cursor(&text);
+            }
+            self.leave_insert();
+        }
+        self.state.borrow_mut().replaying = false;
+    }
+
+    fn command_key(&self, key: Key) {
+        match key {
+            Key::Escape => self.state.borrow_mut().mode = Mode::Normal,
+            Key::Backspace => {
+                let mut state = self.state.borrow_mut();
+                if state.command_line.pop().is_none() {
+                    state.mode = Mode::Normal;
+                }
+            }
+            Key::Enter => {
+                let line = {
+                    let mut state = self.state.borrow_mut();
+                    state.mode = Mode::Normal;
+                    std::mem::take(&mut state.command_line)
+                };
+                self.execute_ex(&line);
+                self.clamp_cursor();
+            }
+            Key::Char(character) => self.state.borrow_mut().command_line.push(character),
+            Key::Ctrl(_) => {}
+        }
+        self.update_status();
+    }
+
+    fn save(&self) -> bool {
+        match self.document.save() {
+            Ok(()) => {
+                self.set_message(&format!("\"{}\" written", self.document.path().display()));
+                true
+            }
+            Err(error) => {
+                self.set_message(&format!("Could not write file: {}", error));
+                false
+            }
+        }
+    }
+
+    fn close_window(&self) {
+        if let Some(window) = self.text_view.root().and_downcast::<gtk4::Window>() {
+            window.close();
+        }
+    }
+
+    fn execute_ex(&self, line: &str) {
+        let buffer = self.buffer();
+        let line = line.trim();
+        match line {
+            "" => {}
+            "w" => {
+                self.save();
+            }
+            "q" if buffer.is_modified() => {
+                self.set_message("E37: No write since last change (add ! to override)")
+            }
+            "q" | "q!" => self.close_window(),
+            "wq" | "x" => {
+                if self.save() {
+                    self.close_window();
+                }
+            }
+            _ if line.chars().all(|character| character.is_ascii_digit()) => {
+                let number: i32 = line.parse().unwrap_or(1);
+                let target = iter_at_line_offset(&buffer, number - 1, 0);
+                self.set_cursor(&first_non_blank(&target));
+            }
+            _ => {
+                let current = buffer.iter_at_mark(&buffer.get_insert()).line();
+                let last = buffer.line_count() - 1;
+                let ex_range = self.state.borrow_mut().ex_range.take();
+                let (range, rest) = if let Some(rest) = line.strip_prefix('%') {
+                    ((0, last), rest)
+                } else if let Some(rest) = line.strip_prefix("'<,'>") {
+                    (ex_range.unwrap_or((current, current)), rest)
+                } else if let Some((first, rest)) = line.split_once(',') {
+                    let split = rest
+                        .find(|character: char| !character.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    match (first.parse::<i32>(), rest[..split].parse::<i32>()) {
+                        (Ok(first), Ok(second)) => ((first - 1, second - 1), &rest[split..]),
+                        _ => ((current, current), line),
+                    }
+                } else {
+                    ((current, current), line.strip_prefix('.').unwrap_or(line))
+                };
+                match rest.strip_prefix('s') {
+                    Some(spec) if !spec.is_empty() => match self.substitute(range, spec) {
+                        Ok(lines) => self.set_message(&format!("{} lines changed", lines)),
+                        Err(error) => self.set_message(&error),
+                    },
+                    _ => self.set_message(&format!("Not an editor command: {}", line)),
+                }
+            }
+        }
+    }
+
+    fn substitute(&self, range: (i32, i32), spec: &str) -> Result<usize, String> {
+        let buffer = self.buffer();
+        let mut characters = spec.chars();
+        let delimiter = characters.next().ok_or("Missing substitute pattern")?;
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for character in characters {
+            if escaped {
+                if character != delimiter {
+                    parts.last_mut().unwrap().push('\\');
+                }
+                parts.last_mut().unwrap().push(character);
+                escaped = false;
+            } else if character == '\\' {
+                escaped = true;
+            } else if character == delimiter {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(character);
+            }
+        }
+        let pattern = parts.first().cloned().unwrap_or_default();
+        let replacement = parts.get(1).cloned().unwrap_or_default();
+        let flags = parts.get(2).cloned().unwrap_or_default();
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(flags.contains('i'))
+            .build()
+            .map_err(|error| format!("Invalid pattern: {}", error))?;
+        let mut converted = String::new();
+        let mut replacement_characters = replacement.chars().peekable();
+        while let Some(character) = replacement_characters.next() {
+            match character {
+                '\\' => match replacement_characters.next() {
+                    Some(digit @ '0'..='9') => converted.push_str(&format!("${{{}}}", digit)),
+                    Some('n') => converted.push('\n'),
+                    Some('t') => converted.push('\t'),
+                    Some('&') => converted.push('&'),
+                    Some(other) => converted.push(other),
+                    None => converted.push('\\'),
+                },
+                '&' => converted.push_str("${0}"),
+                '$' => converted.push_str("$$"),
+                other => converted.push(other),
+            }
+        }
+        let (first, last) = (
+            range.0.clamp(0, buffer.line_count() - 1),
+            range.1.clamp(0, buffer.line_count() - 1),
+        );
+        let mut start = iter_at_line_offset(&buffer, first, 0);
+        let mut end = line_end(&iter_at_line_offset(&buffer, last, 0));
+        let text = buffer.text(&start, &end, true);
+        let mut changed = 0;
+        let lines: Vec<String> = text
+            .split('\n')
+            .map(|line| {
+                let replaced = if flags.contains('g') {
+                    regex.replace_all(line, converted.as_str())
+                } else {
+                    regex.replace(line, converted.as_str())
+                };
+                if replaced != line {
+                    changed += 1;
+                }
+                replaced.into_owned()
+            })
+            .collect();
+        if changed == 0 {
+            return Err(format!("Pattern not found: {}", pattern));
+        }
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &lines.join("\n"));
+        buffer.end_user_action();
+        buffer.place_cursor(&first_non_blank(&iter_at_line_offset(&buffer, first, 0)));
+        Ok(changed)
+    }
+}
+
+pub fn setup_vim(
+    text_view: &TextView,
+    status: &Label,
+    document: &Rc<Document>,
+    enabled: bool,
+) -> Rc<Vim> {
+    let buffer = text_view.buffer();
+    let start = buffer.start_iter();
+    let vim = Rc::new(Vim {
+        text_view: text_view.clone(),
+        status: status.clone(),
+        document: document.clone(),
+        state: RefCell::new(VimState {
+            enabled,
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            registers: HashMap::new(),
+            last_find: None,
+            last_change: Vec::new(),
+            last_insert: None,
+            change_keys: Vec::new(),
+            insert_count: 1,
+            block_insert: None,
+            command_line: String::new(),
+            ex_range: None,
+            preferred_column: None,
+            replaying: false,
+            message: String::new(),
+        }),
+        anchor: buffer.create_mark(Some("vim_anchor"), &start, false),
+        cursor: buffer.create_mark(Some("vim_cursor"), &start, false),
+        insert_start: buffer.create_mark(Some("vim_insert_start"), &start, true),
+        block_tag: buffer
+            .create_tag(Some("vim_block"), &[("background", &"#ffffff33")])
+            .expect("Could not create tag for visual block selection"),
+    });
+    let controller = EventControllerKey::new();
+    controller.set_propagation_phase(PropagationPhase::Capture);
+    let handler = vim.clone();
+    controller
+        .connect_key_pressed(move |_, keyval, _, modifiers| handler.key_pressed(keyval, modifiers));
+    text_view.add_controller(controller);
+    vim.set_enabled(enabled);
+    vim
+}
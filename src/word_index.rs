@@ -0,0 +1,156 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use gtk4::{TextBuffer, TextIter, glib, prelude::*};
+
+const MIN_WORD_LENGTH: usize = 2;
+
+struct Source {
+    buffer: glib::WeakRef<TextBuffer>,
+    counts: HashMap<String, u32>,
+    pending: Option<(i32, i32)>,
+}
+
+#[derive(Default)]
+pub struct WordIndex {
+    sources: RefCell<Vec<Source>>,
+}
+
+fn is_word_char(character: char) -> bool {
+    character.is_alphanumeric() || character == '_'
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|character: char| !is_word_char(character))
+        .filter(|word| {
+            word.chars().count() >= MIN_WORD_LENGTH
+                && !word.starts_with(|first: char| first.is_numeric())
+        })
+}
+
+fn lines_text(buffer: &TextBuffer, first: i32, last: i32) -> String {
+    let start = buffer
+        .iter_at_line(first)
+        .unwrap_or_else(|| buffer.end_iter());
+    let mut end = buffer
+        .iter_at_line(last)
+        .unwrap_or_else(|| buffer.end_iter());
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    buffer.text(&start, &end, true).to_string()
+}
+
+impl Source {
+    fn add(&mut self, text: &str) {
+        for word in words(text) {
+            *self.counts.entry(word.to_owned()).or_insert(0) += 1;
+        }
+    }
+
+    fn remove(&mut self, text: &str) {
+        for word in words(text) {
+            if let Some(count) = self.counts.get_mut(word) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(word);
+                }
+            }
+        }
+    }
+}
+
+impl WordIndex {
+    pub fn new() -> Rc<WordIndex> {
+        Rc::new(WordIndex::default())
+    }
+
+    fn with_source(&self, buffer: &TextBuffer, action: impl FnOnce(&mut Source)) {
+        let mut sources = self.sources.borrow_mut();
+        if let Some(source) = sources
+            .iter_mut()
+            .find(|source| source.buffer.upgrade().as_ref() == Some(buffer))
+        {
+            action(source);
+        }
+    }
+
+    fn before_change(&self, buffer: &TextBuffer, start: &TextIter, end: &TextIter, after: i32) {
+        let (first, last) = (start.line(), end.line());
+        let text = lines_text(buffer, first, last);
+        self.with_source(buffer, |source| {
+            source.remove(&text);
+            source.pending = Some((first, first + after));
+        });
+    }
+
+    fn after_change(&self, buffer: &TextBuffer) {
+        let mut pending = None;
+        self.with_source(buffer, |source| pending = source.pending.take());
+        if let Some((first, last)) = pending {
+            let text = lines_text(buffer, first, last);
+            self.with_source(buffer, |source| source.add(&text));
+        }
+    }
+
+    pub fn add_buffer(self: &Rc<Self>, buffer: &TextBuffer) {
+        let mut source = Source {
+            buffer: buffer.downgrade(),
+            counts: HashMap::new(),
+            pending: None,
+        };
+        source.add(&buffer.text(&buffer.start_iter(), &buffer.end_iter(), true));
+        self.sources.borrow_mut().push(source);
+        let weak = Rc::downgrade(self);
+        buffer.connect_insert_text(move |buffer, iter, text| {
+            if let Some(index) = weak.upgrade() {
+                let added = text.matches('\n').count() as i32;
+                index.before_change(buffer, iter, iter, added);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        buffer.connect_delete_range(move |buffer, start, end| {
+            if let Some(index) = weak.upgrade() {
+                index.before_change(buffer, start, end, 0);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        buffer.connect_changed(move |buffer| {
+            if let Some(index) = weak.upgrade() {
+                index.after_change(buffer);
+            }
+        });
+    }
+
+    pub fn words(&self, current: &TextBuffer, typed: &str) -> Vec<(String, bool)> {
+        let mut sources = self.sources.borrow_mut();
+        sources.retain(|source| source.buffer.upgrade().is_some());
+        let first = typed
+            .chars()
+            .next()
+            .and_then(|first| first.to_lowercase().next());
+        let matches_first = |word: &str| match first {
+            Some(first) => {
+                word.chars()
+                    .next()
+                    .and_then(|character| character.to_lowercase().next())
+                    == Some(first)
+            }
+            None => true,
+        };
+        let mut found: HashMap<String, bool> = HashMap::new();
+        for source in sources.iter() {
+            let in_current = source.buffer.upgrade().as_ref() == Some(current);
+            for (word, count) in &source.counts {
+                if in_current && word == typed && *count == 1 {
+                    continue;
+                }
+                if !matches_first(word) {
+                    continue;
+                }
+                let entry = found.entry(word.clone()).or_insert(false);
+                *entry |= in_current;
+            }
+        }
+        found.into_iter().collect()
+    }
+}
@@ -0,0 +1,699 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque, hash_map::DefaultHasher},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use gtk4::{
+    EventSequenceState, GestureClick, Label, PropagationPhase, TextIter, TextView, TextWindowType,
+    gdk, gio,
+    glib::{self, ControlFlow},
+    prelude::*,
+};
+use tree_sitter::Node;
+
+use crate::{
+    commands::{Argument, CommandRegistry},
+    document::Document,
+    outline::{self, Symbol},
+    results::{self, Location, ResultItem, ResultsPanel},
+    settings, syntax,
+};
+
+const CACHE_VERSION: &str = "moon-symbols 1";
+const FILES_PER_TICK: usize = 20;
+const ENTRIES_PER_BATCH: i32 = 100;
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+struct Definition {
+    name: String,
+    kind: String,
+    container: String,
+    line: i32,
+    column: i32,
+}
+
+impl Definition {
+    fn qualified_name(&self) -> String {
+        if self.container.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.container, self.name)
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Reference {
+    name: String,
+    line: i32,
+    column: i32,
+}
+
+#[derive(Default)]
+struct FileSymbols {
+    modified: u128,
+    definitions: Vec<Definition>,
+    references: Vec<Reference>,
+}
+
+pub struct Workspace {
+    root: PathBuf,
+    document: Rc<Document>,
+    text_view: TextView,
+    status: Label,
+    results: Rc<ResultsPanel>,
+    commands: CommandRegistry,
+    files: RefCell<HashMap<PathBuf, FileSymbols>>,
+    queue: RefCell<VecDeque<PathBuf>>,
+    indexing: Cell<bool>,
+    monitors: RefCell<Vec<gio::FileMonitor>>,
+    save_pending: Rc<Cell<bool>>,
+}
+
+pub fn find_root(path: &Path) -> PathBuf {
+    let start = path.parent().unwrap_or(Path::new("/"));
+    start
+        .ancestors()
+        .find(|directory| directory.join(".git").exists())
+        .unwrap_or(start)
+        .to_path_buf()
+}
+
+fn modified_time(path: &Path) -> u128 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0)
+}
+
+fn is_qat_file(path: &Path) -> bool {
+    path.extension().and_then(|extension| extension.to_str()) == Some("qat")
+}
+
+fn is_skipped_directory(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.') || name == "target")
+}
+
+fn flatten_definitions(symbols: &[Symbol], container: &str, definitions: &mut Vec<Definition>) {
+    for symbol in symbols {
+        definitions.push(Definition {
+            name: symbol.name.clone(),
+            kind: symbol.kind.to_owned(),
+            container: container.to_owned(),
+            line: symbol.name_start.row as i32,
+            column: symbol.name_start.column as i32,
+        });
+        let nested = if container.is_empty() {
+            symbol.name.clone()
+        } else {
+            format!("{}.{}", container, symbol.name)
+        };
+        flatten_definitions(&symbol.children, &nested, definitions);
+    }
+}
+
+fn collect_references(node: Node, content: &str, references: &mut Vec<Reference>) {
+    if matches!(node.kind(), "entity" | "member_access")
+        && let Some(name) = node.child_by_field_name("name")
+        && let Some(text) = content.get(name.byte_range())
+        && !syntax::BUILTIN_TYPES.contains(&text)
+        && !syntax::KEYWORDS.contains(&text)
+    {
+        references.push(Reference {
+            name: text.to_owned(),
+            line: name.start_position().row as i32,
+            column: name.start_position().column as i32,
+        });
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_references(child, content, references);
+    }
+}
+
+fn index_content(content: &str, modified: u128) -> FileSymbols {
+    let mut symbols = FileSymbols {
+        modified,
+        ..FileSymbols::default()
+    };
+    if let Some(tree) = syntax::parse_qat(content) {
+        flatten_definitions(
+            &outline::symbols(&tree, content),
+            "",
+            &mut symbols.definitions,
+        );
+        collect_references(tree.root_node(), content, &mut symbols.references);
+    }
+    symbols
+}
+
+fn word_at(iter: &TextIter) -> Option<String> {
+    let is_word = |character: char| character.is_alphanumeric() || character == '_';
+    let mut start = *iter;
+    while start.backward_char() {
+        if !is_word(start.char()) {
+            start.forward_char();
+            break;
+        }
+    }
+    let mut end = *iter;
+    while is_word(end.char()) && end.forward_char() {}
+    let word = start.text(&end).to_string();
+    (!word.is_empty()).then_some(word)
+}
+
+impl Workspace {
+    pub fn new(
+        text_view: &TextView,
+        document: &Rc<Document>,
+        status: &Label,
+        results: &Rc<ResultsPanel>,
+        commands: &CommandRegistry,
+    ) -> Rc<Workspace> {
+        let workspace = Rc::new(Workspace {
+            root: find_root(&document.path()),
+            document: document.clone(),
+            text_view: text_view.clone(),
+            status: status.clone(),
+            results: results.clone(),
+            commands: commands.clone(),
+            files: RefCell::new(HashMap::new()),
+            queue: RefCell::new(VecDeque::new()),
+            indexing: Cell::new(false),
+            monitors: RefCell::new(Vec::new()),
+            save_pending: Rc::new(Cell::new(false)),
+        });
+        workspace.load_cache();
+        workspace.scan(&workspace.root, true);
+        workspace.update_choices();
+        let gesture = GestureClick::builder().button(1).build();
+        gesture.set_propagation_phase(PropagationPhase::Capture);
+        let weak = Rc::downgrade(&workspace);
+        gesture.connect_pressed(move |gesture, _, x, y| {
+            if !gesture
+                .current_event_state()
+                .contains(gdk::ModifierType::CONTROL_MASK)
+            {
+                return;
+            }
+            let Some(workspace) = weak.upgrade() else {
+                return;
+            };
+            let (x, y) = workspace.text_view.window_to_buffer_coords(
+                TextWindowType::Widget,
+                x as i32,
+                y as i32,
+            );
+            let Some(iter) = workspace.text_view.iter_at_location(x, y) else {
+                return;
+            };
+            gesture.set_state(EventSequenceState::Claimed);
+            workspace.text_view.buffer().place_cursor(&iter);
+            workspace.go_to_definition();
+        });
+        text_view.add_controller(gesture);
+        let weak = Rc::downgrade(&workspace);
+        results.set_opener(move |location| {
+            if let Some(workspace) = weak.upgrade() {
+                workspace.open(location);
+            }
+        });
+        let defining = workspace.clone();
+        commands.register("navigate.definition", "Go to Definition", move || {
+            defining.go_to_definition()
+        });
+        let referencing = workspace.clone();
+        commands.register("navigate.references", "Find All References", move || {
+            referencing.find_references()
+        });
+        workspace
+    }
+
+    fn cache_path(&self) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.root.hash(&mut hasher);
+        settings::cache_dir().join(format!("symbols-{:016x}.tsv", hasher.finish()))
+    }
+
+    fn load_cache(&self) {
+        let Ok(content) = fs::read_to_string(self.cache_path()) else {
+            return;
+        };
+        let mut lines = content.lines();
+        if lines.next() != Some(&format!("{}\t{}", CACHE_VERSION, self.root.display())) {
+            return;
+        }
+        let mut files = self.files.borrow_mut();
+        let mut current: Option<PathBuf> = None;
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["f", path, modified] => {
+                    let path = PathBuf::from(path);
+                    files.insert(
+                        path.clone(),
+                        FileSymbols {
+                            modified: modified.parse().unwrap_or(0),
+                            ..FileSymbols::default()
+                        },
+                    );
+                    current = Some(path);
+                }
+                ["d", name, kind, container, line, column] => {
+                    if let Some(symbols) = current.as_ref().and_then(|path| files.get_mut(path)) {
+                        symbols.definitions.push(Definition {
+                            name: (*name).to_owned(),
+                            kind: (*kind).to_owned(),
+                            container: (*container).to_owned(),
+                            line: line.parse().unwrap_or(0),
+                            column: column.parse().unwrap_or(0),
+                        });
+                    }
+                }
+                ["r", name, line, column] => {
+                    if let Some(symbols) = current.as_ref().and_then(|path| files.get_mut(path)) {
+                        symbols.references.push(Reference {
+                            name: (*name).to_owned(),
+                            line: line.parse().unwrap_or(0),
+                            column: column.parse().unwrap_or(0),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn save_cache(&self) {
+        let mut content = format!("{}\t{}\n", CACHE_VERSION, self.root.display());
+        for (path, symbols) in self.files.borrow().iter() {
+            content.push_str(&format!("f\t{}\t{}\n", path.display(), symbols.modified));
+            for definition in &symbols.definitions {
+                content.push_str(&format!(
+                    "d\t{}\t{}\t{}\t{}\t{}\n",
+                    definition.name,
+                    definition.kind,
+                    definition.container,
+                    definition.line,
+                    definition.column
+                ));
+            }
+            for reference in &symbols.references {
+                content.push_str(&format!(
+                    "r\t{}\t{}\t{}\n",
+                    reference.name, reference.line, reference.column
+                ));
+            }
+        }
+        let path = self.cache_path();
+        if let Some(parent) = path.parent()
+            && let Err(error) = fs::create_dir_all(parent)
+        {
+            eprintln!("Could not create {}: {}", parent.display(), error);
+            return;
+        }
+        if let Err(error) = fs::write(&path, content) {
+            eprintln!("Could not write {}: {}", path.display(), error);
+        }
+    }
+
+    fn schedule_save(self: &Rc<Self>) {
+        if self.save_pending.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::timeout_add_local_once(SAVE_DELAY, move || {
+            if let Some(workspace) = weak.upgrade() {
+                workspace.save_pending.set(false);
+                workspace.save_cache();
+                workspace.update_choices();
+            }
+        });
+    }
+
+    fn scan(self: &Rc<Self>, directory: &Path, prune: bool) {
+        let weak = Rc::downgrade(self);
+        let mut directories = vec![directory.to_path_buf()];
+        glib::spawn_future_local(async move {
+            let mut present = HashSet::new();
+            while let Some(directory) = directories.pop() {
+                let Ok(enumerator) = gio::File::for_path(&directory)
+                    .enumerate_children_future(
+                        "standard::name,standard::type",
+                        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+                        glib::Priority::LOW,
+                    )
+                    .await
+                else {
+                    continue;
+                };
+                match weak.upgrade() {
+                    Some(workspace) => workspace.watch(&directory),
+                    None => return,
+                }
+                while let Ok(entries) = enumerator
+                    .next_files_future(ENTRIES_PER_BATCH, glib::Priority::LOW)
+                    .await
+                    && !entries.is_empty()
+                {
+                    let mut files = Vec::new();
+                    for entry in entries {
+                        let path = directory.join(entry.name());
+                        if entry.file_type() == gio::FileType::Directory {
+                            if !is_skipped_directory(&path) {
+                                directories.push(path);
+                            }
+                        } else if is_qat_file(&path) {
+                            files.push(path);
+                        }
+                    }
+                    let Some(workspace) = weak.upgrade() else {
+                        return;
+                    };
+                    present.extend(files.iter().cloned());
+                    workspace.queue.borrow_mut().extend(files);
+                    workspace.process_queue();
+                }
+            }
+            if prune && let Some(workspace) = weak.upgrade() {
+                workspace
+                    .files
+                    .borrow_mut()
+                    .retain(|path, _| present.contains(path) || path.exists());
+                workspace.schedule_save();
+            }
+        });
+    }
+
+    fn watch(self: &Rc<Self>, directory: &Path) {
+        let monitor = match gio::File::for_path(directory)
+            .monitor_directory(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE)
+        {
+            Ok(monitor) => monitor,
+            Err(error) => {
+                eprintln!("Could not watch {}: {}", directory.display(), error);
+                return;
+            }
+        };
+        let weak = Rc::downgrade(self);
+        monitor.connect_changed(move |_, file, _, event| {
+            let (Some(workspace), Some(path)) = (weak.upgrade(), file.path()) else {
+                return;
+            };
+            match event {
+                gio::FileMonitorEvent::Deleted => {
+                    let mut files = workspace.files.borrow_mut();
+                    let count = files.len();
+                    files.retain(|indexed, _| !indexed.starts_with(&path));
+                    let removed = files.len() != count;
+                    drop(files);
+                    if removed {
+                        workspace.schedule_save();
+                    }
+                }
+                gio::FileMonitorEvent::Created if path.is_dir() => {
+                    if is_skipped_directory(&path) {
+                        return;
+                    }
+                    workspace.scan(&path, false);
+                }
+                gio::FileMonitorEvent::Created | gio::FileMonitorEvent::ChangesDoneHint
+                    if is_qat_file(&path) =>
+                {
+                    let mut queue = workspace.queue.borrow_mut();
+                    if !queue.contains(&path) {
+                        queue.push_back(path);
+                    }
+                    drop(queue);
+                    workspace.process_queue();
+                }
+                _ => {}
+            }
+        });
+        self.monitors.borrow_mut().push(monitor);
+    }
+
+    fn process_queue(self: &Rc<Self>) {
+        if self.indexing.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local(move || {
+            let Some(workspace) = weak.upgrade() else {
+                return ControlFlow::Break;
+            };
+            for _ in 0..FILES_PER_TICK {
+                let Some(path) = workspace.queue.borrow_mut().pop_front() else {
+                    break;
+                };
+                let modified = modified_time(&path);
+                if workspace
+                    .files
+                    .borrow()
+                    .get(&path)
+                    .is_some_and(|symbols| modified != 0 && symbols.modified == modified)
+                {
+                    continue;
+                }
+                match fs::read_to_string(&path) {
+                    Ok(content) => {
+                        let symbols = index_content(&content, modified);
+                        workspace.files.borrow_mut().insert(path, symbols);
+                    }
+                    Err(_) => {
+                        workspace.files.borrow_mut().remove(&path);
+                    }
+                }
+            }
+            if workspace.queue.borrow().is_empty() {
+                workspace.indexing.set(false);
+                workspace.schedule_save();
+                return ControlFlow::Break;
+            }
+            ControlFlow::Continue
+        });
+    }
+
+    fn refresh_current(&self) {
+        let path = self.document.path();
+        if !is_qat_file(&path) || !path.starts_with(&self.root) {
+            return;
+        }
+        let modified = if self.document.buffer.is_modified() {
+            0
+        } else {
+            modified_time(&path)
+        };
+        let symbols = index_content(&self.document.text(), modified);
+        self.files.borrow_mut().insert(path, symbols);
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    fn line_text(
+        &self,
+        path: &Path,
+        line: i32,
+        cache: &mut HashMap<PathBuf, Vec<String>>,
+    ) -> String {
+        if path == self.document.path() {
+            let buffer = &self.document.buffer;
+            let Some(start) = buffer.iter_at_line(line) else {
+                return String::new();
+            };
+            let mut end = start;
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            return buffer.text(&start, &end, true).to_string();
+        }
+        let lines = cache.entry(path.to_path_buf()).or_insert_with(|| {
+            fs::read_to_string(path)
+                .map(|content| content.lines().map(str::to_owned).collect())
+                .unwrap_or_default()
+        });
+        lines.get(line as usize).cloned().unwrap_or_default()
+    }
+
+    fn open(&self, location: &Location) {
+        results::open_location(&self.document, &self.text_view, &self.status, location);
+    }
+
+    fn definitions_where(
+        &self,
+        matches: impl Fn(&Definition) -> bool,
+    ) -> Vec<(PathBuf, Definition)> {
+        let current = self.document.path();
+        let mut found: Vec<(PathBuf, Definition)> = self
+            .files
+            .borrow()
+            .iter()
+            .flat_map(|(path, symbols)| {
+                symbols
+                    .definitions
+                    .iter()
+                    .filter(|definition| matches(definition))
+                    .map(|definition| (path.clone(), definition.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        found.sort_by(|(path, definition), (other_path, other)| {
+            (*path != current)
+                .cmp(&(*other_path != current))
+                .then_with(|| path.cmp(other_path))
+                .then_with(|| definition.line.cmp(&other.line))
+        });
+        found
+    }
+
+    fn show_definitions(&self, title: &str, definitions: Vec<(PathBuf, Definition)>) {
+        if let [(path, definition)] = definitions.as_slice() {
+            self.open(&Location {
+                path: path.clone(),
+                line: definition.line,
+                column: definition.column,
+            });
+            return;
+        }
+        let mut cache = HashMap::new();
+        let items = definitions
+            .into_iter()
+            .map(|(path, definition)| ResultItem {
+                detail: format!(
+                    "{}:{}  {}",
+                    self.relative(&path),
+                    definition.line + 1,
+                    definition.kind
+                ),
+                text: self.line_text(&path, definition.line, &mut cache),
+                location: Location {
+                    path,
+                    line: definition.line,
+                    column: definition.column,
+                },
+            })
+            .collect();
+        self.results.show(title, items);
+    }
+
+    fn word_at_cursor(&self) -> Option<String> {
+        let buffer = self.text_view.buffer();
+        word_at(&buffer.iter_at_mark(&buffer.get_insert()))
+    }
+
+    pub fn go_to_definition(&self) {
+        let Some(word) = self.word_at_cursor() else {
+            return;
+        };
+        self.refresh_current();
+        let definitions = self.definitions_where(|definition| definition.name == word);
+        if definitions.is_empty() {
+            self.status
+                .set_text(&format!("No definition found for {}", word));
+            return;
+        }
+        self.show_definitions(&format!("Definitions of {}", word), definitions);
+    }
+
+    pub fn find_references(&self) {
+        let Some(word) = self.word_at_cursor() else {
+            return;
+        };
+        self.refresh_current();
+        let mut locations: Vec<(PathBuf, i32, i32, &str)> = Vec::new();
+        for (path, symbols) in self.files.borrow().iter() {
+            for definition in &symbols.definitions {
+                if definition.name == word {
+                    locations.push((
+                        path.clone(),
+                        definition.line,
+                        definition.column,
+                        "definition",
+                    ));
+                }
+            }
+            for reference in &symbols.references {
+                if reference.name == word {
+                    locations.push((path.clone(), reference.line, reference.column, ""));
+                }
+            }
+        }
+        locations.sort();
+        locations.dedup_by(|location, other| {
+            location.0 == other.0 && location.1 == other.1 && location.2 == other.2
+        });
+        let mut cache = HashMap::new();
+        let count = locations.len();
+        let items = locations
+            .into_iter()
+            .map(|(path, line, column, kind)| ResultItem {
+                detail: format!("{}:{}  {}", self.relative(&path), line + 1, kind)
+                    .trim_end()
+                    .to_owned(),
+                text: self.line_text(&path, line, &mut cache),
+                location: Location { path, line, column },
+            })
+            .collect();
+        self.results.show(
+            &format!(
+                "{} reference{} to {}",
+                count,
+                if count == 1 { "" } else { "s" },
+                word
+            ),
+            items,
+        );
+    }
+
+    fn update_choices(self: &Rc<Self>) {
+        let mut choices: Vec<(String, String)> = Vec::new();
+        for (path, symbols) in self.files.borrow().iter() {
+            for definition in &symbols.definitions {
+                choices.push((
+                    definition.qualified_name(),
+                    format!(
+                        "{}, {}:{}",
+                        definition.kind,
+                        self.relative(path),
+                        definition.line + 1
+                    ),
+                ));
+            }
+        }
+        choices.sort();
+        choices.dedup_by(|choice, other| choice.0 == other.0);
+        let weak = Rc::downgrade(self);
+        self.commands.register_with_argument(
+            "workspace.symbols",
+            "Go to Symbol in Workspace",
+            Argument {
+                prompt: "Workspace symbol".to_owned(),
+                choices,
+            },
+            move |name| {
+                let Some(workspace) = weak.upgrade() else {
+                    return;
+                };
+                workspace.refresh_current();
+                let definitions =
+                    workspace.definitions_where(|definition| definition.qualified_name() == name);
+                workspace.show_definitions(&format!("Definitions of {}", name), definitions);
+            },
+        );
+    }
+}